@@ -2,729 +2,4988 @@
 const SPEEEEEED: bool = 1==1;
 
 
-pub mod reg {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    TargetOutOfRange { pc: usize, target: usize },
+    FallsOffEnd      { pc: usize },
+    BadRegister      { pc: usize, index: usize },
+    StackUnderflow   { pc: usize, depth: usize },
+    StackOverflow    { pc: usize, depth: usize },
+    DepthMismatch    { pc: usize, expected: usize, found: usize },
+    /// a `Wide` that can't extend the instruction after it,
+    /// or a jump to the instruction after a `Wide`.
+    BadWide          { pc: usize },
+    /// a stack `Call` to something other than an `Enter`.
+    BadCall          { pc: usize },
+}
 
-    #[derive(Clone, Copy, Debug)]
-    #[repr(align(4))]
-    pub enum Instruction {
-        LoadInt     { dst: u8, value: i16 },
-        Copy        { dst: u8, src: u8 },
-        Add         { dst: u8, src1: u8, src2: u8 },
-        Sub         { dst: u8, src1: u8, src2: u8 },
-        Mul         { dst: u8, src1: u8, src2: u8 },
-        Jump        { target: u8 },
-        SetCounter  { src: u8 },
-        GetCounter  { dst: u8 },
-        Loop        { target: u8 },
-        LoopLe      { target: u8, src1: u8, src2: u8 },
-        Return      { src: u8 },
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VmError {
+    TooManyArgs    { count: usize },
+    StackOverflow  { pc: usize },
+    StackUnderflow { pc: usize },
+    PcOutOfBounds  { pc: usize, target: usize },
+    BadRegister    { pc: usize, index: usize },
+    /// execution fell through to `pc == code.len()`.
+    NoReturn       { pc: usize },
+    /// `pc` is the next instruction. the registers (or the stack)
+    /// are left as they were, see `Vm::run_with_fuel`.
+    OutOfFuel      { pc: usize, counter: u32 },
+    /// an operand had the wrong type, see `value`.
+    TypeError      { pc: usize },
+    /// a memory address wasn't an int in `0..memory.len()`.
+    BadAddress     { pc: usize },
+    /// `LoadConst` named a constant past the end of the pool,
+    /// see `reg::Vm::constants`.
+    BadConstant    { pc: usize },
+    /// a `Wide` that can't extend the instruction after it.
+    BadWide        { pc: usize },
+    /// a stack `Call` to something other than an `Enter`.
+    BadCall        { pc: usize },
+}
+
+/// calls nest at most this deep, in either vm.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// arms of vm loops for instructions the program doesn't have,
+/// like `Call` in the loop for programs without calls.
+#[inline(always)]
+fn unreachable_arm() -> ! {
+    if SPEEEEEED {
+        // verified.
+        unsafe { core::hint::unreachable_unchecked() }
+    }
+    else {
+        unreachable!()
     }
+}
+
 
-    pub struct Vm {
-        registers: Vec<f64>,
+pub mod value {
+    //! how the vms represent values, see `Value`.
+    //!
+    //! `f64` is untyped, ints and bools are just floats. `Tagged` and
+    //! `Boxed` keep ints (i32), floats and bools apart, with the same
+    //! semantics and different layouts:
+    //! - `add`, `sub`, `mul`, `neg`, `abs`, `floor`, `min` and `max` of
+    //!   ints are ints, unless the result doesn't fit, then it's a float.
+    //!   ints and floats mix as floats.
+    //! - `div` and `sqrt` are always floats.
+    //! - comparisons are bools. numbers compare by value, bools can
+    //!   only be compared to bools with `eq`.
+    //! - conditional jumps take bools, and numbers that aren't 0.
+    //! - memory addresses are numbers, see `Value::address`.
+    //! - anything else with a bool is a `VmError::TypeError`.
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum BinOp {
+        Add,
+        Sub,
+        Mul,
+        Div,
+        Lt,
+        Le,
+        Eq,
+        Min,
+        Max,
     }
 
-    struct State<'a> {
-        vm: &'a mut Vm,
-        code: &'a [Instruction],
-        pc: usize,
-        pcp: *const Instruction,
-        counter: u32,
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum UnOp {
+        Neg,
+        Sqrt,
+        Abs,
+        Floor,
     }
 
-    impl Vm {
-        pub fn new() -> Self {
-            Vm { registers: vec![0.0; 256] }
-        }
+    /// operations return `None` for type errors.
+    pub trait Value: Copy + core::fmt::Debug + PartialEq {
+        /// for arguments.
+        fn from_f64(x: f64) -> Self;
 
-        #[inline(never)]
-        pub fn run(&mut self, code: &[Instruction], args: &[f64]) -> f64 {
-            let mut s = State {
-                vm: self,
-                code,
-                pc: 0,
-                pcp: core::ptr::null(),
-                counter: 0,
-            };
+        /// for `LoadInt` and `GetCounter`.
+        fn from_int(x: i64) -> Self;
 
-            s.jump(0);
-            for (i, arg) in args.iter().enumerate() {
-                s.vm.registers[i] = *arg;
-            }
+        fn binary(self, op: BinOp, other: Self) -> Option<Self>;
 
-            loop {
-                let instr = s.next_instr();
+        fn unary(self, op: UnOp) -> Option<Self>;
 
-                use Instruction::*;
-                match instr {
-                    LoadInt { dst, value } => {
-                        *s.reg(dst) = value as f64;
-                    }
+        /// whether a conditional jump takes this as true.
+        fn is_true(self) -> bool;
 
-                    Copy { dst, src } => {
-                        *s.reg(dst) = *s.reg(src);
-                    }
+        /// for `SetCounter`, saturating like `as u32`.
+        fn counter(self) -> Option<u32>;
 
-                    Add { dst, src1, src2 } => {
-                        *s.reg(dst) = *s.reg(src1) + *s.reg(src2);
-                    }
+        /// for `LoadMem` and `StoreMem`. numbers that aren't
+        /// non-negative ints are `usize::MAX`, which is out of bounds.
+        fn address(self) -> Option<usize>;
 
-                    Sub { dst, src1, src2 } => {
-                        *s.reg(dst) = *s.reg(src1) - *s.reg(src2);
-                    }
+        /// bools are 1 and 0.
+        fn to_f64(self) -> f64;
+    }
 
-                    Mul { dst, src1, src2 } => {
-                        *s.reg(dst) = *s.reg(src1) * *s.reg(src2);
-                    }
+    impl Value for f64 {
+        #[inline(always)]
+        fn from_f64(x: f64) -> Self { x }
 
-                    Jump { target } => {
-                        s.jump(target);
-                    }
+        #[inline(always)]
+        fn from_int(x: i64) -> Self { x as f64 }
 
-                    SetCounter { src } => {
-                        s.counter = *s.reg(src) as u32;
-                    }
+        #[inline(always)]
+        fn binary(self, op: BinOp, other: Self) -> Option<Self> {
+            Some(match float_binary(op, self, other) {
+                Tagged::Float(x) => x,
+                Tagged::Bool(b)  => f64::from(b),
+                Tagged::Int(_)   => unreachable!(),
+            })
+        }
 
-                    GetCounter { dst } => {
-                        *s.reg(dst) = s.counter as f64;
-                    }
+        #[inline(always)]
+        fn unary(self, op: UnOp) -> Option<Self> {
+            Some(float_unary(op, self))
+        }
 
-                    Loop { target } => {
-                        if s.counter > 0 {
-                            s.counter -= 1;
-                            s.jump(target);
-                        }
-                    }
+        #[inline(always)]
+        fn is_true(self) -> bool { self != 0.0 }
 
-                    LoopLe { target, src1, src2 } => {
-                        let a = *s.reg(src1);
-                        let b = *s.reg(src2);
-                        if a <= b && s.counter > 0 {
-                            s.counter -= 1;
-                            s.jump(target);
-                        }
-                    }
+        #[inline(always)]
+        fn counter(self) -> Option<u32> { Some(self as u32) }
 
-                    Return { src } => {
-                        let result = *s.reg(src);
-                        return result;
-                    }
-                }
-            }
+        #[inline(always)]
+        fn address(self) -> Option<usize> { Some(float_address(self)) }
+
+        #[inline(always)]
+        fn to_f64(self) -> f64 { self }
+    }
+
+    #[inline(always)]
+    fn float_binary(op: BinOp, a: f64, b: f64) -> Tagged {
+        use Tagged::*;
+        match op {
+            BinOp::Add => Float(a + b),
+            BinOp::Sub => Float(a - b),
+            BinOp::Mul => Float(a * b),
+            BinOp::Div => Float(a / b),
+            BinOp::Lt  => Bool(a <  b),
+            BinOp::Le  => Bool(a <= b),
+            BinOp::Eq  => Bool(a == b),
+            BinOp::Min => Float(a.min(b)),
+            BinOp::Max => Float(a.max(b)),
+        }
+    }
+
+    #[inline(always)]
+    fn float_address(x: f64) -> usize {
+        // saturates, so this also catches nans and negatives.
+        let address = x as usize;
+        if address as f64 == x { address } else { usize::MAX }
+    }
+
+    #[inline(always)]
+    fn float_unary(op: UnOp, a: f64) -> f64 {
+        match op {
+            UnOp::Neg   => -a,
+            UnOp::Sqrt  => a.sqrt(),
+            UnOp::Abs   => a.abs(),
+            UnOp::Floor => a.floor(),
         }
     }
 
-    impl<'a> State<'a> {
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Tagged {
+        Int   (i32),
+        Float (f64),
+        Bool  (bool),
+    }
+
+    impl Tagged {
+        /// a float if `x` doesn't fit.
         #[inline(always)]
-        fn next_instr(&mut self) -> Instruction {
-            if super::SPEEEEEED {
-                unsafe {
-                    let result = *self.pcp;
-                    self.pcp = self.pcp.add(1);
-                    result
-                }
+        pub fn int(x: i64) -> Tagged {
+            match i32::try_from(x) {
+                Ok(x)  => Tagged::Int(x),
+                Err(_) => Tagged::Float(x as f64),
             }
-            else {
-                let result = self.code[self.pc];
-                self.pc += 1;
-                result
+        }
+
+        #[inline(always)]
+        fn number(self) -> Option<f64> {
+            match self {
+                Tagged::Int(x)   => Some(x as f64),
+                Tagged::Float(x) => Some(x),
+                Tagged::Bool(_)  => None,
             }
         }
+    }
 
+    impl Value for Tagged {
         #[inline(always)]
-        fn jump(&mut self, target: u8) {
-            if super::SPEEEEEED {
-                unsafe {
-                    self.pcp = self.code.as_ptr().add(target as usize);
+        fn from_f64(x: f64) -> Self { Tagged::Float(x) }
+
+        #[inline(always)]
+        fn from_int(x: i64) -> Self { Tagged::int(x) }
+
+        #[inline(always)]
+        fn binary(self, op: BinOp, other: Self) -> Option<Self> {
+            use Tagged::*;
+            Some(match (self, other) {
+                (Int(a), Int(b)) => {
+                    let (a, b) = (a as i64, b as i64);
+                    match op {
+                        BinOp::Add => Tagged::int(a + b),
+                        BinOp::Sub => Tagged::int(a - b),
+                        BinOp::Mul => Tagged::int(a * b),
+                        BinOp::Div => Float(a as f64 / b as f64),
+                        BinOp::Lt  => Bool(a <  b),
+                        BinOp::Le  => Bool(a <= b),
+                        BinOp::Eq  => Bool(a == b),
+                        BinOp::Min => Tagged::int(a.min(b)),
+                        BinOp::Max => Tagged::int(a.max(b)),
+                    }
                 }
+
+                (Bool(a), Bool(b)) if op == BinOp::Eq => Bool(a == b),
+
+                _ => float_binary(op, self.number()?, other.number()?),
+            })
+        }
+
+        #[inline(always)]
+        fn unary(self, op: UnOp) -> Option<Self> {
+            Some(match (self, op) {
+                (Tagged::Int(a), UnOp::Neg)   => Tagged::int(-(a as i64)),
+                (Tagged::Int(a), UnOp::Abs)   => Tagged::int((a as i64).abs()),
+                (Tagged::Int(a), UnOp::Floor) => Tagged::Int(a),
+                _ => Tagged::Float(float_unary(op, self.number()?)),
+            })
+        }
+
+        #[inline(always)]
+        fn is_true(self) -> bool {
+            match self {
+                Tagged::Int(x)   => x != 0,
+                Tagged::Float(x) => x != 0.0,
+                Tagged::Bool(b)  => b,
             }
-            else {
-                self.pc = target as usize;
+        }
+
+        #[inline(always)]
+        fn counter(self) -> Option<u32> {
+            match self {
+                Tagged::Int(x)   => Some(x.max(0) as u32),
+                Tagged::Float(x) => Some(x as u32),
+                Tagged::Bool(_)  => None,
             }
         }
 
         #[inline(always)]
-        fn reg(&mut self, index: u8) -> &mut f64 {
-            if super::SPEEEEEED {
-                unsafe { self.vm.registers.get_unchecked_mut(index as usize) }
+        fn address(self) -> Option<usize> {
+            match self {
+                Tagged::Int(x)   => Some(usize::try_from(x).unwrap_or(usize::MAX)),
+                Tagged::Float(x) => Some(float_address(x)),
+                Tagged::Bool(_)  => None,
             }
-            else {
-                &mut self.vm.registers[index as usize]
+        }
+
+        #[inline(always)]
+        fn to_f64(self) -> f64 {
+            match self {
+                Tagged::Int(x)   => x as f64,
+                Tagged::Float(x) => x,
+                Tagged::Bool(b)  => f64::from(b),
             }
         }
     }
 
 
-    pub const FIB: &[Instruction] = { use Instruction::*; &[
-        SetCounter { src: 0 },
-        LoadInt { dst: 1, value: 0 },
-        LoadInt { dst: 2, value: 1 },
-        Jump { target: 7 },
-        Add { dst: 3, src1: 1, src2: 2 },
-        Copy { dst: 1, src: 2 },
-        Copy { dst: 2, src: 3 },
-        Loop { target: 4 },
-        Return { src: 1 },
-    ]};
+    /// nan-boxed `Tagged`, in 8 bytes instead of 16.
+    /// floats are stored as they are, with every nan turned into
+    /// `f64::NAN`. ints and bools live in the negative nans above that.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct Boxed(u64);
 
+    const TAG:  u64 = 0xffff << 48;
+    const INT:  u64 = 0xfff9 << 48;
+    const BOOL: u64 = 0xfffa << 48;
 
-    pub const MANDEL: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y, t0, t1) = (0, 1, 2, 3, 4, 5, 6); &[
-        LoadInt { dst: x, value: 0 },
-        LoadInt { dst: y, value: 0 },
+    impl Boxed {
+        #[inline(always)]
+        pub fn new(value: Tagged) -> Boxed {
+            match value {
+                Tagged::Int(x)   => Boxed(INT | x as u32 as u64),
+                Tagged::Float(x) => Boxed::float(x),
+                Tagged::Bool(b)  => Boxed(BOOL | b as u64),
+            }
+        }
 
-        // 2
-        SetCounter { src: n },
+        #[inline(always)]
+        pub fn get(self) -> Tagged {
+            match self.0 & TAG {
+                INT  => Tagged::Int(self.0 as u32 as i32),
+                BOOL => Tagged::Bool(self.0 & 1 != 0),
+                _    => Tagged::Float(f64::from_bits(self.0)),
+            }
+        }
 
-        Jump { target: 13 },
+        #[inline(always)]
+        fn float(x: f64) -> Boxed {
+            if x.is_nan() { Boxed(f64::NAN.to_bits()) }
+            else          { Boxed(x.to_bits()) }
+        }
 
-        // 4
-        // let xtemp = x*x - y*y + x0;
-        Mul { dst: t0, src1: x, src2: x },
-        Mul { dst: t1, src1: y, src2: y },
-        Sub { dst: t0, src1: t0, src2: t1 },
-        Add { dst: t0, src1: t0, src2: x0 },
-        // y = x*y*2.0 + y0;
-        Mul { dst: y, src1: x, src2: y },
-        LoadInt { dst: t1, value: 2 },
-        Mul { dst: y, src1: y, src2: t1 },
-        Add { dst: y, src1: y, src2: y0 },
-        // x = xtemp
-        Copy { dst: x, src: t0 },
+        #[inline(always)]
+        fn is_float(self) -> bool {
+            self.0 < INT
+        }
+    }
 
-        // check x*x + y*y <= 2*2
-        // 13
-        Mul { dst: t0, src1: x, src2: x },
-        Mul { dst: t1, src1: y, src2: y },
-        Add { dst: t0, src1: t0, src2: t1 },
-        LoadInt { dst: t1, value: 4 },
-        LoopLe { target: 4, src1: t0, src2: t1 },
+    impl core::fmt::Debug for Boxed {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            write!(f, "Boxed({:?})", self.get())
+        }
+    }
 
-        // 18
-        GetCounter { dst: t1 },
-        Sub { dst: t0, src1: n, src2: t1 },
-        Return { src: t0 },
-    ]};
+    impl Value for Boxed {
+        #[inline(always)]
+        fn from_f64(x: f64) -> Self { Boxed::float(x) }
 
+        #[inline(always)]
+        fn from_int(x: i64) -> Self { Boxed::new(Tagged::int(x)) }
 
-    pub const ADD_CHAIN: &[Instruction] = { use Instruction::*; &[
-        Add { dst: 0, src1: 0, src2:  1 },
-        Add { dst: 0, src1: 0, src2:  2 },
-        Add { dst: 0, src1: 0, src2:  3 },
-        Add { dst: 0, src1: 0, src2:  4 },
-        Add { dst: 0, src1: 0, src2:  5 },
-        Add { dst: 0, src1: 0, src2:  6 },
-        Add { dst: 0, src1: 0, src2:  7 },
-        Add { dst: 0, src1: 0, src2:  8 },
-        Add { dst: 0, src1: 0, src2:  9 },
-        Add { dst: 0, src1: 0, src2: 10 },
-        Add { dst: 0, src1: 0, src2: 11 },
-        Add { dst: 0, src1: 0, src2: 12 },
-        Add { dst: 0, src1: 0, src2: 13 },
-        Add { dst: 0, src1: 0, src2: 14 },
-        Add { dst: 0, src1: 0, src2: 15 },
-        Return { src: 0 },
-    ]};
+        // floats don't need to be unpacked.
+        #[inline(always)]
+        fn binary(self, op: BinOp, other: Self) -> Option<Self> {
+            if self.is_float() && other.is_float() {
+                return Some(Boxed::new(float_binary(op, f64::from_bits(self.0), f64::from_bits(other.0))));
+            }
+            self.get().binary(op, other.get()).map(Boxed::new)
+        }
 
-    pub const ADD_PAIRS: &[Instruction] = { use Instruction::*; &[
-        Add { dst:  0, src1:  0, src2:  1 },
-        Add { dst:  2, src1:  2, src2:  3 },
-        Add { dst:  4, src1:  4, src2:  5 },
-        Add { dst:  6, src1:  6, src2:  7 },
-        Add { dst:  8, src1:  8, src2:  9 },
-        Add { dst: 10, src1: 10, src2: 11 },
-        Add { dst: 12, src1: 12, src2: 13 },
-        Add { dst: 14, src1: 14, src2: 15 },
-        Add { dst:  0, src1:  0, src2:  2 },
-        Add { dst:  4, src1:  4, src2:  6 },
-        Add { dst:  8, src1:  8, src2: 10 },
-        Add { dst: 12, src1: 12, src2: 14 },
-        Add { dst:  0, src1:  0, src2:  4 },
-        Add { dst:  8, src1:  8, src2: 12 },
-        Add { dst:  0, src1:  0, src2:  8 },
-        Return { src: 0 },
-    ]};
+        #[inline(always)]
+        fn unary(self, op: UnOp) -> Option<Self> {
+            if self.is_float() {
+                return Some(Boxed::float(float_unary(op, f64::from_bits(self.0))));
+            }
+            self.get().unary(op).map(Boxed::new)
+        }
+
+        #[inline(always)]
+        fn is_true(self) -> bool { self.get().is_true() }
+
+        #[inline(always)]
+        fn counter(self) -> Option<u32> { self.get().counter() }
+
+        #[inline(always)]
+        fn address(self) -> Option<usize> { self.get().address() }
+
+        #[inline(always)]
+        fn to_f64(self) -> f64 { self.get().to_f64() }
+    }
 }
 
 
-pub mod stack {
 
-    #[derive(Clone, Copy, Debug)]
-    #[repr(align(2))]
+pub mod reg {
+    use core::marker::PhantomData;
+    use super::{VmError, packed, jit};
+    use super::dispatch::{self, Dispatch, Kind};
+    use super::profile::Profile;
+    use super::value::{Value, BinOp, UnOp};
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[repr(align(4))]
     pub enum Instruction {
-        Load         { src: u8 },
-        Store        { dst: u8 },
-        LoadInt      { value: i8 },
-        Add,
-        Sub,
-        Mul,
-        Pop,
-        Dup,
-        Rot,
-        Swap,
-        Jump         { target: u8 },
-        SetCounter,
-        GetCounter,
-        Loop         { target: u8 },
-        LoopLe       { target: u8 },
-        Return,
-        Nop,
+        LoadInt     { dst: u8, value: i16 },
+        Copy        { dst: u8, src: u8 },
+        Add         { dst: u8, src1: u8, src2: u8 },
+        Sub         { dst: u8, src1: u8, src2: u8 },
+        Mul         { dst: u8, src1: u8, src2: u8 },
+        Div         { dst: u8, src1: u8, src2: u8 },
+        Neg         { dst: u8, src: u8 },
+        /// `dst` is 1 if `src1 < src2`, 0 otherwise. same for `Le` and `Eq`.
+        Lt          { dst: u8, src1: u8, src2: u8 },
+        Le          { dst: u8, src1: u8, src2: u8 },
+        Eq          { dst: u8, src1: u8, src2: u8 },
+        Sqrt        { dst: u8, src: u8 },
+        Abs         { dst: u8, src: u8 },
+        Floor       { dst: u8, src: u8 },
+        Min         { dst: u8, src1: u8, src2: u8 },
+        Max         { dst: u8, src1: u8, src2: u8 },
+        Jump        { target: u8 },
+        /// jumps if `src` isn't 0.
+        JumpIf      { target: u8, src: u8 },
+        /// jumps if `src` is 0.
+        JumpIfNot   { target: u8, src: u8 },
+        SetCounter  { src: u8 },
+        GetCounter  { dst: u8 },
+        Loop        { target: u8 },
+        LoopLe      { target: u8, src1: u8, src2: u8 },
+        Return      { src: u8 },
+        /// calls `target` with a register window starting at `base`:
+        /// the callee's `r0` is the caller's `r[base]`, and so on.
+        Call        { target: u8, base: u8 },
+        /// returns from a `Call`, the result goes to the callee's `r0`.
+        /// outside of calls, like `Return`.
+        Ret         { src: u8 },
+        /// `dst = memory[r[addr]]`, see `Vm::memory`.
+        LoadMem     { dst: u8, addr: u8 },
+        /// `memory[r[addr]] = src`.
+        StoreMem    { src: u8, addr: u8 },
+        /// `dst = constants[index]`, see `Vm::constants`.
+        LoadConst   { dst: u8, index: u8 },
+        /// extends the next instruction's operands to 16 bits, `high[i]`
+        /// is the high byte of its `i`th operand. see `decode`.
+        Wide        { high: [u8; 3] },
+        /// `dst += src1 * src2`, a `Mul` and an `Add` fused by `fuse`.
+        MulAdd      { dst: u8, src1: u8, src2: u8 },
+        /// `dst -= src1 * src2`.
+        MulSub      { dst: u8, src1: u8, src2: u8 },
+        /// jumps if `src1 < src2`, a `Lt` and a `JumpIf` fused by `fuse`.
+        JumpIfLt    { target: u8, src1: u8, src2: u8 },
+        /// jumps if `src1 <= src2`.
+        JumpIfLe    { target: u8, src1: u8, src2: u8 },
     }
 
-    pub struct Vm {
-        stack: Vec<f64>,
+    /// what an operand names, see `Instruction::operands`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Operand {
+        Reg,
+        Target,
+        Const,
     }
 
-    struct State<'a> {
-        vm: &'a mut Vm,
-        code: &'a [Instruction],
-        pc: usize,
-        pcp: *const Instruction,
-        counter: u32,
-        base: *mut f64,
-        top:  *mut f64,
-    }
+    impl Instruction {
+        pub fn target(&self) -> Option<u8> {
+            use Instruction::*;
+            match *self {
+                Jump { target } | JumpIf { target, .. } | JumpIfNot { target, .. } |
+                JumpIfLt { target, .. } | JumpIfLe { target, .. } |
+                Loop { target } | LoopLe { target, .. } |
+                Call { target, .. } => Some(target),
+                _ => None,
+            }
+        }
 
-    impl Vm {
-        pub fn new() -> Self {
-            Vm { stack: Vec::with_capacity(256) }
+        pub fn target_mut(&mut self) -> Option<&mut u8> {
+            use Instruction::*;
+            match self {
+                Jump { target } | JumpIf { target, .. } | JumpIfNot { target, .. } |
+                JumpIfLt { target, .. } | JumpIfLe { target, .. } |
+                Loop { target } | LoopLe { target, .. } |
+                Call { target, .. } => Some(target),
+                _ => None,
+            }
         }
 
-        #[inline(never)]
-        pub fn run(&mut self, code: &[Instruction], args: &[f64]) -> f64 {
-            let base = self.stack.as_mut_ptr();
-            let base = ((base as usize + 63) / 64 * 64) as *mut f64;
+        pub fn falls_through(&self) -> bool {
+            use Instruction::*;
+            !matches!(self, Jump { .. } | Return { .. } | Ret { .. })
+        }
 
-            let mut s = State {
-                code,
-                pc: 0,
-                pcp: core::ptr::null(),
-                base,
-                top: base,
-                counter: 0,
-                vm: self,
+        /// whether this is one of `fuse`'s superinstructions.
+        pub fn is_fused(&self) -> bool {
+            use Instruction::*;
+            matches!(self, MulAdd { .. } | MulSub { .. } | JumpIfLt { .. } | JumpIfLe { .. })
+        }
+
+        pub fn dst(&self) -> Option<u8> {
+            use Instruction::*;
+            match *self {
+                LoadInt { dst, .. } | Copy { dst, .. } | GetCounter { dst } |
+                Add { dst, .. } | Sub { dst, .. } | Mul { dst, .. } | Div { dst, .. } | Neg { dst, .. } |
+                Lt { dst, .. } | Le { dst, .. } | Eq { dst, .. } |
+                Sqrt { dst, .. } | Abs { dst, .. } | Floor { dst, .. } | Min { dst, .. } | Max { dst, .. } |
+                LoadMem { dst, .. } | LoadConst { dst, .. } |
+                MulAdd { dst, .. } | MulSub { dst, .. } => Some(dst),
+                _ => None,
+            }
+        }
+
+        /// `dst` with the high byte of a `Wide` prefix, see `decode`.
+        pub fn wide_dst(&self, high: [u8; 3]) -> Option<usize> {
+            // always the first operand.
+            self.dst().map(|dst| wide(dst, high[0]))
+        }
+
+        pub fn srcs(&self) -> impl Iterator<Item = u8> {
+            self.wide_srcs([0; 3]).map(|src| src as u8)
+        }
+
+        /// `srcs` with the high bytes of a `Wide` prefix, see `decode`.
+        pub fn wide_srcs(&self, [a, b, c]: [u8; 3]) -> impl Iterator<Item = usize> {
+            use Instruction::*;
+            let srcs = match *self {
+                SetCounter { src } | Return { src } | Ret { src } => [Some(wide(src, a)), None, None],
+                Copy { src, .. } |
+                Neg { src, .. } | Sqrt { src, .. } | Abs { src, .. } | Floor { src, .. } |
+                JumpIf { src, .. } | JumpIfNot { src, .. } |
+                LoadMem { addr: src, .. } => [Some(wide(src, b)), None, None],
+                Add { src1, src2, .. } | Sub { src1, src2, .. } | Mul { src1, src2, .. } | Div { src1, src2, .. } |
+                Lt { src1, src2, .. } | Le { src1, src2, .. } | Eq { src1, src2, .. } |
+                Min { src1, src2, .. } | Max { src1, src2, .. } |
+                LoopLe { src1, src2, .. } | JumpIfLt { src1, src2, .. } | JumpIfLe { src1, src2, .. } => [Some(wide(src1, b)), Some(wide(src2, c)), None],
+                StoreMem { src, addr } => [Some(wide(src, a)), Some(wide(addr, b)), None],
+                MulAdd { dst, src1, src2 } | MulSub { dst, src1, src2 } => [Some(wide(src1, b)), Some(wide(src2, c)), Some(wide(dst, a))],
+                _ => [None, None, None],
             };
+            srcs.into_iter().flatten()
+        }
 
-            s.jump(0);
-            for arg in args {
-                s.push(*arg);
+        /// the operands `Wide` can extend, in declaration order.
+        /// `LoadInt`'s value isn't one, larger numbers go into the
+        /// constant pool.
+        pub fn operands(&self) -> [Option<(Operand, u8)>; 3] {
+            use Instruction::*;
+            use Operand::{Reg, Target, Const};
+            match *self {
+                LoadInt { dst, .. } | GetCounter { dst } => [Some((Reg, dst)), None, None],
+                SetCounter { src } | Return { src } | Ret { src } => [Some((Reg, src)), None, None],
+                Copy { dst, src } | Neg { dst, src } |
+                Sqrt { dst, src } | Abs { dst, src } | Floor { dst, src } |
+                LoadMem { dst, addr: src } | StoreMem { src: dst, addr: src } => [Some((Reg, dst)), Some((Reg, src)), None],
+                Add { dst, src1, src2 } | Sub { dst, src1, src2 } | Mul { dst, src1, src2 } | Div { dst, src1, src2 } |
+                Lt { dst, src1, src2 } | Le { dst, src1, src2 } | Eq { dst, src1, src2 } |
+                Min { dst, src1, src2 } | Max { dst, src1, src2 } |
+                MulAdd { dst, src1, src2 } | MulSub { dst, src1, src2 } => [Some((Reg, dst)), Some((Reg, src1)), Some((Reg, src2))],
+                Jump { target } | Loop { target } => [Some((Target, target)), None, None],
+                JumpIf { target, src } | JumpIfNot { target, src } |
+                Call { target, base: src } => [Some((Target, target)), Some((Reg, src)), None],
+                LoopLe { target, src1, src2 } |
+                JumpIfLt { target, src1, src2 } | JumpIfLe { target, src1, src2 } => [Some((Target, target)), Some((Reg, src1)), Some((Reg, src2))],
+                LoadConst { dst, index } => [Some((Reg, dst)), Some((Const, index)), None],
+                Wide { .. } => [None; 3],
             }
+        }
 
-            loop {
-                let instr = s.next_instr();
+        /// the index of the variant, which is also its opcode in
+        /// `bytecode`. less than `OPCODES`.
+        #[inline(always)]
+        pub fn opcode(&self) -> u8 {
+            use Instruction::*;
+            match self {
+                LoadInt    { .. } => 0,
+                Copy       { .. } => 1,
+                Add        { .. } => 2,
+                Sub        { .. } => 3,
+                Mul        { .. } => 4,
+                Div        { .. } => 5,
+                Neg        { .. } => 6,
+                Lt         { .. } => 7,
+                Le         { .. } => 8,
+                Eq         { .. } => 9,
+                Sqrt       { .. } => 10,
+                Abs        { .. } => 11,
+                Floor      { .. } => 12,
+                Min        { .. } => 13,
+                Max        { .. } => 14,
+                Jump       { .. } => 15,
+                JumpIf     { .. } => 16,
+                JumpIfNot  { .. } => 17,
+                SetCounter { .. } => 18,
+                GetCounter { .. } => 19,
+                Loop       { .. } => 20,
+                LoopLe     { .. } => 21,
+                Return     { .. } => 22,
+                Call       { .. } => 23,
+                Ret        { .. } => 24,
+                LoadMem    { .. } => 25,
+                StoreMem   { .. } => 26,
+                LoadConst  { .. } => 27,
+                Wide       { .. } => 28,
+                MulAdd     { .. } => 29,
+                MulSub     { .. } => 30,
+                JumpIfLt   { .. } => 31,
+                JumpIfLe   { .. } => 32,
+            }
+        }
+    }
 
-                use Instruction::*;
-                match instr {
-                    Load { src } => {
-                        let value = *s.get(src);
-                        s.push(value);
-                    }
+    pub const OPCODES: usize = 33;
+
+    /// the instruction at `pc`, the high bytes of its operands, and its
+    /// length, which is 2 with a `Wide` prefix. `None` if `pc` is out of
+    /// bounds, or the prefix has high bytes for operands that don't exist.
+    #[inline(always)]
+    pub fn decode(code: &[Instruction], pc: usize) -> Option<(Instruction, [u8; 3], usize)> {
+        let instr = *code.get(pc)?;
+        let Instruction::Wide { high } = instr else {
+            return Some((instr, [0; 3], 1));
+        };
+        let next = *code.get(pc + 1)?;
+        if matches!(next, Instruction::Wide { .. })
+        || high.iter().zip(next.operands()).any(|(high, operand)| *high != 0 && operand.is_none()) {
+            return None;
+        }
+        Some((next, high, 2))
+    }
 
-                    Store { dst } => {
-                        let value = s.pop();
-                        *s.get(dst) = value;
-                    }
+    /// an operand with its high byte from a `Wide`.
+    #[inline(always)]
+    pub fn wide(low: u8, high: u8) -> usize {
+        u16::from_le_bytes([low, high]) as usize
+    }
 
-                    LoadInt { value } => {
-                        s.push(value as f64);
-                    }
 
-                    Add => {
-                        *s.get_top(1) = *s.get_top(1) + *s.get_top(0);
-                        s.pop();
-                    }
+    /// registers an instruction can name, the size of a window.
+    pub const REGISTER_COUNT: usize = 256;
 
-                    Sub => {
-                        *s.get_top(1) = *s.get_top(1) - *s.get_top(0);
-                        s.pop();
-                    }
+    /// size of the register file, room for the windows of calls.
+    pub const MAX_REGISTERS: usize = 64 * REGISTER_COUNT;
 
-                    Mul => {
-                        *s.get_top(1) = *s.get_top(1) * *s.get_top(0);
-                        s.pop();
-                    }
+    /// registers that are live after each instruction.
+    /// doesn't know what calls read or write.
+    pub fn live_out(code: &[Instruction]) -> Vec<BitSet> {
+        liveness(code, REGISTER_COUNT)
+    }
 
-                    Pop => {
-                        s.pop();
-                    }
+    /// what `liveness` needs from an instruction, so that `regalloc`'s
+    /// virtual registers can share it.
+    pub trait Dataflow: Copy {
+        fn target(&self) -> Option<usize>;
+        fn falls_through(&self) -> bool;
+        fn dst(&self) -> Option<usize>;
+        fn srcs(&self) -> impl Iterator<Item = usize>;
+    }
 
-                    Dup => {
-                        let value = *s.get_top(0);
-                        s.push(value);
-                    }
+    impl Dataflow for Instruction {
+        fn target(&self) -> Option<usize> { Instruction::target(self).map(usize::from) }
+        fn falls_through(&self) -> bool { Instruction::falls_through(self) }
+        fn dst(&self) -> Option<usize> { Instruction::dst(self).map(usize::from) }
+        fn srcs(&self) -> impl Iterator<Item = usize> { Instruction::srcs(self).map(usize::from) }
+    }
 
-                    Rot => {
-                        let a = *s.get_top(2);
-                        *s.get_top(2) = *s.get_top(1);
-                        *s.get_top(1) = *s.get_top(0);
-                        *s.get_top(0) = a;
+    /// `live_out`, for `registers` registers.
+    pub fn liveness<I: Dataflow>(code: &[I], registers: usize) -> Vec<BitSet> {
+        let mut live_in  = vec![BitSet::new(registers); code.len()];
+        let mut live_out = vec![BitSet::new(registers); code.len()];
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for pc in (0..code.len()).rev() {
+                let instr = code[pc];
+
+                let mut out = BitSet::new(registers);
+                if let Some(target) = instr.target() {
+                    if let Some(live) = live_in.get(target) {
+                        out.union_with(live);
                     }
-
-                    Swap => {
-                        let a = *s.get_top(0);
-                        *s.get_top(0) = *s.get_top(1);
-                        *s.get_top(1) = a;
+                }
+                if instr.falls_through() {
+                    if let Some(live) = live_in.get(pc + 1) {
+                        out.union_with(live);
                     }
+                }
 
-                    Jump { target } => {
-                        s.jump(target);
-                    }
+                let mut inn = out.clone();
+                if let Some(dst) = instr.dst() {
+                    inn.remove(dst);
+                }
+                for src in instr.srcs() {
+                    inn.insert(src);
+                }
 
-                    SetCounter => {
-                        let value = s.pop();
-                        s.counter = value as u32;
-                    }
+                if inn != live_in[pc] || out != live_out[pc] {
+                    live_in[pc]  = inn;
+                    live_out[pc] = out;
+                    changed = true;
+                }
+            }
+        }
+        live_out
+    }
 
-                    GetCounter => {
-                        s.push(s.counter as f64);
-                    }
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct BitSet {
+        words: Vec<u64>,
+    }
 
-                    Loop { target } => {
-                        if s.counter > 0 {
-                            s.counter -= 1;
-                            s.jump(target);
-                        }
-                    }
+    impl BitSet {
+        pub fn new(len: usize) -> Self {
+            BitSet { words: vec![0; len.div_ceil(64)] }
+        }
 
-                    LoopLe { target } => {
-                        let b = s.pop();
-                        let a = s.pop();
+        pub fn contains(&self, i: usize) -> bool {
+            self.words[i / 64] & (1 << (i % 64)) != 0
+        }
 
-                        if a <= b && s.counter > 0 {
-                            s.counter -= 1;
-                            s.jump(target);
-                        }
-                    }
+        pub fn insert(&mut self, i: usize) {
+            self.words[i / 64] |= 1 << (i % 64);
+        }
 
-                    Return => {
-                        let result = s.pop();
-                        s.clear();
-                        return result;
-                    }
+        pub fn remove(&mut self, i: usize) {
+            self.words[i / 64] &= !(1 << (i % 64));
+        }
 
-                    Nop => {}
-                }
+        pub fn union_with(&mut self, other: &BitSet) {
+            for (a, b) in self.words.iter_mut().zip(&other.words) {
+                *a |= *b;
             }
         }
+
+        pub fn len(&self) -> usize {
+            self.words.iter().map(|w| w.count_ones() as usize).sum()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+            self.words.iter().enumerate().flat_map(|(i, w)| {
+                (0..64).filter(move |b| w & (1 << b) != 0).map(move |b| i*64 + b)
+            })
+        }
+    }
+
+    /// code that passed `verify`, safe to run with `SPEEEEEED`.
+    #[derive(Clone, Debug)]
+    pub struct VerifiedProgram {
+        code: Vec<Instruction>,
+        registers: usize,
+        calls: bool,
+        compact: bool,
     }
 
-    impl<'a> State<'a> {
+    impl VerifiedProgram {
         #[inline(always)]
-        fn next_instr(&mut self) -> Instruction {
-            if super::SPEEEEEED {
-                unsafe {
-                    let result = *self.pcp;
-                    self.pcp = self.pcp.add(1);
-                    result
-                }
-            }
-            else {
-                let result = self.code[self.pc];
-                self.pc += 1;
-                result
-            }
-        }
+        pub fn code(&self) -> &[Instruction] { &self.code }
 
+        /// number of registers the program touches.
         #[inline(always)]
-        fn jump(&mut self, target: u8) {
-            if super::SPEEEEEED {
-                unsafe {
-                    self.pcp = self.code.as_ptr().add(target as usize);
-                }
+        pub fn registers(&self) -> usize { self.registers }
+
+        /// whether the program has a reachable `Call`.
+        #[inline(always)]
+        pub fn calls(&self) -> bool { self.calls }
+
+        /// whether the program runs in the vm's compact loop: no calls,
+        /// no memory, no constants or `Wide`, none of `Div` through
+        /// `Max`, and no fused jumps. see `Vm::run_impl`.
+        #[inline(always)]
+        pub fn compact(&self) -> bool { self.compact }
+    }
+
+    /// checks that all reachable code stays in bounds and ends in `Return`.
+    /// a `Wide` and the instruction after it are one instruction here,
+    /// nothing may jump between them.
+    pub fn verify(code: &[Instruction]) -> Result<VerifiedProgram, super::VerifyError> {
+        use super::VerifyError::*;
+
+        let mut registers = 0;
+        let mut calls = false;
+        let mut compact = true;
+        let mut visited = vec![false; code.len()];
+        let mut widened = vec![false; code.len()];
+        let mut work = vec![0];
+        while let Some(pc) = work.pop() {
+            if pc >= code.len() {
+                // only the entry can get here, everything else is checked below.
+                return Err(FallsOffEnd { pc });
             }
-            else {
-                self.pc = target as usize;
+            if visited[pc] {
+                continue;
             }
-        }
+            visited[pc] = true;
 
-        #[inline(always)]
-        fn get(&mut self, index: u8) -> &mut f64 {
-            if super::SPEEEEEED {
-                unsafe {
-                    &mut *self.base.add(index as usize)
+            let Some((instr, high, len)) = decode(code, pc) else {
+                return Err(BadWide { pc });
+            };
+            if len == 2 {
+                widened[pc + 1] = true;
+                compact = false;
+            }
+
+            // `Call`'s result lands in `r[base]`, which is an operand.
+            let mut target = None;
+            for (operand, high) in instr.operands().into_iter().zip(high) {
+                match operand {
+                    Some((Operand::Reg, index)) => {
+                        let index = wide(index, high);
+                        if index >= MAX_REGISTERS {
+                            return Err(BadRegister { pc, index });
+                        }
+                        registers = registers.max(index + 1);
+                    }
+                    Some((Operand::Target, low)) => {
+                        target = Some(wide(low, high));
+                    }
+                    _ => {}
                 }
             }
-            else {
-                &mut self.vm.stack[index as usize]
+            match instr {
+                Instruction::Call { .. } => {
+                    calls = true;
+                    compact = false;
+                }
+                Instruction::Div { .. } | Instruction::Neg { .. } |
+                Instruction::Lt { .. } | Instruction::Le { .. } | Instruction::Eq { .. } |
+                Instruction::Sqrt { .. } | Instruction::Abs { .. } | Instruction::Floor { .. } |
+                Instruction::Min { .. } | Instruction::Max { .. } |
+                Instruction::LoadMem { .. } | Instruction::StoreMem { .. } |
+                Instruction::LoadConst { .. } |
+                Instruction::JumpIfLt { .. } | Instruction::JumpIfLe { .. } => {
+                    compact = false;
+                }
+                _ => {}
             }
-        }
 
-        #[inline(always)]
-        fn get_top(&mut self, index: u8) -> &mut f64 {
-            if super::SPEEEEEED {
-                unsafe {
-                    &mut *self.top.sub(1).sub(index as usize)
+            if let Some(target) = target {
+                if target >= code.len() {
+                    return Err(TargetOutOfRange { pc, target });
                 }
+                work.push(target);
             }
-            else {
-                let len = self.vm.stack.len();
-                &mut self.vm.stack[len - 1 - index as usize]
+            if instr.falls_through() {
+                if pc + len >= code.len() {
+                    return Err(FallsOffEnd { pc });
+                }
+                work.push(pc + len);
             }
         }
 
-        #[inline(always)]
-        fn push(&mut self, value: f64) {
-            if super::SPEEEEEED {
-                unsafe {
-                    *self.top = value;
-                    self.top = self.top.add(1);
-                }
-            }
-            else {
-                self.vm.stack.push(value)
+        if let Some(pc) = (0..code.len()).find(|pc| visited[*pc] && widened[*pc]) {
+            return Err(BadWide { pc: pc - 1 });
+        }
+
+        Ok(VerifiedProgram { code: code.into(), registers, calls, compact })
+    }
+
+
+    /// `V` is how values are represented, see `value`.
+    pub struct Vm<V: Value = f64> {
+        registers: Vec<V>,
+        frames: Vec<Frame>,
+        memory: Vec<V>,
+        constants: Vec<V>,
+    }
+
+    /// the caller's state, saved by `Call`.
+    #[derive(Clone, Copy, Debug)]
+    struct Frame {
+        pc: usize,
+        base: usize,
+        counter: u32,
+    }
+
+    struct State<'a, V: Value> {
+        vm: &'a mut Vm<V>,
+        code: &'a [Instruction],
+        pc: usize,
+        pcp: *const Instruction,
+        counter: u32,
+        base: usize,
+        regs: *mut V,
+    }
+
+    /// where a checked execution is at, see `Vm::step`.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct Cursor {
+        pub pc: usize,
+        pub counter: u32,
+        /// where the current register window starts.
+        pub base: usize,
+    }
+
+    /// a handler of `Vm::run_with`, see `State::execute`.
+    type Handler<'a, V> = fn(&mut State<'a, V>, Instruction, &mut Option<VmError>) -> Option<V>;
+
+    /// also takes the instructions left in the chain, see `dispatch::TailCalls`,
+    /// and returns whether the program has stopped, with `result`.
+    type TailHandler<'a, V> = fn(&mut State<'a, V>, Instruction, &mut Option<VmError>, &mut V, u32) -> bool;
+
+    /// an instruction, decoded for `dispatch::Closures`.
+    type Closure<V> = Box<dyn Fn(&mut State<V>, &mut Option<VmError>) -> Option<V>>;
+
+    /// a program, prepared for `Vm::run_with` with the dispatch `D`.
+    pub struct Dispatched<V: Value, D: Dispatch> {
+        program: VerifiedProgram,
+        /// one per instruction, for `dispatch::Closures`.
+        closures: Vec<Closure<V>>,
+        dispatch: PhantomData<D>,
+    }
+
+    impl<V: Value + 'static, D: Dispatch> Dispatched<V, D> {
+        /// `closure` for every opcode.
+        const CLOSURES: [fn(Instruction) -> Closure<V>; OPCODES] = [
+            Self::closure::<0>,  Self::closure::<1>,  Self::closure::<2>,  Self::closure::<3>,
+            Self::closure::<4>,  Self::closure::<5>,  Self::closure::<6>,  Self::closure::<7>,
+            Self::closure::<8>,  Self::closure::<9>,  Self::closure::<10>, Self::closure::<11>,
+            Self::closure::<12>, Self::closure::<13>, Self::closure::<14>, Self::closure::<15>,
+            Self::closure::<16>, Self::closure::<17>, Self::closure::<18>, Self::closure::<19>,
+            Self::closure::<20>, Self::closure::<21>, Self::closure::<22>, Self::closure::<23>,
+            Self::closure::<24>, Self::closure::<25>, Self::closure::<26>, Self::closure::<27>,
+            Self::closure::<28>, Self::closure::<29>, Self::closure::<30>, Self::closure::<31>,
+            Self::closure::<32>,
+        ];
+
+        pub fn new(program: &VerifiedProgram) -> Self {
+            let mut closures = vec![];
+            if D::KIND == Kind::Closures {
+                closures = program.code().iter().map(|instr| Self::CLOSURES[instr.opcode() as usize](*instr)).collect();
             }
+            Dispatched { program: program.clone(), closures, dispatch: PhantomData }
+        }
+
+        /// `State::execute`, with the operands captured from `instr`.
+        fn closure<const OP: u8>(instr: Instruction) -> Closure<V> {
+            Box::new(move |s, error| s.execute::<OP>(instr, error))
+        }
+    }
+
+    impl<V: Value, D: Dispatch> Dispatched<V, D> {
+        pub fn program(&self) -> &VerifiedProgram {
+            &self.program
         }
 
         #[inline(always)]
-        fn pop(&mut self) -> f64 {
+        fn closure_at(&self, pc: usize) -> &Closure<V> {
             if super::SPEEEEEED {
-                unsafe {
-                    self.top = self.top.sub(1);
-                    *self.top
-                }
+                // verified.
+                unsafe { self.closures.get_unchecked(pc) }
             }
             else {
-                self.vm.stack.pop().unwrap()
+                &self.closures[pc]
             }
         }
+    }
 
-        #[inline(always)]
-        fn clear(&mut self) {
-            if super::SPEEEEEED {
-                self.top = self.base;
-            }
-            else {
-                self.vm.stack.clear();
+    impl Vm {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// like `run`, for a program compiled by `jit`. where there's
+        /// no jit, that's just `run`.
+        pub fn run_jit(&mut self, program: &jit::Program, args: &[f64]) -> f64 {
+            assert!(args.len() <= REGISTER_COUNT);
+            self.registers[..args.len()].copy_from_slice(args);
+            match program.run(&mut self.registers, &mut self.memory, &self.constants) {
+                Some(Ok(result)) => result,
+                Some(Err(e)) => panic!("{:?}", e),
+                None => self.run(program.program(), args),
             }
         }
     }
 
+    impl<V: Value> Default for Vm<V> {
+        fn default() -> Self {
+            Vm { registers: vec![V::from_f64(0.0); MAX_REGISTERS], frames: vec![], memory: vec![], constants: vec![] }
+        }
+    }
 
-    pub const FIB_SMART: &[Instruction] = { use Instruction::*; &[
-        SetCounter,
-        LoadInt { value: 0 },
-        LoadInt { value: 1 },
-        Jump { target: 7 },
-        Dup,
-        Rot,
-        Add,
-        Loop { target: 4 },
-        Pop,
-        Return,
-    ]};
-
-    pub const FIB_NAIVE: &[Instruction] = { use Instruction::*; &[
-        SetCounter,
-        LoadInt { value: 0 },
-        LoadInt { value: 1 },
-        Jump { target: 10 },
-        // 4
-        Load { src: 0 },
-        Load { src: 1 },
-        Add,
-        Load { src: 1 },
-        Store { dst: 0 },
-        Store { dst: 1 },
-        // 10
-        Loop { target: 4 },
-        Pop,
-        Return,
-    ]};
+    impl<V: Value> Vm<V> {
+        /// the whole register file, see `Cursor::base` for the current window.
+        pub fn registers(&self) -> &[V] {
+            &self.registers
+        }
 
+        /// linear memory for `LoadMem` and `StoreMem`. starts out empty,
+        /// and keeps its contents between runs. accesses are bounds
+        /// checked in every mode, the program can't be verified for them.
+        pub fn memory(&self) -> &[V] {
+            &self.memory
+        }
 
-    pub const MANDEL_SMART: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y) = (0, 1, 2, 3, 4); &[
-        Load { src: n },
-        SetCounter,
+        pub fn memory_mut(&mut self) -> &mut Vec<V> {
+            &mut self.memory
+        }
 
-        LoadInt { value: 0 },
-        LoadInt { value: 0 },
+        /// the constant pool for `LoadConst`, for numbers that don't fit
+        /// into `LoadInt`. like `memory`, it's up to the caller to fill
+        /// it, see `lang::compile_reg_with_constants`.
+        pub fn constants(&self) -> &[V] {
+            &self.constants
+        }
 
-        Jump { target: 21 },
+        pub fn constants_mut(&mut self) -> &mut Vec<V> {
+            &mut self.constants
+        }
 
-        // 5
-        // let xtemp = x*x - y*y + x0;
-        Load { src: x },
-        Dup,
-        Mul,
-        Load { src: y },
-        Dup,
-        Mul,
-        Sub,
-        Load { src: x0 },
-        Add,
+        /// for a call with a window at `base`.
+        #[inline(always)]
+        fn push_frame(&mut self, frame: Frame, base: usize) -> bool {
+            if self.frames.len() >= super::MAX_CALL_DEPTH || base + REGISTER_COUNT > MAX_REGISTERS {
+                return false;
+            }
+            self.frames.push(frame);
+            true
+        }
 
-        // 14
-        // stack: x0, y0, n, x, y, xtemp
-        Swap,
-        // stack: x0, y0, n, x, xtemp, y
-        Rot,
-        // stack: x0, y0, n, xtemp, y, x
+        /// runs unverified code, returning an error instead of
+        /// misbehaving, regardless of `SPEEEEEED`.
+        pub fn try_run(&mut self, code: &[Instruction], args: &[V]) -> Result<V, VmError> {
+            let mut cursor = self.start(args)?;
+            loop {
+                if let Some(result) = self.step(code, &mut cursor)? {
+                    return Ok(result);
+                }
+            }
+        }
 
-        // 16
-        // y = x*y*2.0 + y0;
-        Mul,
-        LoadInt { value: 2 },
-        Mul,
-        Load { src: y0 },
-        Add,
+        /// like `try_run`, but executes at most `fuel` instructions.
+        pub fn try_run_with_fuel(&mut self, code: &[Instruction], args: &[V], fuel: u64) -> Result<V, VmError> {
+            let mut cursor = self.start(args)?;
+            for _ in 0..fuel {
+                if let Some(result) = self.step(code, &mut cursor)? {
+                    return Ok(result);
+                }
+            }
+            Err(VmError::OutOfFuel { pc: cursor.pc, counter: cursor.counter })
+        }
 
-        // check x*x + y*y <= 2*2
-        // 21
-        Load { src: x },
-        Dup,
-        Mul,
-        Load { src: y },
-        Dup,
-        Mul,
-        Add,
-        LoadInt { value: 4 },
-        LoopLe { target: 5 },
+        pub fn start(&mut self, args: &[V]) -> Result<Cursor, VmError> {
+            if args.len() > REGISTER_COUNT {
+                return Err(VmError::TooManyArgs { count: args.len() });
+            }
+            self.frames.clear();
+            self.registers[..args.len()].copy_from_slice(args);
+            Ok(Cursor::default())
+        }
 
+        /// executes one instruction with all checks.
+        /// returns the result once the program has returned.
+        pub fn step(&mut self, code: &[Instruction], cursor: &mut Cursor) -> Result<Option<V>, VmError> {
+            self.step_branch(code, cursor, &mut false)
+        }
 
-        // 30
-        Load { src: n },
-        GetCounter,
-        Sub,
-        Return,
-    ]};
+        /// `step`, setting `taken` if a branch jumped, for profiles.
+        fn step_branch(&mut self, code: &[Instruction], cursor: &mut Cursor, taken: &mut bool) -> Result<Option<V>, VmError> {
+            let pc = cursor.pc;
+            if pc >= code.len() {
+                return Err(VmError::NoReturn { pc });
+            }
+            let Some((instr, [a, b, c], len)) = decode(code, pc) else {
+                return Err(VmError::BadWide { pc });
+            };
+            cursor.pc += len;
+
+            // operands go through `wide` with the high byte for their
+            // position, which is 0 without a prefix.
+            let base = cursor.base;
+            let regs = &self.registers;
+            let reg = |index: usize| {
+                regs.get(base + index).copied()
+                .ok_or(VmError::BadRegister { pc, index })
+            };
+            let binary = |op, src1, src2| {
+                reg(src1)?.binary(op, reg(src2)?).ok_or(VmError::TypeError { pc })
+            };
+            let unary = |op, src| {
+                reg(src)?.unary(op).ok_or(VmError::TypeError { pc })
+            };
+            let jump = |cursor: &mut Cursor, target: usize| {
+                if target >= code.len() {
+                    return Err(VmError::PcOutOfBounds { pc, target });
+                }
+                cursor.pc = target;
+                Ok(())
+            };
 
-    pub const MANDEL_NAIVE: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y, xtemp) = (0, 1, 2, 3, 4, 5); &[
-        Load { src: n },
-        SetCounter,
+            use Instruction::*;
+            let (dst, value) = match instr {
+                LoadInt { dst, value } => (wide(dst, a), V::from_int(value as i64)),
 
-        LoadInt { value: 0 },
-        LoadInt { value: 0 },
-        LoadInt { value: 0 },
-        // stack: x0, y0, n, x, y, xtemp
+                Copy { dst, src } => (wide(dst, a), reg(wide(src, b))?),
 
-        Jump { target: 26 },
+                Add { dst, src1, src2 } => (wide(dst, a), binary(BinOp::Add, wide(src1, b), wide(src2, c))?),
+                Sub { dst, src1, src2 } => (wide(dst, a), binary(BinOp::Sub, wide(src1, b), wide(src2, c))?),
+                Mul { dst, src1, src2 } => (wide(dst, a), binary(BinOp::Mul, wide(src1, b), wide(src2, c))?),
+                Div { dst, src1, src2 } => (wide(dst, a), binary(BinOp::Div, wide(src1, b), wide(src2, c))?),
+                Neg { dst, src }        => (wide(dst, a), unary(UnOp::Neg, wide(src, b))?),
 
-        // 6
-        // let xtemp = x*x - y*y + x0;
-        Load { src: x },
-        Load { src: x },
-        Mul,
-        Load { src: y },
-        Load { src: y },
-        Mul,
-        Sub,
-        Load { src: x0 },
-        Add,
-        Store { dst: xtemp },
+                Lt { dst, src1, src2 } => (wide(dst, a), binary(BinOp::Lt, wide(src1, b), wide(src2, c))?),
+                Le { dst, src1, src2 } => (wide(dst, a), binary(BinOp::Le, wide(src1, b), wide(src2, c))?),
+                Eq { dst, src1, src2 } => (wide(dst, a), binary(BinOp::Eq, wide(src1, b), wide(src2, c))?),
 
-        // 16
-        // y = x*y*2.0 + y0;
-        Load { src: x },
-        Load { src: y },
-        Mul,
-        LoadInt { value: 2 },
-        Mul,
-        Load { src: y0 },
-        Add,
-        Store { dst: y },
+                Sqrt  { dst, src }        => (wide(dst, a), unary(UnOp::Sqrt, wide(src, b))?),
+                Abs   { dst, src }        => (wide(dst, a), unary(UnOp::Abs, wide(src, b))?),
+                Floor { dst, src }        => (wide(dst, a), unary(UnOp::Floor, wide(src, b))?),
+                Min   { dst, src1, src2 } => (wide(dst, a), binary(BinOp::Min, wide(src1, b), wide(src2, c))?),
+                Max   { dst, src1, src2 } => (wide(dst, a), binary(BinOp::Max, wide(src1, b), wide(src2, c))?),
 
-        // 24
-        // x = xtemp
-        Load { src: xtemp },
-        Store { dst: x },
+                Jump { target } => {
+                    jump(cursor, wide(target, a))?;
+                    return Ok(None);
+                }
 
-        // check x*x + y*y <= 2*2
-        // 26
-        Load { src: x },
-        Load { src: x },
-        Mul,
-        Load { src: y },
-        Load { src: y },
-        Mul,
-        Add,
-        LoadInt { value: 4 },
-        LoopLe { target: 6 },
+                JumpIf { target, src } => {
+                    if reg(wide(src, b))?.is_true() {
+                        jump(cursor, wide(target, a))?;
+                        *taken = true;
+                    }
+                    return Ok(None);
+                }
 
+                JumpIfNot { target, src } => {
+                    if !reg(wide(src, b))?.is_true() {
+                        jump(cursor, wide(target, a))?;
+                        *taken = true;
+                    }
+                    return Ok(None);
+                }
 
-        // 35
-        Load { src: n },
-        GetCounter,
-        Sub,
-        Return,
-    ]};
+                SetCounter { src } => {
+                    cursor.counter = reg(wide(src, a))?.counter().ok_or(VmError::TypeError { pc })?;
+                    return Ok(None);
+                }
 
+                GetCounter { dst } => (wide(dst, a), V::from_int(cursor.counter as i64)),
 
-    pub const MANDEL_SMART_NOPS_SLOW: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y) = (0, 1, 2, 3, 4); &[
-        Load { src: n },
-        SetCounter,
+                Loop { target } => {
+                    if cursor.counter > 0 {
+                        cursor.counter -= 1;
+                        jump(cursor, wide(target, a))?;
+                        *taken = true;
+                    }
+                    return Ok(None);
+                }
 
-        LoadInt { value: 0 },
-        LoadInt { value: 0 },
+                LoopLe { target, src1, src2 } => {
+                    let le = binary(BinOp::Le, wide(src1, b), wide(src2, c))?;
+                    if le.is_true() && cursor.counter > 0 {
+                        cursor.counter -= 1;
+                        jump(cursor, wide(target, a))?;
+                        *taken = true;
+                    }
+                    return Ok(None);
+                }
 
-        Jump { target: 21 + 3 },
+                Return { src } => {
+                    return Ok(Some(reg(wide(src, a))?));
+                }
 
-        // 5
-        // let xtemp = x*x - y*y + x0;
-        Load { src: x },
-        Dup,
-        Mul,
-        Load { src: y },
-        Nop, Nop, Nop,
-        Dup,
-        Mul,
-        Sub,
-        Load { src: x0 },
-        Add,
+                Call { target, base } => {
+                    let callee = cursor.base + wide(base, b);
+                    let frame = Frame { pc: cursor.pc, base: cursor.base, counter: cursor.counter };
+                    jump(cursor, wide(target, a))?;
+                    if !self.push_frame(frame, callee) {
+                        return Err(VmError::StackOverflow { pc });
+                    }
+                    cursor.base = callee;
+                    cursor.counter = 0;
+                    return Ok(None);
+                }
 
-        // 14 + 3
-        // stack: x0, y0, n, x, y, xtemp
-        Swap,
-        // stack: x0, y0, n, x, xtemp, y
-        Rot,
-        // stack: x0, y0, n, xtemp, y, x
+                Ret { src } => {
+                    let result = reg(wide(src, a))?;
+                    let Some(frame) = self.frames.pop() else {
+                        return Ok(Some(result));
+                    };
+                    self.registers[cursor.base] = result;
+                    cursor.pc = frame.pc;
+                    cursor.base = frame.base;
+                    cursor.counter = frame.counter;
+                    return Ok(None);
+                }
 
-        // 16 + 3
-        // y = x*y*2.0 + y0;
-        Mul,
-        LoadInt { value: 2 },
-        Mul,
-        Load { src: y0 },
-        Add,
+                LoadMem { dst, addr } => {
+                    let address = reg(wide(addr, b))?.address().ok_or(VmError::TypeError { pc })?;
+                    let value = self.memory.get(address).ok_or(VmError::BadAddress { pc })?;
+                    (wide(dst, a), *value)
+                }
 
-        // check x*x + y*y <= 2*2
-        // 21 + 3
-        Load { src: x },
-        Dup,
-        Mul,
-        Load { src: y },
-        Dup,
-        Mul,
-        Add,
-        LoadInt { value: 4 },
-        LoopLe { target: 5 },
+                StoreMem { src, addr } => {
+                    let value = reg(wide(src, a))?;
+                    let address = reg(wide(addr, b))?.address().ok_or(VmError::TypeError { pc })?;
+                    let slot = self.memory.get_mut(address).ok_or(VmError::BadAddress { pc })?;
+                    *slot = value;
+                    return Ok(None);
+                }
 
+                LoadConst { dst, index } => {
+                    let value = self.constants.get(wide(index, b)).ok_or(VmError::BadConstant { pc })?;
+                    (wide(dst, a), *value)
+                }
 
-        // 30 + 3
-        Load { src: n },
-        GetCounter,
-        Sub,
-        Return,
-    ]};
+                // `decode` skips the prefix.
+                Wide { .. } => unreachable!(),
 
-    pub const MANDEL_SMART_NOPS_SAME: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y) = (0, 1, 2, 3, 4); &[
-        Load { src: n },
-        SetCounter,
+                MulAdd { dst, src1, src2 } | MulSub { dst, src1, src2 } => {
+                    let op = if matches!(instr, MulAdd { .. }) { BinOp::Add } else { BinOp::Sub };
+                    let product = binary(BinOp::Mul, wide(src1, b), wide(src2, c))?;
+                    let value = reg(wide(dst, a))?.binary(op, product).ok_or(VmError::TypeError { pc })?;
+                    (wide(dst, a), value)
+                }
 
-        LoadInt { value: 0 },
-        LoadInt { value: 0 },
+                JumpIfLt { target, src1, src2 } | JumpIfLe { target, src1, src2 } => {
+                    let op = if matches!(instr, JumpIfLt { .. }) { BinOp::Lt } else { BinOp::Le };
+                    if binary(op, wide(src1, b), wide(src2, c))?.is_true() {
+                        jump(cursor, wide(target, a))?;
+                        *taken = true;
+                    }
+                    return Ok(None);
+                }
+            };
 
-        Jump { target: 21 + 3 },
+            let slot = self.registers.get_mut(base + dst)
+                .ok_or(VmError::BadRegister { pc, index: dst })?;
+            *slot = value;
+            Ok(None)
+        }
 
-        // 5
-        // let xtemp = x*x - y*y + x0;
-        Load { src: x },
-        Dup,
-        Mul,
-        Load { src: y },
-        Dup,
-        Mul,
-        Sub,
-        Load { src: x0 },
-        Add,
+        /// panics if calls nest too deeply, see `run_with_fuel`.
+        #[inline(never)]
+        pub fn run(&mut self, program: &VerifiedProgram, args: &[V]) -> V {
+            let mut error = None;
+            let result =
+                if program.compact() { self.run_impl::<false, false, false>(program, args, &mut Profile::new(), 0, &mut error) }
+                else                 { self.run_impl::<false, false, true >(program, args, &mut Profile::new(), 0, &mut error) };
+            if let Some(e) = error {
+                panic!("{:?}", e);
+            }
+            result
+        }
+
+        /// like `run`, but adds execution counts to `profile`.
+        #[inline(never)]
+        pub fn run_profiled(&mut self, program: &VerifiedProgram, args: &[V], profile: &mut Profile) -> V {
+            profile.prepare(program.code().len());
+            let mut error = None;
+            let result =
+                if program.compact() { self.run_impl::<true, false, false>(program, args, profile, 0, &mut error) }
+                else                 { self.run_impl::<true, false, true >(program, args, profile, 0, &mut error) };
+            if let Some(e) = error {
+                panic!("{:?}", e);
+            }
+            result
+        }
+
+        /// like `run`, but executes at most `fuel` instructions,
+        /// so programs that don't terminate can't hang the caller.
+        /// also returns errors instead of panicking.
+        #[inline(never)]
+        pub fn run_with_fuel(&mut self, program: &VerifiedProgram, args: &[V], fuel: u64) -> Result<V, VmError> {
+            let mut error = None;
+            let result =
+                if program.compact() { self.run_impl::<false, true, false>(program, args, &mut Profile::new(), fuel, &mut error) }
+                else                 { self.run_impl::<false, true, true >(program, args, &mut Profile::new(), fuel, &mut error) };
+            match error {
+                Some(e) => Err(e),
+                None    => Ok(result),
+            }
+        }
+
+        /// like `run`, for a program in the byte encoding of `packed`.
+        /// the operands are decoded from the stream as it goes, the rest
+        /// of the vm's state is the same as for `run`.
+        #[inline(never)]
+        pub fn run_packed(&mut self, program: &packed::RegProgram, args: &[V]) -> V {
+            use packed::{byte, target, failed, reg_op::*};
+            assert!(args.len() <= REGISTER_COUNT);
+            let code = program.code();
+
+            // for the registers, the pc is a byte offset here.
+            let mut s = State {
+                vm: self,
+                code: &[],
+                pc: 0,
+                pcp: core::ptr::null(),
+                counter: 0,
+                base: 0,
+                regs: core::ptr::null_mut(),
+            };
+            s.vm.frames.clear();
+            s.set_base(0);
+            for (i, arg) in args.iter().enumerate() {
+                s.vm.registers[i] = *arg;
+            }
+
+            let mut pc = 0;
+            loop {
+                let at = pc;
+                let b = |i| byte(code, at + i);
+                let type_error = || -> ! { failed(VmError::TypeError { pc: program.pc(at) }) };
+
+                match b(0) {
+                    LOAD_INT => {
+                        *s.reg(b(1)) = V::from_int(i16::from_le_bytes([b(2), b(3)]) as i64);
+                        pc += 4;
+                    }
+
+                    COPY => {
+                        *s.reg(b(1)) = *s.reg(b(2));
+                        pc += 3;
+                    }
+
+                    ADD => {
+                        if !s.binary(BinOp::Add, b(1), b(2), b(3)) {
+                            type_error();
+                        }
+                        pc += 4;
+                    }
+
+                    SUB => {
+                        if !s.binary(BinOp::Sub, b(1), b(2), b(3)) {
+                            type_error();
+                        }
+                        pc += 4;
+                    }
+
+                    MUL => {
+                        if !s.binary(BinOp::Mul, b(1), b(2), b(3)) {
+                            type_error();
+                        }
+                        pc += 4;
+                    }
+
+                    DIV => {
+                        if !s.binary(BinOp::Div, b(1), b(2), b(3)) {
+                            type_error();
+                        }
+                        pc += 4;
+                    }
+
+                    NEG => {
+                        if !s.unary(UnOp::Neg, b(1), b(2)) {
+                            type_error();
+                        }
+                        pc += 3;
+                    }
+
+                    LT => {
+                        if !s.binary(BinOp::Lt, b(1), b(2), b(3)) {
+                            type_error();
+                        }
+                        pc += 4;
+                    }
+
+                    LE => {
+                        if !s.binary(BinOp::Le, b(1), b(2), b(3)) {
+                            type_error();
+                        }
+                        pc += 4;
+                    }
+
+                    EQ => {
+                        if !s.binary(BinOp::Eq, b(1), b(2), b(3)) {
+                            type_error();
+                        }
+                        pc += 4;
+                    }
+
+                    SQRT => {
+                        if !s.unary(UnOp::Sqrt, b(1), b(2)) {
+                            type_error();
+                        }
+                        pc += 3;
+                    }
+
+                    ABS => {
+                        if !s.unary(UnOp::Abs, b(1), b(2)) {
+                            type_error();
+                        }
+                        pc += 3;
+                    }
+
+                    FLOOR => {
+                        if !s.unary(UnOp::Floor, b(1), b(2)) {
+                            type_error();
+                        }
+                        pc += 3;
+                    }
+
+                    MIN => {
+                        if !s.binary(BinOp::Min, b(1), b(2), b(3)) {
+                            type_error();
+                        }
+                        pc += 4;
+                    }
+
+                    MAX => {
+                        if !s.binary(BinOp::Max, b(1), b(2), b(3)) {
+                            type_error();
+                        }
+                        pc += 4;
+                    }
+
+                    JUMP => {
+                        pc = target(code, at + 1);
+                    }
+
+                    JUMP_IF => {
+                        pc = if s.reg(b(3)).is_true() { target(code, at + 1) } else { at + 4 };
+                    }
+
+                    JUMP_IF_NOT => {
+                        pc = if !s.reg(b(3)).is_true() { target(code, at + 1) } else { at + 4 };
+                    }
+
+                    SET_COUNTER => {
+                        let Some(counter) = s.reg(b(1)).counter() else {
+                            type_error();
+                        };
+                        s.counter = counter;
+                        pc += 2;
+                    }
+
+                    GET_COUNTER => {
+                        *s.reg(b(1)) = V::from_int(s.counter as i64);
+                        pc += 2;
+                    }
+
+                    LOOP => {
+                        if s.counter > 0 {
+                            s.counter -= 1;
+                            pc = target(code, at + 1);
+                        }
+                        else {
+                            pc += 3;
+                        }
+                    }
+
+                    LOOP_LE => {
+                        let Some(le) = s.reg(b(3)).binary(BinOp::Le, *s.reg(b(4))) else {
+                            type_error();
+                        };
+                        if le.is_true() && s.counter > 0 {
+                            s.counter -= 1;
+                            pc = target(code, at + 1);
+                        }
+                        else {
+                            pc += 5;
+                        }
+                    }
+
+                    RETURN => {
+                        return *s.reg(b(1));
+                    }
+
+                    CALL => {
+                        let callee = s.base + b(3) as usize;
+                        if !s.vm.push_frame(Frame { pc: at + 4, base: s.base, counter: s.counter }, callee) {
+                            failed(VmError::StackOverflow { pc: program.pc(at) });
+                        }
+                        s.set_base(callee);
+                        s.counter = 0;
+                        pc = target(code, at + 1);
+                    }
+
+                    RET => {
+                        let result = *s.reg(b(1));
+                        let Some(frame) = s.vm.frames.pop() else {
+                            return result;
+                        };
+                        *s.reg(0) = result;
+                        s.set_base(frame.base);
+                        s.counter = frame.counter;
+                        pc = frame.pc;
+                    }
+
+                    LOAD_MEM => {
+                        let Some(address) = s.reg(b(2)).address() else {
+                            type_error();
+                        };
+                        let Some(&value) = s.vm.memory.get(address) else {
+                            failed(VmError::BadAddress { pc: program.pc(at) });
+                        };
+                        *s.reg(b(1)) = value;
+                        pc += 3;
+                    }
+
+                    STORE_MEM => {
+                        let Some(address) = s.reg(b(2)).address() else {
+                            type_error();
+                        };
+                        let value = *s.reg(b(1));
+                        let Some(slot) = s.vm.memory.get_mut(address) else {
+                            failed(VmError::BadAddress { pc: program.pc(at) });
+                        };
+                        *slot = value;
+                        pc += 3;
+                    }
+
+                    LOAD_CONST => {
+                        let Some(&value) = s.vm.constants.get(b(2) as usize) else {
+                            failed(VmError::BadConstant { pc: program.pc(at) });
+                        };
+                        *s.reg(b(1)) = value;
+                        pc += 3;
+                    }
+
+                    _ => super::unreachable_arm(),
+                }
+            }
+        }
+
+        /// like `run`, with the dispatch `D`, see `dispatch`.
+        #[inline(never)]
+        pub fn run_with<D: Dispatch>(&mut self, program: &Dispatched<V, D>, args: &[V]) -> V {
+            if D::KIND == Kind::Match {
+                return self.run(&program.program, args);
+            }
+            assert!(args.len() <= REGISTER_COUNT);
+            let code = program.program.code();
+
+            let mut s = State {
+                vm: self,
+                code,
+                pc: 0,
+                pcp: core::ptr::null(),
+                counter: 0,
+                base: 0,
+                regs: core::ptr::null_mut(),
+            };
+
+            s.jump(0);
+            s.vm.frames.clear();
+            s.set_base(0);
+            for (i, arg) in args.iter().enumerate() {
+                s.vm.registers[i] = *arg;
+            }
+
+            let mut error = None;
+            let result = loop {
+                let pc = s.pc();
+                let instr = *s.next_instr();
+                let result = match D::KIND {
+                    Kind::Table     => State::HANDLERS[instr.opcode() as usize](&mut s, instr, &mut error),
+                    Kind::Closures  => program.closure_at(pc)(&mut s, &mut error),
+                    Kind::TailCalls => {
+                        let mut result = V::from_f64(0.0);
+                        let stopped = State::TAIL_HANDLERS[instr.opcode() as usize](&mut s, instr, &mut error, &mut result, dispatch::CHAIN);
+                        stopped.then_some(result)
+                    }
+                    Kind::Match     => unreachable!(),
+                };
+                if let Some(result) = result {
+                    break result;
+                }
+            };
+            if let Some(e) = error {
+                panic!("{:?}", e);
+            }
+            result
+        }
+
+        // `PROFILE` and `FUEL` are constants, so `run` pays for neither.
+        // the error goes through `error`, returning a `Result` here
+        // made the stack vm's loop measurably slower.
+        // llvm only threads the dispatch of matches with fewer than 16
+        // arms, so `compact` programs get a loop without calls, memory,
+        // `Div` through `Max`, and the fused jumps (`FULL` is false), and
+        // `MulAdd` and `MulSub` share an arm. the operands are read
+        // in the arms, copying the instruction up front also kept it
+        // from that.
+        #[inline(always)]
+        fn run_impl<const PROFILE: bool, const FUEL: bool, const FULL: bool>(&mut self, program: &VerifiedProgram, args: &[V], profile: &mut Profile, mut fuel: u64, error: &mut Option<VmError>) -> V {
+            assert!(args.len() <= REGISTER_COUNT);
+            let code = program.code();
+
+            let mut s = State {
+                vm: self,
+                code,
+                pc: 0,
+                pcp: core::ptr::null(),
+                counter: 0,
+                base: 0,
+                regs: core::ptr::null_mut(),
+            };
+
+            s.jump(0);
+            s.vm.frames.clear();
+            s.set_base(0);
+            for (i, arg) in args.iter().enumerate() {
+                s.vm.registers[i] = *arg;
+            }
+
+            loop {
+                let pc = if PROFILE { s.pc() } else { 0 };
+                if PROFILE {
+                    profile.executed[pc] += 1;
+                }
+                if FUEL {
+                    if fuel == 0 {
+                        *error = Some(s.out_of_fuel());
+                        return V::from_f64(0.0);
+                    }
+                    fuel -= 1;
+                }
+
+                let instr = s.next_instr();
+
+                use Instruction::*;
+                match *instr {
+                    LoadInt { dst, value } => {
+                        *s.reg(dst) = V::from_int(value as i64);
+                    }
+
+                    Copy { dst, src } => {
+                        *s.reg(dst) = *s.reg(src);
+                    }
+
+                    Add { dst, src1, src2 } => {
+                        if !s.binary(BinOp::Add, dst, src1, src2) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Sub { dst, src1, src2 } => {
+                        if !s.binary(BinOp::Sub, dst, src1, src2) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Mul { dst, src1, src2 } => {
+                        if !s.binary(BinOp::Mul, dst, src1, src2) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Div { dst, src1, src2 } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        if !s.binary(BinOp::Div, dst, src1, src2) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Neg { dst, src } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        if !s.unary(UnOp::Neg, dst, src) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Lt { dst, src1, src2 } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        if !s.binary(BinOp::Lt, dst, src1, src2) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Le { dst, src1, src2 } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        if !s.binary(BinOp::Le, dst, src1, src2) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Eq { dst, src1, src2 } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        if !s.binary(BinOp::Eq, dst, src1, src2) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Sqrt { dst, src } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        if !s.unary(UnOp::Sqrt, dst, src) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Abs { dst, src } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        if !s.unary(UnOp::Abs, dst, src) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Floor { dst, src } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        if !s.unary(UnOp::Floor, dst, src) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Min { dst, src1, src2 } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        if !s.binary(BinOp::Min, dst, src1, src2) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Max { dst, src1, src2 } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        if !s.binary(BinOp::Max, dst, src1, src2) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Jump { target } => {
+                        s.jump(target);
+                    }
+
+                    JumpIf { target, src } => {
+                        if s.reg(src).is_true() {
+                            s.jump(target);
+                            if PROFILE {
+                                profile.taken[pc] += 1;
+                            }
+                        }
+                    }
+
+                    JumpIfNot { target, src } => {
+                        if !s.reg(src).is_true() {
+                            s.jump(target);
+                            if PROFILE {
+                                profile.taken[pc] += 1;
+                            }
+                        }
+                    }
+
+                    SetCounter { src } => {
+                        let Some(counter) = s.reg(src).counter() else {
+                            return s.type_error(error);
+                        };
+                        s.counter = counter;
+                    }
+
+                    GetCounter { dst } => {
+                        *s.reg(dst) = V::from_int(s.counter as i64);
+                    }
+
+                    Loop { target } => {
+                        if s.counter > 0 {
+                            s.counter -= 1;
+                            s.jump(target);
+                            if PROFILE {
+                                profile.taken[pc] += 1;
+                            }
+                        }
+                    }
+
+                    LoopLe { target, src1, src2 } => {
+                        let Some(le) = s.reg(src1).binary(BinOp::Le, *s.reg(src2)) else {
+                            return s.type_error(error);
+                        };
+                        if le.is_true() && s.counter > 0 {
+                            s.counter -= 1;
+                            s.jump(target);
+                            if PROFILE {
+                                profile.taken[pc] += 1;
+                            }
+                        }
+                    }
+
+                    Return { src } => {
+                        let result = *s.reg(src);
+                        return result;
+                    }
+
+                    Call { target, base } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        if !s.call(target, base) {
+                            *error = Some(VmError::StackOverflow { pc: s.pc() - 1 });
+                            return V::from_f64(0.0);
+                        }
+                    }
+
+                    Ret { src } => {
+                        let result = *s.reg(src);
+                        if !FULL {
+                            return result;
+                        }
+                        let Some(frame) = s.vm.frames.pop() else {
+                            return result;
+                        };
+                        *s.reg(0) = result;
+                        s.ret(frame);
+                    }
+
+                    LoadMem { dst, addr } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        let Some(address) = s.reg(addr).address() else {
+                            return s.type_error(error);
+                        };
+                        let Some(&value) = s.vm.memory.get(address) else {
+                            return s.bad_address(error);
+                        };
+                        *s.reg(dst) = value;
+                    }
+
+                    StoreMem { src, addr } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        let Some(address) = s.reg(addr).address() else {
+                            return s.type_error(error);
+                        };
+                        let value = *s.reg(src);
+                        let Some(slot) = s.vm.memory.get_mut(address) else {
+                            return s.bad_address(error);
+                        };
+                        *slot = value;
+                    }
+
+                    LoadConst { dst, index } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        let Some(&value) = s.vm.constants.get(index as usize) else {
+                            return s.bad_constant(error);
+                        };
+                        *s.reg(dst) = value;
+                    }
+
+                    Wide { .. } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        let mut taken = false;
+                        if let Some(result) = s.wide(error, &mut taken) {
+                            return result;
+                        }
+                        if PROFILE && taken {
+                            profile.taken[pc] += 1;
+                        }
+                    }
+
+                    MulAdd { dst, src1, src2 } | MulSub { dst, src1, src2 } => {
+                        let op = if let MulAdd { .. } = *instr { BinOp::Add } else { BinOp::Sub };
+                        if !s.fused_mul(op, dst, src1, src2) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    JumpIfLt { target, src1, src2 } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        let Some(lt) = s.reg(src1).binary(BinOp::Lt, *s.reg(src2)) else {
+                            return s.type_error(error);
+                        };
+                        if lt.is_true() {
+                            s.jump(target);
+                            if PROFILE {
+                                profile.taken[pc] += 1;
+                            }
+                        }
+                    }
+
+                    JumpIfLe { target, src1, src2 } => {
+                        if !FULL {
+                            super::unreachable_arm();
+                        }
+                        let Some(le) = s.reg(src1).binary(BinOp::Le, *s.reg(src2)) else {
+                            return s.type_error(error);
+                        };
+                        if le.is_true() {
+                            s.jump(target);
+                            if PROFILE {
+                                profile.taken[pc] += 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    impl<'a, V: Value> State<'a, V> {
+        #[cold]
+        fn out_of_fuel(&mut self) -> VmError {
+            VmError::OutOfFuel { pc: self.pc(), counter: self.counter }
+        }
+
+        /// for the instruction that just ran.
+        #[cold]
+        fn type_error(&mut self, error: &mut Option<VmError>) -> V {
+            *error = Some(VmError::TypeError { pc: self.pc() - 1 });
+            V::from_f64(0.0)
+        }
+
+        #[cold]
+        fn bad_address(&mut self, error: &mut Option<VmError>) -> V {
+            *error = Some(VmError::BadAddress { pc: self.pc() - 1 });
+            V::from_f64(0.0)
+        }
+
+        #[cold]
+        fn bad_constant(&mut self, error: &mut Option<VmError>) -> V {
+            *error = Some(VmError::BadConstant { pc: self.pc() - 1 });
+            V::from_f64(0.0)
+        }
+
+        /// runs the instruction after a `Wide` through `Vm::step`, they
+        /// are too rare for arms of their own. `Some` once the program
+        /// has returned, or failed with `error`. sets `taken` like
+        /// `Vm::step_branch`.
+        #[cold]
+        #[inline(never)]
+        fn wide(&mut self, error: &mut Option<VmError>, taken: &mut bool) -> Option<V> {
+            let mut cursor = Cursor { pc: self.pc() - 1, counter: self.counter, base: self.base };
+            match self.vm.step_branch(self.code, &mut cursor, taken) {
+                Ok(None) => {
+                    self.set_pc(cursor.pc);
+                    self.set_base(cursor.base);
+                    self.counter = cursor.counter;
+                    None
+                }
+                Ok(Some(result)) => Some(result),
+                Err(e) => {
+                    *error = Some(e);
+                    Some(V::from_f64(0.0))
+                }
+            }
+        }
+
+        /// `execute` for every opcode, for `dispatch::Table`.
+        const HANDLERS: [Handler<'a, V>; OPCODES] = [
+            Self::execute::<0>,  Self::execute::<1>,  Self::execute::<2>,  Self::execute::<3>,
+            Self::execute::<4>,  Self::execute::<5>,  Self::execute::<6>,  Self::execute::<7>,
+            Self::execute::<8>,  Self::execute::<9>,  Self::execute::<10>, Self::execute::<11>,
+            Self::execute::<12>, Self::execute::<13>, Self::execute::<14>, Self::execute::<15>,
+            Self::execute::<16>, Self::execute::<17>, Self::execute::<18>, Self::execute::<19>,
+            Self::execute::<20>, Self::execute::<21>, Self::execute::<22>, Self::execute::<23>,
+            Self::execute::<24>, Self::execute::<25>, Self::execute::<26>, Self::execute::<27>,
+            Self::execute::<28>, Self::execute::<29>, Self::execute::<30>, Self::execute::<31>,
+            Self::execute::<32>,
+        ];
+
+        const TAIL_HANDLERS: [TailHandler<'a, V>; OPCODES] = [
+            Self::tail::<0>,  Self::tail::<1>,  Self::tail::<2>,  Self::tail::<3>,
+            Self::tail::<4>,  Self::tail::<5>,  Self::tail::<6>,  Self::tail::<7>,
+            Self::tail::<8>,  Self::tail::<9>,  Self::tail::<10>, Self::tail::<11>,
+            Self::tail::<12>, Self::tail::<13>, Self::tail::<14>, Self::tail::<15>,
+            Self::tail::<16>, Self::tail::<17>, Self::tail::<18>, Self::tail::<19>,
+            Self::tail::<20>, Self::tail::<21>, Self::tail::<22>, Self::tail::<23>,
+            Self::tail::<24>, Self::tail::<25>, Self::tail::<26>, Self::tail::<27>,
+            Self::tail::<28>, Self::tail::<29>, Self::tail::<30>, Self::tail::<31>,
+            Self::tail::<32>,
+        ];
+
+        /// runs `instr`, which was just fetched and has the opcode `OP`.
+        /// the handlers of `Vm::run_with` are instances of this, `OP`
+        /// folds the match down to one arm. `Some` once the program has
+        /// returned, or failed with `error`.
+        #[inline(always)]
+        fn execute<const OP: u8>(&mut self, instr: Instruction, error: &mut Option<VmError>) -> Option<V> {
+            if instr.opcode() != OP {
+                super::unreachable_arm();
+            }
+
+            use Instruction::*;
+            match instr {
+                LoadInt { dst, value } => {
+                    *self.reg(dst) = V::from_int(value as i64);
+                }
+
+                Copy { dst, src } => {
+                    *self.reg(dst) = *self.reg(src);
+                }
+
+                Add { dst, src1, src2 } => {
+                    if !self.binary(BinOp::Add, dst, src1, src2) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Sub { dst, src1, src2 } => {
+                    if !self.binary(BinOp::Sub, dst, src1, src2) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Mul { dst, src1, src2 } => {
+                    if !self.binary(BinOp::Mul, dst, src1, src2) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Div { dst, src1, src2 } => {
+                    if !self.binary(BinOp::Div, dst, src1, src2) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Neg { dst, src } => {
+                    if !self.unary(UnOp::Neg, dst, src) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Lt { dst, src1, src2 } => {
+                    if !self.binary(BinOp::Lt, dst, src1, src2) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Le { dst, src1, src2 } => {
+                    if !self.binary(BinOp::Le, dst, src1, src2) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Eq { dst, src1, src2 } => {
+                    if !self.binary(BinOp::Eq, dst, src1, src2) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Sqrt { dst, src } => {
+                    if !self.unary(UnOp::Sqrt, dst, src) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Abs { dst, src } => {
+                    if !self.unary(UnOp::Abs, dst, src) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Floor { dst, src } => {
+                    if !self.unary(UnOp::Floor, dst, src) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Min { dst, src1, src2 } => {
+                    if !self.binary(BinOp::Min, dst, src1, src2) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Max { dst, src1, src2 } => {
+                    if !self.binary(BinOp::Max, dst, src1, src2) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Jump { target } => {
+                    self.jump(target);
+                }
+
+                JumpIf { target, src } => {
+                    if self.reg(src).is_true() {
+                        self.jump(target);
+                    }
+                }
+
+                JumpIfNot { target, src } => {
+                    if !self.reg(src).is_true() {
+                        self.jump(target);
+                    }
+                }
+
+                SetCounter { src } => {
+                    let Some(counter) = self.reg(src).counter() else {
+                        return Some(self.type_error(error));
+                    };
+                    self.counter = counter;
+                }
+
+                GetCounter { dst } => {
+                    *self.reg(dst) = V::from_int(self.counter as i64);
+                }
+
+                Loop { target } => {
+                    if self.counter > 0 {
+                        self.counter -= 1;
+                        self.jump(target);
+                    }
+                }
+
+                LoopLe { target, src1, src2 } => {
+                    let Some(le) = self.reg(src1).binary(BinOp::Le, *self.reg(src2)) else {
+                        return Some(self.type_error(error));
+                    };
+                    if le.is_true() && self.counter > 0 {
+                        self.counter -= 1;
+                        self.jump(target);
+                    }
+                }
+
+                Return { src } => {
+                    return Some(*self.reg(src));
+                }
+
+                Call { target, base } => {
+                    if !self.call(target, base) {
+                        *error = Some(VmError::StackOverflow { pc: self.pc() - 1 });
+                        return Some(V::from_f64(0.0));
+                    }
+                }
+
+                Ret { src } => {
+                    let result = *self.reg(src);
+                    let Some(frame) = self.vm.frames.pop() else {
+                        return Some(result);
+                    };
+                    *self.reg(0) = result;
+                    self.ret(frame);
+                }
+
+                LoadMem { dst, addr } => {
+                    let Some(address) = self.reg(addr).address() else {
+                        return Some(self.type_error(error));
+                    };
+                    let Some(&value) = self.vm.memory.get(address) else {
+                        return Some(self.bad_address(error));
+                    };
+                    *self.reg(dst) = value;
+                }
+
+                StoreMem { src, addr } => {
+                    let Some(address) = self.reg(addr).address() else {
+                        return Some(self.type_error(error));
+                    };
+                    let value = *self.reg(src);
+                    let Some(slot) = self.vm.memory.get_mut(address) else {
+                        return Some(self.bad_address(error));
+                    };
+                    *slot = value;
+                }
+
+                LoadConst { dst, index } => {
+                    let Some(&value) = self.vm.constants.get(index as usize) else {
+                        return Some(self.bad_constant(error));
+                    };
+                    *self.reg(dst) = value;
+                }
+
+                Wide { .. } => {
+                    return self.wide(error, &mut false);
+                }
+
+                MulAdd { dst, src1, src2 } => {
+                    if !self.fused_mul(BinOp::Add, dst, src1, src2) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                MulSub { dst, src1, src2 } => {
+                    if !self.fused_mul(BinOp::Sub, dst, src1, src2) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                JumpIfLt { target, src1, src2 } => {
+                    let Some(lt) = self.reg(src1).binary(BinOp::Lt, *self.reg(src2)) else {
+                        return Some(self.type_error(error));
+                    };
+                    if lt.is_true() {
+                        self.jump(target);
+                    }
+                }
+
+                JumpIfLe { target, src1, src2 } => {
+                    let Some(le) = self.reg(src1).binary(BinOp::Le, *self.reg(src2)) else {
+                        return Some(self.type_error(error));
+                    };
+                    if le.is_true() {
+                        self.jump(target);
+                    }
+                }
+            }
+            None
+        }
+
+        /// `execute`, then the next instruction's handler, for
+        /// `dispatch::TailCalls`. false once `chain` has run out. with an
+        /// `Option<V>` like `execute`'s, llvm didn't make the call a jump.
+        fn tail<const OP: u8>(&mut self, instr: Instruction, error: &mut Option<VmError>, result: &mut V, chain: u32) -> bool {
+            if let Some(value) = self.execute::<OP>(instr, error) {
+                *result = value;
+                return true;
+            }
+            if chain == 0 {
+                return false;
+            }
+            let next = *self.next_instr();
+            Self::TAIL_HANDLERS[next.opcode() as usize](self, next, error, result, chain - 1)
+        }
+
+        #[inline(always)]
+        fn pc(&self) -> usize {
+            if super::SPEEEEEED {
+                unsafe { self.pcp.offset_from(self.code.as_ptr()) as usize }
+            }
+            else {
+                self.pc
+            }
+        }
+
+        #[inline(always)]
+        fn next_instr(&mut self) -> &'a Instruction {
+            if super::SPEEEEEED {
+                unsafe {
+                    let result = &*self.pcp;
+                    self.pcp = self.pcp.add(1);
+                    result
+                }
+            }
+            else {
+                let result = &self.code[self.pc];
+                self.pc += 1;
+                result
+            }
+        }
+
+        #[inline(always)]
+        fn jump(&mut self, target: u8) {
+            self.set_pc(target as usize);
+        }
+
+        #[inline(always)]
+        fn set_pc(&mut self, pc: usize) {
+            if super::SPEEEEEED {
+                unsafe {
+                    self.pcp = self.code.as_ptr().add(pc);
+                }
+            }
+            else {
+                self.pc = pc;
+            }
+        }
+
+        #[inline(always)]
+        fn reg(&mut self, index: u8) -> &mut V {
+            if super::SPEEEEEED {
+                unsafe { &mut *self.regs.add(index as usize) }
+            }
+            else {
+                &mut self.vm.registers[self.base + index as usize]
+            }
+        }
+
+        /// `r[dst] = r[src1] op r[src2]`, false for type errors.
+        #[inline(always)]
+        fn binary(&mut self, op: BinOp, dst: u8, src1: u8, src2: u8) -> bool {
+            let Some(value) = self.reg(src1).binary(op, *self.reg(src2)) else {
+                return false;
+            };
+            *self.reg(dst) = value;
+            true
+        }
+
+        /// `dst = dst op src1 * src2`, for `MulAdd` and `MulSub`.
+        #[inline(always)]
+        fn fused_mul(&mut self, op: BinOp, dst: u8, src1: u8, src2: u8) -> bool {
+            let Some(product) = self.reg(src1).binary(BinOp::Mul, *self.reg(src2)) else {
+                return false;
+            };
+            let Some(value) = self.reg(dst).binary(op, product) else {
+                return false;
+            };
+            *self.reg(dst) = value;
+            true
+        }
+
+        #[inline(always)]
+        fn unary(&mut self, op: UnOp, dst: u8, src: u8) -> bool {
+            let Some(value) = self.reg(src).unary(op) else {
+                return false;
+            };
+            *self.reg(dst) = value;
+            true
+        }
+
+        #[inline(always)]
+        fn set_base(&mut self, base: usize) {
+            self.base = base;
+            self.regs = unsafe { self.vm.registers.as_mut_ptr().add(base) };
+        }
+
+        #[inline(always)]
+        fn call(&mut self, target: u8, base: u8) -> bool {
+            let callee = self.base + base as usize;
+            let frame = Frame { pc: self.pc(), base: self.base, counter: self.counter };
+            if !self.vm.push_frame(frame, callee) {
+                return false;
+            }
+            self.set_base(callee);
+            self.counter = 0;
+            self.jump(target);
+            true
+        }
+
+        #[inline(always)]
+        fn ret(&mut self, frame: Frame) {
+            self.set_base(frame.base);
+            self.counter = frame.counter;
+            self.set_pc(frame.pc);
+        }
+    }
+
+
+    pub const FIB: &[Instruction] = { use Instruction::*; &[
+        SetCounter { src: 0 },
+        LoadInt { dst: 1, value: 0 },
+        LoadInt { dst: 2, value: 1 },
+        Jump { target: 7 },
+        Add { dst: 3, src1: 1, src2: 2 },
+        Copy { dst: 1, src: 2 },
+        Copy { dst: 2, src: 3 },
+        Loop { target: 4 },
+        Return { src: 1 },
+    ]};
+
+
+    pub const MANDEL: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y, t0, t1) = (0, 1, 2, 3, 4, 5, 6); &[
+        LoadInt { dst: x, value: 0 },
+        LoadInt { dst: y, value: 0 },
+
+        // 2
+        SetCounter { src: n },
+
+        Jump { target: 13 },
+
+        // 4
+        // let xtemp = x*x - y*y + x0;
+        Mul { dst: t0, src1: x, src2: x },
+        Mul { dst: t1, src1: y, src2: y },
+        Sub { dst: t0, src1: t0, src2: t1 },
+        Add { dst: t0, src1: t0, src2: x0 },
+        // y = x*y*2.0 + y0;
+        Mul { dst: y, src1: x, src2: y },
+        LoadInt { dst: t1, value: 2 },
+        Mul { dst: y, src1: y, src2: t1 },
+        Add { dst: y, src1: y, src2: y0 },
+        // x = xtemp
+        Copy { dst: x, src: t0 },
+
+        // check x*x + y*y <= 2*2
+        // 13
+        Mul { dst: t0, src1: x, src2: x },
+        Mul { dst: t1, src1: y, src2: y },
+        Add { dst: t0, src1: t0, src2: t1 },
+        LoadInt { dst: t1, value: 4 },
+        LoopLe { target: 4, src1: t0, src2: t1 },
+
+        // 18
+        GetCounter { dst: t1 },
+        Sub { dst: t0, src1: n, src2: t1 },
+        Return { src: t0 },
+    ]};
+
+
+    pub const ADD_CHAIN: &[Instruction] = { use Instruction::*; &[
+        Add { dst: 0, src1: 0, src2:  1 },
+        Add { dst: 0, src1: 0, src2:  2 },
+        Add { dst: 0, src1: 0, src2:  3 },
+        Add { dst: 0, src1: 0, src2:  4 },
+        Add { dst: 0, src1: 0, src2:  5 },
+        Add { dst: 0, src1: 0, src2:  6 },
+        Add { dst: 0, src1: 0, src2:  7 },
+        Add { dst: 0, src1: 0, src2:  8 },
+        Add { dst: 0, src1: 0, src2:  9 },
+        Add { dst: 0, src1: 0, src2: 10 },
+        Add { dst: 0, src1: 0, src2: 11 },
+        Add { dst: 0, src1: 0, src2: 12 },
+        Add { dst: 0, src1: 0, src2: 13 },
+        Add { dst: 0, src1: 0, src2: 14 },
+        Add { dst: 0, src1: 0, src2: 15 },
+        Return { src: 0 },
+    ]};
+
+    pub const ADD_PAIRS: &[Instruction] = { use Instruction::*; &[
+        Add { dst:  0, src1:  0, src2:  1 },
+        Add { dst:  2, src1:  2, src2:  3 },
+        Add { dst:  4, src1:  4, src2:  5 },
+        Add { dst:  6, src1:  6, src2:  7 },
+        Add { dst:  8, src1:  8, src2:  9 },
+        Add { dst: 10, src1: 10, src2: 11 },
+        Add { dst: 12, src1: 12, src2: 13 },
+        Add { dst: 14, src1: 14, src2: 15 },
+        Add { dst:  0, src1:  0, src2:  2 },
+        Add { dst:  4, src1:  4, src2:  6 },
+        Add { dst:  8, src1:  8, src2: 10 },
+        Add { dst: 12, src1: 12, src2: 14 },
+        Add { dst:  0, src1:  0, src2:  4 },
+        Add { dst:  8, src1:  8, src2: 12 },
+        Add { dst:  0, src1:  0, src2:  8 },
+        Return { src: 0 },
+    ]};
+
+
+    /// `fib_recursive`, each call's window starts at its argument.
+    pub const FIB_RECURSIVE: &[Instruction] = { use Instruction::*; let (n, one, a, b) = (0, 1, 2, 3); &[
+        Call { target: 2, base: n },
+        Return { src: n },
+
+        // 2
+        LoadInt { dst: one, value: 1 },
+        // only for the branch, it's per call.
+        SetCounter { src: one },
+        LoopLe { target: 11, src1: n, src2: one },
+
+        // 5
+        Sub { dst: a, src1: n, src2: one },
+        Call { target: 2, base: a },
+        LoadInt { dst: b, value: 2 },
+        Sub { dst: b, src1: n, src2: b },
+        Call { target: 2, base: b },
+        Add { dst: n, src1: a, src2: b },
+
+        // 11
+        Ret { src: n },
+    ]};
+
+
+    /// `collatz`, halving is `x/2` when that's an integer.
+    pub const COLLATZ: &[Instruction] = { use Instruction::*; let (n, total, i, x, one, two, three, t0, t1) = (0, 1, 2, 3, 4, 5, 6, 7, 8); &[
+        LoadInt { dst: total, value: 0 },
+        LoadInt { dst: one,   value: 1 },
+        LoadInt { dst: two,   value: 2 },
+        LoadInt { dst: three, value: 3 },
+        Copy { dst: i, src: one },
+        Jump { target: 20 },
+
+        // 6
+        Copy { dst: x, src: i },
+        Jump { target: 17 },
+
+        // 8
+        Div { dst: t0, src1: x, src2: two },
+        Floor { dst: t1, src: t0 },
+        Eq { dst: t1, src1: t1, src2: t0 },
+        JumpIfNot { target: 14, src: t1 },
+        Copy { dst: x, src: t0 },
+        Jump { target: 16 },
+
+        // 14
+        Mul { dst: x, src1: x, src2: three },
+        Add { dst: x, src1: x, src2: one },
+
+        // 16
+        Add { dst: total, src1: total, src2: one },
+
+        // 17
+        Lt { dst: t0, src1: one, src2: x },
+        JumpIf { target: 8, src: t0 },
+        Add { dst: i, src1: i, src2: one },
+
+        // 20
+        Le { dst: t0, src1: i, src2: n },
+        JumpIf { target: 6, src: t0 },
+        Return { src: total },
+    ]};
+
+
+    /// the sum of `memory[0..n]`.
+    pub const ARRAY_SUM: &[Instruction] = { use Instruction::*; let (n, sum, i, one, x) = (0, 1, 2, 3, 4); &[
+        SetCounter { src: n },
+        LoadInt { dst: sum, value: 0 },
+        LoadInt { dst: i,   value: 0 },
+        LoadInt { dst: one, value: 1 },
+        Jump { target: 8 },
+
+        // 5
+        LoadMem { dst: x, addr: i },
+        Add { dst: sum, src1: sum, src2: x },
+        Add { dst: i, src1: i, src2: one },
+
+        // 8
+        Loop { target: 5 },
+        Return { src: sum },
+    ]};
+
+
+    /// the dot product of `memory[0..n]` and `memory[n..2*n]`.
+    pub const DOT: &[Instruction] = { use Instruction::*; let (n, sum, i, j, one, x, y) = (0, 1, 2, 3, 4, 5, 6); &[
+        SetCounter { src: n },
+        LoadInt { dst: sum, value: 0 },
+        LoadInt { dst: i,   value: 0 },
+        Copy { dst: j, src: n },
+        LoadInt { dst: one, value: 1 },
+        Jump { target: 12 },
+
+        // 6
+        LoadMem { dst: x, addr: i },
+        LoadMem { dst: y, addr: j },
+        Mul { dst: x, src1: x, src2: y },
+        Add { dst: sum, src1: sum, src2: x },
+        Add { dst: i, src1: i, src2: one },
+        Add { dst: j, src1: j, src2: one },
+
+        // 12
+        Loop { target: 6 },
+        Return { src: sum },
+    ]};
+}
+
+
+pub mod stack {
+    use core::marker::PhantomData;
+    use super::{VmError, packed};
+    use super::dispatch::{self, Dispatch, Kind};
+    use super::profile::Profile;
+    use super::reg::wide;
+    use super::value::{Value, BinOp, UnOp};
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[repr(align(2))]
+    pub enum Instruction {
+        Load         { src: u8 },
+        Store        { dst: u8 },
+        LoadInt      { value: i8 },
+        Add,
+        Sub,
+        Mul,
+        Div,
+        Neg,
+        /// pushes 1 if `a < b`, 0 otherwise, with `b` on top.
+        /// same for `Le` and `Eq`.
+        Lt,
+        Le,
+        Eq,
+        Sqrt,
+        Abs,
+        Floor,
+        Min,
+        Max,
+        Pop,
+        Dup,
+        Rot,
+        Swap,
+        Jump         { target: u8 },
+        /// pops a value and jumps if it isn't 0.
+        JumpIf       { target: u8 },
+        /// pops a value and jumps if it is 0.
+        JumpIfNot    { target: u8 },
+        SetCounter,
+        GetCounter,
+        Loop         { target: u8 },
+        LoopLe       { target: u8 },
+        Return,
+        Nop,
+        /// calls `target`, which is an `Enter`, with the top `argc`
+        /// values (the `Enter`'s) as the callee's first slots.
+        /// `Load` and `Store` are relative to the frame.
+        Call         { target: u8 },
+        /// pops the result, drops the callee's slots, and pushes the
+        /// result for the caller. outside of calls, like `Return`.
+        Ret,
+        /// pops an address and pushes `memory[address]`, see `Vm::memory`.
+        LoadMem,
+        /// pops a value, then an address, and stores the value there.
+        StoreMem,
+        /// pushes `constants[index]`, see `Vm::constants`.
+        LoadConst    { index: u8 },
+        /// extends the next instruction's operand to 16 bits, `high` is
+        /// its high byte. see `decode`.
+        Wide         { high: u8 },
+        /// pushes `src1 * src2`, two `Load`s and a `Mul` fused by `fuse`.
+        /// both slots share the byte, see `pack_slots`.
+        MulSlots     { slots: u8 },
+        /// adds `value` to the top, a `LoadInt` and an `Add`.
+        AddInt       { value: i8 },
+        /// squares the top, a `Dup` and a `Mul`.
+        Square,
+        /// starts a function that takes `argc` arguments, a `Nop` when
+        /// it runs. it keeps `argc` out of `Call`, so that instructions
+        /// have at most one byte of operands.
+        Enter        { argc: u8 },
+    }
+
+    impl Instruction {
+        pub fn target(&self) -> Option<u8> {
+            use Instruction::*;
+            match *self {
+                Jump { target } | JumpIf { target } | JumpIfNot { target } |
+                Loop { target } | LoopLe { target } |
+                Call { target } => Some(target),
+                _ => None,
+            }
+        }
+
+        pub fn target_mut(&mut self) -> Option<&mut u8> {
+            use Instruction::*;
+            match self {
+                Jump { target } | JumpIf { target } | JumpIfNot { target } |
+                Loop { target } | LoopLe { target } |
+                Call { target } => Some(target),
+                _ => None,
+            }
+        }
+
+        pub fn falls_through(&self) -> bool {
+            use Instruction::*;
+            !matches!(self, Jump { .. } | Return | Ret)
+        }
+
+        /// see `reg::Instruction::is_fused`.
+        pub fn is_fused(&self) -> bool {
+            use Instruction::*;
+            matches!(self, MulSlots { .. } | AddInt { .. } | Square)
+        }
+
+        pub fn slots(&self) -> impl Iterator<Item = u8> {
+            let slots = match *self {
+                Instruction::Load { src } => [Some(src), None],
+                Instruction::Store { dst } => [Some(dst), None],
+                Instruction::MulSlots { slots } => {
+                    let (src1, src2) = unpack_slots(slots);
+                    [Some(src1), Some(src2)]
+                }
+                _ => [None, None],
+            };
+            slots.into_iter().flatten()
+        }
+
+        /// (pops, pushes), for a `Call` as seen by the caller. that
+        /// depends on the callee, see `callee_argc`, so `None` for a
+        /// `Call` in `code` whose target isn't an `Enter`.
+        pub fn stack_effect(&self, code: &[Instruction], high: u8) -> Option<(usize, usize)> {
+            use Instruction::*;
+            if let Call { target } = *self {
+                return Some((callee_argc(code, wide(target, high))? as usize, 1));
+            }
+            Some(self.local_effect())
+        }
+
+        fn local_effect(&self) -> (usize, usize) {
+            use Instruction::*;
+            match *self {
+                Load { .. } | LoadInt { .. } | GetCounter => (0, 1),
+                LoadConst { .. } | MulSlots { .. }        => (0, 1),
+                Store { .. } | Pop | SetCounter           => (1, 0),
+                Add | Sub | Mul | Div | Lt | Le | Eq      => (2, 1),
+                Min | Max                                 => (2, 1),
+                Neg | Sqrt | Abs | Floor | LoadMem        => (1, 1),
+                AddInt { .. } | Square                    => (1, 1),
+                StoreMem                                  => (2, 0),
+                Dup                                       => (1, 2),
+                Rot                                       => (3, 3),
+                Swap                                      => (2, 2),
+                JumpIf { .. } | JumpIfNot { .. }          => (1, 0),
+                LoopLe { .. }                             => (2, 0),
+                Return | Ret                              => (1, 0),
+                Jump { .. } | Loop { .. } | Nop           => (0, 0),
+                Wide { .. } | Enter { .. }                => (0, 0),
+                Call { .. }                               => unreachable!(),
+            }
+        }
+
+        /// whether `Wide` can extend the operand: a target, `LoadInt`'s
+        /// value, or a constant. slots don't need it, there are only
+        /// `STACK_SIZE` of them.
+        pub fn has_wide_operand(&self) -> bool {
+            self.target().is_some() || matches!(self, Instruction::LoadInt { .. } | Instruction::LoadConst { .. })
+        }
+
+        /// the stack depth at the target, given this instruction's pops
+        /// and the depth after it. a call target starts a frame with
+        /// just the arguments.
+        pub fn target_depth(&self, pops: usize, after: usize) -> usize {
+            match *self {
+                Instruction::Call { .. } => pops,
+                _ => after,
+            }
+        }
+
+        /// see `reg::Instruction::opcode`.
+        #[inline(always)]
+        pub fn opcode(&self) -> u8 {
+            use Instruction::*;
+            match self {
+                Load       { .. } => 0,
+                Store      { .. } => 1,
+                LoadInt    { .. } => 2,
+                Add               => 3,
+                Sub               => 4,
+                Mul               => 5,
+                Div               => 6,
+                Neg               => 7,
+                Lt                => 8,
+                Le                => 9,
+                Eq                => 10,
+                Sqrt              => 11,
+                Abs               => 12,
+                Floor             => 13,
+                Min               => 14,
+                Max               => 15,
+                Pop               => 16,
+                Dup               => 17,
+                Rot               => 18,
+                Swap              => 19,
+                Jump       { .. } => 20,
+                JumpIf     { .. } => 21,
+                JumpIfNot  { .. } => 22,
+                SetCounter        => 23,
+                GetCounter        => 24,
+                Loop       { .. } => 25,
+                LoopLe     { .. } => 26,
+                Return            => 27,
+                Nop               => 28,
+                Call       { .. } => 29,
+                Ret               => 30,
+                LoadMem           => 31,
+                StoreMem          => 32,
+                LoadConst  { .. } => 33,
+                Wide       { .. } => 34,
+                MulSlots   { .. } => 35,
+                AddInt     { .. } => 36,
+                Square            => 37,
+                Enter      { .. } => 38,
+            }
+        }
+    }
+
+    pub const OPCODES: usize = 39;
+
+    /// like `reg::decode`, for the one operand a prefix extends.
+    /// unlike there, the prefix has to be in front of an instruction
+    /// with a wide operand, even if `high` is 0.
+    #[inline(always)]
+    pub fn decode(code: &[Instruction], pc: usize) -> Option<(Instruction, u8, usize)> {
+        let instr = *code.get(pc)?;
+        let Instruction::Wide { high } = instr else {
+            return Some((instr, 0, 1));
+        };
+        let next = *code.get(pc + 1)?;
+        if !next.has_wide_operand() {
+            return None;
+        }
+        Some((next, high, 2))
+    }
+
+    /// the `argc` of the `Enter` at a call's `target`.
+    #[inline(always)]
+    pub fn callee_argc(code: &[Instruction], target: usize) -> Option<u8> {
+        match code.get(target) {
+            Some(Instruction::Enter { argc }) => Some(*argc),
+            _ => None,
+        }
+    }
+
+    /// `MulSlots`'s operand, four bits per slot, so the instruction
+    /// still fits into two bytes. `None` if either slot is 16 or more.
+    pub fn pack_slots(src1: u8, src2: u8) -> Option<u8> {
+        (src1 < 16 && src2 < 16).then_some(src1 << 4 | src2)
+    }
+
+    #[inline(always)]
+    pub fn unpack_slots(slots: u8) -> (u8, u8) {
+        (slots >> 4, slots & 15)
+    }
+
+    /// `LoadInt`'s value, sign extended without a `Wide`.
+    #[inline(always)]
+    pub fn wide_int(value: i8, high: u8, len: usize) -> i64 {
+        if len == 2 { i16::from_le_bytes([value as u8, high]) as i64 }
+        else        { value as i64 }
+    }
+
+    /// pushes `value`, which may not fit into `LoadInt`.
+    pub fn emit_int(code: &mut Vec<Instruction>, value: i16) {
+        use Instruction::*;
+        if let Ok(value) = i8::try_from(value) {
+            code.push(LoadInt { value });
+        }
+        else {
+            emit_int(code, value / 100);
+            code.push(LoadInt { value: 100 });
+            code.push(Mul);
+            if value % 100 != 0 {
+                code.push(LoadInt { value: (value % 100) as i8 });
+                code.push(Add);
+            }
+        }
+    }
+
+    /// stack depth before each instruction, given `argc` arguments.
+    /// `None` for unreachable code, and from the point where the stack
+    /// would underflow.
+    pub fn stack_depths(code: &[Instruction], argc: usize) -> Vec<Option<usize>> {
+        let mut depths = vec![None; code.len()];
+        let mut work = vec![(0, argc)];
+        while let Some((pc, depth)) = work.pop() {
+            if pc >= code.len() || depths[pc].is_some() {
+                continue;
+            }
+            depths[pc] = Some(depth);
+
+            let Some((instr, high, len)) = decode(code, pc) else { continue };
+            let Some((pops, pushes)) = instr.stack_effect(code, high) else { continue };
+            if depth < pops { continue }
+            let depth = depth - pops + pushes;
+
+            if let Some(target) = instr.target() {
+                work.push((wide(target, high), instr.target_depth(pops, depth)));
+            }
+            if instr.falls_through() {
+                work.push((pc + len, depth));
+            }
+        }
+        depths
+    }
+
+
+
+    /// slots a frame can have.
+    pub const STACK_SIZE: usize = 256;
+
+    /// the stack grows up to this many slots, for calls.
+    pub const MAX_STACK: usize = 64 * STACK_SIZE;
+
+    /// code that passed `verify`, safe to run with `SPEEEEEED`.
+    #[derive(Clone, Debug)]
+    pub struct VerifiedProgram {
+        code:   Vec<Instruction>,
+        argc:   usize,
+        depths: Vec<Option<usize>>,
+        calls:  bool,
+    }
+
+    impl VerifiedProgram {
+        #[inline(always)]
+        pub fn code(&self) -> &[Instruction] { &self.code }
+
+        /// whether the program has a reachable `Call`.
+        #[inline(always)]
+        pub fn calls(&self) -> bool { self.calls }
+
+        #[inline(always)]
+        pub fn argc(&self) -> usize { self.argc }
+
+        /// stack depth before the instruction at `pc`, `None` if unreachable.
+        pub fn depth(&self, pc: usize) -> Option<usize> { self.depths[pc] }
+
+        pub fn max_depth(&self) -> usize {
+            self.depths.iter().flatten().copied().max().unwrap_or(0)
+        }
+    }
+
+    /// like `reg::verify`, but also checks the stack:
+    /// the depth must agree wherever control flow merges,
+    /// and must stay within `0..=STACK_SIZE`.
+    /// depths are relative to the frame, so only calls can exceed that.
+    /// since the arguments are on the stack, `argc` is part of the program.
+    pub fn verify(code: &[Instruction], argc: usize) -> Result<VerifiedProgram, super::VerifyError> {
+        use super::VerifyError::*;
+
+        if argc > STACK_SIZE {
+            return Err(StackOverflow { pc: 0, depth: argc });
+        }
+
+        let mut depths = vec![None; code.len()];
+        let mut calls = false;
+        let mut widened = vec![false; code.len()];
+        let mut work = vec![(0, argc)];
+        while let Some((pc, depth)) = work.pop() {
+            if pc >= code.len() {
+                return Err(FallsOffEnd { pc });
+            }
+            if let Some(expected) = depths[pc] {
+                if expected != depth {
+                    return Err(DepthMismatch { pc, expected, found: depth });
+                }
+                continue;
+            }
+            depths[pc] = Some(depth);
+
+            let Some((instr, high, len)) = decode(code, pc) else {
+                return Err(BadWide { pc });
+            };
+            if len == 2 {
+                widened[pc + 1] = true;
+            }
+            calls |= matches!(instr, Instruction::Call { .. });
+            let (pops, pushes) = instr.stack_effect(code, high).ok_or(BadCall { pc })?;
+            if depth < pops {
+                return Err(StackUnderflow { pc, depth });
+            }
+            let after = depth - pops + pushes;
+            if depth.max(after) > STACK_SIZE {
+                return Err(StackOverflow { pc, depth: depth.max(after) });
+            }
+
+            // `Store` writes after popping.
+            for index in instr.slots() {
+                if index as usize >= depth - pops {
+                    return Err(BadRegister { pc, index: index as usize });
+                }
+            }
+
+            if let Some(target) = instr.target() {
+                let target = wide(target, high);
+                if target >= code.len() {
+                    return Err(TargetOutOfRange { pc, target });
+                }
+                work.push((target, instr.target_depth(pops, after)));
+            }
+            if instr.falls_through() {
+                if pc + len >= code.len() {
+                    return Err(FallsOffEnd { pc });
+                }
+                work.push((pc + len, after));
+            }
+        }
+
+        if let Some(pc) = (0..code.len()).find(|pc| depths[*pc].is_some() && widened[*pc]) {
+            return Err(BadWide { pc: pc - 1 });
+        }
+
+        Ok(VerifiedProgram { code: code.into(), argc, depths, calls })
+    }
+
+
+    /// `V` is how values are represented, see `value`.
+    pub struct Vm<V: Value = f64> {
+        stack: Vec<V>,
+        frames: Vec<Frame>,
+        memory: Vec<V>,
+        constants: Vec<V>,
+    }
+
+    /// the caller's state, saved by `Call`.
+    #[derive(Clone, Copy, Debug)]
+    struct Frame {
+        pc: usize,
+        fp: usize,
+        counter: u32,
+    }
+
+    struct State<'a, V: Value> {
+        vm: &'a mut Vm<V>,
+        code: &'a [Instruction],
+        pc: usize,
+        pcp: *const Instruction,
+        counter: u32,
+        base: *mut V,
+        top:  *mut V,
+        fp:   usize,
+        fpp:  *mut V,
+    }
+
+    /// where a checked execution is at, see `Vm::step`.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct Cursor {
+        pub pc: usize,
+        pub counter: u32,
+        /// where the current frame's slots start.
+        pub fp: usize,
+    }
+
+    /// see `reg::Dispatched`.
+    type Handler<'a, V> = fn(&mut State<'a, V>, Instruction, &mut Option<VmError>) -> Option<V>;
+
+    type TailHandler<'a, V> = fn(&mut State<'a, V>, Instruction, &mut Option<VmError>, &mut V, u32) -> bool;
+
+    type Closure<V> = Box<dyn Fn(&mut State<V>, &mut Option<VmError>) -> Option<V>>;
+
+    /// a program, prepared for `Vm::run_with` with the dispatch `D`.
+    pub struct Dispatched<V: Value, D: Dispatch> {
+        program: VerifiedProgram,
+        /// one per instruction, for `dispatch::Closures`.
+        closures: Vec<Closure<V>>,
+        dispatch: PhantomData<D>,
+    }
+
+    impl<V: Value + 'static, D: Dispatch> Dispatched<V, D> {
+        const CLOSURES: [fn(Instruction) -> Closure<V>; OPCODES] = [
+            Self::closure::<0>,  Self::closure::<1>,  Self::closure::<2>,  Self::closure::<3>,
+            Self::closure::<4>,  Self::closure::<5>,  Self::closure::<6>,  Self::closure::<7>,
+            Self::closure::<8>,  Self::closure::<9>,  Self::closure::<10>, Self::closure::<11>,
+            Self::closure::<12>, Self::closure::<13>, Self::closure::<14>, Self::closure::<15>,
+            Self::closure::<16>, Self::closure::<17>, Self::closure::<18>, Self::closure::<19>,
+            Self::closure::<20>, Self::closure::<21>, Self::closure::<22>, Self::closure::<23>,
+            Self::closure::<24>, Self::closure::<25>, Self::closure::<26>, Self::closure::<27>,
+            Self::closure::<28>, Self::closure::<29>, Self::closure::<30>, Self::closure::<31>,
+            Self::closure::<32>, Self::closure::<33>, Self::closure::<34>, Self::closure::<35>,
+            Self::closure::<36>, Self::closure::<37>, Self::closure::<38>,
+        ];
+
+        pub fn new(program: &VerifiedProgram) -> Self {
+            let mut closures = vec![];
+            if D::KIND == Kind::Closures {
+                closures = program.code().iter().map(|instr| Self::CLOSURES[instr.opcode() as usize](*instr)).collect();
+            }
+            Dispatched { program: program.clone(), closures, dispatch: PhantomData }
+        }
+
+        fn closure<const OP: u8>(instr: Instruction) -> Closure<V> {
+            Box::new(move |s, error| s.execute::<OP>(instr, error))
+        }
+    }
+
+    impl<V: Value, D: Dispatch> Dispatched<V, D> {
+        pub fn program(&self) -> &VerifiedProgram {
+            &self.program
+        }
+
+        #[inline(always)]
+        fn closure_at(&self, pc: usize) -> &Closure<V> {
+            if super::SPEEEEEED {
+                // verified.
+                unsafe { self.closures.get_unchecked(pc) }
+            }
+            else {
+                &self.closures[pc]
+            }
+        }
+    }
+
+    impl Vm {
+        pub fn new() -> Self {
+            Self::default()
+        }
+    }
+
+    impl<V: Value> Default for Vm<V> {
+        fn default() -> Self {
+            // +8 for aligning the base.
+            Vm { stack: Vec::with_capacity(MAX_STACK + 8), frames: vec![], memory: vec![], constants: vec![] }
+        }
+    }
+
+    impl<V: Value> Vm<V> {
+        /// the operand stack of a checked execution.
+        pub fn stack(&self) -> &[V] {
+            &self.stack
+        }
+
+        /// see `reg::Vm::memory`.
+        pub fn memory(&self) -> &[V] {
+            &self.memory
+        }
+
+        pub fn memory_mut(&mut self) -> &mut Vec<V> {
+            &mut self.memory
+        }
+
+        /// see `reg::Vm::constants`.
+        pub fn constants(&self) -> &[V] {
+            &self.constants
+        }
+
+        pub fn constants_mut(&mut self) -> &mut Vec<V> {
+            &mut self.constants
+        }
+
+        /// the start of the fast path's stack, in `stack`'s capacity.
+        #[inline(always)]
+        fn aligned_base(&mut self) -> *mut V {
+            let base = self.stack.as_mut_ptr();
+            ((base as usize + 63) / 64 * 64) as *mut V
+        }
+
+        /// for a call with a frame at `fp`.
+        #[inline(always)]
+        fn push_frame(&mut self, frame: Frame, fp: usize) -> bool {
+            if self.frames.len() >= super::MAX_CALL_DEPTH || fp + STACK_SIZE > MAX_STACK {
+                return false;
+            }
+            self.frames.push(frame);
+            true
+        }
+
+        /// runs unverified code, returning an error instead of
+        /// misbehaving, regardless of `SPEEEEEED`.
+        pub fn try_run(&mut self, code: &[Instruction], args: &[V]) -> Result<V, VmError> {
+            let mut cursor = self.start(args)?;
+            loop {
+                if let Some(result) = self.step(code, &mut cursor)? {
+                    return Ok(result);
+                }
+            }
+        }
+
+        /// like `try_run`, but executes at most `fuel` instructions.
+        pub fn try_run_with_fuel(&mut self, code: &[Instruction], args: &[V], fuel: u64) -> Result<V, VmError> {
+            let mut cursor = self.start(args)?;
+            for _ in 0..fuel {
+                if let Some(result) = self.step(code, &mut cursor)? {
+                    return Ok(result);
+                }
+            }
+            Err(VmError::OutOfFuel { pc: cursor.pc, counter: cursor.counter })
+        }
+
+        pub fn start(&mut self, args: &[V]) -> Result<Cursor, VmError> {
+            if args.len() > STACK_SIZE {
+                return Err(VmError::TooManyArgs { count: args.len() });
+            }
+            self.frames.clear();
+            self.stack.clear();
+            self.stack.extend_from_slice(args);
+            Ok(Cursor::default())
+        }
+
+        /// executes one instruction with all checks.
+        /// returns the result once the program has returned.
+        pub fn step(&mut self, code: &[Instruction], cursor: &mut Cursor) -> Result<Option<V>, VmError> {
+            let pc = cursor.pc;
+            if pc >= code.len() {
+                return Err(VmError::NoReturn { pc });
+            }
+            let Some((instr, high, len)) = decode(code, pc) else {
+                return Err(VmError::BadWide { pc });
+            };
+            cursor.pc += len;
+
+            let stack = &mut self.stack;
+            let jump = |cursor: &mut Cursor, target: u8| {
+                let target = wide(target, high);
+                if target >= code.len() {
+                    return Err(VmError::PcOutOfBounds { pc, target });
+                }
+                cursor.pc = target;
+                Ok(())
+            };
+
+            // check the whole effect up front, so the individual
+            // operations below can only fail with type errors.
+            let fp = cursor.fp;
+            let depth = stack.len() - fp;
+            let Some((pops, pushes)) = instr.stack_effect(code, high) else {
+                return Err(VmError::BadCall { pc });
+            };
+            if depth < pops {
+                return Err(VmError::StackUnderflow { pc });
+            }
+            if depth - pops + pushes > STACK_SIZE {
+                return Err(VmError::StackOverflow { pc });
+            }
+            for index in instr.slots() {
+                if index as usize >= depth - pops {
+                    return Err(VmError::BadRegister { pc, index: index as usize });
+                }
+            }
+
+            // only used by instructions that pop.
+            let top = stack.len().wrapping_sub(1);
+            let mut pop = || stack.pop().unwrap();
+            let type_error = VmError::TypeError { pc };
+
+            use Instruction::*;
+            match instr {
+                Load { src } => {
+                    let value = stack[fp + src as usize];
+                    stack.push(value);
+                }
+
+                Store { dst } => {
+                    let value = pop();
+                    stack[fp + dst as usize] = value;
+                }
+
+                LoadInt { value } => {
+                    stack.push(V::from_int(wide_int(value, high, len)));
+                }
+
+                Add => { let b = pop(); stack[top - 1] = stack[top - 1].binary(BinOp::Add, b).ok_or(type_error)?; }
+                Sub => { let b = pop(); stack[top - 1] = stack[top - 1].binary(BinOp::Sub, b).ok_or(type_error)?; }
+                Mul => { let b = pop(); stack[top - 1] = stack[top - 1].binary(BinOp::Mul, b).ok_or(type_error)?; }
+                Div => { let b = pop(); stack[top - 1] = stack[top - 1].binary(BinOp::Div, b).ok_or(type_error)?; }
+                Neg => { stack[top] = stack[top].unary(UnOp::Neg).ok_or(type_error)?; }
+
+                Lt => { let b = pop(); stack[top - 1] = stack[top - 1].binary(BinOp::Lt, b).ok_or(type_error)?; }
+                Le => { let b = pop(); stack[top - 1] = stack[top - 1].binary(BinOp::Le, b).ok_or(type_error)?; }
+                Eq => { let b = pop(); stack[top - 1] = stack[top - 1].binary(BinOp::Eq, b).ok_or(type_error)?; }
+
+                Sqrt  => { stack[top] = stack[top].unary(UnOp::Sqrt).ok_or(type_error)?; }
+                Abs   => { stack[top] = stack[top].unary(UnOp::Abs).ok_or(type_error)?; }
+                Floor => { stack[top] = stack[top].unary(UnOp::Floor).ok_or(type_error)?; }
+                Min   => { let b = pop(); stack[top - 1] = stack[top - 1].binary(BinOp::Min, b).ok_or(type_error)?; }
+                Max   => { let b = pop(); stack[top - 1] = stack[top - 1].binary(BinOp::Max, b).ok_or(type_error)?; }
+
+                Pop => { pop(); }
+
+                Dup => {
+                    let value = stack[top];
+                    stack.push(value);
+                }
+
+                Rot => {
+                    stack[top - 2 ..].rotate_left(1);
+                }
+
+                Swap => {
+                    stack.swap(top, top - 1);
+                }
+
+                Jump { target } => {
+                    jump(cursor, target)?;
+                }
+
+                JumpIf { target } => {
+                    if pop().is_true() {
+                        jump(cursor, target)?;
+                    }
+                }
+
+                JumpIfNot { target } => {
+                    if !pop().is_true() {
+                        jump(cursor, target)?;
+                    }
+                }
+
+                SetCounter => {
+                    cursor.counter = pop().counter().ok_or(type_error)?;
+                }
+
+                GetCounter => {
+                    stack.push(V::from_int(cursor.counter as i64));
+                }
+
+                Loop { target } => {
+                    if cursor.counter > 0 {
+                        cursor.counter -= 1;
+                        jump(cursor, target)?;
+                    }
+                }
+
+                LoopLe { target } => {
+                    let b = pop();
+                    let a = pop();
+                    if a.binary(BinOp::Le, b).ok_or(type_error)?.is_true() && cursor.counter > 0 {
+                        cursor.counter -= 1;
+                        jump(cursor, target)?;
+                    }
+                }
+
+                Return => {
+                    let result = pop();
+                    stack.clear();
+                    return Ok(Some(result));
+                }
+
+                Nop | Enter { .. } => {}
+
+                Call { target } => {
+                    let callee = stack.len() - pops;
+                    let frame = Frame { pc: cursor.pc, fp, counter: cursor.counter };
+                    jump(cursor, target)?;
+                    if !self.push_frame(frame, callee) {
+                        return Err(VmError::StackOverflow { pc });
+                    }
+                    cursor.fp = callee;
+                    cursor.counter = 0;
+                }
+
+                Ret => {
+                    let result = pop();
+                    let Some(frame) = self.frames.pop() else {
+                        stack.clear();
+                        return Ok(Some(result));
+                    };
+                    stack.truncate(fp);
+                    stack.push(result);
+                    cursor.pc = frame.pc;
+                    cursor.fp = frame.fp;
+                    cursor.counter = frame.counter;
+                }
+
+                LoadMem => {
+                    let address = stack[top].address().ok_or(type_error)?;
+                    stack[top] = *self.memory.get(address).ok_or(VmError::BadAddress { pc })?;
+                }
+
+                StoreMem => {
+                    let value = pop();
+                    let address = pop().address().ok_or(type_error)?;
+                    *self.memory.get_mut(address).ok_or(VmError::BadAddress { pc })? = value;
+                }
+
+                LoadConst { index } => {
+                    let value = *self.constants.get(wide(index, high)).ok_or(VmError::BadConstant { pc })?;
+                    stack.push(value);
+                }
+
+                // `decode` skips the prefix.
+                Wide { .. } => unreachable!(),
+
+                MulSlots { slots } => {
+                    let (src1, src2) = unpack_slots(slots);
+                    let value = stack[fp + src1 as usize].binary(BinOp::Mul, stack[fp + src2 as usize]).ok_or(type_error)?;
+                    stack.push(value);
+                }
+
+                AddInt { value } => { stack[top] = stack[top].binary(BinOp::Add, V::from_int(value as i64)).ok_or(type_error)?; }
+                Square           => { stack[top] = stack[top].binary(BinOp::Mul, stack[top]).ok_or(type_error)?; }
+            }
+            Ok(None)
+        }
+
+        /// panics if calls nest too deeply, see `run_with_fuel`.
+        #[inline(never)]
+        pub fn run(&mut self, program: &VerifiedProgram, args: &[V]) -> V {
+            let mut error = None;
+            let result =
+                if program.calls() { self.run_impl::<false, false, true >(program, args, &mut Profile::new(), 0, &mut error) }
+                else               { self.run_impl::<false, false, false>(program, args, &mut Profile::new(), 0, &mut error) };
+            if let Some(e) = error {
+                panic!("{:?}", e);
+            }
+            result
+        }
+
+        /// like `run`, but adds execution counts to `profile`.
+        #[inline(never)]
+        pub fn run_profiled(&mut self, program: &VerifiedProgram, args: &[V], profile: &mut Profile) -> V {
+            profile.prepare(program.code().len());
+            let mut error = None;
+            let result =
+                if program.calls() { self.run_impl::<true, false, true >(program, args, profile, 0, &mut error) }
+                else               { self.run_impl::<true, false, false>(program, args, profile, 0, &mut error) };
+            if let Some(e) = error {
+                panic!("{:?}", e);
+            }
+            result
+        }
+
+        /// like `run`, but executes at most `fuel` instructions,
+        /// so programs that don't terminate can't hang the caller.
+        /// also returns errors instead of panicking.
+        #[inline(never)]
+        pub fn run_with_fuel(&mut self, program: &VerifiedProgram, args: &[V], fuel: u64) -> Result<V, VmError> {
+            let mut error = None;
+            let result =
+                if program.calls() { self.run_impl::<false, true, true >(program, args, &mut Profile::new(), fuel, &mut error) }
+                else               { self.run_impl::<false, true, false>(program, args, &mut Profile::new(), fuel, &mut error) };
+            match error {
+                Some(e) => Err(e),
+                None    => Ok(result),
+            }
+        }
+
+        /// see `reg::Vm::run_packed`.
+        #[inline(never)]
+        pub fn run_packed(&mut self, program: &packed::StackProgram, args: &[V]) -> V {
+            use packed::{byte, target, failed, stack_op::*};
+            assert_eq!(args.len(), program.argc());
+            let code = program.code();
+
+            let base = self.aligned_base();
+
+            // for the stack, the pc is a byte offset here.
+            let mut s = State {
+                code: &[],
+                pc: 0,
+                pcp: core::ptr::null(),
+                base,
+                top: base,
+                fp: 0,
+                fpp: base,
+                counter: 0,
+                vm: self,
+            };
+            s.clear();
+            s.vm.frames.clear();
+            for arg in args {
+                s.push(*arg);
+            }
+
+            let mut pc = 0;
+            loop {
+                let at = pc;
+                let b = |i| byte(code, at + i);
+                let type_error = || -> ! { failed(VmError::TypeError { pc: program.pc(at) }) };
+
+                match b(0) {
+                    LOAD => {
+                        let value = *s.get(b(1));
+                        s.push(value);
+                        pc += 2;
+                    }
+
+                    STORE => {
+                        let value = s.pop();
+                        *s.get(b(1)) = value;
+                        pc += 2;
+                    }
+
+                    LOAD_INT => {
+                        s.push(V::from_int(b(1) as i8 as i64));
+                        pc += 2;
+                    }
+
+                    ADD => {
+                        if !s.binary(BinOp::Add) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    SUB => {
+                        if !s.binary(BinOp::Sub) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    MUL => {
+                        if !s.binary(BinOp::Mul) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    DIV => {
+                        if !s.binary(BinOp::Div) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    NEG => {
+                        if !s.unary(UnOp::Neg) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    LT => {
+                        if !s.binary(BinOp::Lt) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    LE => {
+                        if !s.binary(BinOp::Le) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    EQ => {
+                        if !s.binary(BinOp::Eq) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    SQRT => {
+                        if !s.unary(UnOp::Sqrt) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    ABS => {
+                        if !s.unary(UnOp::Abs) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    FLOOR => {
+                        if !s.unary(UnOp::Floor) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    MIN => {
+                        if !s.binary(BinOp::Min) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    MAX => {
+                        if !s.binary(BinOp::Max) {
+                            type_error();
+                        }
+                        pc += 1;
+                    }
+
+                    POP => {
+                        s.pop();
+                        pc += 1;
+                    }
+
+                    DUP => {
+                        let value = *s.get_top(0);
+                        s.push(value);
+                        pc += 1;
+                    }
+
+                    ROT => {
+                        let a = *s.get_top(2);
+                        *s.get_top(2) = *s.get_top(1);
+                        *s.get_top(1) = *s.get_top(0);
+                        *s.get_top(0) = a;
+                        pc += 1;
+                    }
+
+                    SWAP => {
+                        let a = *s.get_top(0);
+                        *s.get_top(0) = *s.get_top(1);
+                        *s.get_top(1) = a;
+                        pc += 1;
+                    }
+
+                    JUMP => {
+                        pc = target(code, at + 1);
+                    }
+
+                    JUMP_IF => {
+                        pc = if s.pop().is_true() { target(code, at + 1) } else { at + 3 };
+                    }
+
+                    JUMP_IF_NOT => {
+                        pc = if !s.pop().is_true() { target(code, at + 1) } else { at + 3 };
+                    }
+
+                    SET_COUNTER => {
+                        let Some(counter) = s.pop().counter() else {
+                            type_error();
+                        };
+                        s.counter = counter;
+                        pc += 1;
+                    }
+
+                    GET_COUNTER => {
+                        s.push(V::from_int(s.counter as i64));
+                        pc += 1;
+                    }
+
+                    LOOP => {
+                        if s.counter > 0 {
+                            s.counter -= 1;
+                            pc = target(code, at + 1);
+                        }
+                        else {
+                            pc += 3;
+                        }
+                    }
+
+                    LOOP_LE => {
+                        let rhs = s.pop();
+                        let lhs = s.pop();
+                        let Some(le) = lhs.binary(BinOp::Le, rhs) else {
+                            type_error();
+                        };
+                        if le.is_true() && s.counter > 0 {
+                            s.counter -= 1;
+                            pc = target(code, at + 1);
+                        }
+                        else {
+                            pc += 3;
+                        }
+                    }
+
+                    RETURN => {
+                        let result = s.pop();
+                        s.clear();
+                        return result;
+                    }
+
+                    NOP => {
+                        pc += 1;
+                    }
+
+                    ENTER => {
+                        pc += 2;
+                    }
+
+                    CALL => {
+                        // verified, the target is an `Enter`.
+                        let entry = target(code, at + 1);
+                        let callee = s.depth() - byte(code, entry + 1) as usize;
+                        if !s.vm.push_frame(Frame { pc: at + 3, fp: s.fp, counter: s.counter }, callee) {
+                            failed(VmError::StackOverflow { pc: program.pc(at) });
+                        }
+                        s.set_fp(callee);
+                        s.counter = 0;
+                        pc = entry;
+                    }
+
+                    RET => {
+                        let result = s.pop();
+                        let Some(frame) = s.vm.frames.pop() else {
+                            s.clear();
+                            return result;
+                        };
+                        s.leave(&frame);
+                        s.push(result);
+                        pc = frame.pc;
+                    }
+
+                    LOAD_MEM => {
+                        let Some(address) = s.get_top(0).address() else {
+                            type_error();
+                        };
+                        let Some(&value) = s.vm.memory.get(address) else {
+                            failed(VmError::BadAddress { pc: program.pc(at) });
+                        };
+                        *s.get_top(0) = value;
+                        pc += 1;
+                    }
+
+                    STORE_MEM => {
+                        let value = s.pop();
+                        let Some(address) = s.pop().address() else {
+                            type_error();
+                        };
+                        let Some(slot) = s.vm.memory.get_mut(address) else {
+                            failed(VmError::BadAddress { pc: program.pc(at) });
+                        };
+                        *slot = value;
+                        pc += 1;
+                    }
+
+                    LOAD_CONST => {
+                        let Some(&value) = s.vm.constants.get(b(1) as usize) else {
+                            failed(VmError::BadConstant { pc: program.pc(at) });
+                        };
+                        s.push(value);
+                        pc += 2;
+                    }
+
+                    _ => super::unreachable_arm(),
+                }
+            }
+        }
+
+        /// see `reg::Vm::run_with`.
+        #[inline(never)]
+        pub fn run_with<D: Dispatch>(&mut self, program: &Dispatched<V, D>, args: &[V]) -> V {
+            if D::KIND == Kind::Match {
+                return self.run(&program.program, args);
+            }
+            assert_eq!(args.len(), program.program.argc());
+            let code = program.program.code();
+
+            let base = self.aligned_base();
+
+            let mut s = State {
+                code,
+                pc: 0,
+                pcp: core::ptr::null(),
+                base,
+                top: base,
+                fp: 0,
+                fpp: base,
+                counter: 0,
+                vm: self,
+            };
+
+            s.jump(0);
+            s.clear();
+            s.vm.frames.clear();
+            for arg in args {
+                s.push(*arg);
+            }
+
+            let mut error = None;
+            let result = loop {
+                let pc = s.pc();
+                let instr = s.next_instr();
+                let result = match D::KIND {
+                    Kind::Table     => State::HANDLERS[instr.opcode() as usize](&mut s, instr, &mut error),
+                    Kind::Closures  => program.closure_at(pc)(&mut s, &mut error),
+                    Kind::TailCalls => {
+                        let mut result = V::from_f64(0.0);
+                        let stopped = State::TAIL_HANDLERS[instr.opcode() as usize](&mut s, instr, &mut error, &mut result, dispatch::CHAIN);
+                        stopped.then_some(result)
+                    }
+                    Kind::Match     => unreachable!(),
+                };
+                if let Some(result) = result {
+                    break result;
+                }
+            };
+            if let Some(e) = error {
+                panic!("{:?}", e);
+            }
+            result
+        }
+
+        // see `reg::Vm::run_impl`.
+        #[inline(always)]
+        fn run_impl<const PROFILE: bool, const FUEL: bool, const CALLS: bool>(&mut self, program: &VerifiedProgram, args: &[V], profile: &mut Profile, mut fuel: u64, error: &mut Option<VmError>) -> V {
+            assert_eq!(args.len(), program.argc());
+            let code = program.code();
+
+            let base = self.aligned_base();
+
+            let mut s = State {
+                code,
+                pc: 0,
+                pcp: core::ptr::null(),
+                base,
+                top: base,
+                fp: 0,
+                fpp: base,
+                counter: 0,
+                vm: self,
+            };
+
+            s.jump(0);
+            // an earlier run may have run out of fuel.
+            s.clear();
+            s.vm.frames.clear();
+            for arg in args {
+                s.push(*arg);
+            }
+
+            loop {
+                let pc = if PROFILE { s.pc() } else { 0 };
+                if PROFILE {
+                    profile.executed[pc] += 1;
+                }
+                if FUEL {
+                    if fuel == 0 {
+                        *error = Some(s.out_of_fuel());
+                        return V::from_f64(0.0);
+                    }
+                    fuel -= 1;
+                }
+
+                let instr = s.next_instr();
+
+                use Instruction::*;
+                match instr {
+                    Load { src } => {
+                        let value = *s.get(src);
+                        s.push(value);
+                    }
+
+                    Store { dst } => {
+                        let value = s.pop();
+                        *s.get(dst) = value;
+                    }
+
+                    LoadInt { value } => {
+                        s.push(V::from_int(value as i64));
+                    }
+
+                    Add => {
+                        if !s.binary(BinOp::Add) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Sub => {
+                        if !s.binary(BinOp::Sub) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Mul => {
+                        if !s.binary(BinOp::Mul) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Div => {
+                        if !s.binary(BinOp::Div) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Neg => {
+                        if !s.unary(UnOp::Neg) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Lt => {
+                        if !s.binary(BinOp::Lt) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Le => {
+                        if !s.binary(BinOp::Le) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Eq => {
+                        if !s.binary(BinOp::Eq) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Sqrt => {
+                        if !s.unary(UnOp::Sqrt) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Abs => {
+                        if !s.unary(UnOp::Abs) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Floor => {
+                        if !s.unary(UnOp::Floor) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Min => {
+                        if !s.binary(BinOp::Min) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Max => {
+                        if !s.binary(BinOp::Max) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Pop => {
+                        s.pop();
+                    }
+
+                    Dup => {
+                        let value = *s.get_top(0);
+                        s.push(value);
+                    }
+
+                    Rot => {
+                        let a = *s.get_top(2);
+                        *s.get_top(2) = *s.get_top(1);
+                        *s.get_top(1) = *s.get_top(0);
+                        *s.get_top(0) = a;
+                    }
+
+                    Swap => {
+                        let a = *s.get_top(0);
+                        *s.get_top(0) = *s.get_top(1);
+                        *s.get_top(1) = a;
+                    }
+
+                    Jump { target } => {
+                        s.jump(target);
+                    }
+
+                    JumpIf { target } => {
+                        if s.pop().is_true() {
+                            s.jump(target);
+                            if PROFILE {
+                                profile.taken[pc] += 1;
+                            }
+                        }
+                    }
+
+                    JumpIfNot { target } => {
+                        if !s.pop().is_true() {
+                            s.jump(target);
+                            if PROFILE {
+                                profile.taken[pc] += 1;
+                            }
+                        }
+                    }
+
+                    SetCounter => {
+                        let Some(counter) = s.pop().counter() else {
+                            return s.type_error(error);
+                        };
+                        s.counter = counter;
+                    }
+
+                    GetCounter => {
+                        s.push(V::from_int(s.counter as i64));
+                    }
+
+                    Loop { target } => {
+                        if s.counter > 0 {
+                            s.counter -= 1;
+                            s.jump(target);
+                            if PROFILE {
+                                profile.taken[pc] += 1;
+                            }
+                        }
+                    }
+
+                    LoopLe { target } => {
+                        let b = s.pop();
+                        let a = s.pop();
+                        let Some(le) = a.binary(BinOp::Le, b) else {
+                            return s.type_error(error);
+                        };
+                        if le.is_true() && s.counter > 0 {
+                            s.counter -= 1;
+                            s.jump(target);
+                            if PROFILE {
+                                profile.taken[pc] += 1;
+                            }
+                        }
+                    }
+
+                    Return => {
+                        let result = s.pop();
+                        s.clear();
+                        return result;
+                    }
+
+                    Nop | Enter { .. } => {}
+
+                    Call { target } => {
+                        if !CALLS {
+                            super::unreachable_arm();
+                        }
+                        if !s.call(target as usize) {
+                            *error = Some(VmError::StackOverflow { pc: s.pc() - 1 });
+                            return V::from_f64(0.0);
+                        }
+                    }
+
+                    Ret => {
+                        let result = s.pop();
+                        if !CALLS {
+                            s.clear();
+                            return result;
+                        }
+                        let Some(frame) = s.vm.frames.pop() else {
+                            s.clear();
+                            return result;
+                        };
+                        s.ret(frame);
+                        s.push(result);
+                    }
+
+                    LoadMem => {
+                        let Some(address) = s.get_top(0).address() else {
+                            return s.type_error(error);
+                        };
+                        let Some(&value) = s.vm.memory.get(address) else {
+                            return s.bad_address(error);
+                        };
+                        *s.get_top(0) = value;
+                    }
+
+                    StoreMem => {
+                        let value = s.pop();
+                        let Some(address) = s.pop().address() else {
+                            return s.type_error(error);
+                        };
+                        let Some(slot) = s.vm.memory.get_mut(address) else {
+                            return s.bad_address(error);
+                        };
+                        *slot = value;
+                    }
+
+                    LoadConst { .. } | Wide { .. } => {
+                        match s.wide::<PROFILE>(instr, profile, error) {
+                            Some(next) => s = next,
+                            None => return V::from_f64(0.0),
+                        }
+                    }
+
+                    MulSlots { slots } => {
+                        let (src1, src2) = unpack_slots(slots);
+                        let Some(value) = s.get(src1).binary(BinOp::Mul, *s.get(src2)) else {
+                            return s.type_error(error);
+                        };
+                        s.push(value);
+                    }
+
+                    AddInt { value } => {
+                        if !s.add_int(value) {
+                            return s.type_error(error);
+                        }
+                    }
+
+                    Square => {
+                        if !s.square() {
+                            return s.type_error(error);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    impl<'a, V: Value> State<'a, V> {
+        /// for the instruction that just ran.
+        #[cold]
+        fn type_error(&mut self, error: &mut Option<VmError>) -> V {
+            *error = Some(VmError::TypeError { pc: self.pc() - 1 });
+            V::from_f64(0.0)
+        }
+
+        #[cold]
+        fn bad_address(&mut self, error: &mut Option<VmError>) -> V {
+            *error = Some(VmError::BadAddress { pc: self.pc() - 1 });
+            V::from_f64(0.0)
+        }
+
+        /// `LoadConst`, or a `Wide` and the instruction after it. unlike
+        /// the reg vm, this can't use `Vm::step`, the fast path doesn't
+        /// maintain `vm.stack`. both are kept out of the dispatch loop,
+        /// `self` is passed by value so its fields can stay in registers
+        /// there. `None` for errors.
+        #[cold]
+        #[inline(never)]
+        fn wide<const PROFILE: bool>(mut self, instr: Instruction, profile: &mut Profile, error: &mut Option<VmError>) -> Option<Self> {
+            if !self.wide_in_place::<PROFILE>(instr, profile, error) {
+                return None;
+            }
+            Some(self)
+        }
+
+        /// `wide`, for `execute`. false for errors.
+        #[inline(always)]
+        fn wide_in_place<const PROFILE: bool>(&mut self, instr: Instruction, profile: &mut Profile, error: &mut Option<VmError>) -> bool {
+            let pc = self.pc() - 1;
+            let mut taken = false;
+
+            use Instruction::*;
+            let (instr, high) = match instr {
+                Wide { high } => (self.next_instr(), high),
+                _ => (instr, 0),
+            };
+            match instr {
+                LoadInt { value } => {
+                    self.push(V::from_int(wide_int(value, high, 2)));
+                }
+
+                LoadConst { index } => {
+                    let Some(&value) = self.vm.constants.get(wide(index, high)) else {
+                        *error = Some(VmError::BadConstant { pc });
+                        return false;
+                    };
+                    self.push(value);
+                }
+
+                Jump { target } => {
+                    self.set_pc(wide(target, high));
+                }
+
+                JumpIf { target } => {
+                    if self.pop().is_true() {
+                        self.set_pc(wide(target, high));
+                        taken = true;
+                    }
+                }
+
+                JumpIfNot { target } => {
+                    if !self.pop().is_true() {
+                        self.set_pc(wide(target, high));
+                        taken = true;
+                    }
+                }
+
+                Loop { target } => {
+                    if self.counter > 0 {
+                        self.counter -= 1;
+                        self.set_pc(wide(target, high));
+                        taken = true;
+                    }
+                }
+
+                LoopLe { target } => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let Some(le) = a.binary(BinOp::Le, b) else {
+                        *error = Some(VmError::TypeError { pc });
+                        return false;
+                    };
+                    if le.is_true() && self.counter > 0 {
+                        self.counter -= 1;
+                        self.set_pc(wide(target, high));
+                        taken = true;
+                    }
+                }
+
+                Call { target } => {
+                    if !self.call(wide(target, high)) {
+                        *error = Some(VmError::StackOverflow { pc });
+                        return false;
+                    }
+                }
+
+                // verified, see `decode`.
+                _ => super::unreachable_arm(),
+            }
+            if PROFILE && taken {
+                profile.taken[pc] += 1;
+            }
+            true
+        }
+
+        /// see `reg::State::execute`.
+        const HANDLERS: [Handler<'a, V>; OPCODES] = [
+            Self::execute::<0>,  Self::execute::<1>,  Self::execute::<2>,  Self::execute::<3>,
+            Self::execute::<4>,  Self::execute::<5>,  Self::execute::<6>,  Self::execute::<7>,
+            Self::execute::<8>,  Self::execute::<9>,  Self::execute::<10>, Self::execute::<11>,
+            Self::execute::<12>, Self::execute::<13>, Self::execute::<14>, Self::execute::<15>,
+            Self::execute::<16>, Self::execute::<17>, Self::execute::<18>, Self::execute::<19>,
+            Self::execute::<20>, Self::execute::<21>, Self::execute::<22>, Self::execute::<23>,
+            Self::execute::<24>, Self::execute::<25>, Self::execute::<26>, Self::execute::<27>,
+            Self::execute::<28>, Self::execute::<29>, Self::execute::<30>, Self::execute::<31>,
+            Self::execute::<32>, Self::execute::<33>, Self::execute::<34>, Self::execute::<35>,
+            Self::execute::<36>, Self::execute::<37>, Self::execute::<38>,
+        ];
+
+        const TAIL_HANDLERS: [TailHandler<'a, V>; OPCODES] = [
+            Self::tail::<0>,  Self::tail::<1>,  Self::tail::<2>,  Self::tail::<3>,
+            Self::tail::<4>,  Self::tail::<5>,  Self::tail::<6>,  Self::tail::<7>,
+            Self::tail::<8>,  Self::tail::<9>,  Self::tail::<10>, Self::tail::<11>,
+            Self::tail::<12>, Self::tail::<13>, Self::tail::<14>, Self::tail::<15>,
+            Self::tail::<16>, Self::tail::<17>, Self::tail::<18>, Self::tail::<19>,
+            Self::tail::<20>, Self::tail::<21>, Self::tail::<22>, Self::tail::<23>,
+            Self::tail::<24>, Self::tail::<25>, Self::tail::<26>, Self::tail::<27>,
+            Self::tail::<28>, Self::tail::<29>, Self::tail::<30>, Self::tail::<31>,
+            Self::tail::<32>, Self::tail::<33>, Self::tail::<34>, Self::tail::<35>,
+            Self::tail::<36>, Self::tail::<37>, Self::tail::<38>,
+        ];
+
+        /// see `reg::State::execute`.
+        #[inline(always)]
+        fn execute<const OP: u8>(&mut self, instr: Instruction, error: &mut Option<VmError>) -> Option<V> {
+            if instr.opcode() != OP {
+                super::unreachable_arm();
+            }
+
+            use Instruction::*;
+            match instr {
+                Load { src } => {
+                    let value = *self.get(src);
+                    self.push(value);
+                }
+
+                Store { dst } => {
+                    let value = self.pop();
+                    *self.get(dst) = value;
+                }
+
+                LoadInt { value } => {
+                    self.push(V::from_int(value as i64));
+                }
+
+                Add => {
+                    if !self.binary(BinOp::Add) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Sub => {
+                    if !self.binary(BinOp::Sub) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Mul => {
+                    if !self.binary(BinOp::Mul) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Div => {
+                    if !self.binary(BinOp::Div) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Neg => {
+                    if !self.unary(UnOp::Neg) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Lt => {
+                    if !self.binary(BinOp::Lt) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Le => {
+                    if !self.binary(BinOp::Le) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Eq => {
+                    if !self.binary(BinOp::Eq) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Sqrt => {
+                    if !self.unary(UnOp::Sqrt) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Abs => {
+                    if !self.unary(UnOp::Abs) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Floor => {
+                    if !self.unary(UnOp::Floor) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Min => {
+                    if !self.binary(BinOp::Min) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Max => {
+                    if !self.binary(BinOp::Max) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Pop => {
+                    self.pop();
+                }
+
+                Dup => {
+                    let value = *self.get_top(0);
+                    self.push(value);
+                }
+
+                Rot => {
+                    let a = *self.get_top(2);
+                    *self.get_top(2) = *self.get_top(1);
+                    *self.get_top(1) = *self.get_top(0);
+                    *self.get_top(0) = a;
+                }
+
+                Swap => {
+                    let a = *self.get_top(0);
+                    *self.get_top(0) = *self.get_top(1);
+                    *self.get_top(1) = a;
+                }
+
+                Jump { target } => {
+                    self.jump(target);
+                }
+
+                JumpIf { target } => {
+                    if self.pop().is_true() {
+                        self.jump(target);
+                    }
+                }
+
+                JumpIfNot { target } => {
+                    if !self.pop().is_true() {
+                        self.jump(target);
+                    }
+                }
+
+                SetCounter => {
+                    let Some(counter) = self.pop().counter() else {
+                        return Some(self.type_error(error));
+                    };
+                    self.counter = counter;
+                }
+
+                GetCounter => {
+                    self.push(V::from_int(self.counter as i64));
+                }
+
+                Loop { target } => {
+                    if self.counter > 0 {
+                        self.counter -= 1;
+                        self.jump(target);
+                    }
+                }
+
+                LoopLe { target } => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let Some(le) = a.binary(BinOp::Le, b) else {
+                        return Some(self.type_error(error));
+                    };
+                    if le.is_true() && self.counter > 0 {
+                        self.counter -= 1;
+                        self.jump(target);
+                    }
+                }
+
+                Return => {
+                    let result = self.pop();
+                    self.clear();
+                    return Some(result);
+                }
+
+                Nop | Enter { .. } => {}
+
+                Call { target } => {
+                    if !self.call(target as usize) {
+                        *error = Some(VmError::StackOverflow { pc: self.pc() - 1 });
+                        return Some(V::from_f64(0.0));
+                    }
+                }
+
+                Ret => {
+                    let result = self.pop();
+                    let Some(frame) = self.vm.frames.pop() else {
+                        self.clear();
+                        return Some(result);
+                    };
+                    self.ret(frame);
+                    self.push(result);
+                }
+
+                LoadMem => {
+                    let Some(address) = self.get_top(0).address() else {
+                        return Some(self.type_error(error));
+                    };
+                    let Some(&value) = self.vm.memory.get(address) else {
+                        return Some(self.bad_address(error));
+                    };
+                    *self.get_top(0) = value;
+                }
+
+                StoreMem => {
+                    let value = self.pop();
+                    let Some(address) = self.pop().address() else {
+                        return Some(self.type_error(error));
+                    };
+                    let Some(slot) = self.vm.memory.get_mut(address) else {
+                        return Some(self.bad_address(error));
+                    };
+                    *slot = value;
+                }
+
+                LoadConst { .. } | Wide { .. } => {
+                    if !self.wide_in_place::<false>(instr, &mut Profile::new(), error) {
+                        return Some(V::from_f64(0.0));
+                    }
+                }
+
+                MulSlots { slots } => {
+                    let (src1, src2) = unpack_slots(slots);
+                    let Some(value) = self.get(src1).binary(BinOp::Mul, *self.get(src2)) else {
+                        return Some(self.type_error(error));
+                    };
+                    self.push(value);
+                }
+
+                AddInt { value } => {
+                    if !self.add_int(value) {
+                        return Some(self.type_error(error));
+                    }
+                }
+
+                Square => {
+                    if !self.square() {
+                        return Some(self.type_error(error));
+                    }
+                }
+            }
+            None
+        }
+
+        /// see `reg::State::tail`.
+        fn tail<const OP: u8>(&mut self, instr: Instruction, error: &mut Option<VmError>, result: &mut V, chain: u32) -> bool {
+            if let Some(value) = self.execute::<OP>(instr, error) {
+                *result = value;
+                return true;
+            }
+            if chain == 0 {
+                return false;
+            }
+            let next = self.next_instr();
+            Self::TAIL_HANDLERS[next.opcode() as usize](self, next, error, result, chain - 1)
+        }
+
+        #[cold]
+        fn out_of_fuel(&mut self) -> VmError {
+            if super::SPEEEEEED {
+                // the fast path doesn't maintain `vm.stack`'s length.
+                let values = unsafe {
+                    let depth = self.top.offset_from(self.base) as usize;
+                    core::slice::from_raw_parts(self.base, depth).to_vec()
+                };
+                self.vm.stack.clear();
+                self.vm.stack.extend(values);
+            }
+            VmError::OutOfFuel { pc: self.pc(), counter: self.counter }
+        }
+
+        #[inline(always)]
+        fn pc(&self) -> usize {
+            if super::SPEEEEEED {
+                unsafe { self.pcp.offset_from(self.code.as_ptr()) as usize }
+            }
+            else {
+                self.pc
+            }
+        }
+
+        #[inline(always)]
+        fn next_instr(&mut self) -> Instruction {
+            if super::SPEEEEEED {
+                unsafe {
+                    let result = *self.pcp;
+                    self.pcp = self.pcp.add(1);
+                    result
+                }
+            }
+            else {
+                let result = self.code[self.pc];
+                self.pc += 1;
+                result
+            }
+        }
+
+        #[inline(always)]
+        fn jump(&mut self, target: u8) {
+            self.set_pc(target as usize);
+        }
+
+        #[inline(always)]
+        fn set_pc(&mut self, pc: usize) {
+            if super::SPEEEEEED {
+                unsafe {
+                    self.pcp = self.code.as_ptr().add(pc);
+                }
+            }
+            else {
+                self.pc = pc;
+            }
+        }
+
+        #[inline(always)]
+        fn get(&mut self, index: u8) -> &mut V {
+            if super::SPEEEEEED {
+                unsafe {
+                    &mut *self.fpp.add(index as usize)
+                }
+            }
+            else {
+                &mut self.vm.stack[self.fp + index as usize]
+            }
+        }
+
+        #[inline(always)]
+        fn get_top(&mut self, index: u8) -> &mut V {
+            if super::SPEEEEEED {
+                unsafe {
+                    &mut *self.top.sub(1).sub(index as usize)
+                }
+            }
+            else {
+                let len = self.vm.stack.len();
+                &mut self.vm.stack[len - 1 - index as usize]
+            }
+        }
+
+        #[inline(always)]
+        fn push(&mut self, value: V) {
+            if super::SPEEEEEED {
+                unsafe {
+                    *self.top = value;
+                    self.top = self.top.add(1);
+                }
+            }
+            else {
+                self.vm.stack.push(value)
+            }
+        }
+
+        #[inline(always)]
+        fn pop(&mut self) -> V {
+            if super::SPEEEEEED {
+                unsafe {
+                    self.top = self.top.sub(1);
+                    *self.top
+                }
+            }
+            else {
+                self.vm.stack.pop().unwrap()
+            }
+        }
+
+        /// `a op b` with `b` on top, false for type errors.
+        #[inline(always)]
+        fn binary(&mut self, op: BinOp) -> bool {
+            let Some(value) = self.get_top(1).binary(op, *self.get_top(0)) else {
+                return false;
+            };
+            *self.get_top(1) = value;
+            self.pop();
+            true
+        }
+
+        /// for `AddInt`.
+        #[inline(always)]
+        fn add_int(&mut self, value: i8) -> bool {
+            let Some(value) = self.get_top(0).binary(BinOp::Add, V::from_int(value as i64)) else {
+                return false;
+            };
+            *self.get_top(0) = value;
+            true
+        }
+
+        /// for `Square`.
+        #[inline(always)]
+        fn square(&mut self) -> bool {
+            let a = *self.get_top(0);
+            let Some(value) = a.binary(BinOp::Mul, a) else {
+                return false;
+            };
+            *self.get_top(0) = value;
+            true
+        }
+
+        #[inline(always)]
+        fn unary(&mut self, op: UnOp) -> bool {
+            let Some(value) = self.get_top(0).unary(op) else {
+                return false;
+            };
+            *self.get_top(0) = value;
+            true
+        }
+
+        #[inline(always)]
+        fn clear(&mut self) {
+            if super::SPEEEEEED {
+                self.top = self.base;
+            }
+            else {
+                self.vm.stack.clear();
+            }
+        }
+
+        #[inline(always)]
+        fn depth(&self) -> usize {
+            if super::SPEEEEEED {
+                unsafe { self.top.offset_from(self.base) as usize }
+            }
+            else {
+                self.vm.stack.len()
+            }
+        }
+
+        #[inline(always)]
+        fn set_fp(&mut self, fp: usize) {
+            self.fp = fp;
+            self.fpp = unsafe { self.base.add(fp) };
+        }
+
+        #[inline(always)]
+        fn call(&mut self, target: usize) -> bool {
+            let Some(argc) = callee_argc(self.code, target) else {
+                super::unreachable_arm();
+            };
+            let callee = self.depth() - argc as usize;
+            let frame = Frame { pc: self.pc(), fp: self.fp, counter: self.counter };
+            if !self.vm.push_frame(frame, callee) {
+                return false;
+            }
+            self.set_fp(callee);
+            self.counter = 0;
+            self.set_pc(target);
+            true
+        }
+
+        #[inline(always)]
+        fn ret(&mut self, frame: Frame) {
+            self.leave(&frame);
+            self.set_pc(frame.pc);
+        }
+
+        /// drops the callee's slots, and restores the caller's frame
+        /// except for its pc.
+        #[inline(always)]
+        fn leave(&mut self, frame: &Frame) {
+            if super::SPEEEEEED {
+                self.top = self.fpp;
+            }
+            else {
+                self.vm.stack.truncate(self.fp);
+            }
+            self.set_fp(frame.fp);
+            self.counter = frame.counter;
+        }
+    }
+
+
+    pub const FIB_SMART: &[Instruction] = { use Instruction::*; &[
+        SetCounter,
+        LoadInt { value: 0 },
+        LoadInt { value: 1 },
+        Jump { target: 7 },
+        Dup,
+        Rot,
+        Add,
+        Loop { target: 4 },
+        Pop,
+        Return,
+    ]};
+
+    pub const FIB_NAIVE: &[Instruction] = { use Instruction::*; &[
+        SetCounter,
+        LoadInt { value: 0 },
+        LoadInt { value: 1 },
+        Jump { target: 10 },
+        // 4
+        Load { src: 0 },
+        Load { src: 1 },
+        Add,
+        Load { src: 1 },
+        Store { dst: 0 },
+        Store { dst: 1 },
+        // 10
+        Loop { target: 4 },
+        Pop,
+        Return,
+    ]};
+
+
+    /// `fib_recursive`, the argument is each frame's slot 0.
+    pub const FIB_RECURSIVE: &[Instruction] = { use Instruction::*; &[
+        Call { target: 2 },
+        Return,
+
+        // 2
+        Enter { argc: 1 },
+        // only for the branch, it's per call.
+        LoadInt { value: 1 },
+        SetCounter,
+        Load { src: 0 },
+        LoadInt { value: 1 },
+        LoopLe { target: 18 },
+
+        // 8
+        Load { src: 0 },
+        LoadInt { value: 1 },
+        Sub,
+        Call { target: 2 },
+        Load { src: 0 },
+        LoadInt { value: 2 },
+        Sub,
+        Call { target: 2 },
+        Add,
+        Ret,
+
+        // 18
+        Load { src: 0 },
+        Ret,
+    ]};
+
+
+    /// `collatz`, halving is `x/2` when that's an integer.
+    pub const COLLATZ: &[Instruction] = { use Instruction::*; let (n, total, i, x) = (0, 1, 2, 3); &[
+        LoadInt { value: 0 },
+        LoadInt { value: 1 },
+        LoadInt { value: 0 },
+        Jump { target: 36 },
+
+        // 4
+        Load { src: i },
+        Store { dst: x },
+        Jump { target: 28 },
+
+        // 7
+        Load { src: x },
+        LoadInt { value: 2 },
+        Div,
+        Dup,
+        Dup,
+        Floor,
+        Eq,
+        JumpIfNot { target: 17 },
+        Store { dst: x },
+        Jump { target: 24 },
+
+        // 17
+        Pop,
+        Load { src: x },
+        LoadInt { value: 3 },
+        Mul,
+        LoadInt { value: 1 },
+        Add,
+        Store { dst: x },
+
+        // 24
+        Load { src: total },
+        LoadInt { value: 1 },
+        Add,
+        Store { dst: total },
+
+        // 28
+        LoadInt { value: 1 },
+        Load { src: x },
+        Lt,
+        JumpIf { target: 7 },
+        Load { src: i },
+        LoadInt { value: 1 },
+        Add,
+        Store { dst: i },
+
+        // 36
+        Load { src: i },
+        Load { src: n },
+        Le,
+        JumpIf { target: 4 },
+        Load { src: total },
+        Return,
+    ]};
+
+
+    /// see `reg::ARRAY_SUM`.
+    pub const ARRAY_SUM: &[Instruction] = { use Instruction::*; let (n, sum, i) = (0, 1, 2); &[
+        Load { src: n },
+        SetCounter,
+        LoadInt { value: 0 },
+        LoadInt { value: 0 },
+        Jump { target: 14 },
+
+        // 5
+        Load { src: sum },
+        Load { src: i },
+        LoadMem,
+        Add,
+        Store { dst: sum },
+        Load { src: i },
+        LoadInt { value: 1 },
+        Add,
+        Store { dst: i },
+
+        // 14
+        Loop { target: 5 },
+        Load { src: sum },
+        Return,
+    ]};
+
+
+    /// see `reg::DOT`.
+    pub const DOT: &[Instruction] = { use Instruction::*; let (n, sum, i, j) = (0, 1, 2, 3); &[
+        Load { src: n },
+        SetCounter,
+        LoadInt { value: 0 },
+        LoadInt { value: 0 },
+        Load { src: n },
+        Jump { target: 22 },
+
+        // 6
+        Load { src: sum },
+        Load { src: i },
+        LoadMem,
+        Load { src: j },
+        LoadMem,
+        Mul,
+        Add,
+        Store { dst: sum },
+        Load { src: i },
+        LoadInt { value: 1 },
+        Add,
+        Store { dst: i },
+        Load { src: j },
+        LoadInt { value: 1 },
+        Add,
+        Store { dst: j },
+
+        // 22
+        Loop { target: 6 },
+        Load { src: sum },
+        Return,
+    ]};
+
+
+    pub const MANDEL_SMART: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y) = (0, 1, 2, 3, 4); &[
+        Load { src: n },
+        SetCounter,
+
+        LoadInt { value: 0 },
+        LoadInt { value: 0 },
+
+        Jump { target: 21 },
+
+        // 5
+        // let xtemp = x*x - y*y + x0;
+        Load { src: x },
+        Dup,
+        Mul,
+        Load { src: y },
+        Dup,
+        Mul,
+        Sub,
+        Load { src: x0 },
+        Add,
+
+        // 14
+        // stack: x0, y0, n, x, y, xtemp
+        Swap,
+        // stack: x0, y0, n, x, xtemp, y
+        Rot,
+        // stack: x0, y0, n, xtemp, y, x
+
+        // 16
+        // y = x*y*2.0 + y0;
+        Mul,
+        LoadInt { value: 2 },
+        Mul,
+        Load { src: y0 },
+        Add,
+
+        // check x*x + y*y <= 2*2
+        // 21
+        Load { src: x },
+        Dup,
+        Mul,
+        Load { src: y },
+        Dup,
+        Mul,
+        Add,
+        LoadInt { value: 4 },
+        LoopLe { target: 5 },
+
+
+        // 30
+        Load { src: n },
+        GetCounter,
+        Sub,
+        Return,
+    ]};
+
+    pub const MANDEL_NAIVE: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y, xtemp) = (0, 1, 2, 3, 4, 5); &[
+        Load { src: n },
+        SetCounter,
+
+        LoadInt { value: 0 },
+        LoadInt { value: 0 },
+        LoadInt { value: 0 },
+        // stack: x0, y0, n, x, y, xtemp
+
+        Jump { target: 26 },
+
+        // 6
+        // let xtemp = x*x - y*y + x0;
+        Load { src: x },
+        Load { src: x },
+        Mul,
+        Load { src: y },
+        Load { src: y },
+        Mul,
+        Sub,
+        Load { src: x0 },
+        Add,
+        Store { dst: xtemp },
+
+        // 16
+        // y = x*y*2.0 + y0;
+        Load { src: x },
+        Load { src: y },
+        Mul,
+        LoadInt { value: 2 },
+        Mul,
+        Load { src: y0 },
+        Add,
+        Store { dst: y },
+
+        // 24
+        // x = xtemp
+        Load { src: xtemp },
+        Store { dst: x },
+
+        // check x*x + y*y <= 2*2
+        // 26
+        Load { src: x },
+        Load { src: x },
+        Mul,
+        Load { src: y },
+        Load { src: y },
+        Mul,
+        Add,
+        LoadInt { value: 4 },
+        LoopLe { target: 6 },
+
+
+        // 35
+        Load { src: n },
+        GetCounter,
+        Sub,
+        Return,
+    ]};
+
+
+    pub const MANDEL_SMART_NOPS_SLOW: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y) = (0, 1, 2, 3, 4); &[
+        Load { src: n },
+        SetCounter,
+
+        LoadInt { value: 0 },
+        LoadInt { value: 0 },
+
+        Jump { target: 21 + 3 },
+
+        // 5
+        // let xtemp = x*x - y*y + x0;
+        Load { src: x },
+        Dup,
+        Mul,
+        Load { src: y },
+        Nop, Nop, Nop,
+        Dup,
+        Mul,
+        Sub,
+        Load { src: x0 },
+        Add,
+
+        // 14 + 3
+        // stack: x0, y0, n, x, y, xtemp
+        Swap,
+        // stack: x0, y0, n, x, xtemp, y
+        Rot,
+        // stack: x0, y0, n, xtemp, y, x
+
+        // 16 + 3
+        // y = x*y*2.0 + y0;
+        Mul,
+        LoadInt { value: 2 },
+        Mul,
+        Load { src: y0 },
+        Add,
+
+        // check x*x + y*y <= 2*2
+        // 21 + 3
+        Load { src: x },
+        Dup,
+        Mul,
+        Load { src: y },
+        Dup,
+        Mul,
+        Add,
+        LoadInt { value: 4 },
+        LoopLe { target: 5 },
+
+
+        // 30 + 3
+        Load { src: n },
+        GetCounter,
+        Sub,
+        Return,
+    ]};
+
+    pub const MANDEL_SMART_NOPS_SAME: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y) = (0, 1, 2, 3, 4); &[
+        Load { src: n },
+        SetCounter,
+
+        LoadInt { value: 0 },
+        LoadInt { value: 0 },
+
+        Jump { target: 21 + 3 },
+
+        // 5
+        // let xtemp = x*x - y*y + x0;
+        Load { src: x },
+        Dup,
+        Mul,
+        Load { src: y },
+        Dup,
+        Mul,
+        Sub,
+        Load { src: x0 },
+        Add,
 
         // 14
         // stack: x0, y0, n, x, y, xtemp
@@ -734,220 +4993,8466 @@ pub mod stack {
         // stack: x0, y0, n, xtemp, y, x
         Nop, Nop, Nop,
 
-        // 16 + 3
-        // y = x*y*2.0 + y0;
-        Mul,
-        LoadInt { value: 2 },
-        Mul,
-        Load { src: y0 },
-        Add,
+        // 16 + 3
+        // y = x*y*2.0 + y0;
+        Mul,
+        LoadInt { value: 2 },
+        Mul,
+        Load { src: y0 },
+        Add,
+
+        // check x*x + y*y <= 2*2
+        // 21 + 3
+        Load { src: x },
+        Dup,
+        Mul,
+        Load { src: y },
+        Dup,
+        Mul,
+        Add,
+        LoadInt { value: 4 },
+        LoopLe { target: 5 },
+
+
+        // 30 + 3
+        Load { src: n },
+        GetCounter,
+        Sub,
+        Return,
+    ]};
+
+    pub const MANDEL_SMART_NO_DUP: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y) = (0, 1, 2, 3, 4); &[
+        Load { src: n },
+        SetCounter,
+
+        LoadInt { value: 0 },
+        LoadInt { value: 0 },
+
+        Jump { target: 21 },
+
+        // 5
+        // let xtemp = x*x - y*y + x0;
+        Load { src: x },
+        Load { src: x },
+        Mul,
+        Load { src: y },
+        Load { src: y },
+        Mul,
+        Sub,
+        Load { src: x0 },
+        Add,
+
+        // 14
+        // stack: x0, y0, n, x, y, xtemp
+        Swap,
+        // stack: x0, y0, n, x, xtemp, y
+        Rot,
+        // stack: x0, y0, n, xtemp, y, x
+
+        // 16
+        // y = x*y*2.0 + y0;
+        Mul,
+        LoadInt { value: 2 },
+        Mul,
+        Load { src: y0 },
+        Add,
+
+        // check x*x + y*y <= 2*2
+        // 21
+        Load { src: x },
+        Load { src: x },
+        Mul,
+        Load { src: y },
+        Load { src: y },
+        Mul,
+        Add,
+        LoadInt { value: 4 },
+        LoopLe { target: 5 },
+
+
+        // 30
+        Load { src: n },
+        GetCounter,
+        Sub,
+        Return,
+    ]};
+}
+
+
+
+pub mod asm {
+    //! text form of both instruction sets.
+    //!
+    //! ```text
+    //! // fib
+    //! let n, a, b, t = 0, 1, 2, 3
+    //!
+    //!         set_counter n
+    //!         load_int    a, 0
+    //!         load_int    b, 1
+    //!         jump        check
+    //! body:   add         t, a, b
+    //!         copy        a, b
+    //!         copy        b, t
+    //! check:  loop        body
+    //!         return      a
+    //! ```
+    //!
+    //! mnemonics are the instruction names in snake_case.
+    //! registers (and stack slots) are written as `r3` (`s3`), a plain
+    //! number, or a name introduced with `let`.
+    //! jump targets are labels or plain instruction offsets.
+    //!
+    //! operands that don't fit into a byte (or `LoadInt`'s immediate)
+    //! get a `Wide` prefix, labels account for it.
+
+    use core::cell::Cell;
+    use std::collections::HashMap;
+    use super::{reg, stack};
+
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Error {
+        pub line: usize,
+        pub kind: ErrorKind,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum ErrorKind {
+        UnknownMnemonic (String),
+        UndefinedLabel  (String),
+        DuplicateLabel  (String),
+        DuplicateAlias  (String),
+        OperandCount    { mnemonic: String, expected: usize, found: usize },
+        BadOperand      (String),
+        OutOfRange      (String),
+        Syntax          (String),
+    }
+
+    impl core::fmt::Display for Error {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            write!(f, "line {}: ", self.line)?;
+
+            use ErrorKind::*;
+            match &self.kind {
+                UnknownMnemonic(name) => write!(f, "unknown mnemonic `{}`", name),
+                UndefinedLabel(name)  => write!(f, "undefined label `{}`", name),
+                DuplicateLabel(name)  => write!(f, "label `{}` is already defined", name),
+                DuplicateAlias(name)  => write!(f, "alias `{}` is already defined", name),
+                OperandCount { mnemonic, expected, found } =>
+                    write!(f, "`{}` takes {} operand(s), found {}", mnemonic, expected, found),
+                BadOperand(op)        => write!(f, "invalid operand `{}`", op),
+                OutOfRange(op)        => write!(f, "operand `{}` is out of range", op),
+                Syntax(msg)           => write!(f, "{}", msg),
+            }
+        }
+    }
+
+    impl std::error::Error for Error {}
+
+
+    pub fn assemble_reg(source: &str) -> Result<Vec<reg::Instruction>, Error> {
+        assemble(source, 'r', parse_reg, |high| reg::Instruction::Wide { high })
+    }
+
+    pub fn assemble_stack(source: &str) -> Result<Vec<stack::Instruction>, Error> {
+        // stack instructions have one operand `Wide` can extend.
+        assemble(source, 's', parse_stack, |high| stack::Instruction::Wide { high: high[0] })
+    }
+
+
+    struct Line<'a> {
+        line:     usize,
+        mnemonic: &'a str,
+        operands: Vec<&'a str>,
+    }
+
+    struct Context<'a> {
+        /// the line a label is on.
+        labels:  HashMap<&'a str, usize>,
+        aliases: HashMap<&'a str, u16>,
+        prefix:  char,
+        /// where each line starts, one more for the end.
+        pcs:     Vec<usize>,
+    }
+
+    struct Operands<'a, 'c> {
+        ctx:      &'c Context<'a>,
+        mnemonic: &'a str,
+        items:    &'c [&'a str],
+        /// set by operands that need a `Wide` prefix.
+        wide:     Cell<bool>,
+        high:     Cell<[u8; 3]>,
+    }
+
+    fn assemble<I>(source: &str, prefix: char, parse: fn(&Operands) -> Result<I, ErrorKind>, wide: fn([u8; 3]) -> I) -> Result<Vec<I>, Error> {
+        let mut ctx = Context {
+            labels:  HashMap::new(),
+            aliases: HashMap::new(),
+            prefix,
+            pcs:     vec![],
+        };
+
+        // pass 1: labels & aliases.
+        let mut lines = vec![];
+        for (i, text) in source.lines().enumerate() {
+            let line = i + 1;
+            let err = |kind| Error { line, kind };
+
+            let mut text = match text.find("//") {
+                Some(end) => &text[..end],
+                None      => text,
+            }.trim();
+
+            if let Some(rest) = text.strip_prefix("let ") {
+                parse_let(&mut ctx, rest).map_err(err)?;
+                continue;
+            }
+
+            while let Some(colon) = text.find(':') {
+                let name = text[..colon].trim();
+                if !is_ident(name) {
+                    return Err(err(ErrorKind::Syntax(format!("invalid label `{}`", name))));
+                }
+                if ctx.labels.insert(name, lines.len()).is_some() {
+                    return Err(err(ErrorKind::DuplicateLabel(name.into())));
+                }
+                text = text[colon+1..].trim();
+            }
+
+            if text.is_empty() {
+                continue;
+            }
+
+            let (mnemonic, rest) = match text.find(char::is_whitespace) {
+                Some(end) => (&text[..end], text[end..].trim()),
+                None      => (text, ""),
+            };
+
+            let operands =
+                if rest.is_empty() { vec![] }
+                else { rest.split(',').map(str::trim).collect() };
+
+            lines.push(Line { line, mnemonic, operands });
+        }
+
+        // pass 2: instructions. a prefix moves the labels after it,
+        // which may make more prefixes necessary, so this repeats until
+        // nothing changes. lines never lose their prefix, so it ends.
+        let mut is_wide = vec![false; lines.len()];
+        loop {
+            ctx.pcs.clear();
+            let mut pc = 0;
+            for is_wide in &is_wide {
+                ctx.pcs.push(pc);
+                pc += 1 + *is_wide as usize;
+            }
+            ctx.pcs.push(pc);
+
+            let mut changed = false;
+            let mut result = Vec::with_capacity(pc);
+            for (line, is_wide) in lines.iter().zip(&mut is_wide) {
+                let ops = Operands {
+                    ctx: &ctx, mnemonic: line.mnemonic, items: &line.operands,
+                    wide: Cell::new(false), high: Cell::new([0; 3]),
+                };
+                let instr = parse(&ops).map_err(|kind| Error { line: line.line, kind })?;
+                if ops.wide.get() && !*is_wide {
+                    *is_wide = true;
+                    changed = true;
+                }
+                if *is_wide {
+                    result.push(wide(ops.high.get()));
+                }
+                result.push(instr);
+            }
+            if !changed {
+                return Ok(result);
+            }
+        }
+    }
+
+    // let a, b, c = 0, 1, 2
+    fn parse_let<'a>(ctx: &mut Context<'a>, text: &'a str) -> Result<(), ErrorKind> {
+        let Some((names, values)) = text.split_once('=') else {
+            return Err(ErrorKind::Syntax("expected `=` in `let`".into()));
+        };
+
+        let names:  Vec<&str> = names.split(',').map(str::trim).collect();
+        let values: Vec<&str> = values.split(',').map(str::trim).collect();
+        if names.len() != values.len() {
+            return Err(ErrorKind::Syntax(format!(
+                "`let` binds {} name(s) to {} value(s)", names.len(), values.len())));
+        }
+
+        for (name, value) in names.into_iter().zip(values) {
+            if !is_ident(name) || !matches!(parse_index(ctx.prefix, name), Err(IntError::Bad)) {
+                return Err(ErrorKind::Syntax(format!("invalid alias name `{}`", name)));
+            }
+            let index = ctx.index(value)?;
+            if ctx.aliases.insert(name, index).is_some() {
+                return Err(ErrorKind::DuplicateAlias(name.into()));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_ident(s: &str) -> bool {
+        let mut chars = s.chars();
+        matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    /// why an operand isn't an `i64`.
+    enum IntError {
+        /// not a number at all.
+        Bad,
+        /// a number, but too large for any operand.
+        Overflow,
+    }
+
+    impl IntError {
+        fn kind(self, op: &str) -> ErrorKind {
+            match self {
+                IntError::Bad      => ErrorKind::BadOperand(op.into()),
+                IntError::Overflow => ErrorKind::OutOfRange(op.into()),
+            }
+        }
+    }
+
+    fn parse_int(s: &str) -> Result<i64, IntError> {
+        let (neg, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None       => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(IntError::Bad);
+        }
+        let value: i64 = digits.parse().map_err(|_| IntError::Overflow)?;
+        Ok(if neg { -value } else { value })
+    }
+
+    // `r3` / `s3` / `3`
+    fn parse_index(prefix: char, s: &str) -> Result<i64, IntError> {
+        let digits = s.strip_prefix(prefix).unwrap_or(s);
+        if digits.starts_with('-') { return Err(IntError::Bad) }
+        parse_int(digits)
+    }
+
+    impl<'a> Context<'a> {
+        fn index(&self, op: &str) -> Result<u16, ErrorKind> {
+            if let Some(index) = self.aliases.get(op) {
+                return Ok(*index);
+            }
+            let value = parse_index(self.prefix, op).map_err(|e| e.kind(op))?;
+            u16::try_from(value).map_err(|_| ErrorKind::OutOfRange(op.into()))
+        }
+    }
+
+    impl<'a, 'c> Operands<'a, 'c> {
+        fn count(&self, expected: usize) -> Result<(), ErrorKind> {
+            if self.items.len() != expected {
+                return Err(ErrorKind::OperandCount {
+                    mnemonic: self.mnemonic.into(),
+                    expected,
+                    found: self.items.len(),
+                });
+            }
+            Ok(())
+        }
+
+        /// the low byte of operand `i`, the high byte goes to the prefix.
+        fn wide(&self, i: usize, value: u16) -> u8 {
+            let [low, high] = value.to_le_bytes();
+            if high != 0 {
+                let mut highs = self.high.get();
+                highs[i] = high;
+                self.high.set(highs);
+                self.wide.set(true);
+            }
+            low
+        }
+
+        fn index(&self, i: usize) -> Result<u8, ErrorKind> {
+            Ok(self.wide(i, self.ctx.index(self.items[i])?))
+        }
+
+        /// stack slots, which can't be extended.
+        fn slot(&self, i: usize) -> Result<u8, ErrorKind> {
+            let index = self.ctx.index(self.items[i])?;
+            u8::try_from(index).map_err(|_| ErrorKind::OutOfRange(self.items[i].into()))
+        }
+
+        /// `MulSlots`'s two slots, see `stack::pack_slots`.
+        fn slot_pair(&self) -> Result<u8, ErrorKind> {
+            let (src1, src2) = (self.slot(0)?, self.slot(1)?);
+            stack::pack_slots(src1, src2).ok_or_else(|| {
+                ErrorKind::OutOfRange(self.items[if src1 < 16 { 1 } else { 0 }].into())
+            })
+        }
+
+        fn target(&self, i: usize) -> Result<u8, ErrorKind> {
+            let op = self.items[i];
+            let target =
+                if is_ident(op) {
+                    let line = *self.ctx.labels.get(op).ok_or_else(|| ErrorKind::UndefinedLabel(op.into()))?;
+                    self.ctx.pcs[line]
+                }
+                else {
+                    let value = parse_int(op).map_err(|e| e.kind(op))?;
+                    usize::try_from(value).map_err(|_| ErrorKind::OutOfRange(op.into()))?
+                };
+            let target = u16::try_from(target).map_err(|_| ErrorKind::OutOfRange(op.into()))?;
+            Ok(self.wide(i, target))
+        }
+
+        /// an index into the constant pool.
+        fn constant(&self, i: usize) -> Result<u8, ErrorKind> {
+            Ok(self.wide(i, self.int(i)?))
+        }
+
+        /// the stack's `LoadInt`, which sign extends without a prefix.
+        fn wide_int(&self, i: usize) -> Result<i8, ErrorKind> {
+            let value: i16 = self.int(i)?;
+            if let Ok(value) = i8::try_from(value) {
+                return Ok(value);
+            }
+            let low = self.wide(i, value as u16);
+            self.wide.set(true);
+            Ok(low as i8)
+        }
+
+        fn int<T: TryFrom<i64>>(&self, i: usize) -> Result<T, ErrorKind> {
+            let op = self.items[i];
+            let value = parse_int(op).map_err(|e| e.kind(op))?;
+            T::try_from(value).map_err(|_| ErrorKind::OutOfRange(op.into()))
+        }
+    }
+
+
+    fn parse_reg(ops: &Operands) -> Result<reg::Instruction, ErrorKind> {
+        use reg::Instruction::*;
+        Ok(match ops.mnemonic {
+            "load_int"    => { ops.count(2)?; LoadInt    { dst: ops.index(0)?, value: ops.int(1)? } }
+            "copy"        => { ops.count(2)?; Copy       { dst: ops.index(0)?, src: ops.index(1)? } }
+            "add"         => { ops.count(3)?; Add        { dst: ops.index(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "sub"         => { ops.count(3)?; Sub        { dst: ops.index(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "mul"         => { ops.count(3)?; Mul        { dst: ops.index(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "div"         => { ops.count(3)?; Div        { dst: ops.index(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "neg"         => { ops.count(2)?; Neg        { dst: ops.index(0)?, src: ops.index(1)? } }
+            "lt"          => { ops.count(3)?; Lt         { dst: ops.index(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "le"          => { ops.count(3)?; Le         { dst: ops.index(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "eq"          => { ops.count(3)?; Eq         { dst: ops.index(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "sqrt"        => { ops.count(2)?; Sqrt       { dst: ops.index(0)?, src: ops.index(1)? } }
+            "abs"         => { ops.count(2)?; Abs        { dst: ops.index(0)?, src: ops.index(1)? } }
+            "floor"       => { ops.count(2)?; Floor      { dst: ops.index(0)?, src: ops.index(1)? } }
+            "min"         => { ops.count(3)?; Min        { dst: ops.index(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "max"         => { ops.count(3)?; Max        { dst: ops.index(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "jump"        => { ops.count(1)?; Jump       { target: ops.target(0)? } }
+            "jump_if"     => { ops.count(2)?; JumpIf     { target: ops.target(0)?, src: ops.index(1)? } }
+            "jump_if_not" => { ops.count(2)?; JumpIfNot  { target: ops.target(0)?, src: ops.index(1)? } }
+            "set_counter" => { ops.count(1)?; SetCounter { src: ops.index(0)? } }
+            "get_counter" => { ops.count(1)?; GetCounter { dst: ops.index(0)? } }
+            "loop"        => { ops.count(1)?; Loop       { target: ops.target(0)? } }
+            "loop_le"     => { ops.count(3)?; LoopLe     { target: ops.target(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "return"      => { ops.count(1)?; Return     { src: ops.index(0)? } }
+            "call"        => { ops.count(2)?; Call       { target: ops.target(0)?, base: ops.index(1)? } }
+            "ret"         => { ops.count(1)?; Ret        { src: ops.index(0)? } }
+            "load_mem"    => { ops.count(2)?; LoadMem    { dst: ops.index(0)?, addr: ops.index(1)? } }
+            "store_mem"   => { ops.count(2)?; StoreMem   { src: ops.index(0)?, addr: ops.index(1)? } }
+            "load_const"  => { ops.count(2)?; LoadConst  { dst: ops.index(0)?, index: ops.constant(1)? } }
+            "wide"        => { ops.count(3)?; Wide       { high: [ops.int(0)?, ops.int(1)?, ops.int(2)?] } }
+            "mul_add"     => { ops.count(3)?; MulAdd     { dst: ops.index(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "mul_sub"     => { ops.count(3)?; MulSub     { dst: ops.index(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "jump_if_lt"  => { ops.count(3)?; JumpIfLt   { target: ops.target(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            "jump_if_le"  => { ops.count(3)?; JumpIfLe   { target: ops.target(0)?, src1: ops.index(1)?, src2: ops.index(2)? } }
+            _ => return Err(ErrorKind::UnknownMnemonic(ops.mnemonic.into())),
+        })
+    }
+
+    fn parse_stack(ops: &Operands) -> Result<stack::Instruction, ErrorKind> {
+        use stack::Instruction::*;
+        Ok(match ops.mnemonic {
+            "load"        => { ops.count(1)?; Load      { src: ops.slot(0)? } }
+            "store"       => { ops.count(1)?; Store     { dst: ops.slot(0)? } }
+            "load_int"    => { ops.count(1)?; LoadInt   { value: ops.wide_int(0)? } }
+            "add"         => { ops.count(0)?; Add }
+            "sub"         => { ops.count(0)?; Sub }
+            "mul"         => { ops.count(0)?; Mul }
+            "div"         => { ops.count(0)?; Div }
+            "neg"         => { ops.count(0)?; Neg }
+            "lt"          => { ops.count(0)?; Lt }
+            "le"          => { ops.count(0)?; Le }
+            "eq"          => { ops.count(0)?; Eq }
+            "sqrt"        => { ops.count(0)?; Sqrt }
+            "abs"         => { ops.count(0)?; Abs }
+            "floor"       => { ops.count(0)?; Floor }
+            "min"         => { ops.count(0)?; Min }
+            "max"         => { ops.count(0)?; Max }
+            "pop"         => { ops.count(0)?; Pop }
+            "dup"         => { ops.count(0)?; Dup }
+            "rot"         => { ops.count(0)?; Rot }
+            "swap"        => { ops.count(0)?; Swap }
+            "jump"        => { ops.count(1)?; Jump      { target: ops.target(0)? } }
+            "jump_if"     => { ops.count(1)?; JumpIf    { target: ops.target(0)? } }
+            "jump_if_not" => { ops.count(1)?; JumpIfNot { target: ops.target(0)? } }
+            "set_counter" => { ops.count(0)?; SetCounter }
+            "get_counter" => { ops.count(0)?; GetCounter }
+            "loop"        => { ops.count(1)?; Loop      { target: ops.target(0)? } }
+            "loop_le"     => { ops.count(1)?; LoopLe    { target: ops.target(0)? } }
+            "return"      => { ops.count(0)?; Return }
+            "nop"         => { ops.count(0)?; Nop }
+            "call"        => { ops.count(1)?; Call      { target: ops.target(0)? } }
+            "enter"       => { ops.count(1)?; Enter     { argc: ops.int(0)? } }
+            "ret"         => { ops.count(0)?; Ret }
+            "load_mem"    => { ops.count(0)?; LoadMem }
+            "store_mem"   => { ops.count(0)?; StoreMem }
+            "load_const"  => { ops.count(1)?; LoadConst { index: ops.constant(0)? } }
+            "wide"        => { ops.count(1)?; Wide      { high: ops.int(0)? } }
+            "mul_slots"   => { ops.count(2)?; MulSlots  { slots: ops.slot_pair()? } }
+            "add_int"     => { ops.count(1)?; AddInt    { value: ops.int(0)? } }
+            "square"      => { ops.count(0)?; Square }
+            _ => return Err(ErrorKind::UnknownMnemonic(ops.mnemonic.into())),
+        })
+    }
+}
+
+
+
+pub mod disasm {
+    //! listings in the syntax `asm` reads, with pc offsets
+    //! (and stack depths) in trailing comments. a `Wide` goes on the
+    //! line of the instruction it extends, where `asm` puts it back.
+
+    use core::fmt::Write;
+    use super::{reg, stack};
+
+
+    pub fn disassemble_reg(code: &[reg::Instruction]) -> String {
+        let labels = Labels::reg(code);
+
+        let mut out = String::new();
+        let mut pc = 0;
+        while pc < code.len() {
+            // only prefixes `asm` would emit, so the listing reads back
+            // into the same code.
+            let (mnemonic, operands, len) = match reg::decode(code, pc) {
+                Some((instr, high, 2)) if high != [0; 3] && !labels.is_target[pc + 1] => {
+                    let (mnemonic, operands) = format_reg_wide(&instr, high, &labels);
+                    (mnemonic, operands, 2)
+                }
+                _ => {
+                    let (mnemonic, operands) = format_reg(&code[pc], &labels);
+                    (mnemonic, operands, 1)
+                }
+            };
+            labels.line(&mut out, pc, mnemonic, &operands, &format!("{:3}", pc));
+            pc += len;
+        }
+        labels.end(&mut out);
+        out
+    }
+
+    pub fn disassemble_stack(code: &[stack::Instruction], argc: usize) -> String {
+        let labels = Labels::stack(code);
+        let depths = stack::stack_depths(code, argc);
+
+        let mut out = String::new();
+        let mut pc = 0;
+        while pc < code.len() {
+            let (mnemonic, operands, len) = match stack::decode(code, pc) {
+                Some((instr, high, 2)) if needs_wide(&instr, high) && !labels.is_target[pc + 1] => {
+                    let (mnemonic, operands) = format_stack_wide(&instr, Some(high), &labels);
+                    (mnemonic, operands, 2)
+                }
+                _ => {
+                    let (mnemonic, operands) = format_stack(&code[pc], &labels);
+                    (mnemonic, operands, 1)
+                }
+            };
+            let depth = match depths[pc] {
+                Some(depth) => format!("[{}]", depth),
+                None        => "[?]".into(),
+            };
+            labels.line(&mut out, pc, mnemonic, &operands, &format!("{:3}  {:>5}", pc, depth));
+            pc += len;
+        }
+        labels.end(&mut out);
+        out
+    }
+
+    /// whether the operand doesn't fit into the instruction without the prefix.
+    fn needs_wide(instr: &stack::Instruction, high: u8) -> bool {
+        match *instr {
+            stack::Instruction::LoadInt { value } => i8::try_from(stack::wide_int(value, high, 2)).is_err(),
+            _ => high != 0,
+        }
+    }
+
+
+    pub fn format_reg(instr: &reg::Instruction, labels: &Labels) -> (&'static str, String) {
+        format_reg_wide(instr, [0; 3], labels)
+    }
+
+    /// with the high bytes of a `Wide` prefix, see `reg::decode`.
+    pub fn format_reg_wide(instr: &reg::Instruction, [a, b, c]: [u8; 3], labels: &Labels) -> (&'static str, String) {
+        use reg::Instruction::*;
+        use reg::wide as w;
+        match *instr {
+            LoadInt    { dst, value }         => ("load_int",    format!("r{}, {}", w(dst, a), value)),
+            Copy       { dst, src }           => ("copy",        format!("r{}, r{}", w(dst, a), w(src, b))),
+            Add        { dst, src1, src2 }    => ("add",         format!("r{}, r{}, r{}", w(dst, a), w(src1, b), w(src2, c))),
+            Sub        { dst, src1, src2 }    => ("sub",         format!("r{}, r{}, r{}", w(dst, a), w(src1, b), w(src2, c))),
+            Mul        { dst, src1, src2 }    => ("mul",         format!("r{}, r{}, r{}", w(dst, a), w(src1, b), w(src2, c))),
+            Div        { dst, src1, src2 }    => ("div",         format!("r{}, r{}, r{}", w(dst, a), w(src1, b), w(src2, c))),
+            Neg        { dst, src }           => ("neg",         format!("r{}, r{}", w(dst, a), w(src, b))),
+            Lt         { dst, src1, src2 }    => ("lt",          format!("r{}, r{}, r{}", w(dst, a), w(src1, b), w(src2, c))),
+            Le         { dst, src1, src2 }    => ("le",          format!("r{}, r{}, r{}", w(dst, a), w(src1, b), w(src2, c))),
+            Eq         { dst, src1, src2 }    => ("eq",          format!("r{}, r{}, r{}", w(dst, a), w(src1, b), w(src2, c))),
+            Sqrt       { dst, src }           => ("sqrt",        format!("r{}, r{}", w(dst, a), w(src, b))),
+            Abs        { dst, src }           => ("abs",         format!("r{}, r{}", w(dst, a), w(src, b))),
+            Floor      { dst, src }           => ("floor",       format!("r{}, r{}", w(dst, a), w(src, b))),
+            Min        { dst, src1, src2 }    => ("min",         format!("r{}, r{}, r{}", w(dst, a), w(src1, b), w(src2, c))),
+            Max        { dst, src1, src2 }    => ("max",         format!("r{}, r{}, r{}", w(dst, a), w(src1, b), w(src2, c))),
+            Jump       { target }             => ("jump",        labels.name(w(target, a))),
+            JumpIf     { target, src }        => ("jump_if",     format!("{}, r{}", labels.name(w(target, a)), w(src, b))),
+            JumpIfNot  { target, src }        => ("jump_if_not", format!("{}, r{}", labels.name(w(target, a)), w(src, b))),
+            SetCounter { src }                => ("set_counter", format!("r{}", w(src, a))),
+            GetCounter { dst }                => ("get_counter", format!("r{}", w(dst, a))),
+            Loop       { target }             => ("loop",        labels.name(w(target, a))),
+            LoopLe     { target, src1, src2 } => ("loop_le",     format!("{}, r{}, r{}", labels.name(w(target, a)), w(src1, b), w(src2, c))),
+            Return     { src }                => ("return",      format!("r{}", w(src, a))),
+            Call       { target, base }       => ("call",        format!("{}, r{}", labels.name(w(target, a)), w(base, b))),
+            Ret        { src }                => ("ret",         format!("r{}", w(src, a))),
+            LoadMem    { dst, addr }          => ("load_mem",    format!("r{}, r{}", w(dst, a), w(addr, b))),
+            StoreMem   { src, addr }          => ("store_mem",   format!("r{}, r{}", w(src, a), w(addr, b))),
+            LoadConst  { dst, index }         => ("load_const",  format!("r{}, {}", w(dst, a), w(index, b))),
+            Wide       { high }               => ("wide",        format!("{}, {}, {}", high[0], high[1], high[2])),
+            MulAdd     { dst, src1, src2 }    => ("mul_add",     format!("r{}, r{}, r{}", w(dst, a), w(src1, b), w(src2, c))),
+            MulSub     { dst, src1, src2 }    => ("mul_sub",     format!("r{}, r{}, r{}", w(dst, a), w(src1, b), w(src2, c))),
+            JumpIfLt   { target, src1, src2 } => ("jump_if_lt",  format!("{}, r{}, r{}", labels.name(w(target, a)), w(src1, b), w(src2, c))),
+            JumpIfLe   { target, src1, src2 } => ("jump_if_le",  format!("{}, r{}, r{}", labels.name(w(target, a)), w(src1, b), w(src2, c))),
+        }
+    }
+
+    pub fn format_stack(instr: &stack::Instruction, labels: &Labels) -> (&'static str, String) {
+        format_stack_wide(instr, None, labels)
+    }
+
+    /// with the high byte of a `Wide` prefix, see `stack::decode`.
+    pub fn format_stack_wide(instr: &stack::Instruction, high: Option<u8>, labels: &Labels) -> (&'static str, String) {
+        use stack::Instruction::*;
+        let w = |low| reg::wide(low, high.unwrap_or(0));
+        match *instr {
+            Load       { src }    => ("load",        format!("s{}", src)),
+            Store      { dst }    => ("store",       format!("s{}", dst)),
+            LoadInt    { value }  => ("load_int",    match high {
+                Some(high) => format!("{}", stack::wide_int(value, high, 2)),
+                None       => format!("{}", value),
+            }),
+            Add                   => ("add",         String::new()),
+            Sub                   => ("sub",         String::new()),
+            Mul                   => ("mul",         String::new()),
+            Div                   => ("div",         String::new()),
+            Neg                   => ("neg",         String::new()),
+            Lt                    => ("lt",          String::new()),
+            Le                    => ("le",          String::new()),
+            Eq                    => ("eq",          String::new()),
+            Sqrt                  => ("sqrt",        String::new()),
+            Abs                   => ("abs",         String::new()),
+            Floor                 => ("floor",       String::new()),
+            Min                   => ("min",         String::new()),
+            Max                   => ("max",         String::new()),
+            Pop                   => ("pop",         String::new()),
+            Dup                   => ("dup",         String::new()),
+            Rot                   => ("rot",         String::new()),
+            Swap                  => ("swap",        String::new()),
+            Jump       { target } => ("jump",        labels.name(w(target))),
+            JumpIf     { target } => ("jump_if",     labels.name(w(target))),
+            JumpIfNot  { target } => ("jump_if_not", labels.name(w(target))),
+            SetCounter            => ("set_counter", String::new()),
+            GetCounter            => ("get_counter", String::new()),
+            Loop       { target } => ("loop",        labels.name(w(target))),
+            LoopLe     { target } => ("loop_le",     labels.name(w(target))),
+            Return                => ("return",      String::new()),
+            Nop                   => ("nop",         String::new()),
+            Call       { target } => ("call",        labels.name(w(target))),
+            Enter      { argc }   => ("enter",       argc.to_string()),
+            Ret                   => ("ret",         String::new()),
+            LoadMem               => ("load_mem",    String::new()),
+            StoreMem              => ("store_mem",   String::new()),
+            LoadConst  { index }  => ("load_const",  format!("{}", w(index))),
+            Wide       { high }   => ("wide",        format!("{}", high)),
+            MulSlots { slots } => {
+                let (src1, src2) = stack::unpack_slots(slots);
+                ("mul_slots", format!("s{}, s{}", src1, src2))
+            }
+            AddInt     { value }  => ("add_int",     format!("{}", value)),
+            Square                => ("square",      String::new()),
+        }
+    }
+
+
+    /// synthesized `L<pc>` labels for every jump target.
+    pub struct Labels {
+        is_target: Vec<bool>,
+    }
+
+    impl Labels {
+        pub fn new(len: usize, targets: impl Iterator<Item = usize>) -> Self {
+            let mut is_target = vec![false; len + 1];
+            for target in targets {
+                if let Some(t) = is_target.get_mut(target) {
+                    *t = true;
+                }
+            }
+            Labels { is_target }
+        }
+
+        /// with the targets of `Wide` prefixed jumps.
+        pub fn reg(code: &[reg::Instruction]) -> Self {
+            let mut targets = vec![];
+            let mut pc = 0;
+            while pc < code.len() {
+                let (instr, high, len) = reg::decode(code, pc).unwrap_or((code[pc], [0; 3], 1));
+                // targets are always the first operand.
+                targets.extend(instr.target().map(|target| reg::wide(target, high[0])));
+                pc += len;
+            }
+            Labels::new(code.len(), targets.into_iter())
+        }
+
+        pub fn stack(code: &[stack::Instruction]) -> Self {
+            let mut targets = vec![];
+            let mut pc = 0;
+            while pc < code.len() {
+                let (instr, high, len) = stack::decode(code, pc).unwrap_or((code[pc], 0, 1));
+                targets.extend(instr.target().map(|target| reg::wide(target, high)));
+                pc += len;
+            }
+            Labels::new(code.len(), targets.into_iter())
+        }
+
+        pub fn is_target(&self, pc: usize) -> bool {
+            self.is_target.get(pc).copied().unwrap_or(false)
+        }
+
+        pub fn name(&self, target: usize) -> String {
+            if self.is_target(target) {
+                format!("L{}", target)
+            }
+            else {
+                // past the end, there's nothing to put a label on.
+                format!("{}", target)
+            }
+        }
+
+        fn line(&self, out: &mut String, pc: usize, mnemonic: &str, operands: &str, comment: &str) {
+            let label =
+                if self.is_target[pc] { format!("L{}:", pc) }
+                else                  { String::new() };
+            let text = format!("{:<8}{:<12}{}", label, mnemonic, operands);
+            writeln!(out, "{:<40}// {}", text, comment).unwrap();
+        }
+
+        fn end(&self, out: &mut String) {
+            let len = self.is_target.len() - 1;
+            if self.is_target[len] {
+                writeln!(out, "L{}:", len).unwrap();
+            }
+        }
+    }
+}
+
+
+
+pub mod translate {
+    //! translation between the two instruction sets.
+
+    use super::{reg, stack, VerifyError};
+
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum TranslateError {
+        Verify(VerifyError),
+        /// a jump target doesn't fit into a `u8` anymore.
+        TooLong { len: usize },
+        TooManyRegisters,
+        /// the instruction at `pc` has no equivalent in the other set.
+        Unsupported { pc: usize },
+    }
+
+    impl From<VerifyError> for TranslateError {
+        fn from(e: VerifyError) -> Self { TranslateError::Verify(e) }
+    }
+
+
+    /// translates a stack program to an equivalent register program.
+    ///
+    /// stack slot `i` becomes register `i`, so the arguments end up where
+    /// `reg::Vm` expects them. the operand stack is interpreted abstractly:
+    /// `Load`, `Dup` & co. only record where a value lives, and copies are
+    /// emitted when a value has to be moved into its slot's register.
+    ///
+    /// a call's arguments are its callee's first slots, so they become
+    /// the start of the callee's register window.
+    pub fn stack_to_reg(code: &[stack::Instruction], argc: usize) -> Result<Vec<reg::Instruction>, TranslateError> {
+        let program = stack::verify(code, argc)?;
+        if let Some(pc) = code.iter().position(|instr| matches!(instr, stack::Instruction::Wide { .. })) {
+            return Err(TranslateError::Unsupported { pc });
+        }
+
+        // unreachable code can have any target.
+        let mut is_target = vec![false; code.len()];
+        for instr in code {
+            if let Some(t) = instr.target().and_then(|target| is_target.get_mut(target as usize)) {
+                *t = true;
+            }
+        }
+
+        let mut t = StackToReg { code, out: vec![], stack: vec![], fixups: vec![] };
+        let mut map = vec![0; code.len()];
+        // whether the previous instruction continues into this one.
+        let mut falls_through = false;
+        for (pc, instr) in code.iter().enumerate() {
+            let Some(depth) = program.depth(pc) else {
+                map[pc] = t.out.len();
+                falls_through = false;
+                continue;
+            };
+
+            if is_target[pc] || !falls_through {
+                if falls_through {
+                    t.flush(0)?;
+                }
+                t.stack.clear();
+                t.stack.resize(depth, Value::Home);
+            }
+            map[pc] = t.out.len();
+            falls_through = instr.falls_through();
+
+            t.instr(*instr)?;
+        }
+
+        for (at, target) in t.fixups {
+            // only reachable code has fixups, and `verify` has checked those.
+            let target = map.get(target as usize).copied().unwrap_or(0);
+            let target = u8::try_from(target)
+                .map_err(|_| TranslateError::TooLong { len: t.out.len() })?;
+
+            use reg::Instruction::*;
+            match &mut t.out[at] {
+                Jump { target: t } | JumpIf { target: t, .. } | JumpIfNot { target: t, .. } |
+                Loop { target: t } | LoopLe { target: t, .. } |
+                Call { target: t, .. } => *t = target,
+                _ => unreachable!(),
+            }
+        }
+        Ok(t.out)
+    }
+
+    /// where the value of a stack slot is.
+    /// invariant: `Reg(r)` at position `p` has `r < p`.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Value {
+        /// in the slot's own register.
+        Home,
+        Reg(u8),
+        Int(i8),
+    }
+
+    struct StackToReg<'a> {
+        code:   &'a [stack::Instruction],
+        out:    Vec<reg::Instruction>,
+        stack:  Vec<Value>,
+        fixups: Vec<(usize, u8)>,
+    }
+
+    impl StackToReg<'_> {
+        fn instr(&mut self, instr: stack::Instruction) -> Result<(), TranslateError> {
+            use stack::Instruction::*;
+            let d = self.stack.len();
+            match instr {
+                Load { src } => {
+                    let value = self.source(src as usize);
+                    self.stack.push(value);
+                }
+
+                Store { dst } => {
+                    let dst = dst as usize;
+                    match self.source(d - 1) {
+                        Value::Int(v) => self.stack[dst] = Value::Int(v),
+                        Value::Reg(r) if (r as usize) < dst => self.stack[dst] = Value::Reg(r),
+                        Value::Reg(r) if (r as usize) == dst => self.stack[dst] = Value::Home,
+                        _ => {
+                            // may move the top value, so look it up again.
+                            self.prepare_write(dst, d)?;
+                            let src = self.loc(d - 1);
+                            self.out.push(reg::Instruction::Copy { dst: reg(dst)?, src });
+                            self.stack[dst] = Value::Home;
+                        }
+                    }
+                    self.stack.pop();
+                }
+
+                LoadInt { value } => {
+                    self.stack.push(Value::Int(value));
+                }
+
+                Add | Sub | Mul | Div | Lt | Le | Eq | Min | Max => {
+                    self.materialize_int(d - 2)?;
+                    self.materialize_int(d - 1)?;
+                    let dst  = reg(d - 2)?;
+                    let src1 = self.loc(d - 2);
+                    let src2 = self.loc(d - 1);
+                    self.out.push(match instr {
+                        Add => reg::Instruction::Add { dst, src1, src2 },
+                        Sub => reg::Instruction::Sub { dst, src1, src2 },
+                        Mul => reg::Instruction::Mul { dst, src1, src2 },
+                        Div => reg::Instruction::Div { dst, src1, src2 },
+                        Lt  => reg::Instruction::Lt  { dst, src1, src2 },
+                        Le  => reg::Instruction::Le  { dst, src1, src2 },
+                        Eq  => reg::Instruction::Eq  { dst, src1, src2 },
+                        Min => reg::Instruction::Min { dst, src1, src2 },
+                        Max => reg::Instruction::Max { dst, src1, src2 },
+                        _ => unreachable!(),
+                    });
+                    self.stack.pop();
+                    self.stack[d - 2] = Value::Home;
+                }
+
+                Neg | Sqrt | Abs | Floor => {
+                    self.materialize_int(d - 1)?;
+                    let dst = reg(d - 1)?;
+                    let src = self.loc(d - 1);
+                    self.out.push(match instr {
+                        Neg   => reg::Instruction::Neg   { dst, src },
+                        Sqrt  => reg::Instruction::Sqrt  { dst, src },
+                        Abs   => reg::Instruction::Abs   { dst, src },
+                        Floor => reg::Instruction::Floor { dst, src },
+                        _ => unreachable!(),
+                    });
+                    self.stack[d - 1] = Value::Home;
+                }
+
+                Pop => {
+                    self.stack.pop();
+                }
+
+                Dup => {
+                    let value = self.source(d - 1);
+                    self.stack.push(value);
+                }
+
+                Rot => {
+                    let (a, b, c) = (self.source(d - 3), self.source(d - 2), self.source(d - 1));
+                    if fits(b, d - 3) && fits(c, d - 2) {
+                        self.stack[d - 3] = normalize(b, d - 3);
+                        self.stack[d - 2] = normalize(c, d - 2);
+                        self.stack[d - 1] = a;
+                    }
+                    else {
+                        self.permute(&[d - 3, d - 2, d - 1])?;
+                    }
+                }
+
+                Swap => {
+                    let (a, b) = (self.source(d - 2), self.source(d - 1));
+                    if fits(b, d - 2) {
+                        self.stack[d - 2] = normalize(b, d - 2);
+                        self.stack[d - 1] = a;
+                    }
+                    else {
+                        self.permute(&[d - 2, d - 1])?;
+                    }
+                }
+
+                Jump { target } => {
+                    self.flush(0)?;
+                    self.jump(reg::Instruction::Jump { target: 0 }, target);
+                }
+
+                JumpIf { target } | JumpIfNot { target } => {
+                    self.flush(1)?;
+                    self.materialize_int(d - 1)?;
+                    let src = self.loc(d - 1);
+                    self.jump(match instr {
+                        JumpIf { .. } => reg::Instruction::JumpIf    { target: 0, src },
+                        _             => reg::Instruction::JumpIfNot { target: 0, src },
+                    }, target);
+                    self.stack.pop();
+                }
+
+                SetCounter => {
+                    self.materialize_int(d - 1)?;
+                    let src = self.loc(d - 1);
+                    self.out.push(reg::Instruction::SetCounter { src });
+                    self.stack.pop();
+                }
+
+                GetCounter => {
+                    self.stack.push(Value::Home);
+                    self.out.push(reg::Instruction::GetCounter { dst: reg(d)? });
+                }
+
+                Loop { target } => {
+                    self.flush(0)?;
+                    self.jump(reg::Instruction::Loop { target: 0 }, target);
+                }
+
+                LoopLe { target } => {
+                    self.flush(2)?;
+                    self.materialize_int(d - 2)?;
+                    self.materialize_int(d - 1)?;
+                    let src1 = self.loc(d - 2);
+                    let src2 = self.loc(d - 1);
+                    self.jump(reg::Instruction::LoopLe { target: 0, src1, src2 }, target);
+                    self.stack.truncate(d - 2);
+                }
+
+                Return => {
+                    self.materialize_int(d - 1)?;
+                    let src = self.loc(d - 1);
+                    self.out.push(reg::Instruction::Return { src });
+                }
+
+                Nop | Enter { .. } => {}
+
+                Call { target } => {
+                    // the callee may write anything above its base.
+                    self.flush(0)?;
+                    // verified.
+                    let argc = stack::callee_argc(self.code, target as usize).unwrap();
+                    let base = d - argc as usize;
+                    self.jump(reg::Instruction::Call { target: 0, base: reg(base)? }, target);
+                    self.stack.truncate(base);
+                    self.stack.push(Value::Home);
+                }
+
+                Ret => {
+                    self.materialize_int(d - 1)?;
+                    let src = self.loc(d - 1);
+                    self.out.push(reg::Instruction::Ret { src });
+                }
+
+                LoadMem => {
+                    self.materialize_int(d - 1)?;
+                    let dst  = reg(d - 1)?;
+                    let addr = self.loc(d - 1);
+                    self.out.push(reg::Instruction::LoadMem { dst, addr });
+                    self.stack[d - 1] = Value::Home;
+                }
+
+                StoreMem => {
+                    self.materialize_int(d - 2)?;
+                    self.materialize_int(d - 1)?;
+                    let addr = self.loc(d - 2);
+                    let src  = self.loc(d - 1);
+                    self.out.push(reg::Instruction::StoreMem { src, addr });
+                    self.stack.truncate(d - 2);
+                }
+
+                LoadConst { index } => {
+                    self.stack.push(Value::Home);
+                    self.out.push(reg::Instruction::LoadConst { dst: reg(d)?, index });
+                }
+
+                Wide { .. } => unreachable!(),
+
+                // as the instructions they fused.
+                MulSlots { slots } => {
+                    let (src1, src2) = stack::unpack_slots(slots);
+                    self.instr(Load { src: src1 })?;
+                    self.instr(Load { src: src2 })?;
+                    self.instr(Mul)?;
+                }
+
+                AddInt { value } => {
+                    self.instr(LoadInt { value })?;
+                    self.instr(Add)?;
+                }
+
+                Square => {
+                    self.instr(Dup)?;
+                    self.instr(Mul)?;
+                }
+            }
+            Ok(())
+        }
+
+        fn jump(&mut self, instr: reg::Instruction, target: u8) {
+            self.fixups.push((self.out.len(), target));
+            self.out.push(instr);
+        }
+
+        /// the value at `pos`, as seen from another slot.
+        fn source(&self, pos: usize) -> Value {
+            match self.stack[pos] {
+                Value::Home => Value::Reg(pos as u8),
+                value => value,
+            }
+        }
+
+        /// the register holding the (non-constant) value at `pos`.
+        fn loc(&self, pos: usize) -> u8 {
+            match self.stack[pos] {
+                Value::Home   => pos as u8,
+                Value::Reg(r) => r,
+                Value::Int(_) => unreachable!(),
+            }
+        }
+
+        fn materialize_int(&mut self, pos: usize) -> Result<(), TranslateError> {
+            if let Value::Int(_) = self.stack[pos] {
+                self.materialize(pos)?;
+            }
+            Ok(())
+        }
+
+        /// moves the value at `pos` into its own register.
+        fn materialize(&mut self, pos: usize) -> Result<(), TranslateError> {
+            let instr = match self.stack[pos] {
+                Value::Home   => return Ok(()),
+                Value::Reg(r) => reg::Instruction::Copy    { dst: reg(pos)?, src: r },
+                Value::Int(v) => reg::Instruction::LoadInt { dst: reg(pos)?, value: v as i16 },
+            };
+            self.prepare_write(pos, self.stack.len())?;
+            self.out.push(instr);
+            self.stack[pos] = Value::Home;
+            Ok(())
+        }
+
+        /// saves the values that still need register `r`.
+        /// slots from `keep` up read `r` before it is written.
+        fn prepare_write(&mut self, r: usize, keep: usize) -> Result<(), TranslateError> {
+            for pos in r + 1 .. keep.min(self.stack.len()) {
+                if self.stack[pos] == Value::Reg(r as u8) {
+                    self.materialize(pos)?;
+                }
+            }
+            Ok(())
+        }
+
+        /// materializes everything below the top `keep` values.
+        fn flush(&mut self, keep: usize) -> Result<(), TranslateError> {
+            for pos in 0 .. self.stack.len() - keep {
+                self.materialize(pos)?;
+            }
+            Ok(())
+        }
+
+        /// rotates the registers of `slots` left by one, through a temporary.
+        fn permute(&mut self, slots: &[usize]) -> Result<(), TranslateError> {
+            for &pos in slots {
+                self.materialize(pos)?;
+            }
+            let temp = reg(self.stack.len())?;
+            let first = slots[0] as u8;
+            self.out.push(reg::Instruction::Copy { dst: temp, src: first });
+            for w in slots.windows(2) {
+                self.out.push(reg::Instruction::Copy { dst: w[0] as u8, src: w[1] as u8 });
+            }
+            self.out.push(reg::Instruction::Copy { dst: *slots.last().unwrap() as u8, src: temp });
+            Ok(())
+        }
+    }
+
+    fn reg(index: usize) -> Result<u8, TranslateError> {
+        if index >= reg::REGISTER_COUNT {
+            return Err(TranslateError::TooManyRegisters);
+        }
+        Ok(index as u8)
+    }
+
+    /// whether `value` can be put in slot `pos` without breaking the invariant.
+    fn fits(value: Value, pos: usize) -> bool {
+        match value {
+            Value::Reg(r) => r as usize <= pos,
+            _ => true,
+        }
+    }
+
+    fn normalize(value: Value, pos: usize) -> Value {
+        match value {
+            Value::Reg(r) if r as usize == pos => Value::Home,
+            value => value,
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Strategy {
+        /// every instruction loads its operands and stores its result,
+        /// like `stack::FIB_NAIVE` and `stack::MANDEL_NAIVE`.
+        Naive,
+        /// results that are used right away stay on the stack,
+        /// like the `_SMART` programs.
+        Smart,
+    }
+
+    /// translates a register program to an equivalent stack program.
+    ///
+    /// register `i` becomes stack slot `i`. slots for registers that
+    /// aren't arguments are pushed up front and start out as zero.
+    ///
+    /// calls aren't supported: a callee's window overlaps its caller's
+    /// registers, while a stack frame only shares the arguments.
+    /// neither direction translates `Wide` prefixes.
+    pub fn reg_to_stack(code: &[reg::Instruction], argc: usize, strategy: Strategy) -> Result<Vec<stack::Instruction>, TranslateError> {
+        let program = reg::verify(code)?;
+        if let Some(pc) = code.iter().position(|instr| matches!(instr, reg::Instruction::Call { .. } | reg::Instruction::Ret { .. } | reg::Instruction::Wide { .. })) {
+            return Err(TranslateError::Unsupported { pc });
+        }
+        let slots = program.registers().max(argc);
+
+        // unreachable code can have any target.
+        let mut is_target = vec![false; code.len()];
+        for instr in code {
+            if let Some(t) = instr.target().and_then(|target| is_target.get_mut(target as usize)) {
+                *t = true;
+            }
+        }
+
+        let mut t = RegToStack {
+            out: vec![],
+            fixups: vec![],
+            top: None,
+            smart: strategy == Strategy::Smart,
+        };
+        for _ in argc..slots {
+            t.out.push(stack::Instruction::LoadInt { value: 0 });
+        }
+
+        let live_out = reg::live_out(code);
+
+        let mut map = vec![0; code.len()];
+        for (pc, instr) in code.iter().enumerate() {
+            if is_target[pc] {
+                t.flush();
+            }
+            map[pc] = t.out.len();
+
+            // can the result stay on the stack for the next instruction?
+            let keep = match (strategy, instr.dst()) {
+                (Strategy::Smart, Some(dst)) if pc + 1 < code.len() && !is_target[pc + 1] => {
+                    let next = code[pc + 1];
+                    if next.srcs().any(|src| src == dst) {
+                        let needed_later = live_out[pc + 1].contains(dst as usize) && next.dst() != Some(dst);
+                        Some(needed_later)
+                    }
+                    else { None }
+                }
+                _ => None,
+            };
+
+            t.instr(*instr, &live_out[pc], keep);
+        }
+
+        for (at, target) in t.fixups {
+            // unreachable jumps can go anywhere, they stay unreachable.
+            let target = map.get(target as usize).copied().unwrap_or(0);
+            let target = u8::try_from(target)
+                .map_err(|_| TranslateError::TooLong { len: t.out.len() })?;
+
+            use stack::Instruction::*;
+            match &mut t.out[at] {
+                Jump { target: t } | JumpIf { target: t } | JumpIfNot { target: t } |
+                Loop { target: t } | LoopLe { target: t } => *t = target,
+                _ => unreachable!(),
+            }
+        }
+
+        stack::verify(&t.out, argc)?;
+        Ok(t.out)
+    }
+
+    struct RegToStack {
+        out:    Vec<stack::Instruction>,
+        fixups: Vec<(usize, u8)>,
+        /// the value of this register is on top of the stack,
+        /// and its slot is out of date if the flag is set.
+        top:    Option<(u8, bool)>,
+        smart:  bool,
+    }
+
+    impl RegToStack {
+        /// `keep`: leave the result on the stack for the next instruction,
+        /// `Some(true)` if it must also be stored.
+        fn instr(&mut self, instr: reg::Instruction, live_out: &reg::BitSet, keep: Option<bool>) {
+            use reg::Instruction::*;
+            use stack::Instruction as S;
+            match instr {
+                LoadInt { dst, value } => {
+                    self.flush();
+                    stack::emit_int(&mut self.out, value);
+                    self.result(dst, live_out, keep);
+                }
+
+                Copy { dst, src } => {
+                    self.operands(src, None, false);
+                    self.result(dst, live_out, keep);
+                }
+
+                Add { dst, src1, src2 } => {
+                    self.operands(src1, Some(src2), true);
+                    self.out.push(S::Add);
+                    self.result(dst, live_out, keep);
+                }
+
+                Sub { dst, src1, src2 } => {
+                    self.operands(src1, Some(src2), false);
+                    self.out.push(S::Sub);
+                    self.result(dst, live_out, keep);
+                }
+
+                Mul { dst, src1, src2 } => {
+                    self.operands(src1, Some(src2), true);
+                    self.out.push(S::Mul);
+                    self.result(dst, live_out, keep);
+                }
+
+                // `min` and `max` aren't commutative for `-0.0` and `0.0`.
+                Div { dst, src1, src2 } | Lt { dst, src1, src2 } | Le { dst, src1, src2 } |
+                Min { dst, src1, src2 } | Max { dst, src1, src2 } => {
+                    self.operands(src1, Some(src2), false);
+                    self.out.push(match instr {
+                        Div { .. } => S::Div,
+                        Lt  { .. } => S::Lt,
+                        Le  { .. } => S::Le,
+                        Min { .. } => S::Min,
+                        _          => S::Max,
+                    });
+                    self.result(dst, live_out, keep);
+                }
+
+                Eq { dst, src1, src2 } => {
+                    self.operands(src1, Some(src2), true);
+                    self.out.push(S::Eq);
+                    self.result(dst, live_out, keep);
+                }
+
+                Neg { dst, src } | Sqrt { dst, src } | Abs { dst, src } | Floor { dst, src } => {
+                    self.operands(src, None, false);
+                    self.out.push(match instr {
+                        Neg  { .. } => S::Neg,
+                        Sqrt { .. } => S::Sqrt,
+                        Abs  { .. } => S::Abs,
+                        _           => S::Floor,
+                    });
+                    self.result(dst, live_out, keep);
+                }
+
+                Jump { target } => {
+                    self.flush();
+                    self.jump(S::Jump { target: 0 }, target);
+                }
+
+                JumpIf { target, src } => {
+                    self.operands(src, None, false);
+                    self.jump(S::JumpIf { target: 0 }, target);
+                }
+
+                JumpIfNot { target, src } => {
+                    self.operands(src, None, false);
+                    self.jump(S::JumpIfNot { target: 0 }, target);
+                }
+
+                SetCounter { src } => {
+                    self.operands(src, None, false);
+                    self.out.push(S::SetCounter);
+                }
+
+                GetCounter { dst } => {
+                    self.flush();
+                    self.out.push(S::GetCounter);
+                    self.result(dst, live_out, keep);
+                }
+
+                Loop { target } => {
+                    self.flush();
+                    self.jump(S::Loop { target: 0 }, target);
+                }
+
+                LoopLe { target, src1, src2 } => {
+                    self.operands(src1, Some(src2), false);
+                    self.jump(S::LoopLe { target: 0 }, target);
+                }
+
+                Return { src } => {
+                    self.operands(src, None, false);
+                    self.out.push(S::Return);
+                }
+
+                LoadMem { dst, addr } => {
+                    self.operands(addr, None, false);
+                    self.out.push(S::LoadMem);
+                    self.result(dst, live_out, keep);
+                }
+
+                StoreMem { src, addr } => {
+                    self.operands(addr, Some(src), false);
+                    self.out.push(S::StoreMem);
+                }
+
+                LoadConst { dst, index } => {
+                    self.flush();
+                    self.out.push(S::LoadConst { index });
+                    self.result(dst, live_out, keep);
+                }
+
+                MulAdd { dst, src1, src2 } | MulSub { dst, src1, src2 } => {
+                    self.flush();
+                    self.out.extend([S::Load { src: dst }, S::Load { src: src1 }, S::Load { src: src2 }, S::Mul]);
+                    self.out.push(if matches!(instr, MulAdd { .. }) { S::Add } else { S::Sub });
+                    self.result(dst, live_out, keep);
+                }
+
+                JumpIfLt { target, src1, src2 } | JumpIfLe { target, src1, src2 } => {
+                    self.operands(src1, Some(src2), false);
+                    self.out.push(if matches!(instr, JumpIfLt { .. }) { S::Lt } else { S::Le });
+                    self.jump(S::JumpIf { target: 0 }, target);
+                }
+
+                Call { .. } | Ret { .. } | Wide { .. } => unreachable!(),
+            }
+        }
+
+        fn jump(&mut self, instr: stack::Instruction, target: u8) {
+            self.fixups.push((self.out.len(), target));
+            self.out.push(instr);
+        }
+
+        /// pushes the operands, reusing the value on top of the stack.
+        fn operands(&mut self, src1: u8, src2: Option<u8>, commutative: bool) {
+            use stack::Instruction::*;
+
+            let top = self.top.map(|(r, _)| r);
+            match src2 {
+                None if top == Some(src1) => {
+                    self.top = None;
+                }
+
+                Some(src2) if top == Some(src1) => {
+                    self.top = None;
+                    self.out.push(if src2 == src1 { Dup } else { Load { src: src2 } });
+                }
+
+                Some(src2) if top == Some(src2) => {
+                    self.top = None;
+                    self.out.push(Load { src: src1 });
+                    if !commutative {
+                        self.out.push(Swap);
+                    }
+                }
+
+                _ => {
+                    self.flush();
+                    self.out.push(Load { src: src1 });
+                    if let Some(src2) = src2 {
+                        self.out.push(if src2 == src1 && self.smart { Dup } else { Load { src: src2 } });
+                    }
+                }
+            }
+        }
+
+        /// the result for `dst` is on top of the stack.
+        fn result(&mut self, dst: u8, live_out: &reg::BitSet, keep: Option<bool>) {
+            use stack::Instruction::*;
+            match keep {
+                Some(true) => {
+                    self.out.push(Dup);
+                    self.out.push(Store { dst });
+                    self.top = Some((dst, false));
+                }
+                Some(false) => {
+                    self.top = Some((dst, true));
+                }
+                None => {
+                    self.out.push(if live_out.contains(dst as usize) { Store { dst } } else { Pop });
+                }
+            }
+        }
+
+        /// gets the stack back to just the slots.
+        fn flush(&mut self) {
+            if let Some((r, dirty)) = self.top.take() {
+                self.out.push(
+                    if dirty { stack::Instruction::Store { dst: r } }
+                    else     { stack::Instruction::Pop });
+            }
+        }
+    }
+}
+
+
+
+pub mod lang {
+    //! a tiny language for writing programs for both vms.
+    //!
+    //! ```text
+    //! fn mandel(x0, y0, limit) {
+    //!     let x = 0;
+    //!     let y = 0;
+    //!     repeat limit while x*x + y*y <= 4 {
+    //!         let xtemp = x*x - y*y + x0;
+    //!         y = x*y*2 + y0;
+    //!         x = xtemp;
+    //!     }
+    //!     return limit - counter;
+    //! }
+    //! ```
+    //!
+    //! all values are f64. `repeat n { .. }` runs its body `n` times
+    //! using the vm's counter, `repeat n while a <= b { .. }` also stops
+    //! once the condition fails (like `LoopLe`). `counter` reads the
+    //! remaining iteration count. since there is only one counter,
+    //! loops can't be nested.
+    //!
+    //! comparisons (`<`, `<=`, `>`, `>=`, `==`) are 1 or 0, `if`/`else`
+    //! and `while` take any value that isn't 0 as true. `while` loops
+    //! don't use the counter, so they nest. `sqrt`, `abs`, `floor`,
+    //! `min` and `max` map to the instructions of the same name.
+    //!
+    //! literals are `LoadInt`s, others need a constant pool, see
+    //! `compile_reg_with_constants`. programs that are too long for
+    //! one byte jump targets get a `Wide` prefix on every jump.
+
+    use std::collections::HashMap;
+    use super::{reg, stack};
+
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Error {
+        pub line: usize,
+        pub message: String,
+    }
+
+    impl core::fmt::Display for Error {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+
+    impl std::error::Error for Error {}
+
+    fn error<T>(line: usize, message: impl Into<String>) -> Result<T, Error> {
+        Err(Error { line, message: message.into() })
+    }
+
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Function {
+        pub name:   String,
+        pub params: Vec<String>,
+        pub body:   Vec<Stmt>,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Stmt {
+        pub line: usize,
+        pub kind: StmtKind,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum StmtKind {
+        Let    { name: String, value: Expr },
+        Assign { name: String, value: Expr },
+        /// `cond` is `lhs <= rhs`.
+        Repeat { count: Expr, cond: Option<(Expr, Expr)>, body: Vec<Stmt> },
+        /// `els` is empty without `else`, `else if` nests another `If`.
+        If     { cond: Expr, then: Vec<Stmt>, els: Vec<Stmt> },
+        While  { cond: Expr, body: Vec<Stmt> },
+        Return { value: Expr },
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Expr {
+        Num     (f64),
+        Var     (String),
+        Counter,
+        Neg     (Box<Expr>),
+        Unary   (UnOp, Box<Expr>),
+        Binary  (BinOp, Box<Expr>, Box<Expr>),
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum UnOp {
+        Sqrt,
+        Abs,
+        Floor,
+    }
+
+    /// `a > b` is `Lt` with the operands swapped, same for `>=`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum BinOp {
+        Add,
+        Sub,
+        Mul,
+        Div,
+        Lt,
+        Le,
+        Eq,
+        Min,
+        Max,
+    }
+
+
+    pub fn compile_reg(source: &str) -> Result<Vec<reg::Instruction>, Error> {
+        let function = parse(source)?;
+        Ok(RegGen::function(&function, None)?.0)
+    }
+
+    /// the program expects `params.len()` arguments.
+    pub fn compile_stack(source: &str) -> Result<Vec<stack::Instruction>, Error> {
+        let function = parse(source)?;
+        Ok(StackGen::function(&function, None)?.0)
+    }
+
+    /// like `compile_reg`, but literals that aren't 16 bit integers go
+    /// into the returned constant pool, which `reg::Vm::constants` expects.
+    pub fn compile_reg_with_constants(source: &str) -> Result<(Vec<reg::Instruction>, Vec<f64>), Error> {
+        let function = parse(source)?;
+        RegGen::function(&function, Some(vec![]))
+    }
+
+    pub fn compile_stack_with_constants(source: &str) -> Result<(Vec<stack::Instruction>, Vec<f64>), Error> {
+        let function = parse(source)?;
+        StackGen::function(&function, Some(vec![]))
+    }
+
+
+    //
+    // lexer
+    //
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Token {
+        Num   (f64),
+        Ident (String),
+        Punct (&'static str),
+        End,
+    }
+
+    const PUNCTS: &[&str] = &["<=", ">=", "==", "<", ">", "(", ")", "{", "}", ",", ";", "=", "+", "-", "*", "/"];
+
+    fn lex(source: &str) -> Result<Vec<(Token, usize)>, Error> {
+        let mut tokens = vec![];
+        for (i, text) in source.lines().enumerate() {
+            let line = i + 1;
+            let text = match text.find("//") {
+                Some(end) => &text[..end],
+                None      => text,
+            };
+
+            let mut rest = text.trim_start();
+            while !rest.is_empty() {
+                let c = rest.chars().next().unwrap();
+                let len =
+                    if c.is_ascii_digit() {
+                        let len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+                        let Ok(value) = rest[..len].parse() else {
+                            return error(line, format!("invalid number `{}`", &rest[..len]));
+                        };
+                        tokens.push((Token::Num(value), line));
+                        len
+                    }
+                    else if c.is_ascii_alphabetic() || c == '_' {
+                        let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+                        tokens.push((Token::Ident(rest[..len].into()), line));
+                        len
+                    }
+                    else if let Some(p) = PUNCTS.iter().find(|p| rest.starts_with(**p)) {
+                        tokens.push((Token::Punct(p), line));
+                        p.len()
+                    }
+                    else {
+                        return error(line, format!("unexpected character `{}`", c));
+                    };
+                rest = rest[len..].trim_start();
+            }
+        }
+        let line = source.lines().count().max(1);
+        tokens.push((Token::End, line));
+        Ok(tokens)
+    }
+
+
+    //
+    // parser
+    //
+
+    const KEYWORDS: &[&str] = &["fn", "let", "repeat", "while", "if", "else", "return", "counter"];
+
+    pub fn parse(source: &str) -> Result<Function, Error> {
+        let mut p = Parser { tokens: lex(source)?, pos: 0 };
+
+        p.keyword("fn")?;
+        let name = p.ident()?;
+        p.punct("(")?;
+        let mut params = vec![];
+        while !p.eat(")") {
+            if !params.is_empty() {
+                p.punct(",")?;
+            }
+            params.push(p.ident()?);
+        }
+        let body = p.block()?;
+
+        if p.peek() != &Token::End {
+            return error(p.line(), "expected end of input");
+        }
+        Ok(Function { name, params, body })
+    }
+
+    struct Parser {
+        tokens: Vec<(Token, usize)>,
+        pos: usize,
+    }
+
+    impl Parser {
+        fn peek(&self) -> &Token { &self.tokens[self.pos].0 }
+
+        fn line(&self) -> usize { self.tokens[self.pos].1 }
+
+        fn next(&mut self) -> Token {
+            let token = self.tokens[self.pos].0.clone();
+            if token != Token::End {
+                self.pos += 1;
+            }
+            token
+        }
+
+        fn eat(&mut self, punct: &str) -> bool {
+            if matches!(self.peek(), Token::Punct(p) if *p == punct) {
+                self.pos += 1;
+                return true;
+            }
+            false
+        }
+
+        fn eat_keyword(&mut self, keyword: &str) -> bool {
+            if matches!(self.peek(), Token::Ident(name) if name == keyword) {
+                self.pos += 1;
+                return true;
+            }
+            false
+        }
+
+        fn punct(&mut self, punct: &str) -> Result<(), Error> {
+            if !self.eat(punct) {
+                return error(self.line(), format!("expected `{}`", punct));
+            }
+            Ok(())
+        }
+
+        fn keyword(&mut self, keyword: &str) -> Result<(), Error> {
+            if !self.eat_keyword(keyword) {
+                return error(self.line(), format!("expected `{}`", keyword));
+            }
+            Ok(())
+        }
+
+        fn ident(&mut self) -> Result<String, Error> {
+            let line = self.line();
+            match self.next() {
+                Token::Ident(name) if !KEYWORDS.contains(&name.as_str()) => Ok(name),
+                _ => error(line, "expected identifier"),
+            }
+        }
+
+        fn block(&mut self) -> Result<Vec<Stmt>, Error> {
+            self.punct("{")?;
+            let mut stmts = vec![];
+            while !self.eat("}") {
+                if self.peek() == &Token::End {
+                    return error(self.line(), "expected `}`");
+                }
+                stmts.push(self.stmt()?);
+            }
+            Ok(stmts)
+        }
+
+        fn stmt(&mut self) -> Result<Stmt, Error> {
+            let line = self.line();
+            let kind =
+                if self.eat_keyword("let") {
+                    let name = self.ident()?;
+                    self.punct("=")?;
+                    let value = self.expr()?;
+                    self.punct(";")?;
+                    StmtKind::Let { name, value }
+                }
+                else if self.eat_keyword("repeat") {
+                    let count = self.expr()?;
+                    let cond =
+                        if self.eat_keyword("while") {
+                            let lhs = self.sum()?;
+                            if self.eat("<=") {
+                                Some((lhs, self.sum()?))
+                            }
+                            else if self.eat(">=") {
+                                let rhs = self.sum()?;
+                                Some((rhs, lhs))
+                            }
+                            else {
+                                return error(self.line(), "expected `<=` or `>=`");
+                            }
+                        }
+                        else { None };
+                    let body = self.block()?;
+                    StmtKind::Repeat { count, cond, body }
+                }
+                else if self.eat_keyword("if") {
+                    return self.if_rest(line);
+                }
+                else if self.eat_keyword("while") {
+                    let cond = self.expr()?;
+                    let body = self.block()?;
+                    StmtKind::While { cond, body }
+                }
+                else if self.eat_keyword("return") {
+                    let value = self.expr()?;
+                    self.punct(";")?;
+                    StmtKind::Return { value }
+                }
+                else {
+                    let name = self.ident()?;
+                    self.punct("=")?;
+                    let value = self.expr()?;
+                    self.punct(";")?;
+                    StmtKind::Assign { name, value }
+                };
+            Ok(Stmt { line, kind })
+        }
+
+        /// after the `if`.
+        fn if_rest(&mut self, line: usize) -> Result<Stmt, Error> {
+            let cond = self.expr()?;
+            let then = self.block()?;
+            let els =
+                if self.eat_keyword("else") {
+                    let line = self.line();
+                    if self.eat_keyword("if") { vec![self.if_rest(line)?] }
+                    else                      { self.block()? }
+                }
+                else { vec![] };
+            Ok(Stmt { line, kind: StmtKind::If { cond, then, els } })
+        }
+
+        /// comparisons don't chain.
+        fn expr(&mut self) -> Result<Expr, Error> {
+            let lhs = self.sum()?;
+            let (op, swap) =
+                if self.eat("<")       { (BinOp::Lt, false) }
+                else if self.eat("<=") { (BinOp::Le, false) }
+                else if self.eat(">")  { (BinOp::Lt, true)  }
+                else if self.eat(">=") { (BinOp::Le, true)  }
+                else if self.eat("==") { (BinOp::Eq, false) }
+                else { return Ok(lhs) };
+            let rhs = self.sum()?;
+            let (lhs, rhs) = if swap { (rhs, lhs) } else { (lhs, rhs) };
+            Ok(Expr::Binary(op, Box::new(lhs), Box::new(rhs)))
+        }
+
+        fn sum(&mut self) -> Result<Expr, Error> {
+            let mut lhs = self.term()?;
+            loop {
+                let op =
+                    if self.eat("+")      { BinOp::Add }
+                    else if self.eat("-") { BinOp::Sub }
+                    else { return Ok(lhs) };
+                let rhs = self.term()?;
+                lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+            }
+        }
+
+        fn term(&mut self) -> Result<Expr, Error> {
+            let mut lhs = self.unary()?;
+            loop {
+                let op =
+                    if self.eat("*")      { BinOp::Mul }
+                    else if self.eat("/") { BinOp::Div }
+                    else { return Ok(lhs) };
+                let rhs = self.unary()?;
+                lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+            }
+        }
+
+        fn unary(&mut self) -> Result<Expr, Error> {
+            if self.eat("-") {
+                return Ok(match self.unary()? {
+                    Expr::Num(value) => Expr::Num(-value),
+                    e => Expr::Neg(Box::new(e)),
+                });
+            }
+            self.atom()
+        }
+
+        fn atom(&mut self) -> Result<Expr, Error> {
+            let line = self.line();
+            match self.next() {
+                Token::Num(value) => Ok(Expr::Num(value)),
+                Token::Ident(name) if name == "counter" => Ok(Expr::Counter),
+                Token::Ident(name) if self.eat("(") => {
+                    let arg = self.expr()?;
+                    let e = match name.as_str() {
+                        "sqrt"  => Expr::Unary(UnOp::Sqrt,  Box::new(arg)),
+                        "abs"   => Expr::Unary(UnOp::Abs,   Box::new(arg)),
+                        "floor" => Expr::Unary(UnOp::Floor, Box::new(arg)),
+                        "min" | "max" => {
+                            self.punct(",")?;
+                            let rhs = self.expr()?;
+                            let op = if name == "min" { BinOp::Min } else { BinOp::Max };
+                            Expr::Binary(op, Box::new(arg), Box::new(rhs))
+                        }
+                        _ => return error(line, format!("unknown function `{}`", name)),
+                    };
+                    self.punct(")")?;
+                    Ok(e)
+                }
+                Token::Ident(name) if !KEYWORDS.contains(&name.as_str()) => Ok(Expr::Var(name)),
+                Token::Punct("(") => {
+                    let e = self.expr()?;
+                    self.punct(")")?;
+                    Ok(e)
+                }
+                _ => error(line, "expected expression"),
+            }
+        }
+    }
+
+
+    //
+    // code generation
+    //
+
+    fn int(value: f64, line: usize) -> Result<i16, Error> {
+        if value.fract() != 0.0 || value < i16::MIN as f64 || value > i16::MAX as f64 {
+            return error(line, format!("literal `{}` isn't a 16 bit integer", value));
+        }
+        Ok(value as i16)
+    }
+
+    /// the low and high byte of `pc`, which has to fit into one byte
+    /// unless the jump is `wide`.
+    fn target(pc: usize, wide: bool, line: usize) -> Result<[u8; 2], Error> {
+        let max = if wide { u16::MAX as usize } else { u8::MAX as usize };
+        if pc > max {
+            return error(line, "program is too long");
+        }
+        Ok((pc as u16).to_le_bytes())
+    }
+
+    enum Literal {
+        Int(i16),
+        Const(u16),
+    }
+
+    /// `value` as a `LoadInt` immediate, or as an index into `constants`.
+    fn literal(value: f64, constants: &mut Option<Vec<f64>>, line: usize) -> Result<Literal, Error> {
+        let int = int(value, line);
+        let (Err(_), Some(constants)) = (&int, constants) else {
+            return int.map(Literal::Int);
+        };
+        // by bits, so `-0.0` doesn't reuse `0.0`.
+        let index = match constants.iter().position(|c| c.to_bits() == value.to_bits()) {
+            Some(index) => index,
+            None => {
+                constants.push(value);
+                constants.len() - 1
+            }
+        };
+        u16::try_from(index).map(Literal::Const).or_else(|_| error(line, "too many constants"))
+    }
+
+    fn check_return(function: &Function) -> Result<(), Error> {
+        match function.body.last() {
+            Some(Stmt { kind: StmtKind::Return { .. }, .. }) => Ok(()),
+            Some(stmt) => error(stmt.line, format!("`{}` must end with `return`", function.name)),
+            None       => error(1, format!("`{}` must end with `return`", function.name)),
+        }
+    }
+
+    /// variables in nested blocks.
+    struct Scopes<'a> {
+        scopes: Vec<HashMap<&'a str, u8>>,
+    }
+
+    impl<'a> Scopes<'a> {
+        fn get(&self, name: &str, line: usize) -> Result<u8, Error> {
+            for scope in self.scopes.iter().rev() {
+                if let Some(index) = scope.get(name) {
+                    return Ok(*index);
+                }
+            }
+            error(line, format!("undefined variable `{}`", name))
+        }
+
+        fn define(&mut self, name: &'a str, index: u8) {
+            self.scopes.last_mut().unwrap().insert(name, index);
+        }
+    }
+
+
+    /// variables live in registers, expressions are evaluated
+    /// into temporaries above them.
+    struct RegGen<'a> {
+        out:       Vec<reg::Instruction>,
+        vars:      Scopes<'a>,
+        next:      usize,
+        in_loop:   bool,
+        wide:      bool,
+        constants: Option<Vec<f64>>,
+    }
+
+    impl<'a> RegGen<'a> {
+        /// tries short jumps first, the second attempt makes them all wide.
+        fn function(function: &'a Function, constants: Option<Vec<f64>>) -> Result<(Vec<reg::Instruction>, Vec<f64>), Error> {
+            check_return(function)?;
+
+            let generate = |wide, constants| {
+                let mut g = RegGen {
+                    out:     vec![],
+                    vars:    Scopes { scopes: vec![HashMap::new()] },
+                    next:    0,
+                    in_loop: false,
+                    wide,
+                    constants,
+                };
+                for param in &function.params {
+                    let r = g.alloc(1)?;
+                    g.vars.define(param, r);
+                }
+                g.block(&function.body)?;
+                Ok((g.out, g.constants.unwrap_or_default()))
+            };
+            generate(false, constants.clone()).or_else(|_| generate(true, constants))
+        }
+
+        /// pushes `instr`, jumping to `pc`. returns where it is for `patch`.
+        fn jump(&mut self, instr: reg::Instruction, pc: usize, line: usize) -> Result<usize, Error> {
+            if self.wide {
+                self.out.push(reg::Instruction::Wide { high: [0; 3] });
+            }
+            self.out.push(instr);
+            let at = self.out.len() - 1;
+            self.patch(at, pc, line)?;
+            Ok(at)
+        }
+
+        fn patch(&mut self, at: usize, pc: usize, line: usize) -> Result<(), Error> {
+            let [low, high] = target(pc, self.wide, line)?;
+            if self.wide {
+                self.out[at - 1] = reg::Instruction::Wide { high: [high, 0, 0] };
+            }
+            *self.out[at].target_mut().unwrap() = low;
+            Ok(())
+        }
+
+        fn alloc(&mut self, line: usize) -> Result<u8, Error> {
+            let r = u8::try_from(self.next).or_else(|_| error(line, "out of registers"))?;
+            self.next += 1;
+            Ok(r)
+        }
+
+        fn block(&mut self, stmts: &'a [Stmt]) -> Result<(), Error> {
+            let next = self.next;
+            self.vars.scopes.push(HashMap::new());
+            for stmt in stmts {
+                self.stmt(stmt)?;
+            }
+            self.vars.scopes.pop();
+            self.next = next;
+            Ok(())
+        }
+
+        fn stmt(&mut self, stmt: &'a Stmt) -> Result<(), Error> {
+            use reg::Instruction::*;
+            let line = stmt.line;
+            match &stmt.kind {
+                StmtKind::Let { name, value } => {
+                    let dst = self.alloc(line)?;
+                    self.expr_into(value, dst, line)?;
+                    self.next = dst as usize + 1;
+                    self.vars.define(name, dst);
+                }
+
+                StmtKind::Assign { name, value } => {
+                    let dst = self.vars.get(name, line)?;
+                    let temps = self.next;
+                    self.expr_into(value, dst, line)?;
+                    self.next = temps;
+                }
+
+                StmtKind::Repeat { count, cond, body } => {
+                    if self.in_loop {
+                        return error(line, "`repeat` loops can't be nested");
+                    }
+                    self.in_loop = true;
+
+                    let temps = self.next;
+                    let src = self.expr(count, line)?;
+                    self.next = temps;
+                    self.out.push(SetCounter { src });
+                    let jump = self.jump(Jump { target: 0 }, 0, line)?;
+
+                    let body_pc = self.out.len();
+                    self.block(body)?;
+
+                    self.patch(jump, self.out.len(), line)?;
+                    match cond {
+                        Some((lhs, rhs)) => {
+                            let src1 = self.expr(lhs, line)?;
+                            let src2 = self.expr(rhs, line)?;
+                            self.next = temps;
+                            self.jump(LoopLe { target: 0, src1, src2 }, body_pc, line)?;
+                        }
+                        None => {
+                            self.jump(Loop { target: 0 }, body_pc, line)?;
+                        }
+                    }
+
+                    self.in_loop = false;
+                }
+
+                StmtKind::If { cond, then, els } => {
+                    let temps = self.next;
+                    let src = self.expr(cond, line)?;
+                    self.next = temps;
+                    let jump_else = self.jump(JumpIfNot { target: 0, src }, 0, line)?;
+                    self.block(then)?;
+
+                    if els.is_empty() {
+                        self.patch(jump_else, self.out.len(), line)?;
+                    }
+                    else {
+                        let jump_end = self.jump(Jump { target: 0 }, 0, line)?;
+                        self.patch(jump_else, self.out.len(), line)?;
+                        self.block(els)?;
+                        self.patch(jump_end, self.out.len(), line)?;
+                    }
+                }
+
+                StmtKind::While { cond, body } => {
+                    let jump = self.jump(Jump { target: 0 }, 0, line)?;
+
+                    let body_pc = self.out.len();
+                    self.block(body)?;
+
+                    self.patch(jump, self.out.len(), line)?;
+                    let temps = self.next;
+                    let src = self.expr(cond, line)?;
+                    self.next = temps;
+                    self.jump(JumpIf { target: 0, src }, body_pc, line)?;
+                }
+
+                StmtKind::Return { value } => {
+                    let temps = self.next;
+                    let src = self.expr(value, line)?;
+                    self.next = temps;
+                    self.out.push(Return { src });
+                }
+            }
+            Ok(())
+        }
+
+        /// the register holding the value of `e`.
+        fn expr(&mut self, e: &Expr, line: usize) -> Result<u8, Error> {
+            if let Expr::Var(name) = e {
+                return self.vars.get(name, line);
+            }
+            let dst = self.alloc(line)?;
+            self.expr_into(e, dst, line)?;
+            Ok(dst)
+        }
+
+        /// only writes `dst` once all operands have been read.
+        fn expr_into(&mut self, e: &Expr, dst: u8, line: usize) -> Result<(), Error> {
+            use reg::Instruction::*;
+            match e {
+                Expr::Num(value) => {
+                    match literal(*value, &mut self.constants, line)? {
+                        Literal::Int(value) => self.out.push(LoadInt { dst, value }),
+                        Literal::Const(index) => {
+                            let [index, high] = index.to_le_bytes();
+                            if high != 0 {
+                                self.out.push(Wide { high: [0, high, 0] });
+                            }
+                            self.out.push(LoadConst { dst, index });
+                        }
+                    }
+                }
+
+                Expr::Var(name) => {
+                    let src = self.vars.get(name, line)?;
+                    if src != dst {
+                        self.out.push(Copy { dst, src });
+                    }
+                }
+
+                Expr::Counter => {
+                    self.out.push(GetCounter { dst });
+                }
+
+                Expr::Neg(e) => {
+                    let src = self.expr(e, line)?;
+                    self.out.push(Neg { dst, src });
+                }
+
+                Expr::Unary(op, e) => {
+                    let src = self.expr(e, line)?;
+                    self.out.push(match op {
+                        UnOp::Sqrt  => Sqrt  { dst, src },
+                        UnOp::Abs   => Abs   { dst, src },
+                        UnOp::Floor => Floor { dst, src },
+                    });
+                }
+
+                Expr::Binary(op, lhs, rhs) => {
+                    let src1 = self.expr(lhs, line)?;
+                    let src2 = self.expr(rhs, line)?;
+                    self.out.push(match op {
+                        BinOp::Add => Add { dst, src1, src2 },
+                        BinOp::Sub => Sub { dst, src1, src2 },
+                        BinOp::Mul => Mul { dst, src1, src2 },
+                        BinOp::Div => Div { dst, src1, src2 },
+                        BinOp::Lt  => Lt  { dst, src1, src2 },
+                        BinOp::Le  => Le  { dst, src1, src2 },
+                        BinOp::Eq  => Eq  { dst, src1, src2 },
+                        BinOp::Min => Min { dst, src1, src2 },
+                        BinOp::Max => Max { dst, src1, src2 },
+                    });
+                }
+            }
+            Ok(())
+        }
+    }
+
+
+    /// variables get stack slots, pushed up front like in
+    /// `stack::MANDEL_NAIVE`, so the depth is the same everywhere.
+    struct StackGen<'a> {
+        out:       Vec<stack::Instruction>,
+        vars:      Scopes<'a>,
+        next:      usize,
+        in_loop:   bool,
+        wide:      bool,
+        constants: Option<Vec<f64>>,
+    }
+
+    impl<'a> StackGen<'a> {
+        /// see `RegGen::function`.
+        fn function(function: &'a Function, constants: Option<Vec<f64>>) -> Result<(Vec<stack::Instruction>, Vec<f64>), Error> {
+            check_return(function)?;
+
+            let generate = |wide, constants| {
+                let mut g = StackGen {
+                    out:     vec![],
+                    vars:    Scopes { scopes: vec![HashMap::new()] },
+                    next:    0,
+                    in_loop: false,
+                    wide,
+                    constants,
+                };
+                for param in &function.params {
+                    let slot = g.alloc(1)?;
+                    g.vars.define(param, slot);
+                }
+                for _ in 0..count_lets(&function.body) {
+                    g.out.push(stack::Instruction::LoadInt { value: 0 });
+                }
+                g.block(&function.body)?;
+                Ok((g.out, g.constants.unwrap_or_default()))
+            };
+            generate(false, constants.clone()).or_else(|_| generate(true, constants))
+        }
+
+        fn jump(&mut self, instr: stack::Instruction, pc: usize, line: usize) -> Result<usize, Error> {
+            if self.wide {
+                self.out.push(stack::Instruction::Wide { high: 0 });
+            }
+            self.out.push(instr);
+            let at = self.out.len() - 1;
+            self.patch(at, pc, line)?;
+            Ok(at)
+        }
+
+        fn patch(&mut self, at: usize, pc: usize, line: usize) -> Result<(), Error> {
+            let [low, high] = target(pc, self.wide, line)?;
+            if self.wide {
+                self.out[at - 1] = stack::Instruction::Wide { high };
+            }
+            *self.out[at].target_mut().unwrap() = low;
+            Ok(())
+        }
+
+        fn alloc(&mut self, line: usize) -> Result<u8, Error> {
+            let slot = u8::try_from(self.next).or_else(|_| error(line, "out of stack slots"))?;
+            self.next += 1;
+            Ok(slot)
+        }
+
+        fn block(&mut self, stmts: &'a [Stmt]) -> Result<(), Error> {
+            self.vars.scopes.push(HashMap::new());
+            for stmt in stmts {
+                self.stmt(stmt)?;
+            }
+            self.vars.scopes.pop();
+            Ok(())
+        }
+
+        fn stmt(&mut self, stmt: &'a Stmt) -> Result<(), Error> {
+            use stack::Instruction::*;
+            let line = stmt.line;
+            match &stmt.kind {
+                StmtKind::Let { name, value } => {
+                    self.expr(value, line)?;
+                    let dst = self.alloc(line)?;
+                    self.out.push(Store { dst });
+                    self.vars.define(name, dst);
+                }
+
+                StmtKind::Assign { name, value } => {
+                    let dst = self.vars.get(name, line)?;
+                    self.expr(value, line)?;
+                    self.out.push(Store { dst });
+                }
+
+                StmtKind::Repeat { count, cond, body } => {
+                    if self.in_loop {
+                        return error(line, "`repeat` loops can't be nested");
+                    }
+                    self.in_loop = true;
+
+                    self.expr(count, line)?;
+                    self.out.push(SetCounter);
+                    let jump = self.jump(Jump { target: 0 }, 0, line)?;
+
+                    let body_pc = self.out.len();
+                    self.block(body)?;
+
+                    self.patch(jump, self.out.len(), line)?;
+                    match cond {
+                        Some((lhs, rhs)) => {
+                            self.expr(lhs, line)?;
+                            self.expr(rhs, line)?;
+                            self.jump(LoopLe { target: 0 }, body_pc, line)?;
+                        }
+                        None => {
+                            self.jump(Loop { target: 0 }, body_pc, line)?;
+                        }
+                    }
+
+                    self.in_loop = false;
+                }
+
+                StmtKind::If { cond, then, els } => {
+                    self.expr(cond, line)?;
+                    let jump_else = self.jump(JumpIfNot { target: 0 }, 0, line)?;
+                    self.block(then)?;
+
+                    if els.is_empty() {
+                        self.patch(jump_else, self.out.len(), line)?;
+                    }
+                    else {
+                        let jump_end = self.jump(Jump { target: 0 }, 0, line)?;
+                        self.patch(jump_else, self.out.len(), line)?;
+                        self.block(els)?;
+                        self.patch(jump_end, self.out.len(), line)?;
+                    }
+                }
+
+                StmtKind::While { cond, body } => {
+                    let jump = self.jump(Jump { target: 0 }, 0, line)?;
+
+                    let body_pc = self.out.len();
+                    self.block(body)?;
+
+                    self.patch(jump, self.out.len(), line)?;
+                    self.expr(cond, line)?;
+                    self.jump(JumpIf { target: 0 }, body_pc, line)?;
+                }
+
+                StmtKind::Return { value } => {
+                    self.expr(value, line)?;
+                    self.out.push(Return);
+                }
+            }
+            Ok(())
+        }
+
+        fn expr(&mut self, e: &Expr, line: usize) -> Result<(), Error> {
+            use stack::Instruction::*;
+            match e {
+                Expr::Num(value) => {
+                    match literal(*value, &mut self.constants, line)? {
+                        Literal::Int(value) => stack::emit_int(&mut self.out, value),
+                        Literal::Const(index) => {
+                            let [index, high] = index.to_le_bytes();
+                            if high != 0 {
+                                self.out.push(Wide { high });
+                            }
+                            self.out.push(LoadConst { index });
+                        }
+                    }
+                }
+
+                Expr::Var(name) => {
+                    let src = self.vars.get(name, line)?;
+                    self.out.push(Load { src });
+                }
+
+                Expr::Counter => {
+                    self.out.push(GetCounter);
+                }
+
+                Expr::Neg(e) => {
+                    self.expr(e, line)?;
+                    self.out.push(Neg);
+                }
+
+                Expr::Unary(op, e) => {
+                    self.expr(e, line)?;
+                    self.out.push(match op {
+                        UnOp::Sqrt  => Sqrt,
+                        UnOp::Abs   => Abs,
+                        UnOp::Floor => Floor,
+                    });
+                }
+
+                Expr::Binary(op, lhs, rhs) => {
+                    self.expr(lhs, line)?;
+                    self.expr(rhs, line)?;
+                    self.out.push(match op {
+                        BinOp::Add => Add,
+                        BinOp::Sub => Sub,
+                        BinOp::Mul => Mul,
+                        BinOp::Div => Div,
+                        BinOp::Lt  => Lt,
+                        BinOp::Le  => Le,
+                        BinOp::Eq  => Eq,
+                        BinOp::Min => Min,
+                        BinOp::Max => Max,
+                    });
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn count_lets(stmts: &[Stmt]) -> usize {
+        stmts.iter().map(|stmt| match &stmt.kind {
+            StmtKind::Let { .. } => 1,
+            StmtKind::Repeat { body, .. } => count_lets(body),
+            StmtKind::If { then, els, .. } => count_lets(then) + count_lets(els),
+            StmtKind::While { body, .. } => count_lets(body),
+            _ => 0,
+        }).sum()
+    }
+}
+
+
+
+pub mod peephole {
+    //! rewrites of stack programs, like the ones that turn
+    //! `stack::MANDEL_SMART_NO_DUP` into `stack::MANDEL_SMART`.
+    //! rewrites never span a jump target.
+
+    use super::stack::{self, Instruction};
+    use Instruction::*;
+
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Stats {
+        /// `Load x; Load x` -> `Load x; Dup`
+        pub load_load: usize,
+        /// `Store x; Load x` -> `Dup; Store x`
+        pub store_load: usize,
+        /// `Swap; Swap` -> ``
+        pub swap_swap: usize,
+        /// `Load x; Pop` (or another push) -> ``
+        pub push_pop: usize,
+        /// `Nop` -> ``
+        pub nops: usize,
+    }
+
+    impl Stats {
+        pub fn total(&self) -> usize {
+            self.load_load + self.store_load + self.swap_swap + self.push_pop + self.nops
+        }
+    }
+
+    impl core::fmt::Display for Stats {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            writeln!(f, "load x; load x  -> load x; dup  {:5}", self.load_load)?;
+            writeln!(f, "store x; load x -> dup; store x {:5}", self.store_load)?;
+            writeln!(f, "swap; swap      ->              {:5}", self.swap_swap)?;
+            writeln!(f, "push; pop       ->              {:5}", self.push_pop)?;
+            writeln!(f, "nop             ->              {:5}", self.nops)
+        }
+    }
+
+
+    /// applies all rewrites until none match anymore.
+    /// programs with `Wide` prefixes are left alone.
+    /// `argc` is the program's, see `stack::verify`, since
+    /// `store x; load x` needs room on the stack for the `dup`.
+    pub fn optimize(code: &[Instruction], argc: usize) -> (Vec<Instruction>, Stats) {
+        let mut stats = Stats::default();
+        let mut code = code.to_vec();
+        if code.iter().any(|instr| matches!(instr, Wide { .. })) {
+            return (code, stats);
+        }
+        loop {
+            let before = stats.total();
+            code = pass(&code, argc, &mut stats);
+            if stats.total() == before {
+                return (code, stats);
+            }
+        }
+    }
+
+    fn pass(code: &[Instruction], argc: usize, stats: &mut Stats) -> Vec<Instruction> {
+        // `store x; load x` needs the depth, so it's skipped where
+        // there's none: in unreachable code, or code that doesn't verify.
+        let program = stack::verify(code, argc).ok();
+        let has_room = |pc: usize| {
+            program.as_ref().and_then(|program| program.depth(pc)).is_some_and(|depth| depth < stack::STACK_SIZE)
+        };
+
+        let mut is_target = vec![false; code.len() + 1];
+        for instr in code {
+            if let Some(target) = instr.target() {
+                if let Some(t) = is_target.get_mut(target as usize) {
+                    *t = true;
+                }
+            }
+        }
+
+        let mut out = Vec::with_capacity(code.len());
+        let mut map = vec![0; code.len() + 1];
+        let mut pc = 0;
+        while pc < code.len() {
+            map[pc] = out.len();
+
+            let a = code[pc];
+            let b = code.get(pc + 1).copied().filter(|_| !is_target[pc + 1]);
+
+            let consumed = match (a, b) {
+                (Nop, _) => {
+                    stats.nops += 1;
+                    1
+                }
+
+                (Load { src: x }, Some(Load { src: y })) if x == y => {
+                    stats.load_load += 1;
+                    out.extend([Load { src: x }, Dup]);
+                    2
+                }
+
+                (Store { dst: x }, Some(Load { src: y })) if x == y && has_room(pc) => {
+                    stats.store_load += 1;
+                    out.extend([Dup, Store { dst: x }]);
+                    2
+                }
+
+                (Swap, Some(Swap)) => {
+                    stats.swap_swap += 1;
+                    2
+                }
+
+                (Load { .. } | LoadInt { .. } | Dup | GetCounter, Some(Pop)) => {
+                    stats.push_pop += 1;
+                    2
+                }
+
+                _ => {
+                    out.push(a);
+                    1
+                }
+            };
+            if consumed == 2 {
+                map[pc + 1] = out.len();
+            }
+            pc += consumed;
+        }
+        map[code.len()] = out.len();
+
+        for instr in &mut out {
+            match instr {
+                Jump { target } | JumpIf { target } | JumpIfNot { target } |
+                Loop { target } | LoopLe { target } | Call { target } => {
+                    // verified code has its targets in range.
+                    *target = map.get(*target as usize).map_or(*target as usize, |t| *t) as u8;
+                }
+                _ => {}
+            }
+        }
+        out
+    }
+}
+
+
+
+pub mod regalloc {
+    //! register allocation for programs written against an unlimited
+    //! number of virtual registers.
+
+    use super::{reg, VerifyError};
+    use super::reg::BitSet;
+
+
+    pub type VReg = u32;
+
+    /// `reg::Instruction` with virtual registers.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Instruction {
+        LoadInt     { dst: VReg, value: i16 },
+        Copy        { dst: VReg, src: VReg },
+        Add         { dst: VReg, src1: VReg, src2: VReg },
+        Sub         { dst: VReg, src1: VReg, src2: VReg },
+        Mul         { dst: VReg, src1: VReg, src2: VReg },
+        Div         { dst: VReg, src1: VReg, src2: VReg },
+        Neg         { dst: VReg, src: VReg },
+        Lt          { dst: VReg, src1: VReg, src2: VReg },
+        Le          { dst: VReg, src1: VReg, src2: VReg },
+        Eq          { dst: VReg, src1: VReg, src2: VReg },
+        Sqrt        { dst: VReg, src: VReg },
+        Abs         { dst: VReg, src: VReg },
+        Floor       { dst: VReg, src: VReg },
+        Min         { dst: VReg, src1: VReg, src2: VReg },
+        Max         { dst: VReg, src1: VReg, src2: VReg },
+        Jump        { target: u8 },
+        JumpIf      { target: u8, src: VReg },
+        JumpIfNot   { target: u8, src: VReg },
+        SetCounter  { src: VReg },
+        GetCounter  { dst: VReg },
+        Loop        { target: u8 },
+        LoopLe      { target: u8, src1: VReg, src2: VReg },
+        Return      { src: VReg },
+        /// `base` is a physical register, `allocate` doesn't take calls.
+        Call        { target: u8, base: u8 },
+        Ret         { src: VReg },
+        LoadMem     { dst: VReg, addr: VReg },
+        StoreMem    { src: VReg, addr: VReg },
+        LoadConst   { dst: VReg, index: u8 },
+        /// `allocate` doesn't take these either.
+        Wide        { high: [u8; 3] },
+        MulAdd      { dst: VReg, src1: VReg, src2: VReg },
+        MulSub      { dst: VReg, src1: VReg, src2: VReg },
+        JumpIfLt    { target: u8, src1: VReg, src2: VReg },
+        JumpIfLe    { target: u8, src1: VReg, src2: VReg },
+    }
+
+    impl Instruction {
+        pub fn lift(instr: reg::Instruction) -> Instruction {
+            use reg::Instruction as R;
+            use Instruction::*;
+            let v = |r: u8| r as VReg;
+            match instr {
+                R::LoadInt    { dst, value }         => LoadInt    { dst: v(dst), value },
+                R::Copy       { dst, src }           => Copy       { dst: v(dst), src: v(src) },
+                R::Add        { dst, src1, src2 }    => Add        { dst: v(dst), src1: v(src1), src2: v(src2) },
+                R::Sub        { dst, src1, src2 }    => Sub        { dst: v(dst), src1: v(src1), src2: v(src2) },
+                R::Mul        { dst, src1, src2 }    => Mul        { dst: v(dst), src1: v(src1), src2: v(src2) },
+                R::Div        { dst, src1, src2 }    => Div        { dst: v(dst), src1: v(src1), src2: v(src2) },
+                R::Neg        { dst, src }           => Neg        { dst: v(dst), src: v(src) },
+                R::Lt         { dst, src1, src2 }    => Lt         { dst: v(dst), src1: v(src1), src2: v(src2) },
+                R::Le         { dst, src1, src2 }    => Le         { dst: v(dst), src1: v(src1), src2: v(src2) },
+                R::Eq         { dst, src1, src2 }    => Eq         { dst: v(dst), src1: v(src1), src2: v(src2) },
+                R::Sqrt       { dst, src }           => Sqrt       { dst: v(dst), src: v(src) },
+                R::Abs        { dst, src }           => Abs        { dst: v(dst), src: v(src) },
+                R::Floor      { dst, src }           => Floor      { dst: v(dst), src: v(src) },
+                R::Min        { dst, src1, src2 }    => Min        { dst: v(dst), src1: v(src1), src2: v(src2) },
+                R::Max        { dst, src1, src2 }    => Max        { dst: v(dst), src1: v(src1), src2: v(src2) },
+                R::Jump       { target }             => Jump       { target },
+                R::JumpIf     { target, src }        => JumpIf     { target, src: v(src) },
+                R::JumpIfNot  { target, src }        => JumpIfNot  { target, src: v(src) },
+                R::SetCounter { src }                => SetCounter { src: v(src) },
+                R::GetCounter { dst }                => GetCounter { dst: v(dst) },
+                R::Loop       { target }             => Loop       { target },
+                R::LoopLe     { target, src1, src2 } => LoopLe     { target, src1: v(src1), src2: v(src2) },
+                R::Return     { src }                => Return     { src: v(src) },
+                R::Call       { target, base }       => Call       { target, base },
+                R::Ret        { src }                => Ret        { src: v(src) },
+                R::LoadMem    { dst, addr }          => LoadMem    { dst: v(dst), addr: v(addr) },
+                R::StoreMem   { src, addr }          => StoreMem   { src: v(src), addr: v(addr) },
+                R::LoadConst  { dst, index }         => LoadConst  { dst: v(dst), index },
+                R::Wide       { high }               => Wide       { high },
+                R::MulAdd     { dst, src1, src2 }    => MulAdd     { dst: v(dst), src1: v(src1), src2: v(src2) },
+                R::MulSub     { dst, src1, src2 }    => MulSub     { dst: v(dst), src1: v(src1), src2: v(src2) },
+                R::JumpIfLt   { target, src1, src2 } => JumpIfLt   { target, src1: v(src1), src2: v(src2) },
+                R::JumpIfLe   { target, src1, src2 } => JumpIfLe   { target, src1: v(src1), src2: v(src2) },
+            }
+        }
+
+        pub fn lower(&self, mut f: impl FnMut(VReg) -> u8) -> reg::Instruction {
+            use reg::Instruction as R;
+            use Instruction::*;
+            match *self {
+                LoadInt    { dst, value }         => R::LoadInt    { dst: f(dst), value },
+                Copy       { dst, src }           => R::Copy       { dst: f(dst), src: f(src) },
+                Add        { dst, src1, src2 }    => R::Add        { dst: f(dst), src1: f(src1), src2: f(src2) },
+                Sub        { dst, src1, src2 }    => R::Sub        { dst: f(dst), src1: f(src1), src2: f(src2) },
+                Mul        { dst, src1, src2 }    => R::Mul        { dst: f(dst), src1: f(src1), src2: f(src2) },
+                Div        { dst, src1, src2 }    => R::Div        { dst: f(dst), src1: f(src1), src2: f(src2) },
+                Neg        { dst, src }           => R::Neg        { dst: f(dst), src: f(src) },
+                Lt         { dst, src1, src2 }    => R::Lt         { dst: f(dst), src1: f(src1), src2: f(src2) },
+                Le         { dst, src1, src2 }    => R::Le         { dst: f(dst), src1: f(src1), src2: f(src2) },
+                Eq         { dst, src1, src2 }    => R::Eq         { dst: f(dst), src1: f(src1), src2: f(src2) },
+                Sqrt       { dst, src }           => R::Sqrt       { dst: f(dst), src: f(src) },
+                Abs        { dst, src }           => R::Abs        { dst: f(dst), src: f(src) },
+                Floor      { dst, src }           => R::Floor      { dst: f(dst), src: f(src) },
+                Min        { dst, src1, src2 }    => R::Min        { dst: f(dst), src1: f(src1), src2: f(src2) },
+                Max        { dst, src1, src2 }    => R::Max        { dst: f(dst), src1: f(src1), src2: f(src2) },
+                Jump       { target }             => R::Jump       { target },
+                JumpIf     { target, src }        => R::JumpIf     { target, src: f(src) },
+                JumpIfNot  { target, src }        => R::JumpIfNot  { target, src: f(src) },
+                SetCounter { src }                => R::SetCounter { src: f(src) },
+                GetCounter { dst }                => R::GetCounter { dst: f(dst) },
+                Loop       { target }             => R::Loop       { target },
+                LoopLe     { target, src1, src2 } => R::LoopLe     { target, src1: f(src1), src2: f(src2) },
+                Return     { src }                => R::Return     { src: f(src) },
+                Call       { target, base }       => R::Call       { target, base },
+                Ret        { src }                => R::Ret        { src: f(src) },
+                LoadMem    { dst, addr }          => R::LoadMem    { dst: f(dst), addr: f(addr) },
+                StoreMem   { src, addr }          => R::StoreMem   { src: f(src), addr: f(addr) },
+                LoadConst  { dst, index }         => R::LoadConst  { dst: f(dst), index },
+                Wide       { high }               => R::Wide       { high },
+                MulAdd     { dst, src1, src2 }    => R::MulAdd     { dst: f(dst), src1: f(src1), src2: f(src2) },
+                MulSub     { dst, src1, src2 }    => R::MulSub     { dst: f(dst), src1: f(src1), src2: f(src2) },
+                JumpIfLt   { target, src1, src2 } => R::JumpIfLt   { target, src1: f(src1), src2: f(src2) },
+                JumpIfLe   { target, src1, src2 } => R::JumpIfLe   { target, src1: f(src1), src2: f(src2) },
+            }
+        }
+
+        pub fn target(&self) -> Option<u8> {
+            self.lower(|_| 0).target()
+        }
+
+        pub fn falls_through(&self) -> bool {
+            self.lower(|_| 0).falls_through()
+        }
+
+        pub fn dst(&self) -> Option<VReg> {
+            use Instruction::*;
+            match *self {
+                LoadInt { dst, .. } | Copy { dst, .. } | GetCounter { dst } |
+                Add { dst, .. } | Sub { dst, .. } | Mul { dst, .. } | Div { dst, .. } | Neg { dst, .. } |
+                Lt { dst, .. } | Le { dst, .. } | Eq { dst, .. } |
+                Sqrt { dst, .. } | Abs { dst, .. } | Floor { dst, .. } | Min { dst, .. } | Max { dst, .. } |
+                LoadMem { dst, .. } | LoadConst { dst, .. } |
+                MulAdd { dst, .. } | MulSub { dst, .. } => Some(dst),
+                _ => None,
+            }
+        }
+
+        pub fn srcs(&self) -> impl Iterator<Item = VReg> {
+            use Instruction::*;
+            let srcs = match *self {
+                Copy { src, .. } | SetCounter { src } | Return { src } | Ret { src } |
+                Neg { src, .. } | Sqrt { src, .. } | Abs { src, .. } | Floor { src, .. } |
+                JumpIf { src, .. } | JumpIfNot { src, .. } |
+                LoadMem { addr: src, .. } => [Some(src), None, None],
+                Add { src1, src2, .. } | Sub { src1, src2, .. } | Mul { src1, src2, .. } | Div { src1, src2, .. } |
+                Lt { src1, src2, .. } | Le { src1, src2, .. } | Eq { src1, src2, .. } |
+                Min { src1, src2, .. } | Max { src1, src2, .. } |
+                LoopLe { src1, src2, .. } | JumpIfLt { src1, src2, .. } | JumpIfLe { src1, src2, .. } |
+                StoreMem { src: src1, addr: src2 } => [Some(src1), Some(src2), None],
+                MulAdd { dst, src1, src2 } | MulSub { dst, src1, src2 } => [Some(src1), Some(src2), Some(dst)],
+                _ => [None, None, None],
+            };
+            srcs.into_iter().flatten()
+        }
+    }
+
+    impl reg::Dataflow for Instruction {
+        fn target(&self) -> Option<usize> { Instruction::target(self).map(usize::from) }
+        fn falls_through(&self) -> bool { Instruction::falls_through(self) }
+        fn dst(&self) -> Option<usize> { Instruction::dst(self).map(|dst| dst as usize) }
+        fn srcs(&self) -> impl Iterator<Item = usize> { Instruction::srcs(self).map(|src| src as usize) }
+    }
+
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum AllocError {
+        Verify(VerifyError),
+        /// more values are live at the same time than there are registers.
+        NeedsSpill { required: usize, available: usize },
+        /// a `Call` at `pc`, its window would pin the registers above `base`.
+        /// or a `Wide`, whose operands would have to be allocated together.
+        Unsupported { pc: usize },
+    }
+
+    impl From<VerifyError> for AllocError {
+        fn from(e: VerifyError) -> Self { AllocError::Verify(e) }
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Allocation {
+        pub code: Vec<reg::Instruction>,
+        /// physical register for each virtual register.
+        pub assignment: Vec<u8>,
+        pub registers: usize,
+        /// copies that became no-ops and were removed.
+        pub removed_copies: usize,
+    }
+
+
+    /// assigns physical registers, using as few as it can.
+    /// the first `params` virtual registers are the arguments
+    /// and stay in registers `0..params`.
+    pub fn allocate(code: &[Instruction], params: usize, max_registers: Option<usize>) -> Result<Allocation, AllocError> {
+        let available = max_registers.unwrap_or(reg::REGISTER_COUNT).min(reg::REGISTER_COUNT);
+
+        let vregs = code.iter()
+            .flat_map(|instr| instr.dst().into_iter().chain(instr.srcs()))
+            .map(|r| r as usize + 1)
+            .max().unwrap_or(0)
+            .max(params);
+
+        if params > available {
+            return Err(AllocError::NeedsSpill { required: params, available });
+        }
+
+        // catch bad targets before the analysis.
+        reg::verify(&code.iter().map(|instr| instr.lower(|_| 0)).collect::<Vec<_>>())?;
+        if let Some(pc) = code.iter().position(|instr| matches!(instr, Instruction::Call { .. } | Instruction::Wide { .. })) {
+            return Err(AllocError::Unsupported { pc });
+        }
+
+        let live_out = reg::liveness(code, vregs);
+
+        // interference.
+        let mut graph = vec![BitSet::new(vregs); vregs];
+        let mut edge = |a: usize, b: usize| {
+            if a != b {
+                graph[a].insert(b);
+                graph[b].insert(a);
+            }
+        };
+
+        let mut entry = live_out.first().cloned().unwrap_or(BitSet::new(vregs));
+        if let Some(instr) = code.first() {
+            if let Some(dst) = instr.dst() { entry.remove(dst as usize) }
+            for src in instr.srcs() { entry.insert(src as usize) }
+        }
+        for p in 0..params {
+            entry.insert(p);
+        }
+        for a in entry.iter() {
+            for b in entry.iter() {
+                edge(a, b);
+            }
+        }
+
+        let mut hints = vec![None; vregs];
+        for (pc, instr) in code.iter().enumerate() {
+            let Some(dst) = instr.dst() else { continue };
+            let copy_src = match *instr {
+                Instruction::Copy { src, .. } => Some(src as usize),
+                _ => None,
+            };
+            for live in live_out[pc].iter() {
+                if Some(live) != copy_src {
+                    edge(dst as usize, live);
+                }
+            }
+            if let Some(src) = copy_src {
+                hints[dst as usize] = Some(src);
+                hints[src].get_or_insert(dst as usize);
+            }
+        }
+
+        // smallest-last order.
+        let mut order = Vec::with_capacity(vregs);
+        let mut degree: Vec<usize> = graph.iter().map(|n| n.len()).collect();
+        let mut removed = vec![false; vregs];
+        for _ in params..vregs {
+            let next = (params..vregs)
+                .filter(|v| !removed[*v])
+                .min_by_key(|v| degree[*v])
+                .unwrap();
+            removed[next] = true;
+            order.push(next);
+            for n in graph[next].iter() {
+                degree[n] -= 1;
+            }
+        }
+
+        let mut color: Vec<Option<usize>> = (0..vregs).map(|v| (v < params).then_some(v)).collect();
+        for &v in order.iter().rev() {
+            let mut used = BitSet::new(vregs + 1);
+            for n in graph[v].iter() {
+                if let Some(c) = color[n] {
+                    used.insert(c);
+                }
+            }
+
+            let hint = hints[v].and_then(|h| color[h]).filter(|c| !used.contains(*c));
+            color[v] = Some(hint.unwrap_or_else(|| (0..).find(|c| !used.contains(*c)).unwrap()));
+        }
+
+        let assignment: Vec<usize> = color.into_iter().map(Option::unwrap).collect();
+        let registers = assignment.iter().map(|c| c + 1).max().unwrap_or(0);
+        if registers > available {
+            return Err(AllocError::NeedsSpill { required: registers, available });
+        }
+        let assignment: Vec<u8> = assignment.into_iter().map(|c| c as u8).collect();
+
+        // rewrite, dropping copies between the same register.
+        let mut out = Vec::with_capacity(code.len());
+        let mut map = Vec::with_capacity(code.len());
+        for instr in code {
+            map.push(out.len());
+            let instr = instr.lower(|v| assignment[v as usize]);
+            if let reg::Instruction::Copy { dst, src } = instr {
+                if dst == src { continue }
+            }
+            out.push(instr);
+        }
+        let removed_copies = code.len() - out.len();
+
+        for instr in &mut out {
+            if let Some(target) = instr.target_mut() {
+                // unreachable code can have any target.
+                *target = map.get(*target as usize).map_or(*target as usize, |t| *t) as u8;
+            }
+        }
+
+        Ok(Allocation { code: out, assignment, registers, removed_copies })
+    }
+}
+
+
+
+pub mod trace {
+    //! records of checked executions, one entry per step,
+    //! written as json for generating the motion canvas scenes.
+
+    use core::fmt::Write;
+    use super::{reg, stack, disasm, VmError};
+
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Step {
+        pub pc: usize,
+        /// the instruction, as `disasm` formats it.
+        pub instruction: String,
+        /// `(register or stack slot, value)` before the step.
+        pub reads: Vec<(usize, f64)>,
+        /// `(register or stack slot, value)` after the step.
+        pub writes: Vec<(usize, f64)>,
+        /// the counter after the step.
+        pub counter: u32,
+        /// the whole stack after the step (stack vm only).
+        pub stack: Option<Vec<f64>>,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Trace {
+        /// `"reg"` or `"stack"`.
+        pub isa: &'static str,
+        /// the program, one formatted instruction per pc.
+        pub code: Vec<String>,
+        pub args: Vec<f64>,
+        pub steps: Vec<Step>,
+        /// `None` if the trace was cut off at `max_steps`.
+        pub result: Option<Result<f64, VmError>>,
+    }
+
+    impl reg::Vm {
+        /// runs `code` like `try_run`, recording at most `max_steps` steps.
+        pub fn trace(&mut self, code: &[reg::Instruction], args: &[f64], max_steps: usize) -> Trace {
+            let labels = disasm::Labels::reg(code);
+            let format = |instr: &reg::Instruction| {
+                let (mnemonic, operands) = disasm::format_reg(instr, &labels);
+                join(mnemonic, &operands)
+            };
+
+            let mut trace = Trace {
+                isa: "reg",
+                code: code.iter().map(format).collect(),
+                args: args.into(),
+                steps: vec![],
+                result: None,
+            };
+
+            let mut cursor = match self.start(args) {
+                Ok(cursor) => cursor,
+                Err(e) => {
+                    trace.result = Some(Err(e));
+                    return trace;
+                }
+            };
+
+            while trace.steps.len() < max_steps {
+                let pc = cursor.pc;
+                if pc >= code.len() {
+                    trace.result = Some(Err(VmError::NoReturn { pc }));
+                    break;
+                }
+                // a `Wide` and the instruction it extends are one step.
+                // a bad prefix fails in `step`.
+                let (instr, high, _) = reg::decode(code, pc).unwrap_or((code[pc], [0; 3], 1));
+
+                // indices are into the whole register file.
+                let base = cursor.base;
+                let reads = instr.wide_srcs(high)
+                    .filter_map(|src| Some((base + src, *self.registers().get(base + src)?)))
+                    .collect();
+
+                let done = self.step(code, &mut cursor);
+
+                // a `Ret` from a call writes the callee's `r0`.
+                let dst = match (instr, &done) {
+                    (reg::Instruction::Ret { .. }, Ok(None)) => Some(0),
+                    _ => instr.wide_dst(high),
+                };
+                let writes = dst
+                    .and_then(|dst| Some((base + dst, *self.registers().get(base + dst)?)))
+                    .into_iter().collect();
+
+                match done {
+                    Ok(done) => {
+                        let (mnemonic, operands) = disasm::format_reg_wide(&instr, high, &labels);
+                        trace.steps.push(Step {
+                            pc,
+                            instruction: join(mnemonic, &operands),
+                            reads,
+                            writes,
+                            counter: cursor.counter,
+                            stack: None,
+                        });
+                        if let Some(result) = done {
+                            trace.result = Some(Ok(result));
+                            break;
+                        }
+                    }
+
+                    Err(e) => {
+                        trace.result = Some(Err(e));
+                        break;
+                    }
+                }
+            }
+            trace
+        }
+    }
+
+    impl stack::Vm {
+        /// runs `code` like `try_run`, recording at most `max_steps` steps.
+        pub fn trace(&mut self, code: &[stack::Instruction], args: &[f64], max_steps: usize) -> Trace {
+            let labels = disasm::Labels::stack(code);
+            let format = |instr: &stack::Instruction| {
+                let (mnemonic, operands) = disasm::format_stack(instr, &labels);
+                join(mnemonic, &operands)
+            };
+
+            let mut trace = Trace {
+                isa: "stack",
+                code: code.iter().map(format).collect(),
+                args: args.into(),
+                steps: vec![],
+                result: None,
+            };
+
+            let mut cursor = match self.start(args) {
+                Ok(cursor) => cursor,
+                Err(e) => {
+                    trace.result = Some(Err(e));
+                    return trace;
+                }
+            };
+
+            while trace.steps.len() < max_steps {
+                let pc = cursor.pc;
+                if pc >= code.len() {
+                    trace.result = Some(Err(VmError::NoReturn { pc }));
+                    break;
+                }
+                // like for reg, a bad prefix fails in `step`.
+                let (instr, high, len) = stack::decode(code, pc).unwrap_or((code[pc], 0, 1));
+
+                // reads are the popped values and the loaded slot,
+                // writes the pushed values and the stored slot.
+                // a bad `Call` fails in `step`.
+                let (pops, pushes) = instr.stack_effect(code, high).unwrap_or((0, 0));
+                let before = self.stack().len();
+                let fp = cursor.fp;
+
+                let mut reads: Vec<(usize, f64)> = vec![];
+                if let stack::Instruction::Load { src } = instr {
+                    let src = fp + src as usize;
+                    reads.extend(self.stack().get(src).map(|v| (src, *v)));
+                }
+                let popped = before.saturating_sub(pops)..before;
+                reads.extend(popped.map(|i| (i, self.stack()[i])));
+
+                let done = self.step(code, &mut cursor);
+
+                let after = self.stack().len();
+                let mut writes: Vec<(usize, f64)> = vec![];
+                if let stack::Instruction::Store { dst } = instr {
+                    let dst = fp + dst as usize;
+                    writes.extend(self.stack().get(dst).map(|v| (dst, *v)));
+                }
+                // a call's arguments stay where they are,
+                // and a `Ret` from a call pushes the result.
+                let pushes = match (instr, &done) {
+                    (stack::Instruction::Call { .. }, _)        => 0,
+                    (stack::Instruction::Ret,         Ok(None)) => 1,
+                    _ => pushes,
+                };
+                let pushed = after.saturating_sub(pushes)..after;
+                writes.extend(pushed.map(|i| (i, self.stack()[i])));
+
+                match done {
+                    Ok(done) => {
+                        let high = (len == 2).then_some(high);
+                        let (mnemonic, operands) = disasm::format_stack_wide(&instr, high, &labels);
+                        trace.steps.push(Step {
+                            pc,
+                            instruction: join(mnemonic, &operands),
+                            reads,
+                            writes,
+                            counter: cursor.counter,
+                            stack: Some(self.stack().into()),
+                        });
+                        if let Some(result) = done {
+                            trace.result = Some(Ok(result));
+                            break;
+                        }
+                    }
+
+                    Err(e) => {
+                        trace.result = Some(Err(e));
+                        break;
+                    }
+                }
+            }
+            trace
+        }
+    }
+
+    fn join(mnemonic: &str, operands: &str) -> String {
+        if operands.is_empty() { mnemonic.into() }
+        else                   { format!("{} {}", mnemonic, operands) }
+    }
+
+
+    impl Trace {
+        /// one step per line, so diffs of traces stay readable.
+        pub fn to_json(&self) -> String {
+            let mut out = String::new();
+            writeln!(out, "{{").unwrap();
+            writeln!(out, "  \"isa\": {},", string(self.isa)).unwrap();
+
+            let code: Vec<String> = self.code.iter().map(|line| string(line)).collect();
+            writeln!(out, "  \"code\": [{}],", code.join(", ")).unwrap();
+
+            let args: Vec<String> = self.args.iter().map(|v| number(*v)).collect();
+            writeln!(out, "  \"args\": [{}],", args.join(", ")).unwrap();
+
+            writeln!(out, "  \"steps\": [").unwrap();
+            for (i, step) in self.steps.iter().enumerate() {
+                let accesses = |accesses: &[(usize, f64)]| {
+                    let accesses: Vec<String> = accesses.iter()
+                        .map(|(index, value)| format!("{{\"index\": {}, \"value\": {}}}", index, number(*value)))
+                        .collect();
+                    format!("[{}]", accesses.join(", "))
+                };
+
+                write!(out, "    {{\"pc\": {}, \"instruction\": {}, \"reads\": {}, \"writes\": {}, \"counter\": {}",
+                    step.pc, string(&step.instruction), accesses(&step.reads), accesses(&step.writes), step.counter).unwrap();
+                if let Some(stack) = &step.stack {
+                    let stack: Vec<String> = stack.iter().map(|v| number(*v)).collect();
+                    write!(out, ", \"stack\": [{}]", stack.join(", ")).unwrap();
+                }
+                let comma = if i + 1 < self.steps.len() { "," } else { "" };
+                writeln!(out, "}}{}", comma).unwrap();
+            }
+            writeln!(out, "  ],").unwrap();
+
+            match &self.result {
+                Some(Ok(result)) => writeln!(out, "  \"result\": {}", number(*result)).unwrap(),
+                Some(Err(e))     => writeln!(out, "  \"error\": {}", string(&format!("{:?}", e))).unwrap(),
+                None             => writeln!(out, "  \"result\": null").unwrap(),
+            }
+            writeln!(out, "}}").unwrap();
+            out
+        }
+    }
+
+    fn number(value: f64) -> String {
+        // json has no inf or nan.
+        if value.is_finite() { format!("{}", value) }
+        else                 { "null".into() }
+    }
+
+    fn string(value: &str) -> String {
+        let mut out = String::from("\"");
+        for c in value.chars() {
+            match c {
+                '"'  => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+                c    => out.push(c),
+            }
+        }
+        out.push('"');
+        out
+    }
+}
+
+
+
+pub mod profile {
+    //! execution counts from `Vm::run_profiled`,
+    //! summarized into comparable `Report`s.
+
+    use core::fmt::Write;
+    use super::{reg, stack, disasm};
+
+
+    /// raw counts, accumulated over any number of runs of one program.
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct Profile {
+        pub runs: u64,
+        /// how often each pc was executed.
+        pub executed: Vec<u64>,
+        /// how often the branch (`loop`, `loop_le`, and the conditional jumps)
+        /// at each pc jumped.
+        pub taken: Vec<u64>,
+    }
+
+    impl Profile {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// sizes the counters for a program of `len` instructions
+        /// on the first run, and counts the run.
+        pub fn prepare(&mut self, len: usize) {
+            if self.executed.is_empty() {
+                self.executed = vec![0; len];
+                self.taken    = vec![0; len];
+            }
+            assert_eq!(self.executed.len(), len, "profile is for a different program");
+            self.runs += 1;
+        }
+    }
+
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct PcCount {
+        pub pc: usize,
+        pub instruction: String,
+        pub executed: u64,
+        pub taken: u64,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Report {
+        /// `"reg"` or `"stack"`.
+        pub isa: &'static str,
+        pub runs: u64,
+        /// instructions executed, one dispatch each.
+        pub dispatches: u64,
+        /// executions per mnemonic, most executed first.
+        pub per_op: Vec<(&'static str, u64)>,
+        pub per_pc: Vec<PcCount>,
+        /// of the branches, see `Profile::taken`.
+        pub taken: u64,
+        pub not_taken: u64,
+        /// values read from registers or the stack.
+        pub loads: u64,
+        /// values written to registers or the stack.
+        pub stores: u64,
+    }
+
+    pub fn report_reg(code: &[reg::Instruction], profile: &Profile) -> Report {
+        let labels = disasm::Labels::reg(code);
+        let mut instrs = vec![];
+        let mut pc = 0;
+        while pc < code.len() {
+            use reg::Instruction::*;
+            // unverified code can have a bad prefix, that's one pc then.
+            let (instr, high, len) = reg::decode(code, pc).unwrap_or((code[pc], [0; 3], 1));
+            let (mnemonic, operands) = disasm::format_reg_wide(&instr, high, &labels);
+            let is_branch = matches!(instr, Loop { .. } | LoopLe { .. } | JumpIf { .. } | JumpIfNot { .. } | JumpIfLt { .. } | JumpIfLe { .. });
+            let loads  = instr.srcs().count() as u64;
+            let stores = instr.dst().is_some() as u64;
+            instrs.push(Row { pcs: pc..pc + len, mnemonic, operands, is_branch, loads, stores });
+            pc += len;
+        }
+        report("reg", instrs, profile)
+    }
+
+    pub fn report_stack(code: &[stack::Instruction], profile: &Profile) -> Report {
+        let labels = disasm::Labels::stack(code);
+        let mut instrs = vec![];
+        let mut pc = 0;
+        while pc < code.len() {
+            use stack::Instruction::*;
+            let (instr, high, len) = stack::decode(code, pc).unwrap_or((code[pc], 0, 1));
+            let (mnemonic, operands) = disasm::format_stack_wide(&instr, (len == 2).then_some(high), &labels);
+            let is_branch = matches!(instr, Loop { .. } | LoopLe { .. } | JumpIf { .. } | JumpIfNot { .. });
+            // pops read, pushes write, plus the slot of `load`/`store`.
+            let (pops, pushes) = instr.stack_effect(code, high).unwrap_or((0, 0));
+            let loads  = pops   as u64 + matches!(instr, Load  { .. }) as u64;
+            let stores = pushes as u64 + matches!(instr, Store { .. }) as u64;
+            instrs.push(Row { pcs: pc..pc + len, mnemonic, operands, is_branch, loads, stores });
+            pc += len;
+        }
+        report("stack", instrs, profile)
+    }
+
+    /// an instruction of a report. a `Wide` prefix and the instruction it
+    /// extends are one, at the prefix's pc, like in traces.
+    struct Row {
+        pcs:       core::ops::Range<usize>,
+        mnemonic:  &'static str,
+        operands:  String,
+        is_branch: bool,
+        loads:     u64,
+        stores:    u64,
+    }
+
+    fn report(isa: &'static str, instrs: Vec<Row>, profile: &Profile) -> Report {
+        let mut result = Report {
+            isa,
+            runs: profile.runs,
+            dispatches: 0,
+            per_op: vec![],
+            per_pc: vec![],
+            taken: 0,
+            not_taken: 0,
+            loads: 0,
+            stores: 0,
+        };
+
+        for Row { pcs, mnemonic, operands, is_branch, loads, stores } in instrs {
+            let pc = pcs.start;
+            let executed: u64 = pcs.clone().map(|pc| profile.executed.get(pc).copied().unwrap_or(0)).sum();
+            let taken:    u64 = pcs.map(|pc| profile.taken.get(pc).copied().unwrap_or(0)).sum();
+
+            result.dispatches += executed;
+            result.loads      += executed * loads;
+            result.stores     += executed * stores;
+            if is_branch {
+                result.taken     += taken;
+                result.not_taken += executed - taken;
+            }
+
+            match result.per_op.iter_mut().find(|(op, _)| *op == mnemonic) {
+                Some((_, count)) => *count += executed,
+                None             => result.per_op.push((mnemonic, executed)),
+            }
+
+            let instruction =
+                if operands.is_empty() { mnemonic.into() }
+                else                   { format!("{} {}", mnemonic, operands) };
+            result.per_pc.push(PcCount { pc, instruction, executed, taken });
+        }
+
+        // stable, so ties stay in program order.
+        result.per_op.sort_by_key(|&(_, count)| core::cmp::Reverse(count));
+        result
+    }
+
+    impl core::fmt::Display for Report {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            writeln!(f, "{} vm, {} runs", self.isa, self.runs)?;
+            writeln!(f, "{:<20}{:>12}", "dispatches", self.dispatches)?;
+            writeln!(f, "{:<20}{:>12}", "branches taken", self.taken)?;
+            writeln!(f, "{:<20}{:>12}", "branches not taken", self.not_taken)?;
+            writeln!(f, "{:<20}{:>12}", "loads", self.loads)?;
+            writeln!(f, "{:<20}{:>12}", "stores", self.stores)?;
+
+            writeln!(f)?;
+            for (op, count) in &self.per_op {
+                writeln!(f, "{:<20}{:>12}  {:5.1}%", op, count, percent(*count, self.dispatches))?;
+            }
+
+            writeln!(f)?;
+            for pc in &self.per_pc {
+                write!(f, "{:3}  {:<28}{:>12}", pc.pc, pc.instruction, pc.executed)?;
+                if pc.taken > 0 {
+                    write!(f, "  taken {}", pc.taken)?;
+                }
+                writeln!(f)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// the totals of two reports side by side, with the ratio `b / a`.
+    pub fn compare(a: &Report, b: &Report) -> String {
+        let mut out = String::new();
+        writeln!(out, "{:<20}{:>12}{:>12}{:>8}", "", a.isa, b.isa, "ratio").unwrap();
+
+        let row = |out: &mut String, name: &str, a: u64, b: u64| {
+            let ratio = if a == 0 { String::from("-") } else { format!("{:.2}", b as f64 / a as f64) };
+            writeln!(out, "{:<20}{:>12}{:>12}{:>8}", name, a, b, ratio).unwrap();
+        };
+        row(&mut out, "runs",               a.runs,       b.runs);
+        row(&mut out, "dispatches",         a.dispatches, b.dispatches);
+        row(&mut out, "branches taken",     a.taken,      b.taken);
+        row(&mut out, "branches not taken", a.not_taken,  b.not_taken);
+        row(&mut out, "loads",              a.loads,      b.loads);
+        row(&mut out, "stores",             a.stores,     b.stores);
+        out
+    }
+
+    fn percent(count: u64, total: u64) -> f64 {
+        if total == 0 { 0.0 } else { count as f64 * 100.0 / total as f64 }
+    }
+}
+
+
+
+pub mod debug {
+    //! a step debugger over the checked `start`/`step` api of either vm.
+
+    use super::{reg, stack, VmError};
+
+
+    /// what the debugger needs from a vm.
+    pub trait Machine {
+        type Instruction: Copy;
+        type Cursor: Copy;
+
+        fn start(&mut self, args: &[f64]) -> Result<Self::Cursor, VmError>;
+        fn step(&mut self, code: &[Self::Instruction], cursor: &mut Self::Cursor) -> Result<Option<f64>, VmError>;
+        fn pc(cursor: &Self::Cursor) -> usize;
+        fn counter(cursor: &Self::Cursor) -> u32;
+        /// the registers, or the operand stack.
+        fn slots(&self) -> &[f64];
+    }
+
+    impl Machine for reg::Vm {
+        type Instruction = reg::Instruction;
+        type Cursor = reg::Cursor;
+
+        fn start(&mut self, args: &[f64]) -> Result<reg::Cursor, VmError> { reg::Vm::start(self, args) }
+        fn step(&mut self, code: &[reg::Instruction], cursor: &mut reg::Cursor) -> Result<Option<f64>, VmError> { reg::Vm::step(self, code, cursor) }
+        fn pc(cursor: &reg::Cursor) -> usize { cursor.pc }
+        fn counter(cursor: &reg::Cursor) -> u32 { cursor.counter }
+        fn slots(&self) -> &[f64] { self.registers() }
+    }
+
+    impl Machine for stack::Vm {
+        type Instruction = stack::Instruction;
+        type Cursor = stack::Cursor;
+
+        fn start(&mut self, args: &[f64]) -> Result<stack::Cursor, VmError> { stack::Vm::start(self, args) }
+        fn step(&mut self, code: &[stack::Instruction], cursor: &mut stack::Cursor) -> Result<Option<f64>, VmError> { stack::Vm::step(self, code, cursor) }
+        fn pc(cursor: &stack::Cursor) -> usize { cursor.pc }
+        fn counter(cursor: &stack::Cursor) -> u32 { cursor.counter }
+        fn slots(&self) -> &[f64] { self.stack() }
+    }
+
+
+    /// why the debugger stopped.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Stop {
+        /// `step` executed one instruction.
+        Step,
+        /// the instruction at `pc` is next.
+        Breakpoint { pc: usize },
+        /// the last instruction changed these watched slots,
+        /// in the order they were watched.
+        Watchpoint (Vec<Change>),
+        Returned   (f64),
+    }
+
+    /// `None` is a stack slot that doesn't exist.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Change {
+        pub slot: usize,
+        pub old:  Option<f64>,
+        pub new:  Option<f64>,
+    }
+
+    pub struct Debugger<M: Machine> {
+        vm: M,
+        code: Vec<M::Instruction>,
+        cursor: M::Cursor,
+        breakpoints: Vec<usize>,
+        watchpoints: Vec<usize>,
+        done: Option<Result<f64, VmError>>,
+    }
+
+    impl<M: Machine> Debugger<M> {
+        pub fn new(mut vm: M, code: &[M::Instruction], args: &[f64]) -> Result<Self, VmError> {
+            let cursor = vm.start(args)?;
+            Ok(Debugger {
+                vm,
+                code: code.into(),
+                cursor,
+                breakpoints: vec![],
+                watchpoints: vec![],
+                done: None,
+            })
+        }
+
+        pub fn set_breakpoint(&mut self, pc: usize) {
+            if !self.breakpoints.contains(&pc) {
+                self.breakpoints.push(pc);
+            }
+        }
+
+        pub fn clear_breakpoint(&mut self, pc: usize) {
+            self.breakpoints.retain(|bp| *bp != pc);
+        }
+
+        /// stops when a register (or stack slot) changes.
+        pub fn watch(&mut self, slot: usize) {
+            if !self.watchpoints.contains(&slot) {
+                self.watchpoints.push(slot);
+            }
+        }
+
+        pub fn unwatch(&mut self, slot: usize) {
+            self.watchpoints.retain(|wp| *wp != slot);
+        }
+
+        /// executes one instruction.
+        /// once the program has returned or failed, keeps reporting that.
+        pub fn step(&mut self) -> Result<Stop, VmError> {
+            if let Some(done) = self.done {
+                return done.map(Stop::Returned);
+            }
+
+            let before: Vec<Option<f64>> = self.watchpoints.iter()
+                .map(|slot| self.vm.slots().get(*slot).copied())
+                .collect();
+
+            let result = self.vm.step(&self.code, &mut self.cursor);
+            match result {
+                Ok(Some(value)) => {
+                    self.done = Some(Ok(value));
+                    return Ok(Stop::Returned(value));
+                }
+                Ok(None) => (),
+                Err(e) => {
+                    self.done = Some(Err(e));
+                    return Err(e);
+                }
+            }
+
+            let changes: Vec<Change> = self.watchpoints.iter().zip(before)
+                .map(|(slot, old)| Change { slot: *slot, old, new: self.vm.slots().get(*slot).copied() })
+                // by bits, so a nan doesn't trigger forever.
+                .filter(|change| change.old.map(f64::to_bits) != change.new.map(f64::to_bits))
+                .collect();
+            if !changes.is_empty() {
+                return Ok(Stop::Watchpoint(changes));
+            }
+            Ok(Stop::Step)
+        }
+
+        /// steps at least once, then until a breakpoint or watchpoint
+        /// hits, or the program returns.
+        pub fn run_until_breakpoint(&mut self) -> Result<Stop, VmError> {
+            loop {
+                match self.step()? {
+                    Stop::Step => (),
+                    stop => return Ok(stop),
+                }
+                let pc = self.pc();
+                if self.breakpoints.contains(&pc) {
+                    return Ok(Stop::Breakpoint { pc });
+                }
+            }
+        }
+
+        pub fn pc(&self) -> usize {
+            M::pc(&self.cursor)
+        }
+
+        pub fn counter(&self) -> u32 {
+            M::counter(&self.cursor)
+        }
+
+        /// the instruction `step` executes next.
+        pub fn next_instruction(&self) -> Option<M::Instruction> {
+            self.code.get(self.pc()).copied()
+        }
+
+        pub fn code(&self) -> &[M::Instruction] {
+            &self.code
+        }
+
+        pub fn vm(&self) -> &M {
+            &self.vm
+        }
+    }
+
+    impl Debugger<reg::Vm> {
+        pub fn registers(&self) -> &[f64] {
+            self.vm.registers()
+        }
+    }
+
+    impl Debugger<stack::Vm> {
+        pub fn stack(&self) -> &[f64] {
+            self.vm.stack()
+        }
+    }
+}
+
+
+
+pub mod bench {
+    //! timings of every program over fixed workloads.
+    //!
+    //! ```text
+    //! rustc -O --edition 2021 stack_vs_reg.rs && ./stack_vs_reg bench
+    //! ```
+
+    use core::fmt::Write;
+    use core::hint::black_box;
+    use std::time::Instant;
+    use super::{reg, stack, packed, jit, fib, fib_recursive, mandel, collatz, dot};
+    use super::dispatch::{self, Dispatch};
+    use super::value::{Value, Tagged, Boxed};
+    use super::fuse;
+    use super::profile::Profile;
+
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Config {
+        /// untimed runs before sampling.
+        pub warmup: usize,
+        pub samples: usize,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config { warmup: 3, samples: 21 }
+        }
+    }
+
+
+    pub struct Benchmark {
+        /// programs in the same group run the same workload.
+        pub group: &'static str,
+        pub name: &'static str,
+        /// runs the workload once, returns a checksum of the results.
+        pub run: Box<dyn FnMut() -> f64>,
+    }
+
+    // the workloads.
+    const FIB_N: u32 = 90;
+    const FIB_RECURSIVE_N: u32 = 20;
+    const MANDEL_SIZE: u32 = 48;
+    const MANDEL_LIMIT: f64 = 256.0;
+    const ADD_REPEAT: u32 = 20_000;
+    const COLLATZ_N: u32 = 300;
+    const DOT_N: u32 = 1000;
+    const DOT_REPEAT: u32 = 100;
+
+    fn fib_workload(mut f: impl FnMut(f64) -> f64) -> f64 {
+        (0..=FIB_N).map(|n| f(black_box(n as f64))).sum()
+    }
+
+    fn fib_recursive_workload(mut f: impl FnMut(f64) -> f64) -> f64 {
+        (0..=FIB_RECURSIVE_N).map(|n| f(black_box(n as f64))).sum()
+    }
+
+    fn mandel_workload(mut f: impl FnMut(f64, f64, f64) -> f64) -> f64 {
+        let step = 3.0 / MANDEL_SIZE as f64;
+        let mut sum = 0.0;
+        for j in 0..MANDEL_SIZE {
+            for i in 0..MANDEL_SIZE {
+                let x = -2.0 + i as f64 * step;
+                let y = -1.5 + j as f64 * step;
+                sum += f(black_box(x), black_box(y), MANDEL_LIMIT);
+            }
+        }
+        sum
+    }
+
+    fn collatz_workload(mut f: impl FnMut(f64) -> f64) -> f64 {
+        f(black_box(COLLATZ_N as f64))
+    }
+
+    /// the two vectors, `DOT_N` values each.
+    fn dot_memory() -> Vec<f64> {
+        (0..2*DOT_N).map(|i| (i % 7) as f64 - 3.0).collect()
+    }
+
+    fn dot_workload(mut f: impl FnMut(f64) -> f64) -> f64 {
+        (0..DOT_REPEAT).map(|_| f(black_box(DOT_N as f64))).sum()
+    }
+
+    fn add_workload(mut f: impl FnMut(&[f64]) -> f64) -> f64 {
+        let args: [f64; 16] = core::array::from_fn(|i| i as f64);
+        (0..ADD_REPEAT).map(|_| f(black_box(&args))).sum()
+    }
+
+    /// every registered program, grouped, native first.
+    pub fn benchmarks() -> Vec<Benchmark> {
+        let mut result = vec![];
+
+        result.push(Benchmark { group: "fib", name: "native", run: Box::new(|| fib_workload(fib)) });
+        result.push(reg_benchmark("fib", "reg::FIB", reg::FIB, |f| fib_workload(|n| f(&[n]))));
+        for (name, code) in [("stack::FIB_SMART", stack::FIB_SMART), ("stack::FIB_NAIVE", stack::FIB_NAIVE)] {
+            result.push(stack_benchmark("fib", name, code, 1, |f| fib_workload(|n| f(&[n]))));
+        }
+        result.push(packed_reg_benchmark("fib", "reg::FIB packed", reg::FIB, &[], |f| fib_workload(|n| f(&[n]))));
+        result.push(jit_reg_benchmark("fib", "reg::FIB jit", reg::FIB, &[], |f| fib_workload(|n| f(&[n]))));
+        result.push(packed_stack_benchmark("fib", "stack::FIB_SMART packed", stack::FIB_SMART, 1, &[], |f| fib_workload(|n| f(&[n]))));
+
+        result.push(Benchmark { group: "fib_rec", name: "native", run: Box::new(|| fib_recursive_workload(fib_recursive)) });
+        result.push(reg_benchmark("fib_rec", "reg::FIB_RECURSIVE", reg::FIB_RECURSIVE, |f| fib_recursive_workload(|n| f(&[n]))));
+        result.push(stack_benchmark("fib_rec", "stack::FIB_RECURSIVE", stack::FIB_RECURSIVE, 1, |f| fib_recursive_workload(|n| f(&[n]))));
+        result.push(packed_reg_benchmark("fib_rec", "reg::FIB_RECURSIVE packed", reg::FIB_RECURSIVE, &[], |f| fib_recursive_workload(|n| f(&[n]))));
+        result.push(jit_reg_benchmark("fib_rec", "reg::FIB_RECURSIVE jit", reg::FIB_RECURSIVE, &[], |f| fib_recursive_workload(|n| f(&[n]))));
+        result.push(packed_stack_benchmark("fib_rec", "stack::FIB_RECURSIVE packed", stack::FIB_RECURSIVE, 1, &[], |f| fib_recursive_workload(|n| f(&[n]))));
+
+        result.push(Benchmark { group: "mandel", name: "native", run: Box::new(|| mandel_workload(mandel)) });
+        result.push(reg_benchmark("mandel", "reg::MANDEL", reg::MANDEL, |f| mandel_workload(|x, y, n| f(&[x, y, n]))));
+        for (name, code) in [
+            ("stack::MANDEL_SMART",           stack::MANDEL_SMART),
+            ("stack::MANDEL_NAIVE",           stack::MANDEL_NAIVE),
+            ("stack::MANDEL_SMART_NOPS_SLOW", stack::MANDEL_SMART_NOPS_SLOW),
+            ("stack::MANDEL_SMART_NOPS_SAME", stack::MANDEL_SMART_NOPS_SAME),
+            ("stack::MANDEL_SMART_NO_DUP",    stack::MANDEL_SMART_NO_DUP),
+        ] {
+            result.push(stack_benchmark("mandel", name, code, 3, |f| mandel_workload(|x, y, n| f(&[x, y, n]))));
+        }
+        result.push(packed_reg_benchmark("mandel", "reg::MANDEL packed", reg::MANDEL, &[], |f| mandel_workload(|x, y, n| f(&[x, y, n]))));
+        result.push(jit_reg_benchmark("mandel", "reg::MANDEL jit", reg::MANDEL, &[], |f| mandel_workload(|x, y, n| f(&[x, y, n]))));
+        result.push(packed_stack_benchmark("mandel", "stack::MANDEL_SMART packed", stack::MANDEL_SMART, 3, &[], |f| mandel_workload(|x, y, n| f(&[x, y, n]))));
+        result.push(typed_reg_benchmark::<Tagged>("mandel", "reg::MANDEL tagged", reg::MANDEL, |f| mandel_workload(|x, y, n| f(&[x, y, n]))));
+        result.push(typed_reg_benchmark::<Boxed> ("mandel", "reg::MANDEL boxed",  reg::MANDEL, |f| mandel_workload(|x, y, n| f(&[x, y, n]))));
+        result.push(typed_stack_benchmark::<Tagged>("mandel", "stack::MANDEL_SMART tagged", stack::MANDEL_SMART, 3, |f| mandel_workload(|x, y, n| f(&[x, y, n]))));
+        result.push(typed_stack_benchmark::<Boxed> ("mandel", "stack::MANDEL_SMART boxed",  stack::MANDEL_SMART, 3, |f| mandel_workload(|x, y, n| f(&[x, y, n]))));
+
+        result.push(Benchmark { group: "collatz", name: "native", run: Box::new(|| collatz_workload(collatz)) });
+        result.push(reg_benchmark("collatz", "reg::COLLATZ", reg::COLLATZ, |f| collatz_workload(|n| f(&[n]))));
+        result.push(stack_benchmark("collatz", "stack::COLLATZ", stack::COLLATZ, 1, |f| collatz_workload(|n| f(&[n]))));
+        result.push(packed_reg_benchmark("collatz", "reg::COLLATZ packed", reg::COLLATZ, &[], |f| collatz_workload(|n| f(&[n]))));
+        result.push(jit_reg_benchmark("collatz", "reg::COLLATZ jit", reg::COLLATZ, &[], |f| collatz_workload(|n| f(&[n]))));
+        result.push(packed_stack_benchmark("collatz", "stack::COLLATZ packed", stack::COLLATZ, 1, &[], |f| collatz_workload(|n| f(&[n]))));
+        result.push(typed_reg_benchmark::<Tagged>("collatz", "reg::COLLATZ tagged", reg::COLLATZ, |f| collatz_workload(|n| f(&[n]))));
+        result.push(typed_reg_benchmark::<Boxed> ("collatz", "reg::COLLATZ boxed",  reg::COLLATZ, |f| collatz_workload(|n| f(&[n]))));
+        result.push(typed_stack_benchmark::<Tagged>("collatz", "stack::COLLATZ tagged", stack::COLLATZ, 1, |f| collatz_workload(|n| f(&[n]))));
+        result.push(typed_stack_benchmark::<Boxed> ("collatz", "stack::COLLATZ boxed",  stack::COLLATZ, 1, |f| collatz_workload(|n| f(&[n]))));
+
+        let memory = dot_memory();
+        result.push(Benchmark { group: "dot", name: "native", run: Box::new(move || dot_workload(|n| {
+            let n = n as usize;
+            dot(&memory[..n], &memory[n..2*n])
+        })) });
+        result.push(reg_memory_benchmark("dot", "reg::DOT", reg::DOT, &dot_memory(), |f| dot_workload(|n| f(&[n]))));
+        result.push(stack_memory_benchmark("dot", "stack::DOT", stack::DOT, 1, &dot_memory(), |f| dot_workload(|n| f(&[n]))));
+        result.push(packed_reg_benchmark("dot", "reg::DOT packed", reg::DOT, &dot_memory(), |f| dot_workload(|n| f(&[n]))));
+        result.push(jit_reg_benchmark("dot", "reg::DOT jit", reg::DOT, &dot_memory(), |f| dot_workload(|n| f(&[n]))));
+        result.push(packed_stack_benchmark("dot", "stack::DOT packed", stack::DOT, 1, &dot_memory(), |f| dot_workload(|n| f(&[n]))));
+
+        result.push(Benchmark { group: "add", name: "native", run: Box::new(|| add_workload(|args| args.iter().sum())) });
+        result.push(reg_benchmark("add", "reg::ADD_CHAIN", reg::ADD_CHAIN, |f| add_workload(|args| f(args))));
+        result.push(reg_benchmark("add", "reg::ADD_PAIRS", reg::ADD_PAIRS, |f| add_workload(|args| f(args))));
+
+        result
+    }
+
+    /// runs a workload with a vm bound to a program.
+    type Workload = fn(&mut dyn FnMut(&[f64]) -> f64) -> f64;
+
+    fn reg_benchmark(group: &'static str, name: &'static str, code: &[reg::Instruction],
+        workload: Workload,
+    ) -> Benchmark {
+        reg_memory_benchmark(group, name, code, &[], workload)
+    }
+
+    fn stack_benchmark(group: &'static str, name: &'static str, code: &[stack::Instruction], argc: usize,
+        workload: Workload,
+    ) -> Benchmark {
+        stack_memory_benchmark(group, name, code, argc, &[], workload)
+    }
+
+    /// like `reg_benchmark`, with `memory` in the vm's memory.
+    fn reg_memory_benchmark(group: &'static str, name: &'static str, code: &[reg::Instruction], memory: &[f64],
+        workload: Workload,
+    ) -> Benchmark {
+        let program = reg::verify(code).unwrap();
+        let mut vm = reg::Vm::new();
+        vm.memory_mut().extend_from_slice(memory);
+        Benchmark { group, name, run: Box::new(move || workload(&mut |args| vm.run(&program, args))) }
+    }
+
+    fn stack_memory_benchmark(group: &'static str, name: &'static str, code: &[stack::Instruction], argc: usize, memory: &[f64],
+        workload: Workload,
+    ) -> Benchmark {
+        let program = stack::verify(code, argc).unwrap();
+        let mut vm = stack::Vm::new();
+        vm.memory_mut().extend_from_slice(memory);
+        Benchmark { group, name, run: Box::new(move || workload(&mut |args| vm.run(&program, args))) }
+    }
+
+    /// like `reg_memory_benchmark`, with the program's `packed` encoding.
+    fn packed_reg_benchmark(group: &'static str, name: &'static str, code: &[reg::Instruction], memory: &[f64],
+        workload: Workload,
+    ) -> Benchmark {
+        let program = packed::encode_reg(&reg::verify(code).unwrap()).unwrap();
+        let mut vm = reg::Vm::new();
+        vm.memory_mut().extend_from_slice(memory);
+        Benchmark { group, name, run: Box::new(move || workload(&mut |args| vm.run_packed(&program, args))) }
+    }
+
+    fn packed_stack_benchmark(group: &'static str, name: &'static str, code: &[stack::Instruction], argc: usize, memory: &[f64],
+        workload: Workload,
+    ) -> Benchmark {
+        let program = packed::encode_stack(&stack::verify(code, argc).unwrap()).unwrap();
+        let mut vm = stack::Vm::new();
+        vm.memory_mut().extend_from_slice(memory);
+        Benchmark { group, name, run: Box::new(move || workload(&mut |args| vm.run_packed(&program, args))) }
+    }
+
+    /// like `reg_memory_benchmark`, with the program compiled by `jit`,
+    /// so the same as `reg_memory_benchmark` where there's no jit.
+    fn jit_reg_benchmark(group: &'static str, name: &'static str, code: &[reg::Instruction], memory: &[f64],
+        workload: Workload,
+    ) -> Benchmark {
+        let program = jit::Program::new(&reg::verify(code).unwrap());
+        let mut vm = reg::Vm::new();
+        vm.memory_mut().extend_from_slice(memory);
+        Benchmark { group, name, run: Box::new(move || workload(&mut |args| vm.run_jit(&program, args))) }
+    }
+
+    /// every program of the main groups under every `dispatch`, grouped
+    /// by program, `dispatch::Match` first. see `dispatch_table`.
+    pub fn dispatch_benchmarks() -> Vec<Benchmark> {
+        let mut result = vec![];
+        reg_dispatches(&mut result, "reg::FIB",           reg::FIB,           &[], |f| fib_workload(|n| f(&[n])));
+        reg_dispatches(&mut result, "reg::FIB_RECURSIVE", reg::FIB_RECURSIVE, &[], |f| fib_recursive_workload(|n| f(&[n])));
+        reg_dispatches(&mut result, "reg::MANDEL",        reg::MANDEL,        &[], |f| mandel_workload(|x, y, n| f(&[x, y, n])));
+        reg_dispatches(&mut result, "reg::COLLATZ",       reg::COLLATZ,       &[], |f| collatz_workload(|n| f(&[n])));
+        reg_dispatches(&mut result, "reg::DOT",           reg::DOT,           &dot_memory(), |f| dot_workload(|n| f(&[n])));
+        stack_dispatches(&mut result, "stack::FIB_SMART",     stack::FIB_SMART,     1, &[], |f| fib_workload(|n| f(&[n])));
+        stack_dispatches(&mut result, "stack::FIB_RECURSIVE", stack::FIB_RECURSIVE, 1, &[], |f| fib_recursive_workload(|n| f(&[n])));
+        stack_dispatches(&mut result, "stack::MANDEL_SMART",  stack::MANDEL_SMART,  3, &[], |f| mandel_workload(|x, y, n| f(&[x, y, n])));
+        stack_dispatches(&mut result, "stack::COLLATZ",       stack::COLLATZ,       1, &[], |f| collatz_workload(|n| f(&[n])));
+        stack_dispatches(&mut result, "stack::DOT",           stack::DOT,           1, &dot_memory(), |f| dot_workload(|n| f(&[n])));
+        result
+    }
+
+    fn reg_dispatches(result: &mut Vec<Benchmark>, group: &'static str, code: &[reg::Instruction], memory: &[f64], workload: Workload) {
+        result.push(reg_dispatch_benchmark::<dispatch::Match>    (group, code, memory, workload));
+        result.push(reg_dispatch_benchmark::<dispatch::Table>    (group, code, memory, workload));
+        result.push(reg_dispatch_benchmark::<dispatch::Closures> (group, code, memory, workload));
+        result.push(reg_dispatch_benchmark::<dispatch::TailCalls>(group, code, memory, workload));
+    }
+
+    fn stack_dispatches(result: &mut Vec<Benchmark>, group: &'static str, code: &[stack::Instruction], argc: usize, memory: &[f64], workload: Workload) {
+        result.push(stack_dispatch_benchmark::<dispatch::Match>    (group, code, argc, memory, workload));
+        result.push(stack_dispatch_benchmark::<dispatch::Table>    (group, code, argc, memory, workload));
+        result.push(stack_dispatch_benchmark::<dispatch::Closures> (group, code, argc, memory, workload));
+        result.push(stack_dispatch_benchmark::<dispatch::TailCalls>(group, code, argc, memory, workload));
+    }
+
+    /// like `reg_memory_benchmark`, named after the dispatch `D`.
+    fn reg_dispatch_benchmark<D: Dispatch + 'static>(group: &'static str, code: &[reg::Instruction], memory: &[f64],
+        workload: Workload,
+    ) -> Benchmark {
+        let program = reg::Dispatched::<f64, D>::new(&reg::verify(code).unwrap());
+        let mut vm = reg::Vm::new();
+        vm.memory_mut().extend_from_slice(memory);
+        Benchmark { group, name: D::NAME, run: Box::new(move || workload(&mut |args| vm.run_with(&program, args))) }
+    }
+
+    fn stack_dispatch_benchmark<D: Dispatch + 'static>(group: &'static str, code: &[stack::Instruction], argc: usize, memory: &[f64],
+        workload: Workload,
+    ) -> Benchmark {
+        let program = stack::Dispatched::<f64, D>::new(&stack::verify(code, argc).unwrap());
+        let mut vm = stack::Vm::new();
+        vm.memory_mut().extend_from_slice(memory);
+        Benchmark { group, name: D::NAME, run: Box::new(move || workload(&mut |args| vm.run_with(&program, args))) }
+    }
+
+    /// a program of `fuse_benchmarks` before and after `fuse`.
+    #[derive(Clone, Debug)]
+    pub struct Fused {
+        pub program: &'static str,
+        /// instructions in the code, before and after.
+        pub instrs: [usize; 2],
+        /// instructions executed by one run of the workload, before and after.
+        pub dispatches: [u64; 2],
+        pub stats: fuse::Stats,
+    }
+
+    /// the main programs, each `fuse`d with the profile of a run of its
+    /// workload, as benchmarks named "unfused" and "fused", grouped by
+    /// program. panics if a fused program computes something else.
+    pub fn fuse_benchmarks() -> (Vec<Benchmark>, Vec<Fused>) {
+        let mut result = vec![];
+        let mut fused  = vec![];
+        reg_fused(&mut result, &mut fused, "reg::FIB",           reg::FIB,           &[], |f| fib_workload(|n| f(&[n])));
+        reg_fused(&mut result, &mut fused, "reg::FIB_RECURSIVE", reg::FIB_RECURSIVE, &[], |f| fib_recursive_workload(|n| f(&[n])));
+        reg_fused(&mut result, &mut fused, "reg::MANDEL",        reg::MANDEL,        &[], |f| mandel_workload(|x, y, n| f(&[x, y, n])));
+        reg_fused(&mut result, &mut fused, "reg::COLLATZ",       reg::COLLATZ,       &[], |f| collatz_workload(|n| f(&[n])));
+        reg_fused(&mut result, &mut fused, "reg::DOT",           reg::DOT,           &dot_memory(), |f| dot_workload(|n| f(&[n])));
+        stack_fused(&mut result, &mut fused, "stack::FIB_SMART",     stack::FIB_SMART,     1, &[], |f| fib_workload(|n| f(&[n])));
+        stack_fused(&mut result, &mut fused, "stack::FIB_NAIVE",     stack::FIB_NAIVE,     1, &[], |f| fib_workload(|n| f(&[n])));
+        stack_fused(&mut result, &mut fused, "stack::FIB_RECURSIVE", stack::FIB_RECURSIVE, 1, &[], |f| fib_recursive_workload(|n| f(&[n])));
+        stack_fused(&mut result, &mut fused, "stack::MANDEL_SMART",  stack::MANDEL_SMART,  3, &[], |f| mandel_workload(|x, y, n| f(&[x, y, n])));
+        stack_fused(&mut result, &mut fused, "stack::MANDEL_NAIVE",  stack::MANDEL_NAIVE,  3, &[], |f| mandel_workload(|x, y, n| f(&[x, y, n])));
+        stack_fused(&mut result, &mut fused, "stack::COLLATZ",       stack::COLLATZ,       1, &[], |f| collatz_workload(|n| f(&[n])));
+        stack_fused(&mut result, &mut fused, "stack::DOT",           stack::DOT,           1, &dot_memory(), |f| dot_workload(|n| f(&[n])));
+        (result, fused)
+    }
+
+    fn reg_fused(result: &mut Vec<Benchmark>, fused: &mut Vec<Fused>, program: &'static str, code: &[reg::Instruction], memory: &[f64],
+        workload: Workload,
+    ) {
+        let profiled = |code: &[reg::Instruction]| {
+            let verified = reg::verify(code).unwrap();
+            let mut vm = reg::Vm::new();
+            vm.memory_mut().extend_from_slice(memory);
+            let mut profile = Profile::new();
+            let checksum = workload(&mut |args| vm.run_profiled(&verified, args, &mut profile));
+            (checksum, profile.executed.iter().sum::<u64>(), profile)
+        };
+        let (checksum, dispatches, profile) = profiled(code);
+        let (fused_code, stats) = fuse::reg(code, &profile);
+        let (fused_checksum, fused_dispatches, _) = profiled(&fused_code);
+        assert_eq!(checksum, fused_checksum, "{} computes something else fused", program);
+
+        fused.push(Fused { program, instrs: [code.len(), fused_code.len()], dispatches: [dispatches, fused_dispatches], stats });
+        result.push(reg_memory_benchmark(program, "unfused", code,        memory, workload));
+        result.push(reg_memory_benchmark(program, "fused",   &fused_code, memory, workload));
+    }
+
+    fn stack_fused(result: &mut Vec<Benchmark>, fused: &mut Vec<Fused>, program: &'static str, code: &[stack::Instruction], argc: usize, memory: &[f64],
+        workload: Workload,
+    ) {
+        let profiled = |code: &[stack::Instruction]| {
+            let verified = stack::verify(code, argc).unwrap();
+            let mut vm = stack::Vm::new();
+            vm.memory_mut().extend_from_slice(memory);
+            let mut profile = Profile::new();
+            let checksum = workload(&mut |args| vm.run_profiled(&verified, args, &mut profile));
+            (checksum, profile.executed.iter().sum::<u64>(), profile)
+        };
+        let (checksum, dispatches, profile) = profiled(code);
+        let (fused_code, stats) = fuse::stack(code, &profile);
+        let (fused_checksum, fused_dispatches, _) = profiled(&fused_code);
+        assert_eq!(checksum, fused_checksum, "{} computes something else fused", program);
+
+        fused.push(Fused { program, instrs: [code.len(), fused_code.len()], dispatches: [dispatches, fused_dispatches], stats });
+        result.push(stack_memory_benchmark(program, "unfused", code,        argc, memory, workload));
+        result.push(stack_memory_benchmark(program, "fused",   &fused_code, argc, memory, workload));
+    }
+
+    /// the arguments of a typed run, workloads take at most 16.
+    fn typed_args<V: Value>(args: &[f64]) -> [V; 16] {
+        let mut values = [V::from_f64(0.0); 16];
+        for (value, arg) in values.iter_mut().zip(args) {
+            *value = V::from_f64(*arg);
+        }
+        values
+    }
+
+    /// like `reg_benchmark`, with values represented as `V`.
+    fn typed_reg_benchmark<V: Value + 'static>(group: &'static str, name: &'static str, code: &[reg::Instruction],
+        workload: Workload,
+    ) -> Benchmark {
+        let program = reg::verify(code).unwrap();
+        let mut vm = reg::Vm::<V>::default();
+        Benchmark { group, name, run: Box::new(move || workload(&mut |args| {
+            vm.run(&program, &typed_args(args)[..args.len()]).to_f64()
+        })) }
+    }
+
+    fn typed_stack_benchmark<V: Value + 'static>(group: &'static str, name: &'static str, code: &[stack::Instruction], argc: usize,
+        workload: Workload,
+    ) -> Benchmark {
+        let program = stack::verify(code, argc).unwrap();
+        let mut vm = stack::Vm::<V>::default();
+        Benchmark { group, name, run: Box::new(move || workload(&mut |args| {
+            vm.run(&program, &typed_args(args)[..args.len()]).to_f64()
+        })) }
+    }
+
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Measurement {
+        pub group: &'static str,
+        pub name: &'static str,
+        pub checksum: f64,
+        /// seconds per workload run, sorted.
+        pub samples: Vec<f64>,
+    }
+
+    impl Measurement {
+        pub fn median(&self) -> f64 {
+            median(&self.samples)
+        }
+
+        pub fn min(&self) -> f64 {
+            self.samples[0]
+        }
+
+        pub fn max(&self) -> f64 {
+            self.samples[self.samples.len() - 1]
+        }
+
+        /// median absolute deviation.
+        pub fn spread(&self) -> f64 {
+            let median = self.median();
+            let mut deviations: Vec<f64> = self.samples.iter().map(|s| (s - median).abs()).collect();
+            deviations.sort_by(f64::total_cmp);
+            self::median(&deviations)
+        }
+    }
+
+    fn median(sorted: &[f64]) -> f64 {
+        let n = sorted.len();
+        if n % 2 == 1 { sorted[n/2] }
+        else          { (sorted[n/2 - 1] + sorted[n/2]) / 2.0 }
+    }
+
+    pub fn measure(benchmark: &mut Benchmark, config: Config) -> Measurement {
+        assert!(config.samples > 0);
+
+        let mut checksum = 0.0;
+        for _ in 0..config.warmup {
+            checksum = black_box((benchmark.run)());
+        }
+
+        let mut samples = Vec::with_capacity(config.samples);
+        for _ in 0..config.samples {
+            let t0 = Instant::now();
+            checksum = black_box((benchmark.run)());
+            samples.push(t0.elapsed().as_secs_f64());
+        }
+        samples.sort_by(f64::total_cmp);
+
+        Measurement { group: benchmark.group, name: benchmark.name, checksum, samples }
+    }
+
+    /// `run_benchmarks` of `benchmarks`.
+    pub fn run_all(config: Config, filter: &str) -> Vec<Measurement> {
+        run_benchmarks(benchmarks(), config, filter)
+    }
+
+    /// measures the benchmarks whose `group/name` contains `filter`.
+    /// panics if a program disagrees with the first one of its group.
+    pub fn run_benchmarks(benchmarks: Vec<Benchmark>, config: Config, filter: &str) -> Vec<Measurement> {
+        let mut result: Vec<Measurement> = vec![];
+        for mut benchmark in benchmarks {
+            if !format!("{}/{}", benchmark.group, benchmark.name).contains(filter) {
+                continue;
+            }
+
+            let m = measure(&mut benchmark, config);
+            if let Some(first) = result.iter().find(|r| r.group == m.group) {
+                assert_eq!(m.checksum, first.checksum, "{} disagrees with {}", m.name, first.name);
+            }
+            result.push(m);
+        }
+        result
+    }
+
+
+    /// times in ms, relative to the first program of each group.
+    pub fn table(measurements: &[Measurement]) -> String {
+        let mut out = String::new();
+        writeln!(out, "{:<8}{:<32}{:>10}{:>10}{:>10}{:>8}{:>9}",
+            "group", "program", "median", "min", "max", "mad", "rel").unwrap();
+
+        let mut base = (measurements.first().map(|m| m.group).unwrap_or(""), 0.0);
+        for (i, m) in measurements.iter().enumerate() {
+            if i == 0 || m.group != base.0 {
+                if i != 0 {
+                    writeln!(out).unwrap();
+                }
+                base = (m.group, m.median());
+            }
+
+            let ms = |s: f64| s * 1000.0;
+            writeln!(out, "{:<8}{:<32}{:>10.3}{:>10.3}{:>10.3}{:>7.1}%{:>8.2}x",
+                m.group, m.name, ms(m.median()), ms(m.min()), ms(m.max()),
+                m.spread() / m.median() * 100.0, m.median() / base.1).unwrap();
+        }
+        out
+    }
+
+    /// `dispatch_benchmarks` in ms, a row per program and a column per
+    /// dispatch, relative to the first one of each row.
+    pub fn dispatch_table(measurements: &[Measurement]) -> String {
+        let names = [dispatch::Match::NAME, dispatch::Table::NAME, dispatch::Closures::NAME, dispatch::TailCalls::NAME];
+
+        let mut out = String::new();
+        write!(out, "{:<24}", "program").unwrap();
+        for name in names {
+            write!(out, "{:>18}", name).unwrap();
+        }
+        writeln!(out).unwrap();
+
+        let mut groups: Vec<&str> = measurements.iter().map(|m| m.group).collect();
+        groups.dedup();
+        for group in groups {
+            let row: Vec<&Measurement> = measurements.iter().filter(|m| m.group == group).collect();
+            let base = row[0].median();
+            write!(out, "{:<24}", group).unwrap();
+            for name in names {
+                match row.iter().find(|m| m.name == name) {
+                    Some(m) => write!(out, "{:>10.3}{:>7.2}x", m.median() * 1000.0, m.median() / base).unwrap(),
+                    None    => write!(out, "{:>18}", "-").unwrap(),
+                }
+            }
+            writeln!(out).unwrap();
+        }
+        out
+    }
+
+    /// `fuse_benchmarks`, a row per program with its instructions,
+    /// dispatches and ms before and after, and the superinstructions
+    /// it got. programs `filter`ed out of `measurements` have no times.
+    pub fn fuse_table(fused: &[Fused], measurements: &[Measurement]) -> String {
+        let mut out = String::new();
+        writeln!(out, "{:<24}{:<14}{:<24}{:<20}{:>9}  fused",
+            "program", "   instrs", "     dispatches", "     ms", "speedup").unwrap();
+
+        for f in fused {
+            let time = |name: &str| measurements.iter()
+                .find(|m| m.group == f.program && m.name == name)
+                .map(|m| m.median());
+            write!(out, "{:<24}{:>6} -> {:<4}{:>11} -> {:<9}", f.program,
+                f.instrs[0], f.instrs[1], f.dispatches[0], f.dispatches[1]).unwrap();
+            match (time("unfused"), time("fused")) {
+                (Some(before), Some(after)) => write!(out, "{:>9.3} -> {:<7.3}{:>8.2}x",
+                    before * 1000.0, after * 1000.0, before / after).unwrap(),
+                _ => write!(out, "{:>20}{:>9}", "-", "-").unwrap(),
+            }
+            let stats: Vec<String> = f.stats.fused.iter().map(|(m, n)| format!("{} {}", m, n)).collect();
+            if !stats.is_empty() {
+                write!(out, "  {}", stats.join(", ")).unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+        out
+    }
+
+    /// code size of every program, as `Instruction`s and `packed`.
+    pub fn density() -> String {
+        let mut out = String::new();
+        writeln!(out, "{:<32}{:>8}{:>8}{:>8}{:>9}", "program", "instrs", "enum", "packed", "bytes").unwrap();
+        let mut row = |name: &str, len: usize, size: usize, packed: usize| {
+            writeln!(out, "{:<32}{:>8}{:>8}{:>8}{:>9.2}", name, len, len * size, packed, packed as f64 / len as f64).unwrap();
+        };
+
+        for (name, code) in [
+            ("reg::FIB",           reg::FIB),
+            ("reg::FIB_RECURSIVE", reg::FIB_RECURSIVE),
+            ("reg::MANDEL",        reg::MANDEL),
+            ("reg::COLLATZ",       reg::COLLATZ),
+            ("reg::DOT",           reg::DOT),
+            ("reg::ADD_CHAIN",     reg::ADD_CHAIN),
+        ] {
+            let program = packed::encode_reg(&reg::verify(code).unwrap()).unwrap();
+            row(name, code.len(), core::mem::size_of::<reg::Instruction>(), program.code().len());
+        }
+        for (name, code, argc) in [
+            ("stack::FIB_SMART",     stack::FIB_SMART,     1),
+            ("stack::FIB_NAIVE",     stack::FIB_NAIVE,     1),
+            ("stack::FIB_RECURSIVE", stack::FIB_RECURSIVE, 1),
+            ("stack::MANDEL_SMART",  stack::MANDEL_SMART,  3),
+            ("stack::MANDEL_NAIVE",  stack::MANDEL_NAIVE,  3),
+            ("stack::COLLATZ",       stack::COLLATZ,       1),
+            ("stack::DOT",           stack::DOT,           1),
+        ] {
+            let program = packed::encode_stack(&stack::verify(code, argc).unwrap()).unwrap();
+            row(name, code.len(), core::mem::size_of::<stack::Instruction>(), program.code().len());
+        }
+        out
+    }
+
+    /// one row per program, times in ns.
+    pub fn csv(measurements: &[Measurement]) -> String {
+        let mut out = String::from("group,program,samples,median_ns,min_ns,max_ns,mad_ns,checksum\n");
+        for m in measurements {
+            let ns = |s: f64| (s * 1e9).round();
+            writeln!(out, "{},{},{},{},{},{},{},{}",
+                m.group, m.name, m.samples.len(),
+                ns(m.median()), ns(m.min()), ns(m.max()), ns(m.spread()), m.checksum).unwrap();
+        }
+        out
+    }
+
+    /// `bench [--csv] [--dispatch | --fuse] [--samples n] [--warmup n] [filter]`,
+    /// or `bench --density` for the code sizes. `--dispatch` times
+    /// `dispatch_benchmarks` instead, see `dispatch_table`, and `--fuse`
+    /// times `fuse_benchmarks`, see `fuse_table`.
+    pub fn main(args: &[String]) -> Result<(), String> {
+        let mut config = Config::default();
+        let mut csv = false;
+        let mut dispatch = false;
+        let mut fuse = false;
+        let mut filter = String::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut count = |name: &str| {
+                args.next().and_then(|n| n.parse::<usize>().ok())
+                .ok_or(format!("{} expects a number", name))
+            };
+            match arg.as_str() {
+                "--csv"      => csv = true,
+                "--dispatch" => dispatch = true,
+                "--fuse"     => fuse = true,
+                "--density"  => {
+                    print!("{}", density());
+                    return Ok(());
+                }
+                "--samples"  => config.samples = count("--samples")?.max(1),
+                "--warmup"   => config.warmup  = count("--warmup")?,
+                _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                _ => filter = arg.clone(),
+            }
+        }
+
+        if dispatch {
+            let measurements = run_benchmarks(dispatch_benchmarks(), config, &filter);
+            if csv { print!("{}", self::csv(&measurements)) }
+            else   { print!("{}", dispatch_table(&measurements)) }
+            return Ok(());
+        }
+
+        if fuse {
+            let (benchmarks, fused) = fuse_benchmarks();
+            let measurements = run_benchmarks(benchmarks, config, &filter);
+            if csv { print!("{}", self::csv(&measurements)) }
+            else   { print!("{}", fuse_table(&fused, &measurements)) }
+            return Ok(());
+        }
+
+        let measurements = run_all(config, &filter);
+        if csv { print!("{}", self::csv(&measurements)) }
+        else   { print!("{}", table(&measurements)) }
+        Ok(())
+    }
+}
+
+
+
+pub mod fuzz {
+    //! differential testing: random register programs, run on both vms
+    //! (checked and fast), directly and through the translators and the
+    //! register allocator.
+    //! everything derives from a seed, so failures are reproducible.
+
+    use super::{reg, stack, translate, peephole, fuse, jit, regalloc, disasm};
+    use super::profile::Profile;
+    use reg::Instruction::{self, *};
+    use translate::Strategy;
+
+
+    /// xorshift64*.
+    #[derive(Clone, Debug)]
+    pub struct Rng(u64);
+
+    impl Rng {
+        pub fn new(seed: u64) -> Self {
+            // the state must not be zero.
+            Rng(seed ^ 0x9e37_79b9_7f4a_7c15 | 1)
+        }
+
+        pub fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        /// in `lo..hi`.
+        pub fn range(&mut self, lo: usize, hi: usize) -> usize {
+            lo + (self.next_u64() % (hi - lo) as u64) as usize
+        }
+
+        pub fn chance(&mut self, percent: usize) -> bool {
+            self.range(0, 100) < percent
+        }
+    }
+
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Config {
+        pub seed: u64,
+        pub cases: usize,
+        /// instructions between the prologue and the final `return`.
+        pub max_len: usize,
+        pub max_registers: usize,
+        /// per run, generated programs always terminate,
+        /// so running out is a failure too.
+        pub fuel: u64,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config { seed: 1, cases: 1000, max_len: 40, max_registers: 8, fuel: 1_000_000 }
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Case {
+        pub code: Vec<Instruction>,
+        pub args: Vec<f64>,
+    }
+
+    /// a verified program that terminates:
+    /// the counter is only set once, before any jump target,
+    /// and only `loop`/`loop_le` jump backwards. sometimes followed by
+    /// unreachable jumps to anywhere, which `verify` allows.
+    pub fn generate(rng: &mut Rng, config: &Config) -> Case {
+        let registers = rng.range(1, config.max_registers + 1);
+        let argc = rng.range(0, registers.min(3) + 1);
+        let reg = |rng: &mut Rng| rng.range(0, registers) as u8;
+
+        let mut code = vec![];
+        for dst in argc..registers {
+            if rng.chance(50) {
+                code.push(LoadInt { dst: dst as u8, value: rng.range(0, 21) as i16 - 10 });
+            }
+        }
+
+        let counter = reg(rng);
+        code.push(LoadInt { dst: counter, value: rng.range(0, 13) as i16 });
+        code.push(SetCounter { src: counter });
+
+        let start = code.len();
+        let end = start + rng.range(1, config.max_len + 1);
+        while code.len() < end {
+            let pc = code.len();
+            let instr = match rng.range(0, 100) {
+                0..=11 => {
+                    let value =
+                        if rng.chance(10) { rng.next_u64() as i16 }
+                        else              { rng.range(0, 201) as i16 - 100 };
+                    LoadInt { dst: reg(rng), value }
+                }
+                12..=19 => Copy       { dst: reg(rng), src: reg(rng) },
+                20..=29 => Add        { dst: reg(rng), src1: reg(rng), src2: reg(rng) },
+                30..=37 => Sub        { dst: reg(rng), src1: reg(rng), src2: reg(rng) },
+                38..=45 => Mul        { dst: reg(rng), src1: reg(rng), src2: reg(rng) },
+                46..=48 => Div        { dst: reg(rng), src1: reg(rng), src2: reg(rng) },
+                49..=50 => Neg        { dst: reg(rng), src: reg(rng) },
+                51..=52 => Lt         { dst: reg(rng), src1: reg(rng), src2: reg(rng) },
+                53..=54 => Le         { dst: reg(rng), src1: reg(rng), src2: reg(rng) },
+                55..=56 => Eq         { dst: reg(rng), src1: reg(rng), src2: reg(rng) },
+                57      => Sqrt       { dst: reg(rng), src: reg(rng) },
+                58      => Abs        { dst: reg(rng), src: reg(rng) },
+                59      => Floor      { dst: reg(rng), src: reg(rng) },
+                60      => Min        { dst: reg(rng), src1: reg(rng), src2: reg(rng) },
+                61      => Max        { dst: reg(rng), src1: reg(rng), src2: reg(rng) },
+                62..=66 => GetCounter { dst: reg(rng) },
+                67..=71 => Jump       { target: rng.range(pc + 1, end + 1) as u8 },
+                72..=74 => JumpIf     { target: rng.range(pc + 1, end + 1) as u8, src: reg(rng) },
+                75..=77 => JumpIfNot  { target: rng.range(pc + 1, end + 1) as u8, src: reg(rng) },
+                78..=87 => Loop       { target: rng.range(start, pc + 1) as u8 },
+                88..=97 => LoopLe     { target: rng.range(start, pc + 1) as u8, src1: reg(rng), src2: reg(rng) },
+                _       => Return     { src: reg(rng) },
+            };
+            code.push(instr);
+        }
+        code.push(Return { src: reg(rng) });
+
+        if rng.chance(20) {
+            for _ in 0..rng.range(1, 4) {
+                let target = rng.range(0, 256) as u8;
+                code.push(match rng.range(0, 3) {
+                    0 => Jump   { target },
+                    1 => JumpIf { target, src: reg(rng) },
+                    _ => LoopLe { target, src1: reg(rng), src2: reg(rng) },
+                });
+            }
+        }
+
+        let args = (0..argc).map(|_| (rng.range(0, 801) as f64 - 400.0) / 100.0).collect();
+        Case { code, args }
+    }
+
+
+    pub type Outcome = Result<f64, String>;
+
+    /// the result of `case` on every path through the vms.
+    /// panics are caught and become errors.
+    pub fn run(case: &Case, fuel: u64) -> Vec<(&'static str, Outcome)> {
+        let Case { code, args } = case;
+        let argc = args.len();
+
+        let mut result = vec![
+            ("reg checked", guard(|| reg::Vm::new().try_run_with_fuel(code, args, fuel).map_err(debug))),
+            ("reg fast",    guard(|| run_reg(code, args, fuel))),
+            ("reg fused",   guard(|| run_reg(&fuse::reg(code, &everywhere(code.len())).0, args, fuel))),
+            ("reg allocated", guard(|| {
+                let lifted: Vec<_> = code.iter().map(|instr| regalloc::Instruction::lift(*instr)).collect();
+                run_reg(&regalloc::allocate(&lifted, argc, None).map_err(debug)?.code, args, fuel)
+            })),
+        ];
+
+        // the jit has no fuel, so it only runs what terminated.
+        let jit = match &result[1].1 {
+            Ok(_)  => guard(|| run_jit(code, args)),
+            Err(e) => Err(e.clone()),
+        };
+        result.push(("reg jit", jit));
+
+        for strategy in [Strategy::Naive, Strategy::Smart] {
+            let (checked, fast) = match strategy {
+                Strategy::Naive => ("stack naive checked", "stack naive fast"),
+                Strategy::Smart => ("stack smart checked", "stack smart fast"),
+            };
+
+            let stack_code = match guard(|| translate::reg_to_stack(code, argc, strategy).map_err(debug)) {
+                Ok(stack_code) => stack_code,
+                Err(e) => {
+                    result.push((checked, Err(e)));
+                    continue;
+                }
+            };
+            result.push((checked, guard(|| stack::Vm::new().try_run_with_fuel(&stack_code, args, fuel).map_err(debug))));
+            result.push((fast,    guard(|| run_stack(&stack_code, args, fuel))));
+
+            if strategy == Strategy::Smart {
+                result.push(("stack smart peephole", guard(|| {
+                    run_stack(&peephole::optimize(&stack_code, argc).0, args, fuel)
+                })));
+                result.push(("stack smart fused", guard(|| {
+                    let stack_code = peephole::optimize(&stack_code, argc).0;
+                    run_stack(&fuse::stack(&stack_code, &everywhere(stack_code.len())).0, args, fuel)
+                })));
+                result.push(("reg round trip", guard(|| {
+                    run_reg(&translate::stack_to_reg(&stack_code, argc).map_err(debug)?, args, fuel)
+                })));
+            }
+        }
+        result
+    }
+
+    fn run_reg(code: &[Instruction], args: &[f64], fuel: u64) -> Outcome {
+        let program = reg::verify(code).map_err(debug)?;
+        reg::Vm::new().run_with_fuel(&program, args, fuel).map_err(debug)
+    }
+
+    fn run_jit(code: &[Instruction], args: &[f64]) -> Outcome {
+        let program = jit::Program::new(&reg::verify(code).map_err(debug)?);
+        Ok(reg::Vm::new().run_jit(&program, args))
+    }
+
+    fn run_stack(code: &[stack::Instruction], args: &[f64], fuel: u64) -> Outcome {
+        let program = stack::verify(code, args.len()).map_err(debug)?;
+        stack::Vm::new().run_with_fuel(&program, args, fuel).map_err(debug)
+    }
+
+    /// a profile for `fuse` in which every pc ran once. that makes every
+    /// site of a program with at most 100 instructions hot, so all
+    /// rewrites get tested, without a profiling run that could hang.
+    fn everywhere(len: usize) -> Profile {
+        Profile { runs: 1, executed: vec![1; len], taken: vec![0; len] }
+    }
+
+    fn guard<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+        .unwrap_or_else(|panic| {
+            let message = panic.downcast_ref::<String>().cloned()
+                .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_default();
+            Err(format!("panic: {}", message))
+        })
+    }
+
+    fn debug(e: impl core::fmt::Debug) -> String {
+        format!("{:?}", e)
+    }
+
+    /// all outcomes are the same value, nans being equal.
+    pub fn agree(outcomes: &[(&'static str, Outcome)]) -> bool {
+        let same = |a: &Outcome, b: &Outcome| match (a, b) {
+            (Ok(a), Ok(b)) => a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan()),
+            _ => false,
+        };
+        outcomes.iter().all(|(_, outcome)| same(outcome, &outcomes[0].1))
+    }
+
+
+    /// removes instructions from `case` while `fails` still holds
+    /// and the program still verifies.
+    pub fn minimize(case: &Case, mut fails: impl FnMut(&Case) -> bool) -> Case {
+        let mut case = case.clone();
+        loop {
+            let mut changed = false;
+            let mut pc = 0;
+            while pc < case.code.len() {
+                let candidate = Case { code: remove(&case.code, pc), args: case.args.clone() };
+                if reg::verify(&candidate.code).is_ok() && fails(&candidate) {
+                    case = candidate;
+                    changed = true;
+                }
+                else {
+                    pc += 1;
+                }
+            }
+            if !changed {
+                return case;
+            }
+        }
+    }
+
+    // removing keeps programs terminating:
+    // back edges stay back edges (or become self loops).
+    fn remove(code: &[Instruction], pc: usize) -> Vec<Instruction> {
+        let mut result = code.to_vec();
+        result.remove(pc);
+        for instr in &mut result {
+            if let Jump { target } | JumpIf { target, .. } | JumpIfNot { target, .. } |
+                   Loop { target } | LoopLe { target, .. } = instr {
+                if *target as usize > pc {
+                    *target -= 1;
+                }
+            }
+        }
+        result
+    }
+
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Failure {
+        /// which generated case, for reproducing with the same seed.
+        pub index: usize,
+        pub original: Case,
+        pub minimized: Case,
+        pub outcomes: Vec<(&'static str, Outcome)>,
+    }
+
+    impl core::fmt::Display for Failure {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            writeln!(f, "case {} disagrees, minimized from {} to {} instructions.",
+                self.index, self.original.code.len(), self.minimized.code.len())?;
+            writeln!(f, "args: {:?}", self.minimized.args)?;
+            write!(f, "{}", disasm::disassemble_reg(&self.minimized.code))?;
+            for (name, outcome) in &self.outcomes {
+                writeln!(f, "{:<24}{:?}", name, outcome)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// runs `config.cases` cases, stopping at the first disagreement.
+    pub fn fuzz(config: &Config) -> Result<(), Box<Failure>> {
+        let mut rng = Rng::new(config.seed);
+        for index in 0..config.cases {
+            let case = generate(&mut rng, config);
+            if !agree(&run(&case, config.fuel)) {
+                let minimized = minimize(&case, |case| !agree(&run(case, config.fuel)));
+                let outcomes = run(&minimized, config.fuel);
+                return Err(Box::new(Failure { index, original: case, minimized, outcomes }));
+            }
+        }
+        Ok(())
+    }
+
+    /// `fuzz [--seed n] [--cases n]`
+    pub fn main(args: &[String]) -> Result<(), String> {
+        let mut config = Config::default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut number = |name: &str| {
+                args.next().and_then(|n| n.parse::<u64>().ok())
+                .ok_or(format!("{} expects a number", name))
+            };
+            match arg.as_str() {
+                "--seed"  => config.seed  = number("--seed")?,
+                "--cases" => config.cases = number("--cases")? as usize,
+                _ => return Err(format!("unknown option `{}`", arg)),
+            }
+        }
+
+        match fuzz(&config) {
+            Ok(()) => {
+                println!("{} cases from seed {}, no disagreements.", config.cases, config.seed);
+                Ok(())
+            }
+            Err(failure) => Err(failure.to_string()),
+        }
+    }
+}
+
+
+
+pub mod bytecode {
+    //! a binary file format for programs of either instruction set.
+    //!
+    //! ```text
+    //! offset  size
+    //!      0     4  magic, `SVRB`
+    //!      4     2  version, see `VERSION`
+    //!      6     1  isa, 0 for `reg` and 1 for `stack`
+    //!      7     1  reserved, 0
+    //!      8     2  number of arguments
+    //!     10     2  registers (`reg`) or max stack depth (`stack`)
+    //!     12     4  number of instructions
+    //!     16        code
+    //!               4  number of constants (since version 2)
+    //!                  constants, 8 bytes each
+    //! ```
+    //!
+    //! numbers are little endian. an instruction is its opcode, the
+    //! index of its variant in `Instruction`, followed by its operands
+    //! in declaration order. operands are one byte, except for the two
+    //! byte value of `reg::Instruction::LoadInt`. constants are f64s.
+    //!
+    //! `read` validates everything, including `verify`, so what it
+    //! returns can be run with `SPEEEEEED`.
+
+    use super::{reg, stack, asm, lang, disasm, VerifyError};
+
+
+    pub const MAGIC: [u8; 4] = *b"SVRB";
+    /// `read` also takes older files, without the opcodes added since
+    /// their version: version 1 has no constants, `Wide`, `LoadConst`
+    /// or fused instructions, and before version 3, stack programs have
+    /// no calls, whose `Call` had its `argc` instead of an `Enter` at
+    /// the target.
+    pub const VERSION: u16 = 3;
+
+    const HEADER_SIZE: usize = 16;
+
+    /// `constants` is the vm's constant pool, see `reg::Vm::constants`.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Program {
+        Reg   { code: Vec<reg::Instruction>,   argc: usize, constants: Vec<f64> },
+        Stack { code: Vec<stack::Instruction>, argc: usize, constants: Vec<f64> },
+    }
+
+    impl Program {
+        pub fn argc(&self) -> usize {
+            match *self {
+                Program::Reg { argc, .. } | Program::Stack { argc, .. } => argc,
+            }
+        }
+
+        pub fn constants(&self) -> &[f64] {
+            match self {
+                Program::Reg { constants, .. } | Program::Stack { constants, .. } => constants,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Error {
+        /// the input ends before the header or the code does.
+        Truncated,
+        BadMagic,
+        UnsupportedVersion { version: u16 },
+        UnknownIsa         { isa: u8 },
+        /// the reserved header byte isn't 0.
+        BadHeader,
+        /// `offset` is in bytes, from the start of the input.
+        UnknownOpcode      { offset: usize, opcode: u8 },
+        TrailingBytes      { offset: usize },
+        TooManyArgs        { count: usize },
+        /// the header's registers or stack depth don't match the code's.
+        FrameMismatch      { header: usize, code: usize },
+        Verify(VerifyError),
+    }
+
+    impl From<VerifyError> for Error {
+        fn from(e: VerifyError) -> Self { Error::Verify(e) }
+    }
+
+    impl core::fmt::Display for Error {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            use Error::*;
+            match *self {
+                Truncated                       => write!(f, "unexpected end of input"),
+                BadMagic                        => write!(f, "not a bytecode file"),
+                UnsupportedVersion { version }  => write!(f, "unsupported version {}, expected {}", version, VERSION),
+                UnknownIsa { isa }              => write!(f, "unknown instruction set {}", isa),
+                BadHeader                       => write!(f, "reserved header byte isn't 0"),
+                UnknownOpcode { offset, opcode } => write!(f, "unknown opcode {} at offset {}", opcode, offset),
+                TrailingBytes { offset }        => write!(f, "trailing bytes at offset {}", offset),
+                TooManyArgs { count }           => write!(f, "too many arguments ({})", count),
+                FrameMismatch { header, code }  => write!(f, "the header says {} slots, the code needs {}", header, code),
+                Verify(e)                       => write!(f, "invalid program: {:?}", e),
+            }
+        }
+    }
+
+    impl std::error::Error for Error {}
+
+
+    /// the file for `program`, which has to pass `verify`.
+    pub fn write(program: &Program) -> Result<Vec<u8>, Error> {
+        let (isa, argc, frame, len) = match program {
+            Program::Reg { code, argc, .. } => {
+                if *argc > reg::REGISTER_COUNT {
+                    return Err(Error::TooManyArgs { count: *argc });
+                }
+                (0, *argc, reg::verify(code)?.registers(), code.len())
+            }
+            Program::Stack { code, argc, .. } => (1, *argc, stack::verify(code, *argc)?.max_depth(), code.len()),
+        };
+
+        // verified, so `argc` is at most 256 and `frame` at most
+        // `reg::MAX_REGISTERS`.
+        let mut out = Vec::with_capacity(HEADER_SIZE + 4*len);
+        out.extend(MAGIC);
+        out.extend(VERSION.to_le_bytes());
+        out.extend([isa, 0]);
+        out.extend((argc as u16).to_le_bytes());
+        out.extend((frame as u16).to_le_bytes());
+        out.extend(u32::try_from(len).expect("code fits into u32").to_le_bytes());
+        match program {
+            Program::Reg   { code, .. } => code.iter().for_each(|instr| encode_reg(instr, &mut out)),
+            Program::Stack { code, .. } => code.iter().for_each(|instr| encode_stack(instr, &mut out)),
+        }
+        let constants = program.constants();
+        out.extend(u32::try_from(constants.len()).expect("constants fit into u32").to_le_bytes());
+        for constant in constants {
+            out.extend(constant.to_le_bytes());
+        }
+        Ok(out)
+    }
+
+    pub fn read(bytes: &[u8]) -> Result<Program, Error> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(4)? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let version = r.u16()?;
+        if version == 0 || version > VERSION {
+            return Err(Error::UnsupportedVersion { version });
+        }
+        let isa = r.u8()?;
+        if isa > 1 {
+            return Err(Error::UnknownIsa { isa });
+        }
+        if r.u8()? != 0 {
+            return Err(Error::BadHeader);
+        }
+        let argc  = r.u16()? as usize;
+        let frame = r.u16()? as usize;
+        let len   = r.u32()? as usize;
+
+        // every instruction takes at least a byte,
+        // don't allocate for a length the input can't have.
+        if len > bytes.len() - r.pos {
+            return Err(Error::Truncated);
+        }
+
+        let program =
+            if isa == 0 {
+                let mut code = Vec::with_capacity(len);
+                for _ in 0..len {
+                    code.push(decode_reg(&mut r, version)?);
+                }
+                let constants = read_constants(&mut r, version)?;
+                if argc > reg::REGISTER_COUNT {
+                    return Err(Error::TooManyArgs { count: argc });
+                }
+                check_frame(frame, reg::verify(&code)?.registers())?;
+                Program::Reg { code, argc, constants }
+            }
+            else {
+                let mut code = Vec::with_capacity(len);
+                for _ in 0..len {
+                    code.push(decode_stack(&mut r, version)?);
+                }
+                let constants = read_constants(&mut r, version)?;
+                check_frame(frame, stack::verify(&code, argc)?.max_depth())?;
+                Program::Stack { code, argc, constants }
+            };
+        Ok(program)
+    }
+
+    /// the rest of the input.
+    fn read_constants(r: &mut Reader, version: u16) -> Result<Vec<f64>, Error> {
+        if version < 2 {
+            r.end()?;
+            return Ok(vec![]);
+        }
+        let len = r.u32()? as usize;
+        if len > (r.bytes.len() - r.pos) / 8 {
+            return Err(Error::Truncated);
+        }
+        let mut constants = Vec::with_capacity(len);
+        for _ in 0..len {
+            constants.push(f64::from_le_bytes(r.take(8)?.try_into().unwrap()));
+        }
+        r.end()?;
+        Ok(constants)
+    }
+
+    fn check_frame(header: usize, code: usize) -> Result<(), Error> {
+        if header != code {
+            return Err(Error::FrameMismatch { header, code });
+        }
+        Ok(())
+    }
+
+
+    /// appends `instr`, `packed` shares the encoding.
+    pub fn encode_reg(instr: &reg::Instruction, out: &mut Vec<u8>) {
+        use reg::Instruction::*;
+        match *instr {
+            LoadInt    { dst, value }         => { out.extend([0, dst]); out.extend(value.to_le_bytes()); }
+            Copy       { dst, src }           => out.extend([1, dst, src]),
+            Add        { dst, src1, src2 }    => out.extend([2, dst, src1, src2]),
+            Sub        { dst, src1, src2 }    => out.extend([3, dst, src1, src2]),
+            Mul        { dst, src1, src2 }    => out.extend([4, dst, src1, src2]),
+            Div        { dst, src1, src2 }    => out.extend([5, dst, src1, src2]),
+            Neg        { dst, src }           => out.extend([6, dst, src]),
+            Lt         { dst, src1, src2 }    => out.extend([7, dst, src1, src2]),
+            Le         { dst, src1, src2 }    => out.extend([8, dst, src1, src2]),
+            Eq         { dst, src1, src2 }    => out.extend([9, dst, src1, src2]),
+            Sqrt       { dst, src }           => out.extend([10, dst, src]),
+            Abs        { dst, src }           => out.extend([11, dst, src]),
+            Floor      { dst, src }           => out.extend([12, dst, src]),
+            Min        { dst, src1, src2 }    => out.extend([13, dst, src1, src2]),
+            Max        { dst, src1, src2 }    => out.extend([14, dst, src1, src2]),
+            Jump       { target }             => out.extend([15, target]),
+            JumpIf     { target, src }        => out.extend([16, target, src]),
+            JumpIfNot  { target, src }        => out.extend([17, target, src]),
+            SetCounter { src }                => out.extend([18, src]),
+            GetCounter { dst }                => out.extend([19, dst]),
+            Loop       { target }             => out.extend([20, target]),
+            LoopLe     { target, src1, src2 } => out.extend([21, target, src1, src2]),
+            Return     { src }                => out.extend([22, src]),
+            Call       { target, base }       => out.extend([23, target, base]),
+            Ret        { src }                => out.extend([24, src]),
+            LoadMem    { dst, addr }          => out.extend([25, dst, addr]),
+            StoreMem   { src, addr }          => out.extend([26, src, addr]),
+            LoadConst  { dst, index }         => out.extend([27, dst, index]),
+            Wide       { high }               => { out.push(28); out.extend(high); }
+            MulAdd     { dst, src1, src2 }    => out.extend([29, dst, src1, src2]),
+            MulSub     { dst, src1, src2 }    => out.extend([30, dst, src1, src2]),
+            JumpIfLt   { target, src1, src2 } => out.extend([31, target, src1, src2]),
+            JumpIfLe   { target, src1, src2 } => out.extend([32, target, src1, src2]),
+        }
+    }
+
+    // struct fields are evaluated in the order they're written.
+    fn decode_reg(r: &mut Reader, version: u16) -> Result<reg::Instruction, Error> {
+        use reg::Instruction::*;
+        let offset = r.pos;
+        let opcode = r.u8()?;
+        let since = match opcode { 27..=32 => 2, _ => 1 };
+        if version < since {
+            return Err(Error::UnsupportedVersion { version });
+        }
+        Ok(match opcode {
+            0  => LoadInt    { dst: r.u8()?, value: r.u16()? as i16 },
+            1  => Copy       { dst: r.u8()?, src: r.u8()? },
+            2  => Add        { dst: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            3  => Sub        { dst: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            4  => Mul        { dst: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            5  => Div        { dst: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            6  => Neg        { dst: r.u8()?, src: r.u8()? },
+            7  => Lt         { dst: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            8  => Le         { dst: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            9  => Eq         { dst: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            10 => Sqrt       { dst: r.u8()?, src: r.u8()? },
+            11 => Abs        { dst: r.u8()?, src: r.u8()? },
+            12 => Floor      { dst: r.u8()?, src: r.u8()? },
+            13 => Min        { dst: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            14 => Max        { dst: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            15 => Jump       { target: r.u8()? },
+            16 => JumpIf     { target: r.u8()?, src: r.u8()? },
+            17 => JumpIfNot  { target: r.u8()?, src: r.u8()? },
+            18 => SetCounter { src: r.u8()? },
+            19 => GetCounter { dst: r.u8()? },
+            20 => Loop       { target: r.u8()? },
+            21 => LoopLe     { target: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            22 => Return     { src: r.u8()? },
+            23 => Call       { target: r.u8()?, base: r.u8()? },
+            24 => Ret        { src: r.u8()? },
+            25 => LoadMem    { dst: r.u8()?, addr: r.u8()? },
+            26 => StoreMem   { src: r.u8()?, addr: r.u8()? },
+            27 => LoadConst  { dst: r.u8()?, index: r.u8()? },
+            28 => Wide       { high: [r.u8()?, r.u8()?, r.u8()?] },
+            29 => MulAdd     { dst: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            30 => MulSub     { dst: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            31 => JumpIfLt   { target: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            32 => JumpIfLe   { target: r.u8()?, src1: r.u8()?, src2: r.u8()? },
+            opcode => return Err(Error::UnknownOpcode { offset, opcode }),
+        })
+    }
+
+    pub fn encode_stack(instr: &stack::Instruction, out: &mut Vec<u8>) {
+        use stack::Instruction::*;
+        match *instr {
+            Load       { src }    => out.extend([0, src]),
+            Store      { dst }    => out.extend([1, dst]),
+            LoadInt    { value }  => out.extend([2, value as u8]),
+            Add                   => out.push(3),
+            Sub                   => out.push(4),
+            Mul                   => out.push(5),
+            Div                   => out.push(6),
+            Neg                   => out.push(7),
+            Lt                    => out.push(8),
+            Le                    => out.push(9),
+            Eq                    => out.push(10),
+            Sqrt                  => out.push(11),
+            Abs                   => out.push(12),
+            Floor                 => out.push(13),
+            Min                   => out.push(14),
+            Max                   => out.push(15),
+            Pop                   => out.push(16),
+            Dup                   => out.push(17),
+            Rot                   => out.push(18),
+            Swap                  => out.push(19),
+            Jump       { target } => out.extend([20, target]),
+            JumpIf     { target } => out.extend([21, target]),
+            JumpIfNot  { target } => out.extend([22, target]),
+            SetCounter            => out.push(23),
+            GetCounter            => out.push(24),
+            Loop       { target } => out.extend([25, target]),
+            LoopLe     { target } => out.extend([26, target]),
+            Return                => out.push(27),
+            Nop                   => out.push(28),
+            Call       { target } => out.extend([29, target]),
+            Ret                   => out.push(30),
+            LoadMem               => out.push(31),
+            StoreMem              => out.push(32),
+            LoadConst  { index }  => out.extend([33, index]),
+            Wide       { high }   => out.extend([34, high]),
+            MulSlots   { slots }  => out.extend([35, slots]),
+            AddInt     { value }  => out.extend([36, value as u8]),
+            Square                => out.push(37),
+            Enter      { argc }   => out.extend([38, argc]),
+        }
+    }
+
+    fn decode_stack(r: &mut Reader, version: u16) -> Result<stack::Instruction, Error> {
+        use stack::Instruction::*;
+        let offset = r.pos;
+        let opcode = r.u8()?;
+        let since = match opcode { 33..=37 => 2, 29 | 38 => 3, _ => 1 };
+        if version < since {
+            return Err(Error::UnsupportedVersion { version });
+        }
+        Ok(match opcode {
+            0  => Load       { src: r.u8()? },
+            1  => Store      { dst: r.u8()? },
+            2  => LoadInt    { value: r.u8()? as i8 },
+            3  => Add,
+            4  => Sub,
+            5  => Mul,
+            6  => Div,
+            7  => Neg,
+            8  => Lt,
+            9  => Le,
+            10 => Eq,
+            11 => Sqrt,
+            12 => Abs,
+            13 => Floor,
+            14 => Min,
+            15 => Max,
+            16 => Pop,
+            17 => Dup,
+            18 => Rot,
+            19 => Swap,
+            20 => Jump       { target: r.u8()? },
+            21 => JumpIf     { target: r.u8()? },
+            22 => JumpIfNot  { target: r.u8()? },
+            23 => SetCounter,
+            24 => GetCounter,
+            25 => Loop       { target: r.u8()? },
+            26 => LoopLe     { target: r.u8()? },
+            27 => Return,
+            28 => Nop,
+            29 => Call       { target: r.u8()? },
+            30 => Ret,
+            31 => LoadMem,
+            32 => StoreMem,
+            33 => LoadConst  { index: r.u8()? },
+            34 => Wide       { high: r.u8()? },
+            35 => MulSlots   { slots: r.u8()? },
+            36 => AddInt     { value: r.u8()? as i8 },
+            37 => Square,
+            38 => Enter      { argc: r.u8()? },
+            opcode => return Err(Error::UnknownOpcode { offset, opcode }),
+        })
+    }
+
+
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        pos:   usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+            let bytes = self.bytes.get(self.pos .. self.pos + n).ok_or(Error::Truncated)?;
+            self.pos += n;
+            Ok(bytes)
+        }
+
+        fn u8(&mut self) -> Result<u8, Error> {
+            Ok(self.take(1)?[0])
+        }
+
+        fn u16(&mut self) -> Result<u16, Error> {
+            Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+        }
+
+        fn u32(&mut self) -> Result<u32, Error> {
+            Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+        }
+
+        fn end(&self) -> Result<(), Error> {
+            if self.pos != self.bytes.len() {
+                return Err(Error::TrailingBytes { offset: self.pos });
+            }
+            Ok(())
+        }
+    }
+
+
+    /// `build [--argc n] (reg|stack) <source> <out>`, `run <file> [args]`
+    /// or `dis <file>`. sources ending in `.asm` are assembled, anything
+    /// else is compiled with `lang`, which knows its number of arguments.
+    pub fn main(args: &[String]) -> Result<(), String> {
+        let read_file = |path: &str| {
+            let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            read(&bytes).map_err(|e| format!("{}: {}", path, e))
+        };
+
+        match args.first().map(|arg| arg.as_str()) {
+            Some("build") => {
+                let mut argc = None;
+                let mut operands = vec![];
+                let mut args = args[1..].iter();
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--argc" => argc = Some(
+                            args.next().and_then(|n| n.parse::<usize>().ok())
+                            .ok_or("--argc expects a number")?),
+                        _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                        _ => operands.push(arg.as_str()),
+                    }
+                }
+                let [isa, source_path, out_path] = operands[..] else {
+                    return Err("usage: build [--argc n] (reg|stack) <source> <out>".into());
+                };
+
+                let source = std::fs::read_to_string(source_path).map_err(|e| format!("{}: {}", source_path, e))?;
+                let program = build(isa, &source, source_path.ends_with(".asm"), argc)
+                    .map_err(|e| format!("{}: {}", source_path, e))?;
+                let bytes = write(&program).map_err(|e| format!("{}: {}", source_path, e))?;
+                std::fs::write(out_path, bytes).map_err(|e| format!("{}: {}", out_path, e))
+            }
+
+            Some("run") => {
+                let Some(path) = args.get(1) else {
+                    return Err("usage: run <file> [args]".into());
+                };
+                let values = args[2..].iter()
+                    .map(|arg| arg.parse::<f64>().map_err(|_| format!("invalid argument `{}`", arg)))
+                    .collect::<Result<Vec<f64>, String>>()?;
+
+                let program = read_file(path)?;
+                if values.len() != program.argc() {
+                    return Err(format!("{} expects {} argument(s), found {}", path, program.argc(), values.len()));
+                }
+                let result = match program {
+                    Program::Reg { code, constants, .. } => {
+                        let mut vm = reg::Vm::new();
+                        *vm.constants_mut() = constants;
+                        vm.try_run(&code, &values)
+                    }
+                    Program::Stack { code, constants, .. } => {
+                        let mut vm = stack::Vm::new();
+                        *vm.constants_mut() = constants;
+                        vm.try_run(&code, &values)
+                    }
+                };
+                println!("{}", result.map_err(|e| format!("{:?}", e))?);
+                Ok(())
+            }
+
+            Some("dis") => {
+                let Some(path) = args.get(1) else {
+                    return Err("usage: dis <file>".into());
+                };
+                let program = read_file(path)?;
+                for (i, constant) in program.constants().iter().enumerate() {
+                    println!("// constant {}: {:?}", i, constant);
+                }
+                match program {
+                    Program::Reg   { code, argc, .. } => print!("// reg, {} argument(s)\n{}", argc, disasm::disassemble_reg(&code)),
+                    Program::Stack { code, argc, .. } => print!("// stack, {} argument(s)\n{}", argc, disasm::disassemble_stack(&code, argc)),
+                }
+                Ok(())
+            }
+
+            _ => Err("expected `build`, `run` or `dis`".into()),
+        }
+    }
+
+    /// `source` in the syntax of `asm` or `lang`,
+    /// `argc` is required for `asm`, and not allowed for `lang`.
+    pub fn build(isa: &str, source: &str, is_asm: bool, argc: Option<usize>) -> Result<Program, String> {
+        let argc = match (is_asm, argc) {
+            (true,  Some(argc)) => argc,
+            (true,  None)       => return Err("asm sources need `--argc`".into()),
+            (false, None)       => lang::parse(source).map_err(|e| e.to_string())?.params.len(),
+            (false, Some(_))    => return Err("`--argc` is only for asm sources".into()),
+        };
+        // asm has no syntax for constants.
+        Ok(match (isa, is_asm) {
+            ("reg", true) => Program::Reg { code: asm::assemble_reg(source).map_err(|e| e.to_string())?, argc, constants: vec![] },
+            ("reg", false) => {
+                let (code, constants) = lang::compile_reg_with_constants(source).map_err(|e| e.to_string())?;
+                Program::Reg { code, argc, constants }
+            }
+            ("stack", true) => Program::Stack { code: asm::assemble_stack(source).map_err(|e| e.to_string())?, argc, constants: vec![] },
+            ("stack", false) => {
+                let (code, constants) = lang::compile_stack_with_constants(source).map_err(|e| e.to_string())?;
+                Program::Stack { code, argc, constants }
+            }
+            _ => return Err(format!("unknown instruction set `{}`, expected `reg` or `stack`", isa)),
+        })
+    }
+}
+
+
+
+pub mod packed {
+    //! programs as a packed byte stream that the vms decode as they go,
+    //! instead of the fixed size `Instruction` enums. those are 4 bytes
+    //! for reg and 2 for stack.
+    //!
+    //! an instruction is encoded like in `bytecode`, a one byte opcode
+    //! followed by its operands, except that targets are two byte
+    //! offsets into the stream. see `reg::Vm::run_packed` and
+    //! `stack::Vm::run_packed`, and `bench --density` for the sizes.
+
+    use super::{reg, stack, bytecode, VmError};
+
+
+    /// `bytecode`'s opcodes for `reg::Instruction`.
+    pub mod reg_op {
+        pub const LOAD_INT:    u8 = 0;
+        pub const COPY:        u8 = 1;
+        pub const ADD:         u8 = 2;
+        pub const SUB:         u8 = 3;
+        pub const MUL:         u8 = 4;
+        pub const DIV:         u8 = 5;
+        pub const NEG:         u8 = 6;
+        pub const LT:          u8 = 7;
+        pub const LE:          u8 = 8;
+        pub const EQ:          u8 = 9;
+        pub const SQRT:        u8 = 10;
+        pub const ABS:         u8 = 11;
+        pub const FLOOR:       u8 = 12;
+        pub const MIN:         u8 = 13;
+        pub const MAX:         u8 = 14;
+        pub const JUMP:        u8 = 15;
+        pub const JUMP_IF:     u8 = 16;
+        pub const JUMP_IF_NOT: u8 = 17;
+        pub const SET_COUNTER: u8 = 18;
+        pub const GET_COUNTER: u8 = 19;
+        pub const LOOP:        u8 = 20;
+        pub const LOOP_LE:     u8 = 21;
+        pub const RETURN:      u8 = 22;
+        pub const CALL:        u8 = 23;
+        pub const RET:         u8 = 24;
+        pub const LOAD_MEM:    u8 = 25;
+        pub const STORE_MEM:   u8 = 26;
+        pub const LOAD_CONST:  u8 = 27;
+    }
+
+    /// `bytecode`'s opcodes for `stack::Instruction`.
+    pub mod stack_op {
+        pub const LOAD:        u8 = 0;
+        pub const STORE:       u8 = 1;
+        pub const LOAD_INT:    u8 = 2;
+        pub const ADD:         u8 = 3;
+        pub const SUB:         u8 = 4;
+        pub const MUL:         u8 = 5;
+        pub const DIV:         u8 = 6;
+        pub const NEG:         u8 = 7;
+        pub const LT:          u8 = 8;
+        pub const LE:          u8 = 9;
+        pub const EQ:          u8 = 10;
+        pub const SQRT:        u8 = 11;
+        pub const ABS:         u8 = 12;
+        pub const FLOOR:       u8 = 13;
+        pub const MIN:         u8 = 14;
+        pub const MAX:         u8 = 15;
+        pub const POP:         u8 = 16;
+        pub const DUP:         u8 = 17;
+        pub const ROT:         u8 = 18;
+        pub const SWAP:        u8 = 19;
+        pub const JUMP:        u8 = 20;
+        pub const JUMP_IF:     u8 = 21;
+        pub const JUMP_IF_NOT: u8 = 22;
+        pub const SET_COUNTER: u8 = 23;
+        pub const GET_COUNTER: u8 = 24;
+        pub const LOOP:        u8 = 25;
+        pub const LOOP_LE:     u8 = 26;
+        pub const RETURN:      u8 = 27;
+        pub const NOP:         u8 = 28;
+        pub const CALL:        u8 = 29;
+        pub const RET:         u8 = 30;
+        pub const LOAD_MEM:    u8 = 31;
+        pub const STORE_MEM:   u8 = 32;
+        pub const LOAD_CONST:  u8 = 33;
+        pub const ENTER:       u8 = 38;
+    }
+
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Error {
+        /// a `Wide` at `pc` extends something other than a target.
+        /// only targets can be wider than a byte. or a superinstruction
+        /// from `fuse`, the packed loops don't have those.
+        Unsupported { pc: usize },
+        /// the stream is longer than two byte targets can reach.
+        TooLong,
+    }
+
+    /// a verified reg program, safe to run with `SPEEEEEED`.
+    #[derive(Clone, Debug)]
+    pub struct RegProgram {
+        code:    Vec<u8>,
+        offsets: Vec<usize>,
+    }
+
+    /// a verified stack program.
+    #[derive(Clone, Debug)]
+    pub struct StackProgram {
+        code:    Vec<u8>,
+        offsets: Vec<usize>,
+        argc:    usize,
+    }
+
+    impl RegProgram {
+        #[inline(always)]
+        pub fn code(&self) -> &[u8] { &self.code }
+
+        /// the instruction at byte `offset`, for errors.
+        pub fn pc(&self, offset: usize) -> usize { pc(&self.offsets, offset) }
+    }
+
+    impl StackProgram {
+        #[inline(always)]
+        pub fn code(&self) -> &[u8] { &self.code }
+
+        #[inline(always)]
+        pub fn argc(&self) -> usize { self.argc }
+
+        pub fn pc(&self, offset: usize) -> usize { pc(&self.offsets, offset) }
+    }
+
+    // a `Wide` and the instruction after it share an offset,
+    // errors name the prefix, like the vms do.
+    fn pc(offsets: &[usize], offset: usize) -> usize {
+        offsets.partition_point(|o| *o < offset)
+    }
+
+
+    pub fn encode_reg(program: &reg::VerifiedProgram) -> Result<RegProgram, Error> {
+        let code = program.code();
+        let mut e = Encoder::new(code.len());
+        let mut pc = 0;
+        while pc < code.len() {
+            let (instr, [a, b, c], len) = reg::decode(code, pc).ok_or(Error::Unsupported { pc })?;
+            if instr.is_fused() {
+                return Err(Error::Unsupported { pc });
+            }
+            let target = instr.target().map(|low| reg::wide(low, a));
+            let high = if target.is_some() { [0, b, c] } else { [a, b, c] };
+            if high != [0; 3] {
+                return Err(Error::Unsupported { pc });
+            }
+            e.start(pc, len);
+            bytecode::encode_reg(&instr, &mut e.out);
+            e.target(target);
+            pc += len;
+        }
+        Ok(RegProgram { offsets: e.finish()?, code: e.out })
+    }
+
+    pub fn encode_stack(program: &stack::VerifiedProgram) -> Result<StackProgram, Error> {
+        let code = program.code();
+        let mut e = Encoder::new(code.len());
+        let mut pc = 0;
+        while pc < code.len() {
+            let (instr, high, len) = stack::decode(code, pc).ok_or(Error::Unsupported { pc })?;
+            if instr.is_fused() {
+                return Err(Error::Unsupported { pc });
+            }
+            let target = instr.target().map(|low| reg::wide(low, high));
+            if target.is_none() && high != 0 {
+                return Err(Error::Unsupported { pc });
+            }
+            e.start(pc, len);
+            bytecode::encode_stack(&instr, &mut e.out);
+            e.target(target);
+            pc += len;
+        }
+        Ok(StackProgram { offsets: e.finish()?, code: e.out, argc: program.argc() })
+    }
+
+    struct Encoder {
+        out:     Vec<u8>,
+        /// the offset of each instruction, and of the end.
+        offsets: Vec<usize>,
+        /// where to write which instruction's offset.
+        patches: Vec<(usize, usize)>,
+        start:   usize,
+    }
+
+    impl Encoder {
+        fn new(len: usize) -> Self {
+            Encoder { out: vec![], offsets: vec![0; len + 1], patches: vec![], start: 0 }
+        }
+
+        fn start(&mut self, pc: usize, len: usize) {
+            self.start = self.out.len();
+            for offset in &mut self.offsets[pc .. pc + len] {
+                *offset = self.start;
+            }
+        }
+
+        /// widens the target, the operand after the opcode, to two bytes.
+        fn target(&mut self, target: Option<usize>) {
+            if let Some(target) = target {
+                self.out.insert(self.start + 2, 0);
+                self.patches.push((self.start + 1, target));
+            }
+        }
+
+        fn finish(&mut self) -> Result<Vec<usize>, Error> {
+            let end = self.out.len();
+            if end > u16::MAX as usize {
+                return Err(Error::TooLong);
+            }
+            *self.offsets.last_mut().unwrap() = end;
+            for &(at, target) in &self.patches {
+                // unreachable code can have any target,
+                // `verify` has checked the reachable ones.
+                let offset = self.offsets.get(target).copied().unwrap_or(0) as u16;
+                self.out[at .. at + 2].copy_from_slice(&offset.to_le_bytes());
+            }
+            Ok(core::mem::take(&mut self.offsets))
+        }
+    }
+
+
+    /// the byte at `at`, which an encoder has put there.
+    #[inline(always)]
+    pub fn byte(code: &[u8], at: usize) -> u8 {
+        if super::SPEEEEEED {
+            unsafe { *code.get_unchecked(at) }
+        }
+        else {
+            code[at]
+        }
+    }
+
+    /// the two byte target at `at`.
+    #[inline(always)]
+    pub fn target(code: &[u8], at: usize) -> usize {
+        u16::from_le_bytes([byte(code, at), byte(code, at + 1)]) as usize
+    }
+
+    /// `run_packed` panics like `run` does.
+    #[cold]
+    #[inline(never)]
+    pub fn failed(error: VmError) -> ! {
+        panic!("{:?}", error)
+    }
+}
+
+
+
+pub mod dispatch {
+    //! ways for the vms to get from one instruction to the next, to tell
+    //! the cost of dispatch apart from that of the instruction sets, see
+    //! `bench --dispatch`.
+    //!
+    //! a program is prepared for one of them with `reg::Dispatched` or
+    //! `stack::Dispatched`, and run with `Vm::run_with`. except for
+    //! `Match`, they all call handlers that are instances of one
+    //! `execute` per vm, one for each opcode, so they share the semantics
+    //! of `run`, including its panics.
+
+
+    pub trait Dispatch {
+        const NAME: &'static str;
+        const KIND: Kind;
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Kind {
+        Match,
+        Table,
+        Closures,
+        TailCalls,
+    }
+
+    /// the `match` loop of `run`.
+    pub struct Match;
+
+    /// a table of handlers, indexed by opcode, called from a loop.
+    pub struct Table;
+
+    /// the program decoded up front, into a boxed closure per
+    /// instruction, called from a loop.
+    pub struct Closures;
+
+    /// handlers that end by calling the next instruction's handler.
+    /// rust can't guarantee tail calls, so after `CHAIN` instructions
+    /// a handler returns to the loop instead, which bounds the native
+    /// stack in debug builds, where the calls aren't jumps.
+    pub struct TailCalls;
+
+    pub const CHAIN: u32 = 64;
+
+    impl Dispatch for Match {
+        const NAME: &'static str = "match";
+        const KIND: Kind = Kind::Match;
+    }
+
+    impl Dispatch for Table {
+        const NAME: &'static str = "table";
+        const KIND: Kind = Kind::Table;
+    }
+
+    impl Dispatch for Closures {
+        const NAME: &'static str = "closures";
+        const KIND: Kind = Kind::Closures;
+    }
+
+    impl Dispatch for TailCalls {
+        const NAME: &'static str = "tail calls";
+        const KIND: Kind = Kind::TailCalls;
+    }
+}
+
+
+
+pub mod fuse {
+    //! superinstructions: pairs (and a triple) of instructions that run
+    //! back to back a lot, fused into one that costs a single dispatch,
+    //! see `reg::Instruction::is_fused`. `reg` and `stack` rewrite a
+    //! program to use them at the sites where its `Profile` says it
+    //! pays off, and `pairs_reg` and `pairs_stack` rank the pairs of a
+    //! profile, which is how these were picked.
+    //!
+    //! like `peephole`, rewrites never span a jump target, and programs
+    //! with `Wide` prefixes are left alone.
+
+    use super::{reg, stack, disasm};
+    use super::profile::Profile;
+
+
+    /// the share of a program's dispatches a site has to save to be
+    /// fused, so code that hardly runs keeps its shape.
+    pub const MIN_SHARE: f64 = 0.01;
+
+
+    /// an instruction followed by another, and how often that ran.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Pair {
+        pub first:  &'static str,
+        pub second: &'static str,
+        pub count:  u64,
+    }
+
+    /// sites rewritten, per superinstruction.
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct Stats {
+        pub fused: Vec<(&'static str, usize)>,
+    }
+
+    impl Stats {
+        pub fn total(&self) -> usize {
+            self.fused.iter().map(|(_, n)| n).sum()
+        }
+
+        pub fn get(&self, mnemonic: &str) -> usize {
+            self.fused.iter().find(|(m, _)| *m == mnemonic).map_or(0, |(_, n)| *n)
+        }
+
+        fn add(&mut self, mnemonic: &'static str) {
+            match self.fused.iter_mut().find(|(m, _)| *m == mnemonic) {
+                Some((_, n)) => *n += 1,
+                None => self.fused.push((mnemonic, 1)),
+            }
+        }
+    }
+
+    impl core::fmt::Display for Stats {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            for (mnemonic, n) in &self.fused {
+                writeln!(f, "{:12} {:5}", mnemonic, n)?;
+            }
+            Ok(())
+        }
+    }
+
+
+    /// the pairs that ran in `profile`, most frequent first. only pairs
+    /// that could be fused count, so the second instruction can't be a
+    /// jump target, and the first has to fall through to it.
+    pub fn pairs_reg(code: &[reg::Instruction], profile: &Profile) -> Vec<Pair> {
+        let labels = disasm::Labels::reg(code);
+        let ops: Vec<_> = code.iter().map(|instr| {
+            let mnemonic = disasm::format_reg(instr, &labels).0;
+            (mnemonic, instr.falls_through() && !matches!(instr, reg::Instruction::Call { .. }))
+        }).collect();
+        pairs(&ops, &labels, profile)
+    }
+
+    pub fn pairs_stack(code: &[stack::Instruction], profile: &Profile) -> Vec<Pair> {
+        let labels = disasm::Labels::stack(code);
+        let ops: Vec<_> = code.iter().map(|instr| {
+            let mnemonic = disasm::format_stack(instr, &labels).0;
+            (mnemonic, instr.falls_through() && !matches!(instr, stack::Instruction::Call { .. }))
+        }).collect();
+        pairs(&ops, &labels, profile)
+    }
+
+    fn pairs(ops: &[(&'static str, bool)], labels: &disasm::Labels, profile: &Profile) -> Vec<Pair> {
+        let mut result: Vec<Pair> = vec![];
+        for pc in 1..ops.len() {
+            let (first, falls_through) = ops[pc - 1];
+            let count = count(profile, pc - 1);
+            if !falls_through || labels.is_target(pc) || count == 0 {
+                continue;
+            }
+            let second = ops[pc].0;
+            match result.iter_mut().find(|pair| pair.first == first && pair.second == second) {
+                Some(pair) => pair.count += count,
+                None => result.push(Pair { first, second, count }),
+            }
+        }
+        result.sort_by_key(|pair| core::cmp::Reverse(pair.count));
+        result
+    }
+
+    /// how often the instruction at `pc` fell through to the next one.
+    fn count(profile: &Profile, pc: usize) -> u64 {
+        let executed = profile.executed.get(pc).copied().unwrap_or(0);
+        let taken    = profile.taken.get(pc).copied().unwrap_or(0);
+        executed - taken
+    }
+
+    /// whether fusing at `pc` saves at least `MIN_SHARE` of the dispatches.
+    fn is_hot(profile: &Profile, pc: usize) -> bool {
+        let dispatches: u64 = profile.executed.iter().sum();
+        let count = count(profile, pc);
+        count > 0 && count as f64 >= MIN_SHARE * dispatches as f64
+    }
+
+
+    /// rewrites the hot sites of `mul t, a, b; add d, d, t` to
+    /// `mul_add d, a, b` (`sub` to `mul_sub`), and of `lt t, a, b;
+    /// jump_if L, t` to `jump_if_lt L, a, b` (`le` to `jump_if_le`),
+    /// if `t` isn't read afterwards. programs with calls are left alone
+    /// too, `reg::live_out` doesn't know what those read.
+    pub fn reg(code: &[reg::Instruction], profile: &Profile) -> (Vec<reg::Instruction>, Stats) {
+        use reg::Instruction::*;
+
+        let mut stats = Stats::default();
+        if code.iter().any(|instr| matches!(instr, Wide { .. } | Call { .. })) {
+            return (code.to_vec(), stats);
+        }
+        let labels   = disasm::Labels::reg(code);
+        let live_out = reg::live_out(code);
+
+        let mut out = Vec::with_capacity(code.len());
+        let mut map = vec![0; code.len() + 1];
+        let mut pc = 0;
+        while pc < code.len() {
+            map[pc] = out.len();
+
+            let a = code[pc];
+            let b = code.get(pc + 1).copied().filter(|_| !labels.is_target(pc + 1) && is_hot(profile, pc));
+            let dead = |t: u8| !live_out[pc + 1].contains(t as usize);
+
+            let fused = match (a, b) {
+                (Mul { dst: t, src1, src2 }, Some(Add { dst, src1: d, src2: u }))
+                if d == dst && u == t && t != dst && dead(t) => {
+                    Some(("mul_add", MulAdd { dst, src1, src2 }))
+                }
+
+                (Mul { dst: t, src1, src2 }, Some(Sub { dst, src1: d, src2: u }))
+                if d == dst && u == t && t != dst && dead(t) => {
+                    Some(("mul_sub", MulSub { dst, src1, src2 }))
+                }
+
+                (Lt { dst: t, src1, src2 }, Some(JumpIf { target, src })) if src == t && dead(t) => {
+                    Some(("jump_if_lt", JumpIfLt { target, src1, src2 }))
+                }
+
+                (Le { dst: t, src1, src2 }, Some(JumpIf { target, src })) if src == t && dead(t) => {
+                    Some(("jump_if_le", JumpIfLe { target, src1, src2 }))
+                }
+
+                _ => None,
+            };
+            match fused {
+                Some((mnemonic, instr)) => {
+                    stats.add(mnemonic);
+                    out.push(instr);
+                    map[pc + 1] = out.len();
+                    pc += 2;
+                }
+                None => {
+                    out.push(a);
+                    pc += 1;
+                }
+            }
+        }
+        map[code.len()] = out.len();
+
+        for instr in &mut out {
+            if let Some(target) = instr.target_mut() {
+                // verified code has its targets in range.
+                *target = map.get(*target as usize).map_or(*target as usize, |t| *t) as u8;
+            }
+        }
+        (out, stats)
+    }
+
+    /// rewrites the hot sites of `load a; load b; mul` to `mul_slots a, b`
+    /// (where `a` and `b` fit, see `stack::pack_slots`),
+    /// `load_int v; add` to `add_int v`, `load_int v; sub` to `add_int -v`
+    /// (unless `v` is 0, `x - 0` and `x + -0` differ for `x = -0`),
+    /// and `dup; mul` to `square`.
+    pub fn stack(code: &[stack::Instruction], profile: &Profile) -> (Vec<stack::Instruction>, Stats) {
+        use stack::Instruction::*;
+
+        let mut stats = Stats::default();
+        if code.iter().any(|instr| matches!(instr, Wide { .. })) {
+            return (code.to_vec(), stats);
+        }
+        let labels = disasm::Labels::stack(code);
+
+        let mut out = Vec::with_capacity(code.len());
+        let mut map = vec![0; code.len() + 1];
+        let mut pc = 0;
+        while pc < code.len() {
+            map[pc] = out.len();
+
+            let next = |i: usize| {
+                code.get(pc + i).copied().filter(|_| !labels.is_target(pc + i) && is_hot(profile, pc + i - 1))
+            };
+            let a = code[pc];
+            let b = next(1);
+            let c = b.and_then(|_| next(2));
+
+            let fused = match (a, b, c) {
+                (Load { src: src1 }, Some(Load { src: src2 }), Some(Mul)) => {
+                    stack::pack_slots(src1, src2).map(|slots| (3, "mul_slots", MulSlots { slots }))
+                }
+
+                (LoadInt { value }, Some(Add), _) => {
+                    Some((2, "add_int", AddInt { value }))
+                }
+
+                (LoadInt { value }, Some(Sub), _) if value != 0 && value != i8::MIN => {
+                    Some((2, "add_int", AddInt { value: -value }))
+                }
+
+                (Dup, Some(Mul), _) => {
+                    Some((2, "square", Square))
+                }
+
+                _ => None,
+            };
+            match fused {
+                Some((consumed, mnemonic, instr)) => {
+                    stats.add(mnemonic);
+                    out.push(instr);
+                    for i in 1..consumed {
+                        map[pc + i] = out.len();
+                    }
+                    pc += consumed;
+                }
+                None => {
+                    out.push(a);
+                    pc += 1;
+                }
+            }
+        }
+        map[code.len()] = out.len();
+
+        for instr in &mut out {
+            if let Some(target) = instr.target_mut() {
+                // verified code has its targets in range.
+                *target = map.get(*target as usize).map_or(*target as usize, |t| *t) as u8;
+            }
+        }
+        (out, stats)
+    }
+}
+
+
+
+pub mod jit {
+    //! a template jit for `reg` programs with `f64` values: each
+    //! instruction becomes a fixed sequence of x86-64 code, so there's
+    //! no dispatch and no decoding left, just the registers, which stay
+    //! in the vm's register file. see `reg::Vm::run_jit`, and the `jit`
+    //! rows of `bench` for how far that gets towards the native `fib`
+    //! and `mandel`.
+    //!
+    //! only on x86-64 linux, elsewhere programs aren't `native` and run
+    //! in the interpreter.
+
+    use super::{reg, VmError};
+
+
+    /// a verified program, and its machine code where there's a jit.
+    pub struct Program {
+        program: reg::VerifiedProgram,
+        code: Option<native::Code>,
+    }
+
+    impl Program {
+        pub fn new(program: &reg::VerifiedProgram) -> Self {
+            Program { program: program.clone(), code: native::compile(program) }
+        }
+
+        pub fn program(&self) -> &reg::VerifiedProgram {
+            &self.program
+        }
+
+        /// whether `run` runs machine code.
+        pub fn is_native(&self) -> bool {
+            self.code.is_some()
+        }
+
+        /// bytes of machine code.
+        pub fn code_size(&self) -> usize {
+            self.code.as_ref().map_or(0, |code| code.len())
+        }
+
+        /// runs the machine code with the register file `registers`,
+        /// which has the arguments in place, `None` if there's none.
+        /// calls fail like in `reg::Vm` once their window would go past
+        /// the end of `registers`.
+        pub fn run(&self, registers: &mut [f64], memory: &mut [f64], constants: &[f64]) -> Option<Result<f64, VmError>> {
+            let code = self.code.as_ref()?;
+            assert!(registers.len() >= reg::REGISTER_COUNT);
+            let mut env = Env {
+                registers:     registers.as_mut_ptr(),
+                registers_end: registers.as_mut_ptr_range().end,
+                memory:        memory.as_mut_ptr(),
+                memory_len:    memory.len(),
+                constants:     constants.as_ptr(),
+                constants_len: constants.len(),
+                result:        0.0,
+            };
+            Some(code.run(&mut env))
+        }
+    }
+
+    /// what the machine code gets, in `rdi`. the offsets are in `native`.
+    #[repr(C)]
+    struct Env {
+        registers:     *mut f64,
+        registers_end: *mut f64,
+        memory:        *mut f64,
+        memory_len:    usize,
+        constants:     *const f64,
+        constants_len: usize,
+        result:        f64,
+    }
+
+
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    mod native {
+        //! while the code runs, `rbx` points to the current register
+        //! window, `r12` to the `Env`, `r13` holds the counter, `r14`
+        //! the end of the register file, and `r15` the call depth.
+        //! those are callee saved, so the helpers for what's awkward to
+        //! get bit for bit the same as rust (`floor`, `min`, `max`, and
+        //! the saturating conversion to the counter) are plain calls.
+        //!
+        //! `Call` is a native call, with the caller's window and counter
+        //! pushed before it, 32 bytes in all, which keeps the stack
+        //! aligned for the helpers. `Ret` is a native return, after
+        //! which the caller pops them again. `Return` and errors leave
+        //! through the epilogue, which resets the stack, so they can
+        //! happen at any depth.
+
+        use super::{reg, Env, VmError};
+        use super::super::MAX_CALL_DEPTH;
+
+        const RAX: u8 = 0;
+        const RDX: u8 = 2;
+        const XMM0: u8 = 0;
+        const XMM1: u8 = 1;
+
+        // offsets into `Env`.
+        const REGISTERS:     u8 = 0;
+        const REGISTERS_END: u8 = 8;
+        const MEMORY:        u8 = 16;
+        const MEMORY_LEN:    u8 = 24;
+        const CONSTANTS:     u8 = 32;
+        const CONSTANTS_LEN: u8 = 40;
+        const RESULT:        u8 = 48;
+
+        const MOVSD_LOAD:  [u8; 3] = [0xf2, 0x0f, 0x10];
+        const MOVSD_STORE: [u8; 3] = [0xf2, 0x0f, 0x11];
+        const ADDSD:       [u8; 3] = [0xf2, 0x0f, 0x58];
+        const MULSD:       [u8; 3] = [0xf2, 0x0f, 0x59];
+        const SUBSD:       [u8; 3] = [0xf2, 0x0f, 0x5c];
+        const DIVSD:       [u8; 3] = [0xf2, 0x0f, 0x5e];
+        const SQRTSD:      [u8; 3] = [0xf2, 0x0f, 0x51];
+        const UCOMISD:     [u8; 3] = [0x66, 0x0f, 0x2e];
+        const MOV_LOAD:  u8 = 0x8b;
+        const MOV_STORE: u8 = 0x89;
+
+        const JMP:  &[u8] = &[0xe9];
+        const CALL: &[u8] = &[0xe8];
+        const JA:   &[u8] = &[0x0f, 0x87];
+        const JAE:  &[u8] = &[0x0f, 0x83];
+        const JB:   &[u8] = &[0x0f, 0x82];
+        const JBE:  &[u8] = &[0x0f, 0x86];
+        const JE:   &[u8] = &[0x0f, 0x84];
+        const JNE:  &[u8] = &[0x0f, 0x85];
+        const JP:   &[u8] = &[0x0f, 0x8a];
+
+        extern "sysv64" fn counter(x: f64) -> u64 { x as u32 as u64 }
+        extern "sysv64" fn floor(x: f64) -> f64 { x.floor() }
+        extern "sysv64" fn min(a: f64, b: f64) -> f64 { a.min(b) }
+        extern "sysv64" fn max(a: f64, b: f64) -> f64 { a.max(b) }
+
+
+        /// machine code in its own executable mapping.
+        pub struct Code {
+            ptr: *mut u8,
+            len: usize,
+            /// what the exit with status `i + 1` means.
+            errors: Vec<VmError>,
+        }
+
+        extern "C" {
+            fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8;
+            fn mprotect(addr: *mut u8, len: usize, prot: i32) -> i32;
+            fn munmap(addr: *mut u8, len: usize) -> i32;
+        }
+
+        const PROT_READ:     i32 = 1;
+        const PROT_WRITE:    i32 = 2;
+        const PROT_EXEC:     i32 = 4;
+        const MAP_PRIVATE:   i32 = 2;
+        const MAP_ANONYMOUS: i32 = 0x20;
+
+        impl Code {
+            /// `None` if the mapping fails.
+            fn new(bytes: &[u8], errors: Vec<VmError>) -> Option<Self> {
+                unsafe {
+                    let ptr = mmap(core::ptr::null_mut(), bytes.len(), PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+                    if ptr as isize == -1 {
+                        return None;
+                    }
+                    let code = Code { ptr, len: bytes.len(), errors };
+                    core::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len());
+                    if mprotect(ptr, bytes.len(), PROT_READ | PROT_EXEC) != 0 {
+                        return None;
+                    }
+                    Some(code)
+                }
+            }
+
+            pub fn len(&self) -> usize {
+                self.len
+            }
+
+            pub fn run(&self, env: &mut Env) -> Result<f64, VmError> {
+                let status = unsafe {
+                    let entry: extern "sysv64" fn(*mut Env) -> u64 = core::mem::transmute(self.ptr);
+                    entry(env)
+                };
+                match status {
+                    0 => Ok(env.result),
+                    _ => Err(self.errors[status as usize - 1]),
+                }
+            }
+        }
+
+        impl Drop for Code {
+            fn drop(&mut self) {
+                unsafe { munmap(self.ptr, self.len); }
+            }
+        }
+
+
+        #[derive(Clone, Copy)]
+        enum Label {
+            Pc(usize),
+            /// returns `xmm0`.
+            Exit,
+            /// returns the status.
+            Epilogue,
+            /// the `i`th of `errors`.
+            Error(usize),
+        }
+
+        struct Asm {
+            bytes: Vec<u8>,
+            /// where the code of each pc starts.
+            pcs: Vec<usize>,
+            /// rel32s to patch.
+            fixups: Vec<(usize, Label)>,
+            errors: Vec<VmError>,
+        }
+
+        impl Asm {
+            fn emit(&mut self, bytes: &[u8]) {
+                self.bytes.extend_from_slice(bytes);
+            }
+
+            fn emit_i32(&mut self, x: i32) {
+                self.emit(&x.to_le_bytes());
+            }
+
+            /// the modrm byte and displacement for `[rbx + 8*index]`,
+            /// register `index` of the current window.
+            fn window(&mut self, reg: u8, index: usize) {
+                let disp = (index * 8) as i32;
+                if disp < 128 {
+                    self.emit(&[0x43 | reg << 3, disp as u8]);
+                }
+                else {
+                    self.emit(&[0x83 | reg << 3]);
+                    self.emit_i32(disp);
+                }
+            }
+
+            /// an sse op with a register of the window as its source
+            /// (or destination, for `MOVSD_STORE`).
+            fn sse(&mut self, op: [u8; 3], xmm: u8, index: usize) {
+                self.emit(&op);
+                self.window(xmm, index);
+            }
+
+            /// a 64 bit `mov` between a general purpose register and
+            /// a register of the window.
+            fn mov(&mut self, op: u8, reg: u8, index: usize) {
+                self.emit(&[0x48, op]);
+                self.window(reg, index);
+            }
+
+            fn jump(&mut self, op: &[u8], label: Label) {
+                self.emit(op);
+                self.fixups.push((self.bytes.len(), label));
+                self.emit_i32(0);
+            }
+
+            fn error(&mut self, op: &[u8], error: VmError) {
+                self.errors.push(error);
+                self.jump(op, Label::Error(self.errors.len() - 1));
+            }
+
+            /// calls a helper, its arguments are in `xmm0` and `xmm1`.
+            fn call(&mut self, f: *const ()) {
+                self.emit(&[0x48, 0xb8]);
+                self.emit(&(f as u64).to_le_bytes());
+                self.emit(&[0xff, 0xd0]);
+            }
+
+            /// `rax` = the address in register `index`, or exits with
+            /// `BadAddress`. the conversion back catches everything
+            /// that isn't an int, see `Value::address`.
+            fn address(&mut self, index: usize, pc: usize) {
+                self.sse(MOVSD_LOAD, XMM0, index);
+                self.emit(&[0xf2, 0x48, 0x0f, 0x2c, 0xc0]);       // cvttsd2si rax, xmm0
+                self.emit(&[0xf2, 0x48, 0x0f, 0x2a, 0xc8]);       // cvtsi2sd xmm1, rax
+                self.emit(&[0x66, 0x0f, 0x2e, 0xc1]);             // ucomisd xmm0, xmm1
+                self.error(JNE, VmError::BadAddress { pc });
+                self.error(JP,  VmError::BadAddress { pc });
+                self.emit(&[0x49, 0x3b, 0x44, 0x24, MEMORY_LEN]); // cmp rax, [r12 + MEMORY_LEN]
+                self.error(JAE, VmError::BadAddress { pc });
+                self.emit(&[0x49, 0x8b, 0x4c, 0x24, MEMORY]);     // mov rcx, [r12 + MEMORY]
+            }
+
+            /// `r[dst] = op(r[src1], r[src2])` for the sse arithmetic.
+            fn arith(&mut self, op: [u8; 3], dst: usize, src1: usize, src2: usize) {
+                self.sse(MOVSD_LOAD, XMM0, src1);
+                self.sse(op, XMM0, src2);
+                self.sse(MOVSD_STORE, XMM0, dst);
+            }
+
+            /// `r[dst] = f(r[src1], r[src2])` for a helper.
+            fn helper(&mut self, f: *const (), dst: usize, src1: usize, src2: Option<usize>) {
+                self.sse(MOVSD_LOAD, XMM0, src1);
+                if let Some(src2) = src2 {
+                    self.sse(MOVSD_LOAD, XMM1, src2);
+                }
+                self.call(f);
+                self.sse(MOVSD_STORE, XMM0, dst);
+            }
+
+            /// `ucomisd` of `r[src2]` with `r[src1]`, so `ja` is
+            /// `src1 < src2` and `jae` is `src1 <= src2`, both false
+            /// for nans.
+            fn compare(&mut self, src1: usize, src2: usize) {
+                self.sse(MOVSD_LOAD, XMM0, src2);
+                self.sse(UCOMISD, XMM0, src1);
+            }
+
+            /// `r[dst]` = 1 or 0, from the flag in `al`.
+            fn store_bool(&mut self, dst: usize) {
+                self.emit(&[0x0f, 0xb6, 0xc0]);       // movzx eax, al
+                self.emit(&[0xf2, 0x0f, 0x2a, 0xc0]); // cvtsi2sd xmm0, eax
+                self.sse(MOVSD_STORE, XMM0, dst);
+            }
+
+            /// jumps to `target` if `counter > 0`, decrementing it.
+            fn count_down(&mut self, target: usize, next: usize) {
+                self.emit(&[0x45, 0x85, 0xed]); // test r13d, r13d
+                self.jump(JE, Label::Pc(next));
+                self.emit(&[0x41, 0xff, 0xcd]); // dec r13d
+                self.jump(JMP, Label::Pc(target));
+            }
+
+            fn instr(&mut self, instr: reg::Instruction, [a, b, c]: [u8; 3], pc: usize, next: usize) {
+                use reg::Instruction::*;
+                use reg::wide as w;
+                match instr {
+                    LoadInt { dst, value } => {
+                        self.emit(&[0x48, 0xb8]);
+                        self.emit(&(value as f64).to_bits().to_le_bytes());
+                        self.mov(MOV_STORE, RAX, w(dst, a));
+                    }
+
+                    Copy { dst, src } => {
+                        self.mov(MOV_LOAD,  RAX, w(src, b));
+                        self.mov(MOV_STORE, RAX, w(dst, a));
+                    }
+
+                    Add { dst, src1, src2 } => self.arith(ADDSD, w(dst, a), w(src1, b), w(src2, c)),
+                    Sub { dst, src1, src2 } => self.arith(SUBSD, w(dst, a), w(src1, b), w(src2, c)),
+                    Mul { dst, src1, src2 } => self.arith(MULSD, w(dst, a), w(src1, b), w(src2, c)),
+                    Div { dst, src1, src2 } => self.arith(DIVSD, w(dst, a), w(src1, b), w(src2, c)),
+
+                    // the sign bit, like rust's `-` and `abs`.
+                    Neg { dst, src } | Abs { dst, src } => {
+                        let op = if let Neg { .. } = instr { 0xf8 } else { 0xf0 };
+                        self.mov(MOV_LOAD, RAX, w(src, b));
+                        self.emit(&[0x48, 0x0f, 0xba, op, 63]); // btc/btr rax, 63
+                        self.mov(MOV_STORE, RAX, w(dst, a));
+                    }
+
+                    Lt { dst, src1, src2 } | Le { dst, src1, src2 } => {
+                        self.compare(w(src1, b), w(src2, c));
+                        let setcc = if let Lt { .. } = instr { 0x97 } else { 0x93 };
+                        self.emit(&[0x0f, setcc, 0xc0]); // seta/setae al
+                        self.store_bool(w(dst, a));
+                    }
+
+                    Eq { dst, src1, src2 } => {
+                        self.sse(MOVSD_LOAD, XMM0, w(src1, b));
+                        self.sse(UCOMISD, XMM0, w(src2, c));
+                        self.emit(&[0x0f, 0x94, 0xc0]); // sete al
+                        self.emit(&[0x0f, 0x9b, 0xc1]); // setnp cl
+                        self.emit(&[0x20, 0xc8]);       // and al, cl
+                        self.store_bool(w(dst, a));
+                    }
+
+                    Sqrt { dst, src } => {
+                        self.sse(SQRTSD, XMM0, w(src, b));
+                        self.sse(MOVSD_STORE, XMM0, w(dst, a));
+                    }
+
+                    Floor { dst, src } => self.helper(floor as *const (), w(dst, a), w(src, b), None),
+
+                    Min { dst, src1, src2 } => self.helper(min as *const (), w(dst, a), w(src1, b), Some(w(src2, c))),
+                    Max { dst, src1, src2 } => self.helper(max as *const (), w(dst, a), w(src1, b), Some(w(src2, c))),
+
+                    Jump { target } => self.jump(JMP, Label::Pc(w(target, a))),
+
+                    // nans are true.
+                    JumpIf { target, src } | JumpIfNot { target, src } => {
+                        self.sse(MOVSD_LOAD, XMM0, w(src, b));
+                        self.emit(&[0x66, 0x0f, 0x57, 0xc9]); // xorpd xmm1, xmm1
+                        self.emit(&[0x66, 0x0f, 0x2e, 0xc1]); // ucomisd xmm0, xmm1
+                        if let JumpIf { .. } = instr {
+                            self.jump(JNE, Label::Pc(w(target, a)));
+                            self.jump(JP,  Label::Pc(w(target, a)));
+                        }
+                        else {
+                            self.jump(JP, Label::Pc(next));
+                            self.jump(JE, Label::Pc(w(target, a)));
+                        }
+                    }
+
+                    SetCounter { src } => {
+                        self.sse(MOVSD_LOAD, XMM0, w(src, a));
+                        self.call(counter as *const ());
+                        self.emit(&[0x49, 0x89, 0xc5]); // mov r13, rax
+                    }
+
+                    GetCounter { dst } => {
+                        self.emit(&[0xf2, 0x49, 0x0f, 0x2a, 0xc5]); // cvtsi2sd xmm0, r13
+                        self.sse(MOVSD_STORE, XMM0, w(dst, a));
+                    }
+
+                    Loop { target } => self.count_down(w(target, a), next),
+
+                    LoopLe { target, src1, src2 } => {
+                        self.compare(w(src1, b), w(src2, c));
+                        self.jump(JB, Label::Pc(next));
+                        self.count_down(w(target, a), next);
+                    }
+
+                    Return { src } => {
+                        self.sse(MOVSD_LOAD, XMM0, w(src, a));
+                        self.jump(JMP, Label::Exit);
+                    }
+
+                    Call { target, base } => {
+                        let base = w(base, b);
+                        self.emit(&[0x49, 0x81, 0xff]); // cmp r15, MAX_CALL_DEPTH
+                        self.emit_i32(MAX_CALL_DEPTH as i32);
+                        self.error(JAE, VmError::StackOverflow { pc });
+                        self.emit(&[0x48, 0x8d, 0x83]); // lea rax, [rbx + 8*(base + REGISTER_COUNT)]
+                        self.emit_i32(((base + reg::REGISTER_COUNT) * 8) as i32);
+                        self.emit(&[0x4c, 0x39, 0xf0]); // cmp rax, r14
+                        self.error(JA, VmError::StackOverflow { pc });
+
+                        self.emit(&[0x53]);                   // push rbx
+                        self.emit(&[0x41, 0x55]);             // push r13
+                        self.emit(&[0x48, 0x83, 0xec, 0x08]); // sub rsp, 8
+                        self.emit(&[0x48, 0x8d, 0x9b]);       // lea rbx, [rbx + 8*base]
+                        self.emit_i32((base * 8) as i32);
+                        self.emit(&[0x45, 0x31, 0xed]);       // xor r13d, r13d
+                        self.emit(&[0x49, 0xff, 0xc7]);       // inc r15
+                        self.jump(CALL, Label::Pc(w(target, a)));
+                        self.emit(&[0x48, 0x83, 0xc4, 0x08]); // add rsp, 8
+                        self.emit(&[0x41, 0x5d]);             // pop r13
+                        self.emit(&[0x5b]);                   // pop rbx
+                    }
+
+                    // the result lands in the callee's `r0`, the caller's `r[base]`.
+                    Ret { src } => {
+                        self.sse(MOVSD_LOAD, XMM0, w(src, a));
+                        self.emit(&[0x4d, 0x85, 0xff]); // test r15, r15
+                        self.jump(JE, Label::Exit);
+                        self.sse(MOVSD_STORE, XMM0, 0);
+                        self.emit(&[0x49, 0xff, 0xcf]); // dec r15
+                        self.emit(&[0xc3]);             // ret
+                    }
+
+                    LoadMem { dst, addr } => {
+                        self.address(w(addr, b), pc);
+                        self.emit(&[0x48, 0x8b, 0x04, 0xc1]); // mov rax, [rcx + 8*rax]
+                        self.mov(MOV_STORE, RAX, w(dst, a));
+                    }
+
+                    StoreMem { src, addr } => {
+                        self.address(w(addr, b), pc);
+                        self.mov(MOV_LOAD, RDX, w(src, a));
+                        self.emit(&[0x48, 0x89, 0x14, 0xc1]); // mov [rcx + 8*rax], rdx
+                    }
+
+                    LoadConst { dst, index } => {
+                        let index = w(index, b);
+                        self.emit(&[0x49, 0x81, 0x7c, 0x24, CONSTANTS_LEN]); // cmp qword [r12 + CONSTANTS_LEN], index
+                        self.emit_i32(index as i32);
+                        self.error(JBE, VmError::BadConstant { pc });
+                        self.emit(&[0x49, 0x8b, 0x4c, 0x24, CONSTANTS]);     // mov rcx, [r12 + CONSTANTS]
+                        self.emit(&[0x48, 0x8b, 0x81]);                      // mov rax, [rcx + 8*index]
+                        self.emit_i32((index * 8) as i32);
+                        self.mov(MOV_STORE, RAX, w(dst, a));
+                    }
+
+                    // `decode` skips the prefix.
+                    Wide { .. } => unreachable!(),
+
+                    MulAdd { dst, src1, src2 } | MulSub { dst, src1, src2 } => {
+                        let op = if let MulAdd { .. } = instr { ADDSD } else { SUBSD };
+                        self.sse(MOVSD_LOAD, XMM0, w(src1, b));
+                        self.sse(MULSD, XMM0, w(src2, c));
+                        self.sse(MOVSD_LOAD, XMM1, w(dst, a));
+                        self.emit(&[op[0], op[1], op[2], 0xc8]); // addsd/subsd xmm1, xmm0
+                        self.sse(MOVSD_STORE, XMM1, w(dst, a));
+                    }
+
+                    JumpIfLt { target, src1, src2 } | JumpIfLe { target, src1, src2 } => {
+                        self.compare(w(src1, b), w(src2, c));
+                        let op = if let JumpIfLt { .. } = instr { JA } else { JAE };
+                        self.jump(op, Label::Pc(w(target, a)));
+                    }
+                }
+            }
+        }
+
+        /// `None` if the code can't be mapped.
+        pub fn compile(program: &reg::VerifiedProgram) -> Option<Code> {
+            let code = program.code();
+            let mut asm = Asm { bytes: vec![], pcs: vec![0; code.len() + 1], fixups: vec![], errors: vec![] };
+
+            asm.emit(&[0x55]);                         // push rbp
+            asm.emit(&[0x48, 0x89, 0xe5]);             // mov rbp, rsp
+            asm.emit(&[0x53]);                         // push rbx
+            asm.emit(&[0x41, 0x54]);                   // push r12
+            asm.emit(&[0x41, 0x55]);                   // push r13
+            asm.emit(&[0x41, 0x56]);                   // push r14
+            asm.emit(&[0x41, 0x57]);                   // push r15
+            asm.emit(&[0x48, 0x83, 0xec, 0x08]);       // sub rsp, 8
+            asm.emit(&[0x49, 0x89, 0xfc]);             // mov r12, rdi
+            asm.emit(&[0x49, 0x8b, 0x5c, 0x24, REGISTERS]);     // mov rbx, [r12 + REGISTERS]
+            asm.emit(&[0x4d, 0x8b, 0x74, 0x24, REGISTERS_END]); // mov r14, [r12 + REGISTERS_END]
+            asm.emit(&[0x45, 0x31, 0xed]);             // xor r13d, r13d
+            asm.emit(&[0x45, 0x31, 0xff]);             // xor r15d, r15d
+
+            let mut pc = 0;
+            while pc < code.len() {
+                asm.pcs[pc] = asm.bytes.len();
+                let Some((instr, high, len)) = reg::decode(code, pc) else {
+                    // unreachable, `verify` would have rejected it.
+                    asm.emit(&[0x0f, 0x0b]); // ud2
+                    pc += 1;
+                    continue;
+                };
+                if len == 2 {
+                    asm.pcs[pc + 1] = asm.bytes.len();
+                    // only a prefix can name registers past the window,
+                    // which is all `Vm::step` checks. `Call` checks the
+                    // callee's window instead.
+                    if !matches!(instr, reg::Instruction::Call { .. }) {
+                        for (operand, high) in instr.operands().into_iter().zip(high) {
+                            let Some((reg::Operand::Reg, low)) = operand else { continue };
+                            let index = reg::wide(low, high);
+                            asm.emit(&[0x48, 0x8d, 0x83]); // lea rax, [rbx + 8*(index + 1)]
+                            asm.emit_i32(((index + 1) * 8) as i32);
+                            asm.emit(&[0x4c, 0x39, 0xf0]); // cmp rax, r14
+                            asm.error(JA, VmError::BadRegister { pc, index });
+                        }
+                    }
+                }
+                asm.instr(instr, high, pc, pc + len);
+                pc += len;
+            }
+            // unreachable too, verified code doesn't fall off the end,
+            // and jumps past it are only in unreachable code.
+            asm.pcs[code.len()] = asm.bytes.len();
+            asm.emit(&[0x0f, 0x0b]);
+
+            let exit = asm.bytes.len();
+            asm.emit(&[0xf2, 0x41, 0x0f, 0x11, 0x44, 0x24, RESULT]); // movsd [r12 + RESULT], xmm0
+            asm.emit(&[0x31, 0xc0]);                   // xor eax, eax
+            let epilogue = asm.bytes.len();
+            asm.emit(&[0x48, 0x8d, 0x65, 0xd8]);       // lea rsp, [rbp - 40]
+            asm.emit(&[0x41, 0x5f]);                   // pop r15
+            asm.emit(&[0x41, 0x5e]);                   // pop r14
+            asm.emit(&[0x41, 0x5d]);                   // pop r13
+            asm.emit(&[0x41, 0x5c]);                   // pop r12
+            asm.emit(&[0x5b]);                         // pop rbx
+            asm.emit(&[0x5d]);                         // pop rbp
+            asm.emit(&[0xc3]);                         // ret
+
+            let mut errors = vec![];
+            for i in 0..asm.errors.len() {
+                errors.push(asm.bytes.len());
+                asm.emit(&[0xb8]);                     // mov eax, i + 1
+                asm.emit_i32(i as i32 + 1);
+                asm.jump(JMP, Label::Epilogue);
+            }
+
+            for (at, label) in core::mem::take(&mut asm.fixups) {
+                let to = match label {
+                    Label::Pc(pc)       => asm.pcs[pc.min(code.len())],
+                    Label::Exit         => exit,
+                    Label::Epilogue     => epilogue,
+                    Label::Error(i)     => errors[i],
+                };
+                let rel = to as i32 - (at as i32 + 4);
+                asm.bytes[at..at + 4].copy_from_slice(&rel.to_le_bytes());
+            }
+
+            Code::new(&asm.bytes, asm.errors)
+        }
+    }
+
+    #[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+    mod native {
+        use super::{reg, Env, VmError};
+
+        pub enum Code {}
+
+        impl Code {
+            pub fn len(&self) -> usize {
+                match *self {}
+            }
+
+            pub fn run(&self, _env: &mut Env) -> Result<f64, VmError> {
+                match *self {}
+            }
+        }
+
+        pub fn compile(_program: &reg::VerifiedProgram) -> Option<Code> {
+            None
+        }
+    }
+}
+
+
+#[inline(never)]
+pub fn fib(n: f64) -> f64 {
+    let mut a = 0.0;
+    let mut b = 1.0;
+    for _ in 0..n as u32 {
+        (a, b) = (b, a+b);
+    }
+    a
+}
+
+#[inline(never)]
+pub fn fib_recursive(n: f64) -> f64 {
+    if n <= 1.0 {
+        return n;
+    }
+    fib_recursive(n - 1.0) + fib_recursive(n - 2.0)
+}
+
+#[inline(never)]
+pub fn mandel(x0: f64, y0: f64, limit: f64) -> f64 {
+    let mut x = 0.0;
+    let mut y = 0.0;
+    let mut i = 0;
+    while x*x + y*y <= 4.0 && i < limit as u32 {
+        let xtemp = x*x - y*y + x0;
+        y = x*y*2.0 + y0;
+        x = xtemp;
+        i += 1;
+    }
+    return i as f64;
+}
+
+/// the total number of steps to reach 1, for each start in `1..=n`.
+#[inline(never)]
+pub fn collatz(n: f64) -> f64 {
+    let mut total = 0u64;
+    for start in 1..=n as u64 {
+        let mut x = start;
+        while x != 1 {
+            x = if x % 2 == 0 { x / 2 } else { 3*x + 1 };
+            total += 1;
+        }
+    }
+    total as f64
+}
+
+#[inline(never)]
+pub fn dot(a: &[f64], b: &[f64]) -> f64 {
+    let mut sum = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        sum += x*y;
+    }
+    sum
+}
+
+
+/// `stack_vs_reg bench ...`, `fuzz ...`, or `build`, `run` and `dis`
+/// for bytecode files. see `bench::main`, `fuzz::main` and `bytecode::main`.
+pub fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.first().map(|arg| arg.as_str()) {
+        Some("bench") => bench::main(&args[1..]),
+        Some("fuzz")  => fuzz::main(&args[1..]),
+        Some("build" | "run" | "dis") => bytecode::main(&args),
+        _ => Err("usage: stack_vs_reg bench [--csv] [--samples n] [--warmup n] [filter]\n       \
+                         stack_vs_reg fuzz [--seed n] [--cases n]\n       \
+                         stack_vs_reg build [--argc n] (reg|stack) <source> <out>\n       \
+                         stack_vs_reg run <file> [args]\n       \
+                         stack_vs_reg dis <file>".into()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_fib<F: FnMut(f64) -> f64>(mut f: F) {
+        for i in 0..1000 {
+            assert_eq!(fib(i as f64), f(i as f64));
+        }
+    }
+
+    fn test_mandel<F: FnMut(f64, f64, f64) -> f64>(mut f: F) {
+        let limit = 10000.0;
+        for (x, y) in [(0.0, 0.0), (1.0, 1.0), (0.239, -0.981), (-0.648, 0.129), (-0.812, -0.021), (0.687, 0.387), (-0.950786716408015, 0.7448839625558739), (-0.41795412221209083, 0.3891104473889062), (-0.30454550287093674, 0.3662807658368674), (-0.38954007305330274, 0.6099259700840349), (0.07342221713511665, -0.8427951153696895), (-0.11064854851199213, -0.926639764442595), (-0.7596206479056045, 0.048924374342336874), (-0.49581061588344877, -0.12784717276741908), (0.6437608077269181, 0.4495234224170379), (-0.35902711244632446, -0.27337416802046066), (0.46119571367001844, 0.6369625077084937), (-0.8337757474334115, 0.30803404049145233), (0.7567477274895906, 0.7425733436366662), (-0.05666196234341658, -0.7153313926145124), (-0.1712571411242514, -0.7293648513842237), (-0.18029887642403808, 0.9337582385391014), (0.15025609901930093, -0.2562979396981093), (-0.8740713363980595, -0.3026974138730323), (0.734282601227346, 0.44824566299076163), (-0.6450519022478793, 0.8973350411345271), (-0.12951887416193664, -0.9675733335426315), (-0.8496395992141339, -0.45856363991261984), (-0.05006755838234733, 0.08815703999052182), (-0.0819349209740825, 0.392640373018607), (0.38818643312858203, 0.017802320175963837), (-0.5804782726092039, -0.8114288891880093), (0.21174287430534067, -0.9779934844282805), (0.48485403501831414, -0.32072813452059323), (0.11816231997762872, 0.9901345882876014), (-0.32987818756568665, -0.790041653198585), (-0.751877131490938, 0.7158915912037904), (-0.27952689192889313, -0.990237696155138), (0.27114775350097875, -0.4153077703274628), (0.058473091300088376, -0.9049579783144228), (0.4753437616586973, 0.14657146392303133), (-0.1745580736849639, 0.4775850047960255), (-0.7249298752680955, -0.9641325799319125), (0.12624851616576604, -0.44348534069122003), (-0.973539445295293, 0.832103245702291), (-0.6743590940233655, 0.9387520845818487), (-0.16662572477960436, -0.900765313197615), (-0.5912590709584258, -0.5102679290062448), (0.8503235254831967, -0.33485304549647044), (0.08977059985414226, -0.5524679311716958), (0.9092630698742039, 0.651495043750099), (0.06998115038035468, -0.1554190026368456), (-0.7399797760579021, -0.006137159260573455), (-0.2757987315085746, -0.537347842353135), (-0.30389255510677926, 0.542418339351705), (0.05627319272720821, 0.6292518136210365), (-0.6203030673427403, 0.7900418292632594), (-0.7909573091413067, -0.396979443474341), (0.24824386339155158, 0.3388202675220937), (-0.0513868729451854, 0.17817438126698026), (0.9462416973938339, 0.8676690260189799), (-0.1139783605452167, 0.0038380988757436008), (-0.23121289597591965, -0.47531036160660034), (0.5828474701609332, -0.7839471493639378), (0.9770948454943438, -0.4683608606405316), (0.47740801560355584, -0.2883965096412553), (0.34009761202328015, 0.6546012541731507), (-0.4924202923168335, 0.7856942391002899), (0.6230983780135113, -0.19678697882493723), (-0.140144286728481, 0.9028641035510641), (0.02303038331068996, -0.3161713074917263), (-0.6435921111392493, 0.6404964303777942), (-0.7066891000784838, 0.45541585282911434), (-0.24137952675687369, -0.7447054863100193), (0.19991366412404066, -0.23703689667205108), (-0.9570794031819638, -0.9884389720144522), (-0.6797569127393104, 0.2082269559276082), (0.769667914938132, 0.3787028736840006), (0.5848065627210588, 0.21127658721982723), (0.4844247997855926, 0.7800864760973392), (0.4583131215187135, 0.8734790105541512), (-0.3952706913645596, 0.633963083692719), (-0.9486119273737694, 0.7587301881016015), (-0.46070283891613584, 0.5041607377115895), (0.995619007269551, 0.3803371649267848), (-0.2797833504752094, 0.9908037027712009), (-0.9167995403904445, -0.807580777212757), (-0.18027266265314745, -0.851839465543321), (0.6128426674227088, -0.09875532852024493), (0.011860446602936614, -0.8681653691688065), (-0.7107796408969822, 0.02980060781663707), (-0.06413027397564885, -0.13037851125697242), (0.11553944723254239, -0.4890274430377839), (0.3479886739255793, -0.8699677560350583), (0.38333167839236126, 0.4218707211211812), (0.638723023074957, -0.9683866946207558), (0.46354649116031754, 0.07563976922668769), (-0.06351773294699603, 0.8144404275239079), (0.5119372264249904, -0.9096216627089817), (-0.09883485075616494, 0.3297820865529715), (0.10358292605721098, -0.36692635243618077), (0.15047436546885562, -0.9806781334753645), (0.7617574165547447, 0.4763568144395216), (0.9160725920362325, 0.9823994209157814), (0.7548032671745575, 0.812465693071418), (0.3246690244662118, -0.3065870163715265), (-0.560268464119319, 0.9221103278210057), (0.4626072017685068, 0.15317773756585118), (0.6545368464007859, 0.9506033905866516), (0.5098893892275631, 0.7830254619622639), (-0.9966341637112501, 0.6861534690011151), (-0.385961778663223, 0.09296319784275209), (-0.6439053469798957, 0.8752161182375295), (0.5684180435556756, -0.7391524569031711), (-0.5741612839751691, -0.7694325902986678), (0.8870349468029142, -0.0626416154235423), (-0.7931632997873719, -0.1847117421834228), (-0.4170616485992771, -0.4058127574957917), (-0.012491529971446091, -0.7246396095112151), (-0.7011675969925535, -0.28220086603407024), (-0.1863646808060755, -0.9348786412235945), (0.7010535378320495, -0.8136524427155922), (-0.62997318656867, -0.5673706697790437), (0.20571346964486614, 0.8494676030862429), (-0.9582906806422553, 0.13304666328969184), (-0.9017636413609094, -0.7840101289980281), (-0.06844213395069287, 0.4449811706731899), (-0.6396613633959511, 0.7560570032865803), (0.17821628814178592, -0.6343776437447937), (0.644101079419162, -0.10231890186410197), (-0.9538502098940163, -0.4005239063329318), (-0.45575562738990283, 0.6260262571336148), (-0.022746028755935566, 0.7273548892777673), (-0.5843548196344637, -0.8970791745762465), (-0.134489546848358, -0.23508372044200576), (-0.4426578957700551, -0.4140837629408358), (-0.8960196125459656, 0.9648402032597405), (-0.5559247435005124, -0.9627298282745853), (-0.9816649175072354, 0.23566904569481584), (0.37830338720570555, 0.9659962089961276), (0.07032093120792404, -0.8448450554291562), (-0.043415679985484124, 0.2982047225662403), (-0.7426172167006169, -0.9450540467855513), (-0.7833001490152167, 0.8587880458484556), (0.5753231270739165, -0.3550369532737825), (0.9847762265520275, 0.5601827115776938), (0.7547992831124333, 0.28584939475098947), (-0.8448259708273849, -0.35476136665194846), (0.879827174886626, 0.88500567042747), (0.00876529646860802, -0.30836856894337306), (0.7747669072600987, 0.03129425244220663), (-0.9181318043271416, -0.1982212204694076), (-0.06470118131158964, 0.6372280272430135), (0.6586665563523673, -0.5780624300151294), (0.19158793464893065, -0.8718738170981424), (-0.6405141431119539, -0.5399480154588656), (-0.014859791537689349, 0.5613188554256261), (-0.5781514716971152, 0.2559171658208903), (-0.09436027264047842, -0.6637963328398158), (-0.6217035983366301, -0.4498016587418536), (-0.02539730686860331, -0.8160511451622021), (-0.1819750814557659, -0.48686881610529165), (-0.8311143734208255, 0.7345232437803397), (0.35720487022736136, 0.446537629591925), (0.040582999596036506, 0.27151188053807496), (-0.8582374284322447, -0.37397514967056456), (-0.2234253044314234, -0.5021431610668297), (0.061836579223409016, 0.2334741235096851), (-0.80899619273474, 0.061122116008547556), (0.09888582378400912, 0.13157005798444055), (0.4445239732731163, -0.00797318519075918), (-0.9232548892900476, 0.015610893892384903), (0.17258779115188272, -0.25500953936981885), (0.21398456462176796, 0.6500598840741447), (-0.3659869812873422, 0.29484229817933216), (-0.2749147404367873, -0.48538050541310906), (-0.5870409692233305, 0.282757960020392), (-0.009340854254250353, -0.5661727070520584), (0.715684675641828, 0.675955746635732), (-0.8905081791246903, 0.7234796547395257), (0.6429947842459898, 0.67193501065015), (0.42730640534452435, -0.925032998389832), (0.7829803203729666, 0.8067771780949617), (-0.9775718658097963, 0.5710827388382336), (0.0038854322067862768, 0.9691541535883614), (-0.7354493698452671, -0.7740512298723263), (0.6066203299748345, 0.7907036360462232), (-0.2224438094868928, -0.5466373525288557), (-0.6613459909460426, 0.17482481697589058), (-0.14091009854697045, -0.25741985123563094), (-0.6049343628632007, 0.7132474164128548), (0.6767791588126548, 0.46222825969908365), (-0.019881898612260862, 0.42921013485612036), (-0.024289173227493688, -0.6326047825520571), (0.9273609755463252, 0.9650020725623369), (0.43880148036486677, 0.9648468957926457), (0.7820211869090097, 0.7143859917652413), (0.10713771400332006, 0.87644702975162), (0.3603460364845872, 0.9734868379754906), (0.4646237172759058, -0.6227490350204024), (0.8685337181342596, -0.9486561516837511), (-0.4290526472599958, -0.07105881127723457), (-0.43449703896808, -0.43932628835061993), (0.8619314767156487, 0.959378844974651), (0.2524793886536818, -0.5086760933393686), (-0.968926808165882, -0.48180443592302513), (-0.9344049892159925, -0.9719354066164145), (0.8417012429814341, -0.846162566469103), (0.605011478012363, 0.6877711581719606), (0.29583324991339044, 0.3897339588911992), (0.3063194062122856, -0.4969091145392086), (-0.07403093613837086, -0.06210765636399951), (-0.8870943179841004, 0.13186549816749604), (-0.13905452148322728, -0.14200541685720025), (0.9059296265527592, -0.8674413586878444), (-0.8134297959876413, 0.8602852560727625), (0.5574039666928619, 0.5935991790218782), (-0.05988992995905518, 0.8331438388387209), (-0.15690479005758462, -0.09787464921091593), (-0.3499683268803646, -0.07036877228129979), (-0.7348170188722913, 0.6489097570964577), (0.08030663152612805, -0.4062556913602422), (-0.3948116107741906, -0.8419118545265418), (-0.7080619975080553, -0.9532463704924592), (0.9525099193607636, 0.7770440519594752), (0.8356696335520295, 0.11139106868318094), (0.37389056109299457, -0.10176149244871913), (-0.5179332268046919, 0.906755738195657), (-0.5101578900967876, -0.9795688509682434), (0.15559279752437916, -0.6175284856819807), (-0.3563748416756034, -0.7785942522281912), (-0.19025374281021157, 0.6459693559382012), (0.8948354100256368, -0.7186710154824598), (0.44374924585090136, 0.5837918431600173), (-0.4672648256848859, -0.3637024717244548), (-0.14026461181970284, 0.40814454352232743), (-0.8978006636593574, -0.7145974937988178), (0.11539804649202101, 0.14383105313034195), (-0.6062938753740512, 0.7502189488775537), (-0.4391572025223398, 0.9752356885866993), (-0.38715366747667934, -0.7985315162371476), (0.1255993226373906, 0.34908952025487716), (0.4225405126612505, 0.09137785183737068), (-0.13177079487908627, 0.22058823667211525), (-0.28381252837930226, 0.7711425341318037), (0.12869352408940005, -0.00020271374035063516), (-0.1448983949760465, -0.9880872282380235), (-0.0465272602088036, 0.3825699981736381), (-0.9077389357297825, 0.9009893307505754), (0.8110194938755062, -0.9800467040477308), (-0.01036226648551164, -0.20791141520235312), (-0.8491795910760915, -0.5161174572537066), (0.21510754077334227, 0.046435915914714965), (0.7015051124679705, -0.7139484315828417), (-0.8270226510406857, -0.037067448340805775), (0.3284454053998058, 0.7011277797513877), (0.8237931165115819, 0.7651378342874593), (0.3254212701250152, 0.9467579439573748), (0.6551253150336631, 0.09542055528534776), (0.059721730531613115, -0.2171872986083485), (0.4025247537451424, -0.23519298808428113), (-0.3656975388853696, 0.9488462674799432), (0.10234697465954268, -0.034042905991529704), (-0.021076834862308047, -0.30834904994034873), (-0.5425800701779875, -0.7901297323360688), (-0.5163096338241866, -0.0910731448043538), (0.775907526479902, -0.029927810692431223), (-0.5787010634781413, -0.17724734524515395), (-0.686139712926781, -0.6582853023191224), (-0.8201124220854916, 0.5324206558841762), (-0.5127613921846674, -0.46650367860717123), (0.4891824637526496, 0.061346961080255724), (-0.5571482675377373, 0.9411510539379417), (0.8707373419055771, 0.6207284256515975), (0.8219858954429966, -0.8779285711624081), (0.45726631268327833, 0.03270982726025018), (0.1853046782842407, -0.22408852515290034), (0.4255401852644969, -0.4449354754646797), (0.4317054766054196, -0.4691969281855446), (-0.5141023626569792, 0.7586320148155574), (0.7059121498036909, -0.2683408346536418), (0.09009019999661194, 0.7579800601346505), (0.747078544161313, -0.26381189782764225), (0.5335162579135664, -0.8201395593255578), (0.4399092773156905, 0.4001922617899951), (-0.2749366699470286, 0.22086561231547597), (0.09771281478875804, -0.8128675949424682), (0.8172959884714706, -0.22236236268899234), (-0.17248615077637086, 0.7974885426480312), (0.842252396378558, 0.7288571906820647), (0.7334957992325437, 0.07938913059765351), (0.39793866023322955, 0.8688245906412282), (0.7870291797356017, 0.06165030599614352), (-0.21473486896046423, 0.4392857648561843), (-0.5073085444740155, 0.4678281268067612), (-0.03340450051571797, 0.3363381514347139), (-0.30550362154772936, 0.7072959211525238), (-0.08235208900128632, 0.45302815156489107), (0.14964118143127214, -0.7335083814629508), (0.6494909995782758, -0.7605196537643031), (0.37417634836018854, -0.2745582477672208), (0.24402671028084932, -0.9659474167888), (0.6850340610798742, 0.3156075945350234), (-0.3353094019784082, 0.15936722855440655), (-0.35383706533582737, -0.4898161541205872), (0.08096129676569741, 0.984231777908692), (-0.09225205063861064, 0.11924130015978296), (-0.19117673582023098, -0.4570527844157015), (0.04484730422003991, -0.20431919075758143), (-0.214123335641055, -0.9923780601705212), (-0.5994170582611429, 0.6971508827916248), (0.8125161673575663, -0.5904108880645373), (-0.34462253292283207, 0.39677814793807964), (-0.4666775475043421, 0.7563498846928809), (-0.5353939698802537, 0.9372234589613415), (0.816330849123519, 0.7980310088153351), (-0.2442766721743026, 0.18660398148145663), (-0.795812123557933, -0.8439443207812483), (-0.7316221860345711, -0.00679059803926263), (0.029322252210048916, -0.039863229769652175), (0.8415655075406308, 0.2438708303677739), (-0.9738347839788026, 0.1820873912774983), (0.5812432844185678, -0.41807701493178806), (-0.11121009276117788, 0.09108995663689967), (0.4947780413182141, 0.731341348626044), (0.7549316153846815, 0.9940007248455749), (0.22703057664382742, -0.5973739903480808), (-0.5443322898224618, -0.9174453695604308), (0.32356085335906437, -0.4888945103497353), (0.359539113819318, -0.7408168135541069), (-0.22338680684947865, 0.28696110757103854), (-0.6034832533179653, -0.17871677245046125), (-0.1922451022511551, 0.6350068107028115), (-0.26205534723658164, -0.9428261933544171), (0.7931239883719934, 0.32715467498864514), (-0.37506617261760544, -0.1408871705323862), (0.060770083585807155, 0.8060976545229288), (-0.7736828407722232, -0.38511545538424485), (-0.4145664314854487, 0.5137234572891332), (0.05234994257136738, 0.832142969883906), (0.6007597711309947, 0.2405601651487772), (0.27450568752981797, -0.4408499884659134), (0.3389752585537307, 0.9808145570890736), (-0.5241924228584112, 0.2669518809982916), (-0.8763341953698638, -0.21111615960052754), (0.33981530567566987, -0.09232472231112476), (-0.8508778459163162, -0.2587109005842909), (0.8737211808432022, -0.5648439845189517), (0.8083427960826393, 0.11234062682554247), (-0.2712476783355593, -0.8774439655390605)] {
+            assert_eq!(mandel(x, y, limit), f(x, y, limit));
+        }
+    }
+
+
+    #[test]
+    fn reg_fib() {
+        let program = reg::verify(reg::FIB).unwrap();
+        let mut vm = reg::Vm::new();
+        test_fib(|n| {
+            vm.run(&program, &[n])
+        });
+    }
+
+    #[test]
+    fn reg_mandel() {
+        let program = reg::verify(reg::MANDEL).unwrap();
+        let mut vm = reg::Vm::new();
+        test_mandel(|x, y, n| {
+            vm.run(&program, &[x, y, n])
+        });
+    }
+
+    #[test]
+    fn stack_fib_smart() {
+        let program = stack::verify(stack::FIB_SMART, 1).unwrap();
+        let mut vm = stack::Vm::new();
+        test_fib(|n| {
+            vm.run(&program, &[n])
+        });
+    }
+
+    #[test]
+    fn stack_fib_naive() {
+        let program = stack::verify(stack::FIB_NAIVE, 1).unwrap();
+        let mut vm = stack::Vm::new();
+        test_fib(|n| {
+            vm.run(&program, &[n])
+        });
+    }
+
+    #[test]
+    fn stack_mandel_smart() {
+        let program = stack::verify(stack::MANDEL_SMART, 3).unwrap();
+        let mut vm = stack::Vm::new();
+        test_mandel(|x, y, n| {
+            vm.run(&program, &[x, y, n])
+        });
+    }
+
+    #[test]
+    fn stack_mandel_naive() {
+        let program = stack::verify(stack::MANDEL_NAIVE, 3).unwrap();
+        let mut vm = stack::Vm::new();
+        test_mandel(|x, y, n| {
+            vm.run(&program, &[x, y, n])
+        });
+    }
+
+    #[test]
+    fn stack_mandel_smart_nops_slow() {
+        let program = stack::verify(stack::MANDEL_SMART_NOPS_SLOW, 3).unwrap();
+        let mut vm = stack::Vm::new();
+        test_mandel(|x, y, n| {
+            vm.run(&program, &[x, y, n])
+        });
+    }
+
+    #[test]
+    fn stack_mandel_smart_nops_same() {
+        let program = stack::verify(stack::MANDEL_SMART_NOPS_SAME, 3).unwrap();
+        let mut vm = stack::Vm::new();
+        test_mandel(|x, y, n| {
+            vm.run(&program, &[x, y, n])
+        });
+    }
+
+    #[test]
+    fn stack_mandel_smart_no_dup() {
+        let program = stack::verify(stack::MANDEL_SMART_NO_DUP, 3).unwrap();
+        let mut vm = stack::Vm::new();
+        test_mandel(|x, y, n| {
+            vm.run(&program, &[x, y, n])
+        });
+    }
+
+    #[test]
+    fn reg_add_chain() {
+        let add_regs: [f64; 16] = core::array::from_fn(|i| i as f64);
+        let program = reg::verify(reg::ADD_CHAIN).unwrap();
+        let mut vm = reg::Vm::new();
+        assert_eq!(vm.run(&program, &add_regs), (16*15/2) as f64);
+    }
+
+    #[test]
+    fn reg_add_fast() {
+        let add_regs: [f64; 16] = core::array::from_fn(|i| i as f64);
+        let program = reg::verify(reg::ADD_PAIRS).unwrap();
+        let mut vm = reg::Vm::new();
+        assert_eq!(vm.run(&program, &add_regs), (16*15/2) as f64);
+    }
+
+
+    #[test]
+    fn asm_reg_fib() {
+        let code = asm::assemble_reg("
+            // fib
+            let n, a, b, t = 0, 1, 2, r3
+
+                    set_counter n
+                    load_int    a, 0
+                    load_int    b, 1
+                    jump        check
+            body:   add         t, a, b
+                    copy        a, b
+                    copy        b, t
+            check:  loop        body
+                    return      a
+        ").unwrap();
+        assert_eq!(code, reg::FIB);
+    }
+
+    #[test]
+    fn asm_stack_mandel() {
+        let code = asm::assemble_stack("
+            let x0, y0, n, x, y = 0, 1, 2, 3, 4
+
+                load        n
+                set_counter
+                load_int    0
+                load_int    0
+                jump        check
+
+            body:
+                // let xtemp = x*x - y*y + x0;
+                load x
+                dup
+                mul
+                load y
+                dup
+                mul
+                sub
+                load x0
+                add
+                swap
+                rot
+                // y = x*y*2.0 + y0;
+                mul
+                load_int 2
+                mul
+                load y0
+                add
+
+            check:
+                load x
+                dup
+                mul
+                load y
+                dup
+                mul
+                add
+                load_int 4
+                loop_le body
+
+                load n
+                get_counter
+                sub
+                return
+        ").unwrap();
+        assert_eq!(code, stack::MANDEL_SMART);
+
+        let program = stack::verify(&code, 3).unwrap();
+        let mut vm = stack::Vm::new();
+        test_mandel(|x, y, n| {
+            vm.run(&program, &[x, y, n])
+        });
+    }
+
+    #[test]
+    fn asm_errors() {
+        use asm::ErrorKind::*;
+
+        let err = asm::assemble_reg("load_int r0, 1\n  mull r0, r0, r0").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.kind, UnknownMnemonic("mull".into()));
+
+        let err = asm::assemble_stack("jump nowhere").unwrap_err();
+        assert_eq!(err.kind, UndefinedLabel("nowhere".into()));
+
+        let err = asm::assemble_stack("a: nop\na: nop").unwrap_err();
+        assert_eq!(err.kind, DuplicateLabel("a".into()));
+
+        let err = asm::assemble_reg("add r0, r1").unwrap_err();
+        assert_eq!(err.kind, OperandCount { mnemonic: "add".into(), expected: 3, found: 2 });
+
+        let err = asm::assemble_stack("load_int 40000").unwrap_err();
+        assert_eq!(err.kind, OutOfRange("40000".into()));
+
+        let err = asm::assemble_stack("load 300").unwrap_err();
+        assert_eq!(err.kind, OutOfRange("300".into()));
+
+        // too large for an i64 is still just out of range.
+        for source in ["load_int r0, 99999999999999999999", "copy r0, r99999999999999999999", "jump 99999999999999999999"] {
+            let err = asm::assemble_reg(source).unwrap_err();
+            assert!(matches!(err.kind, OutOfRange(_)), "{}", source);
+        }
+
+        let err = asm::assemble_reg("copy r0, q").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: invalid operand `q`");
+    }
+
+    #[test]
+    fn disasm_round_trip() {
+        for code in [reg::FIB, reg::MANDEL, reg::ADD_CHAIN, reg::ADD_PAIRS, reg::FIB_RECURSIVE, reg::COLLATZ, reg::ARRAY_SUM, reg::DOT] {
+            let listing = disasm::disassemble_reg(code);
+            assert_eq!(asm::assemble_reg(&listing).unwrap(), code, "{}", listing);
+        }
+
+        for code in [stack::FIB_SMART, stack::FIB_NAIVE, stack::FIB_RECURSIVE, stack::COLLATZ, stack::ARRAY_SUM, stack::DOT] {
+            let listing = disasm::disassemble_stack(code, 1);
+            assert_eq!(asm::assemble_stack(&listing).unwrap(), code, "{}", listing);
+        }
+
+        for code in [stack::MANDEL_SMART, stack::MANDEL_NAIVE, stack::MANDEL_SMART_NOPS_SLOW, stack::MANDEL_SMART_NOPS_SAME, stack::MANDEL_SMART_NO_DUP] {
+            let listing = disasm::disassemble_stack(code, 3);
+            assert_eq!(asm::assemble_stack(&listing).unwrap(), code, "{}", listing);
+        }
+    }
+
+    #[test]
+    fn disasm_listing() {
+        let listing = disasm::disassemble_stack(stack::FIB_SMART, 1);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[3], "        jump        L7                  //   3    [2]");
+        assert_eq!(lines[4], "L4:     dup                             //   4    [2]");
+        assert_eq!(lines[7], "L7:     loop        L4                  //   7    [2]");
+        assert_eq!(lines[9], "        return                          //   9    [1]");
+
+        let listing = disasm::disassemble_reg(reg::MANDEL);
+        assert!(listing.contains("L13:    mul         r5, r3, r3"));
+        assert!(listing.contains("loop_le     L4, r5, r6"));
+    }
+
+    #[test]
+    fn verify_errors() {
+        use VerifyError::*;
+
+        let code = asm::assemble_reg("jump 5\nreturn r0").unwrap();
+        assert_eq!(reg::verify(&code).unwrap_err(), TargetOutOfRange { pc: 0, target: 5 });
+
+        let code = asm::assemble_reg("load_int r0, 1\nadd r0, r0, r0").unwrap();
+        assert_eq!(reg::verify(&code).unwrap_err(), FallsOffEnd { pc: 1 });
+        assert_eq!(reg::verify(&[]).unwrap_err(), FallsOffEnd { pc: 0 });
+
+        // unreachable code doesn't need to end in a return.
+        let code = asm::assemble_reg("return r0\nadd r0, r0, r0").unwrap();
+        assert_eq!(reg::verify(&code).unwrap().registers(), 1);
+
+        let code = asm::assemble_stack("add\nreturn").unwrap();
+        assert_eq!(stack::verify(&code, 1).unwrap_err(), StackUnderflow { pc: 0, depth: 1 });
+        assert!(stack::verify(&code, 2).is_ok());
+
+        let code = asm::assemble_stack("load s2\nreturn").unwrap();
+        assert_eq!(stack::verify(&code, 2).unwrap_err(), BadRegister { pc: 0, index: 2 });
+
+        let code = asm::assemble_stack("
+            set_counter
+        l:  load_int 1
+            loop l
+            return
+        ").unwrap();
+        assert_eq!(stack::verify(&code, 1).unwrap_err(), DepthMismatch { pc: 1, expected: 0, found: 1 });
+
+        let code = asm::assemble_stack("l: dup\njump l").unwrap();
+        assert_eq!(stack::verify(&code, 1).unwrap_err(), DepthMismatch { pc: 0, expected: 1, found: 2 });
+
+        let program = stack::verify(stack::MANDEL_NAIVE, 3).unwrap();
+        assert_eq!(program.depth(6), Some(6));
+        assert_eq!(program.max_depth(), 9);
+    }
+
+    #[test]
+    fn try_run_programs() {
+        let mut vm = reg::Vm::new();
+        test_fib(|n| vm.try_run(reg::FIB, &[n]).unwrap());
+        test_mandel(|x, y, n| vm.try_run(reg::MANDEL, &[x, y, n]).unwrap());
+
+        let mut vm = stack::Vm::new();
+        test_fib(|n| vm.try_run(stack::FIB_SMART, &[n]).unwrap());
+        test_fib(|n| vm.try_run(stack::FIB_NAIVE, &[n]).unwrap());
+        test_mandel(|x, y, n| vm.try_run(stack::MANDEL_SMART, &[x, y, n]).unwrap());
+        test_mandel(|x, y, n| vm.try_run(stack::MANDEL_NAIVE, &[x, y, n]).unwrap());
+    }
+
+    #[test]
+    fn try_run_errors() {
+        use VmError::*;
+
+        let mut vm = reg::Vm::new();
+        assert_eq!(vm.try_run(reg::FIB, &[0.0; 300]), Err(TooManyArgs { count: 300 }));
+        let code = asm::assemble_reg("load_int r0, 1\njump 7").unwrap();
+        assert_eq!(vm.try_run(&code, &[]), Err(PcOutOfBounds { pc: 1, target: 7 }));
+        let code = asm::assemble_reg("load_int r0, 1").unwrap();
+        assert_eq!(vm.try_run(&code, &[]), Err(NoReturn { pc: 1 }));
+
+        let mut vm = stack::Vm::new();
+        assert_eq!(vm.try_run(stack::FIB_SMART, &[]), Err(StackUnderflow { pc: 0 }));
+        let code = asm::assemble_stack("l: load_int 1\njump l").unwrap();
+        assert_eq!(vm.try_run(&code, &[]), Err(StackOverflow { pc: 0 }));
+        let code = asm::assemble_stack("store s1\nreturn").unwrap();
+        assert_eq!(vm.try_run(&code, &[1.0, 2.0]), Err(BadRegister { pc: 0, index: 1 }));
+        let code = asm::assemble_stack("nop").unwrap();
+        assert_eq!(vm.try_run(&code, &[]), Err(NoReturn { pc: 1 }));
+
+        // the vm is still usable after an error.
+        assert_eq!(vm.try_run(stack::FIB_SMART, &[10.0]), Ok(55.0));
+    }
+
+    #[test]
+    fn translate_stack_to_reg() {
+        for code in [stack::FIB_SMART, stack::FIB_NAIVE] {
+            let code = translate::stack_to_reg(code, 1).unwrap();
+            let program = reg::verify(&code).unwrap();
+            let mut vm = reg::Vm::new();
+            test_fib(|n| vm.run(&program, &[n]));
+        }
+
+        for code in [stack::MANDEL_SMART, stack::MANDEL_NAIVE, stack::MANDEL_SMART_NOPS_SLOW, stack::MANDEL_SMART_NOPS_SAME, stack::MANDEL_SMART_NO_DUP] {
+            let code = translate::stack_to_reg(code, 3).unwrap();
+            let program = reg::verify(&code).unwrap();
+            let mut vm = reg::Vm::new();
+            test_mandel(|x, y, n| vm.run(&program, &[x, y, n]));
+        }
+
+        // loads are free, only the adds are left.
+        let code = asm::assemble_stack("
+            load s0
+            load s1
+            add
+            load s0
+            add
+            return
+        ").unwrap();
+        let code = translate::stack_to_reg(&code, 2).unwrap();
+        assert_eq!(disasm::disassemble_reg(&code), disasm::disassemble_reg(&asm::assemble_reg("
+            add r2, r0, r1
+            add r2, r2, r0
+            return r2
+        ").unwrap()));
+
+        let code = asm::assemble_stack("swap\nsub\nreturn").unwrap();
+        let code = translate::stack_to_reg(&code, 2).unwrap();
+        let mut vm = reg::Vm::new();
+        assert_eq!(vm.try_run(&code, &[1.0, 5.0]), Ok(4.0));
+
+        let code = asm::assemble_stack("load_int 1\nrot\nsub\nsub\nreturn").unwrap();
+        let code = translate::stack_to_reg(&code, 2).unwrap();
+        let mut vm = reg::Vm::new();
+        assert_eq!(vm.try_run(&code, &[10.0, 3.0]), stack::Vm::new().try_run(&asm::assemble_stack("load_int 1\nrot\nsub\nsub\nreturn").unwrap(), &[10.0, 3.0]));
+
+        let err = translate::stack_to_reg(&asm::assemble_stack("add\nreturn").unwrap(), 0);
+        assert_eq!(err, Err(translate::TranslateError::Verify(VerifyError::StackUnderflow { pc: 0, depth: 0 })));
+
+        // unreachable code can jump anywhere.
+        use stack::Instruction as S;
+        let code = translate::stack_to_reg(&[S::LoadInt { value: 1 }, S::Return, S::Jump { target: 200 }], 0).unwrap();
+        assert_eq!(reg::Vm::new().try_run(&code, &[]), Ok(1.0));
+    }
+
+    #[test]
+    fn translate_reg_to_stack() {
+        use translate::Strategy::*;
+
+        for strategy in [Naive, Smart] {
+            let code = translate::reg_to_stack(reg::FIB, 1, strategy).unwrap();
+            let program = stack::verify(&code, 1).unwrap();
+            let mut vm = stack::Vm::new();
+            test_fib(|n| vm.run(&program, &[n]));
+
+            let code = translate::reg_to_stack(reg::MANDEL, 3, strategy).unwrap();
+            let program = stack::verify(&code, 3).unwrap();
+            let mut vm = stack::Vm::new();
+            test_mandel(|x, y, n| vm.run(&program, &[x, y, n]));
+
+            let add_regs: [f64; 16] = core::array::from_fn(|i| i as f64);
+            for code in [reg::ADD_CHAIN, reg::ADD_PAIRS] {
+                let code = translate::reg_to_stack(code, 16, strategy).unwrap();
+                let mut vm = stack::Vm::new();
+                assert_eq!(vm.try_run(&code, &add_regs), Ok((16*15/2) as f64));
+            }
+        }
+
+        let naive = translate::reg_to_stack(reg::MANDEL, 3, Naive).unwrap();
+        let smart = translate::reg_to_stack(reg::MANDEL, 3, Smart).unwrap();
+        assert!(smart.len() < naive.len());
+
+        // `Naive` is exactly what the `_NAIVE` programs do.
+        let code = translate::reg_to_stack(&asm::assemble_reg("
+            add r2, r0, r1
+            mul r2, r2, r2
+            return r2
+        ").unwrap(), 2, Naive).unwrap();
+        assert_eq!(code, asm::assemble_stack("
+            load_int 0
+            load s0
+            load s1
+            add
+            store s2
+            load s2
+            load s2
+            mul
+            store s2
+            load s2
+            return
+        ").unwrap());
+
+        let code = translate::reg_to_stack(&asm::assemble_reg("
+            add r2, r0, r1
+            mul r2, r2, r2
+            return r2
+        ").unwrap(), 2, Smart).unwrap();
+        assert_eq!(code, asm::assemble_stack("
+            load_int 0
+            load s0
+            load s1
+            add
+            dup
+            mul
+            return
+        ").unwrap());
+
+        // immediates that don't fit into an i8.
+        let code = translate::reg_to_stack(&asm::assemble_reg("load_int r0, -1234\nreturn r0").unwrap(), 0, Naive).unwrap();
+        assert_eq!(stack::Vm::new().try_run(&code, &[]), Ok(-1234.0));
+
+        // unreachable code can jump anywhere.
+        use reg::Instruction::*;
+        for strategy in [Naive, Smart] {
+            let code = translate::reg_to_stack(&[LoadInt { dst: 0, value: 1 }, Return { src: 0 }, Jump { target: 200 }], 0, strategy).unwrap();
+            assert_eq!(stack::Vm::new().try_run(&code, &[]), Ok(1.0));
+        }
+    }
+
+    const FIB_SOURCE: &str = "
+        fn fib(n) {
+            let a = 0;
+            let b = 1;
+            repeat n {
+                let t = a + b;
+                a = b;
+                b = t;
+            }
+            return a;
+        }
+    ";
+
+    const MANDEL_SOURCE: &str = "
+        fn mandel(x0, y0, limit) {
+            let x = 0;
+            let y = 0;
+            repeat limit while x*x + y*y <= 4 {
+                let xtemp = x*x - y*y + x0;
+                y = x*y*2 + y0;
+                x = xtemp;
+            }
+            return limit - counter;
+        }
+    ";
+
+    /// `fn f(n)`, a loop of 200 lines `x = x + step(line)`, which is
+    /// too long for one byte jump targets.
+    fn long_source(step: impl Fn(usize) -> String) -> String {
+        let mut source = String::from("fn f(n) {\n let x = 0;\n while n > 0 {\n");
+        for i in 0..200 {
+            source += &format!("  x = x + {};\n", step(i));
+        }
+        source += "  n = n - 1;\n }\n return x;\n}";
+        source
+    }
+
+    #[test]
+    fn lang_fib() {
+        let program = reg::verify(&lang::compile_reg(FIB_SOURCE).unwrap()).unwrap();
+        let mut vm = reg::Vm::new();
+        test_fib(|n| vm.run(&program, &[n]));
+
+        let program = stack::verify(&lang::compile_stack(FIB_SOURCE).unwrap(), 1).unwrap();
+        let mut vm = stack::Vm::new();
+        test_fib(|n| vm.run(&program, &[n]));
+    }
+
+    #[test]
+    fn lang_mandel() {
+        let program = reg::verify(&lang::compile_reg(MANDEL_SOURCE).unwrap()).unwrap();
+        let mut vm = reg::Vm::new();
+        test_mandel(|x, y, n| vm.run(&program, &[x, y, n]));
+
+        let program = stack::verify(&lang::compile_stack(MANDEL_SOURCE).unwrap(), 3).unwrap();
+        let mut vm = stack::Vm::new();
+        test_mandel(|x, y, n| vm.run(&program, &[x, y, n]));
+    }
+
+    #[test]
+    fn lang_expressions() {
+        let source = "
+            fn f(a, b) {
+                let c = -(a - b) * 3 + -2;   // 3b - 3a - 2
+                repeat 1000 while 1 >= c {
+                    c = c + 500;
+                }
+                return c - 300*2;
+            }
+        ";
+        let expected = |a: f64, b: f64| {
+            let mut c = -(a - b) * 3.0 - 2.0;
+            for _ in 0..1000 {
+                if c > 1.0 { break }
+                c += 500.0;
+            }
+            c - 600.0
+        };
+
+        let reg_code = lang::compile_reg(source).unwrap();
+        let stack_code = lang::compile_stack(source).unwrap();
+        for (a, b) in [(0.0, 0.0), (1.0, 2.0), (-400.0, 3.5), (1000.0, -2.0)] {
+            assert_eq!(reg::Vm::new().try_run(&reg_code, &[a, b]), Ok(expected(a, b)));
+            assert_eq!(stack::Vm::new().try_run(&stack_code, &[a, b]), Ok(expected(a, b)));
+        }
+    }
+
+    #[test]
+    fn lang_errors() {
+        let err = |source| lang::compile_reg(source).unwrap_err().to_string();
+        assert_eq!(err("fn f() { return x; }"), "line 1: undefined variable `x`");
+        assert_eq!(err("fn f() {\n let x = 0.5;\n return x; }"), "line 2: literal `0.5` isn't a 16 bit integer");
+        assert_eq!(err("fn f() { let x = 1; }"), "line 1: `f` must end with `return`");
+        assert_eq!(err("fn f(n) {\n repeat n {\n  repeat n { }\n }\n return n;\n}"), "line 3: `repeat` loops can't be nested");
+        assert_eq!(err("fn f(n) { return n +; }"), "line 1: expected expression");
+        assert_eq!(err("fn f(n) { repeat n while n < 1 { } return n; }"), "line 1: expected `<=` or `>=`");
+        assert_eq!(err("fn f(n) { return n % 2; }"), "line 1: unexpected character `%`");
+        assert_eq!(err("fn f(n) { return pow(n); }"), "line 1: unknown function `pow`");
+    }
+
+    #[test]
+    fn peephole_mandel() {
+        let (code, stats) = peephole::optimize(stack::MANDEL_SMART_NO_DUP, 3);
+        assert_eq!(code, stack::MANDEL_SMART);
+        assert_eq!(stats, peephole::Stats { load_load: 4, ..Default::default() });
+
+        for code in [stack::MANDEL_SMART_NOPS_SLOW, stack::MANDEL_SMART_NOPS_SAME] {
+            let (code, stats) = peephole::optimize(code, 3);
+            assert_eq!(code, stack::MANDEL_SMART);
+            assert_eq!(stats, peephole::Stats { nops: 3, ..Default::default() });
+        }
+
+        let (code, stats) = peephole::optimize(stack::MANDEL_NAIVE, 3);
+        assert_eq!(stats, peephole::Stats { load_load: 4, ..Default::default() });
+        let program = stack::verify(&code, 3).unwrap();
+        let mut vm = stack::Vm::new();
+        test_mandel(|x, y, n| vm.run(&program, &[x, y, n]));
+
+        let code = translate::reg_to_stack(reg::MANDEL, 3, translate::Strategy::Naive).unwrap();
+        let (code, stats) = peephole::optimize(&code, 3);
+        assert!(stats.total() > 0);
+        let program = stack::verify(&code, 3).unwrap();
+        let mut vm = stack::Vm::new();
+        test_mandel(|x, y, n| vm.run(&program, &[x, y, n]));
+    }
+
+    #[test]
+    fn peephole_rewrites() {
+        let (code, stats) = peephole::optimize(&asm::assemble_stack("
+                jump l
+                nop
+            l:  swap
+                swap
+                load_int 1
+                nop
+                pop
+                store s0
+                load s0
+                return
+        ").unwrap(), 2);
+        assert_eq!(code, asm::assemble_stack("
+                jump l
+            l:  dup
+                store s0
+                return
+        ").unwrap());
+        assert_eq!(stats, peephole::Stats { store_load: 1, swap_swap: 1, push_pop: 1, nops: 2, ..Default::default() });
+
+        // the second `load` is a jump target.
+        let code = asm::assemble_stack("
+                set_counter
+                load s0
+            l:  load s0
+                loop l
+                return
+        ").unwrap();
+        assert_eq!(peephole::optimize(&code, 1).0, code);
+
+        // no room for the `dup` on a full stack.
+        let code = asm::assemble_stack("store s0 \n load s0 \n return").unwrap();
+        assert_eq!(peephole::optimize(&code, stack::STACK_SIZE).0, code);
+        let (optimized, stats) = peephole::optimize(&code, stack::STACK_SIZE - 1);
+        assert_eq!(stats.store_load, 1);
+        assert!(stack::verify(&optimized, stack::STACK_SIZE - 1).is_ok());
+        assert!(stack::verify(&code, stack::STACK_SIZE).is_ok());
+    }
+
+    #[test]
+    fn regalloc_programs() {
+        let lift = |code: &[reg::Instruction]| -> Vec<regalloc::Instruction> {
+            code.iter().map(|instr| regalloc::Instruction::lift(*instr)).collect()
+        };
+
+        // all 7 registers are live while computing `xtemp`.
+        let alloc = regalloc::allocate(&lift(reg::MANDEL), 3, None).unwrap();
+        assert_eq!(alloc.registers, 7);
+        let program = reg::verify(&alloc.code).unwrap();
+        let mut vm = reg::Vm::new();
+        test_mandel(|x, y, n| vm.run(&program, &[x, y, n]));
+
+        let code = lang::compile_reg(FIB_SOURCE).unwrap();
+        // `n` is dead once it's in the counter.
+        let alloc = regalloc::allocate(&lift(&code), 1, None).unwrap();
+        assert_eq!(alloc.registers, 3);
+        let program = reg::verify(&alloc.code).unwrap();
+        let mut vm = reg::Vm::new();
+        test_fib(|n| vm.run(&program, &[n]));
+
+        let alloc = regalloc::allocate(&lift(reg::ADD_PAIRS), 16, None).unwrap();
+        assert_eq!(alloc.code, reg::ADD_PAIRS);
+
+        assert_eq!(regalloc::allocate(&lift(reg::MANDEL), 3, Some(6)).unwrap_err(),
+            regalloc::AllocError::NeedsSpill { required: 7, available: 6 });
+
+        // unreachable code can jump anywhere.
+        use reg::Instruction::*;
+        let alloc = regalloc::allocate(&lift(&[LoadInt { dst: 0, value: 1 }, Return { src: 0 }, Jump { target: 200 }]), 0, None).unwrap();
+        assert_eq!(reg::Vm::new().try_run(&alloc.code, &[]), Ok(1.0));
+    }
+
+    #[test]
+    fn regalloc_virtual() {
+        use regalloc::Instruction::*;
+
+        // 300 values, but only a few live at a time.
+        let mut code = vec![LoadInt { dst: 1, value: 3 }];
+        for v in 2..300 {
+            code.push(Add { dst: v, src1: v - 1, src2: 0 });
+            code.push(Copy { dst: v + 1000, src: v });
+            code.push(Mul { dst: v, src1: v + 1000, src2: v });
+        }
+        code.push(Return { src: 299 });
+
+        let alloc = regalloc::allocate(&code, 1, None).unwrap();
+        assert_eq!(alloc.registers, 2);
+        assert_eq!(alloc.removed_copies, 298);
+
+        let mut expected = 3.0;
+        for _ in 2..300 {
+            expected += 0.5;
+            expected *= expected;
+            if expected > 1e300 { break }
+        }
+        let mut vm = reg::Vm::new();
+        let result = vm.try_run(&alloc.code, &[0.5]).unwrap();
+        assert!(result == expected || (result.is_infinite() && expected > 1e300));
+
+        // a value live around the loop keeps its register.
+        let code = [
+            LoadInt    { dst: 5, value: 10 },
+            SetCounter { src: 0 },
+            LoadInt    { dst: 9, value: 0 },
+            Add        { dst: 9, src1: 9, src2: 5 },
+            LoadInt    { dst: 7, value: 1 },
+            Add        { dst: 5, src1: 5, src2: 7 },
+            Loop       { target: 3 },
+            Return     { src: 9 },
+        ];
+        let alloc = regalloc::allocate(&code, 1, None).unwrap();
+        assert_eq!(alloc.registers, 3);
+        assert_eq!(reg::Vm::new().try_run(&alloc.code, &[3.0]), Ok(10.0 + 11.0 + 12.0 + 13.0));
+    }
+
+    #[test]
+    fn trace_reg_fib() {
+        let mut vm = reg::Vm::new();
+        let trace = vm.trace(reg::FIB, &[2.0], 1000);
+        assert_eq!(trace.result, Some(Ok(fib(2.0))));
+        assert_eq!(trace.code[3], "jump L7");
+
+        let pcs: Vec<usize> = trace.steps.iter().map(|step| step.pc).collect();
+        assert_eq!(pcs, [0, 1, 2, 3, 7, 4, 5, 6, 7, 4, 5, 6, 7, 8]);
+        for step in &trace.steps {
+            assert_eq!(step.instruction, trace.code[step.pc]);
+        }
+
+        let add = &trace.steps[5];
+        assert_eq!(add.instruction, "add r3, r1, r2");
+        assert_eq!(add.reads,  [(1, 0.0), (2, 1.0)]);
+        assert_eq!(add.writes, [(3, 1.0)]);
+        assert_eq!(add.counter, 1);
+        assert_eq!(add.stack, None);
+
+        let json = trace.to_json();
+        assert!(json.starts_with("{\n  \"isa\": \"reg\",\n"));
+        assert!(json.contains("{\"pc\": 4, \"instruction\": \"add r3, r1, r2\", \"reads\": [{\"index\": 1, \"value\": 0}, {\"index\": 2, \"value\": 1}], \"writes\": [{\"index\": 3, \"value\": 1}], \"counter\": 1},\n"));
+        assert!(json.ends_with("  ],\n  \"result\": 1\n}\n"));
+    }
+
+    #[test]
+    fn trace_stack_fib() {
+        let mut vm = stack::Vm::new();
+        let trace = vm.trace(stack::FIB_SMART, &[2.0], 1000);
+        assert_eq!(trace.result, Some(Ok(fib(2.0))));
+
+        let dup = &trace.steps[5];
+        assert_eq!(dup.instruction, "dup");
+        assert_eq!(dup.reads,  [(1, 1.0)]);
+        assert_eq!(dup.writes, [(1, 1.0), (2, 1.0)]);
+        assert_eq!(dup.stack.as_deref(), Some(&[0.0, 1.0, 1.0][..]));
+
+        let program = stack::verify(stack::MANDEL_NAIVE, 3).unwrap();
+        let trace = vm.trace(stack::MANDEL_NAIVE, &[0.25, 0.5, 10.0], 100_000);
+        assert_eq!(trace.result, Some(Ok(mandel(0.25, 0.5, 10.0))));
+        for step in &trace.steps {
+            if let stack::Instruction::Store { dst } = program.code()[step.pc] {
+                assert_eq!(step.writes[0].0, dst as usize);
+                assert_eq!(step.reads.len(), 1);
+            }
+        }
+        for steps in trace.steps.windows(2) {
+            let stack = steps[0].stack.as_ref().unwrap();
+            assert_eq!(stack.len(), program.depth(steps[1].pc).unwrap());
+        }
+    }
+
+    #[test]
+    fn trace_wide() {
+        use reg::Instruction::*;
+        let code = [
+            Wide { high: [1, 0, 0] }, LoadInt { dst: 1, value: 1 },
+            Wide { high: [0, 1, 0] }, JumpIf { target: 5, src: 1 },
+            Return { src: 0 },
+            Wide { high: [1, 0, 0] }, Return { src: 1 },
+        ];
+        let trace = reg::Vm::new().trace(&code, &[], 1000);
+        assert_eq!(trace.result, Some(Ok(1.0)));
+        let steps: Vec<_> = trace.steps.iter().map(|step| (step.pc, step.instruction.as_str(), &step.reads[..], &step.writes[..])).collect();
+        assert_eq!(steps, [
+            (0, "load_int r257, 1",  &[][..],         &[(257, 1.0)][..]),
+            (2, "jump_if L5, r257",  &[(257, 1.0)][..], &[][..]),
+            (5, "return r257",       &[(257, 1.0)][..], &[][..]),
+        ]);
+
+        use stack::Instruction as S;
+        let code = [S::Wide { high: 1 }, S::LoadInt { value: 2 }, S::Wide { high: 0 }, S::LoadInt { value: -1 }, S::Add, S::Return];
+        let trace = stack::Vm::new().trace(&code, &[], 1000);
+        assert_eq!(trace.result, Some(Ok(513.0)));
+        let steps: Vec<_> = trace.steps.iter().map(|step| (step.pc, step.instruction.as_str(), &step.reads[..], &step.writes[..])).collect();
+        assert_eq!(steps[..2], [
+            (0, "load_int 258", &[][..], &[(0, 258.0)][..]),
+            (2, "load_int 255", &[][..], &[(1, 255.0)][..]),
+        ]);
+        assert_eq!(trace.steps[1].stack.as_deref(), Some(&[258.0, 255.0][..]));
+    }
+
+    #[test]
+    fn trace_cut_off() {
+        let mut vm = reg::Vm::new();
+        let trace = vm.trace(&[reg::Instruction::Jump { target: 0 }], &[], 5);
+        assert_eq!(trace.steps.len(), 5);
+        assert_eq!(trace.result, None);
+        assert!(trace.to_json().ends_with("  \"result\": null\n}\n"));
+
+        let mut vm = stack::Vm::new();
+        let trace = vm.trace(&[stack::Instruction::Add], &[1.0], 5);
+        assert_eq!(trace.steps.len(), 0);
+        assert_eq!(trace.result, Some(Err(VmError::StackUnderflow { pc: 0 })));
+        assert!(trace.to_json().ends_with("  \"steps\": [\n  ],\n  \"error\": \"StackUnderflow { pc: 0 }\"\n}\n"));
+    }
 
-        // check x*x + y*y <= 2*2
-        // 21 + 3
-        Load { src: x },
-        Dup,
-        Mul,
-        Load { src: y },
-        Dup,
-        Mul,
-        Add,
-        LoadInt { value: 4 },
-        LoopLe { target: 5 },
+    #[test]
+    fn profile_fib() {
+        let program = reg::verify(reg::FIB).unwrap();
+        let mut vm = reg::Vm::new();
+        let mut profile = profile::Profile::new();
+        for n in [2.0, 5.0] {
+            assert_eq!(vm.run_profiled(&program, &[n], &mut profile), fib(n));
+        }
 
+        // the same counts as a trace.
+        let mut executed = vec![0; reg::FIB.len()];
+        for n in [2.0, 5.0] {
+            for step in vm.trace(reg::FIB, &[n], 1000).steps {
+                executed[step.pc] += 1;
+            }
+        }
+        assert_eq!(profile.runs, 2);
+        assert_eq!(profile.executed, executed);
+        assert_eq!(profile.taken, [0, 0, 0, 0, 0, 0, 0, 7, 0]);
+
+        let report = profile::report_reg(reg::FIB, &profile);
+        assert_eq!(report.dispatches, executed.iter().sum::<u64>());
+        assert_eq!((report.taken, report.not_taken), (7, 2));
+        assert_eq!(report.per_op[0], ("copy", 14));
+        assert_eq!(report.per_pc[4].instruction, "add r3, r1, r2");
+        assert!(format!("{}", report).starts_with("reg vm, 2 runs\ndispatches                    40\n"));
+    }
 
-        // 30 + 3
-        Load { src: n },
-        GetCounter,
-        Sub,
-        Return,
-    ]};
+    #[test]
+    fn profile_wide() {
+        use reg::Instruction::*;
+        let code = [
+            Wide { high: [1, 0, 0] }, LoadInt { dst: 0, value: 3 },
+            Wide { high: [1, 0, 0] }, SetCounter { src: 0 },
+            Wide { high: [0, 0, 0] }, Loop { target: 4 },
+            Wide { high: [1, 0, 0] }, Return { src: 0 },
+        ];
+        let mut profile = profile::Profile::new();
+        assert_eq!(reg::Vm::new().run_profiled(&reg::verify(&code).unwrap(), &[], &mut profile), 3.0);
+
+        // the prefix's counts go to the instruction it extends.
+        let report = profile::report_reg(&code, &profile);
+        assert_eq!(report.dispatches, 7);
+        assert_eq!((report.taken, report.not_taken), (3, 1));
+        assert_eq!((report.loads, report.stores), (2, 1));
+        assert_eq!(report.per_op, [("loop", 4), ("load_int", 1), ("set_counter", 1), ("return", 1)]);
+        let per_pc: Vec<_> = report.per_pc.iter().map(|pc| (pc.pc, pc.instruction.as_str(), pc.executed, pc.taken)).collect();
+        assert_eq!(per_pc, [(0, "load_int r256, 3", 1, 0), (2, "set_counter r256", 1, 0), (4, "loop L4", 4, 3), (6, "return r256", 1, 0)]);
+
+        use stack::Instruction as S;
+        let code = [S::Wide { high: 1 }, S::LoadInt { value: 0 }, S::Return];
+        let mut profile = profile::Profile::new();
+        assert_eq!(stack::Vm::new().run_profiled(&stack::verify(&code, 0).unwrap(), &[], &mut profile), 256.0);
+        let report = profile::report_stack(&code, &profile);
+        assert_eq!(report.per_op, [("load_int", 1), ("return", 1)]);
+        assert_eq!((report.per_pc[0].pc, report.per_pc[0].instruction.as_str()), (0, "load_int 256"));
+    }
 
-    pub const MANDEL_SMART_NO_DUP: &[Instruction] = { use Instruction::*; let (x0, y0, n, x, y) = (0, 1, 2, 3, 4); &[
-        Load { src: n },
-        SetCounter,
+    #[test]
+    fn profile_mandel() {
+        let reg_program   = reg::verify(reg::MANDEL).unwrap();
+        let stack_program = stack::verify(stack::MANDEL_SMART, 3).unwrap();
+        let mut reg_vm   = reg::Vm::new();
+        let mut stack_vm = stack::Vm::new();
+        let mut reg_profile   = profile::Profile::new();
+        let mut stack_profile = profile::Profile::new();
+
+        for (x, y) in [(0.0, 0.0), (0.239, -0.981), (-0.648, 0.129), (0.687, 0.387)] {
+            let expected = mandel(x, y, 100.0);
+            assert_eq!(reg_vm.run_profiled(&reg_program, &[x, y, 100.0], &mut reg_profile), expected);
+            assert_eq!(stack_vm.run_profiled(&stack_program, &[x, y, 100.0], &mut stack_profile), expected);
+        }
 
-        LoadInt { value: 0 },
-        LoadInt { value: 0 },
+        let a = profile::report_reg(reg::MANDEL, &reg_profile);
+        let b = profile::report_stack(stack::MANDEL_SMART, &stack_profile);
 
-        Jump { target: 21 },
+        // same algorithm, same iterations.
+        assert_eq!((a.taken, a.not_taken), (b.taken, b.not_taken));
+        assert!(b.dispatches > a.dispatches);
 
-        // 5
-        // let xtemp = x*x - y*y + x0;
-        Load { src: x },
-        Load { src: x },
-        Mul,
-        Load { src: y },
-        Load { src: y },
-        Mul,
-        Sub,
-        Load { src: x0 },
-        Add,
+        let table = profile::compare(&a, &b);
+        assert!(table.starts_with("                             reg       stack   ratio\nruns                           4           4    1.00\n"));
+    }
 
-        // 14
-        // stack: x0, y0, n, x, y, xtemp
-        Swap,
-        // stack: x0, y0, n, x, xtemp, y
-        Rot,
-        // stack: x0, y0, n, xtemp, y, x
+    #[test]
+    fn debug_breakpoints() {
+        use debug::{Debugger, Stop};
+
+        let mut dbg = Debugger::new(reg::Vm::new(), reg::FIB, &[5.0]).unwrap();
+        dbg.set_breakpoint(4);
+
+        let mut sums = vec![];
+        loop {
+            match dbg.run_until_breakpoint().unwrap() {
+                Stop::Breakpoint { pc } => {
+                    assert_eq!(pc, 4);
+                    assert_eq!(dbg.next_instruction(), Some(reg::Instruction::Add { dst: 3, src1: 1, src2: 2 }));
+                    sums.push((dbg.counter(), dbg.registers()[1] + dbg.registers()[2]));
+                }
+                Stop::Returned(result) => {
+                    assert_eq!(result, fib(5.0));
+                    break;
+                }
+                stop => panic!("{:?}", stop),
+            }
+        }
+        assert_eq!(sums, [(4, 1.0), (3, 2.0), (2, 3.0), (1, 5.0), (0, 8.0)]);
+
+        // finished stays finished.
+        assert_eq!(dbg.step(), Ok(Stop::Returned(fib(5.0))));
+
+        // a mistyped jump target.
+        let code = { use stack::Instruction::*; &[
+            LoadInt { value: 2 },
+            SetCounter,
+            Loop { target: 5 },
+            LoadInt { value: 1 },
+            Return,
+        ]};
+        let mut dbg = Debugger::new(stack::Vm::new(), code, &[]).unwrap();
+        dbg.set_breakpoint(2);
+        assert_eq!(dbg.run_until_breakpoint(), Ok(Stop::Breakpoint { pc: 2 }));
+        assert_eq!(dbg.counter(), 2);
+        assert_eq!(dbg.stack(), []);
+        assert_eq!(dbg.run_until_breakpoint(), Err(VmError::PcOutOfBounds { pc: 2, target: 5 }));
+        assert_eq!(dbg.step(), Err(VmError::PcOutOfBounds { pc: 2, target: 5 }));
+    }
 
-        // 16
-        // y = x*y*2.0 + y0;
-        Mul,
-        LoadInt { value: 2 },
-        Mul,
-        Load { src: y0 },
-        Add,
+    #[test]
+    fn debug_watchpoints() {
+        use debug::{Debugger, Stop, Change};
+
+        let mut dbg = Debugger::new(stack::Vm::new(), stack::FIB_SMART, &[3.0]).unwrap();
+        dbg.watch(1);
+
+        let mut changes = vec![];
+        loop {
+            match dbg.run_until_breakpoint().unwrap() {
+                Stop::Watchpoint(slots) => {
+                    let [Change { slot: 1, old, new }] = slots[..] else { panic!("{:?}", slots) };
+                    changes.push((dbg.pc(), old, new));
+                }
+                Stop::Returned(result) => {
+                    assert_eq!(result, fib(3.0));
+                    break;
+                }
+                stop => panic!("{:?}", stop),
+            }
+        }
+        assert_eq!(changes, [
+            (3, None,      Some(1.0)),
+            (7, Some(1.0), Some(2.0)),
+            (7, Some(2.0), Some(3.0)),
+            (9, Some(3.0), None),
+        ]);
+
+        // `add` changes both of its operands' slots.
+        let code = { use stack::Instruction::*; &[
+            LoadInt { value: 1 },
+            LoadInt { value: 2 },
+            Add,
+            Return,
+        ]};
+        let mut dbg = Debugger::new(stack::Vm::new(), code, &[]).unwrap();
+        dbg.watch(1);
+        dbg.watch(0);
+        dbg.step().unwrap();
+        dbg.step().unwrap();
+        assert_eq!(dbg.step(), Ok(Stop::Watchpoint(vec![
+            Change { slot: 1, old: Some(2.0), new: None },
+            Change { slot: 0, old: Some(1.0), new: Some(3.0) },
+        ])));
+
+        let mut dbg = Debugger::new(reg::Vm::new(), reg::FIB, &[3.0]).unwrap();
+        dbg.watch(3);
+        dbg.unwatch(3);
+        assert_eq!(dbg.step(), Ok(Stop::Step));
+        assert_eq!(dbg.pc(), 1);
+        assert_eq!(dbg.run_until_breakpoint(), Ok(Stop::Returned(fib(3.0))));
+    }
 
-        // check x*x + y*y <= 2*2
-        // 21
-        Load { src: x },
-        Load { src: x },
-        Mul,
-        Load { src: y },
-        Load { src: y },
-        Mul,
-        Add,
-        LoadInt { value: 4 },
-        LoopLe { target: 5 },
+    #[test]
+    fn bench_stats() {
+        let m = bench::Measurement {
+            group: "g", name: "p", checksum: 1.5,
+            samples: vec![0.001, 0.002, 0.003, 0.004, 0.010],
+        };
+        assert_eq!(m.median(), 0.003);
+        assert_eq!((m.min(), m.max()), (0.001, 0.010));
+        assert_eq!(m.spread(), 0.001);
+
+        let even = bench::Measurement { samples: vec![1.0, 2.0, 4.0, 8.0], ..m.clone() };
+        assert_eq!(even.median(), 3.0);
+
+        assert_eq!(bench::csv(core::slice::from_ref(&m)),
+            "group,program,samples,median_ns,min_ns,max_ns,mad_ns,checksum\n\
+             g,p,5,3000000,1000000,10000000,1000000,1.5\n");
+
+        let table = bench::table(&[m.clone(), bench::Measurement { name: "q", samples: vec![0.006], ..m }]);
+        assert_eq!(table.lines().nth(1).unwrap(), "g       p                                    3.000     1.000    10.000   33.3%    1.00x");
+        assert_eq!(table.lines().nth(2).unwrap(), "g       q                                    6.000     6.000     6.000    0.0%    2.00x");
+    }
 
+    #[test]
+    fn bench_workloads() {
+        let config = bench::Config { warmup: 0, samples: 1 };
 
-        // 30
-        Load { src: n },
-        GetCounter,
-        Sub,
-        Return,
-    ]};
-}
+        // checksums are compared within each group.
+        let measurements = bench::run_all(config, "fib/");
+        let names: Vec<&str> = measurements.iter().map(|m| m.name).collect();
+        assert_eq!(names, ["native", "reg::FIB", "stack::FIB_SMART", "stack::FIB_NAIVE", "reg::FIB packed", "reg::FIB jit", "stack::FIB_SMART packed"]);
 
+        let measurements = bench::run_all(config, "add/");
+        assert_eq!(measurements.len(), 3);
+        assert_eq!(measurements[0].checksum, (20_000 * 120) as f64);
+    }
 
+    #[test]
+    fn fuzz_vms_agree() {
+        let config = fuzz::Config { seed: 42, cases: 500, ..Default::default() };
+        if let Err(failure) = fuzz::fuzz(&config) {
+            panic!("{}", failure);
+        }
 
-#[inline(never)]
-pub fn fib(n: f64) -> f64 {
-    let mut a = 0.0;
-    let mut b = 1.0;
-    for _ in 0..n as u32 {
-        (a, b) = (b, a+b);
+        // same seed, same programs, and they terminate.
+        let (mut a, mut b) = (fuzz::Rng::new(3), fuzz::Rng::new(3));
+        for _ in 0..50 {
+            let case = fuzz::generate(&mut a, &config);
+            assert_eq!(case, fuzz::generate(&mut b, &config));
+            assert!(reg::verify(&case.code).is_ok());
+            assert!(reg::Vm::new().trace(&case.code, &case.args, 100_000).result.is_some());
+        }
     }
-    a
-}
 
-#[inline(never)]
-pub fn mandel(x0: f64, y0: f64, limit: f64) -> f64 {
-    let mut x = 0.0;
-    let mut y = 0.0;
-    let mut i = 0;
-    while x*x + y*y <= 4.0 && i < limit as u32 {
-        let xtemp = x*x - y*y + x0;
-        y = x*y*2.0 + y0;
-        x = xtemp;
-        i += 1;
+    #[test]
+    fn fuzz_minimize() {
+        let outcomes = |a: fuzz::Outcome, b: fuzz::Outcome| vec![("a", a), ("b", b)];
+        assert!( fuzz::agree(&outcomes(Ok(f64::NAN), Ok(-f64::NAN))));
+        assert!(!fuzz::agree(&outcomes(Ok(0.0), Ok(-0.0))));
+        assert!(!fuzz::agree(&outcomes(Err("e".into()), Err("e".into()))));
+
+        let mut rng = fuzz::Rng::new(5);
+        let config = fuzz::Config::default();
+        let case = loop {
+            let case = fuzz::generate(&mut rng, &config);
+            if case.code.len() > 20 && case.code.iter().any(|i| matches!(i, reg::Instruction::Mul { .. })) {
+                break case;
+            }
+        };
+
+        let has_mul = |case: &fuzz::Case| case.code.iter().any(|i| matches!(i, reg::Instruction::Mul { .. }));
+        let minimized = fuzz::minimize(&case, has_mul);
+        assert_eq!(minimized.args, case.args);
+        assert_eq!(minimized.code.len(), 2);
+        assert!(matches!(minimized.code[..], [reg::Instruction::Mul { .. }, reg::Instruction::Return { .. }]));
     }
-    return i as f64;
-}
 
+    #[test]
+    fn fuel() {
+        use VmError::OutOfFuel;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let spin = reg::verify(&[reg::Instruction::Jump { target: 0 }]).unwrap();
+        let mut vm = reg::Vm::new();
+        assert_eq!(vm.run_with_fuel(&spin, &[], 1000), Err(OutOfFuel { pc: 0, counter: 0 }));
+        assert_eq!(vm.try_run_with_fuel(spin.code(), &[], 1000), Err(OutOfFuel { pc: 0, counter: 0 }));
+
+        // exactly enough, and one short of it.
+        let steps = vm.trace(reg::FIB, &[10.0], 1000).steps.len() as u64;
+        let program = reg::verify(reg::FIB).unwrap();
+        assert_eq!(vm.run_with_fuel(&program, &[10.0], steps), Ok(55.0));
+        assert_eq!(vm.try_run_with_fuel(reg::FIB, &[10.0], steps), Ok(55.0));
+        assert_eq!(vm.run_with_fuel(&program, &[10.0], steps - 1), Err(OutOfFuel { pc: 8, counter: 0 }));
+        assert_eq!(vm.registers()[1], 55.0);
+        assert_eq!(vm.try_run_with_fuel(reg::FIB, &[10.0], 5), Err(OutOfFuel { pc: 4, counter: 9 }));
 
-    fn test_fib<F: FnMut(f64) -> f64>(mut f: F) {
-        for i in 0..1000 {
-            assert_eq!(fib(i as f64), f(i as f64));
-        }
+        let mut vm = stack::Vm::new();
+        let steps = vm.trace(stack::FIB_SMART, &[10.0], 1000).steps.len() as u64;
+        let program = stack::verify(stack::FIB_SMART, 1).unwrap();
+        assert_eq!(vm.run_with_fuel(&program, &[10.0], steps), Ok(55.0));
+        assert_eq!(vm.run_with_fuel(&program, &[10.0], steps - 1), Err(OutOfFuel { pc: 9, counter: 0 }));
+        assert_eq!(vm.stack(), [55.0]);
+        assert_eq!(vm.run_with_fuel(&program, &[10.0], 7), Err(OutOfFuel { pc: 6, counter: 9 }));
+        assert_eq!(vm.stack(), [1.0, 1.0, 0.0]);
+        assert_eq!(vm.try_run_with_fuel(stack::FIB_SMART, &[10.0], 7), Err(OutOfFuel { pc: 6, counter: 9 }));
+        assert_eq!(vm.stack(), [1.0, 1.0, 0.0]);
+
+        // and the fast path still works afterwards.
+        assert_eq!(vm.run(&program, &[10.0]), 55.0);
     }
 
-    fn test_mandel<F: FnMut(f64, f64, f64) -> f64>(mut f: F) {
-        let limit = 10000.0;
-        for (x, y) in [(0.0, 0.0), (1.0, 1.0), (0.239, -0.981), (-0.648, 0.129), (-0.812, -0.021), (0.687, 0.387), (-0.950786716408015, 0.7448839625558739), (-0.41795412221209083, 0.3891104473889062), (-0.30454550287093674, 0.3662807658368674), (-0.38954007305330274, 0.6099259700840349), (0.07342221713511665, -0.8427951153696895), (-0.11064854851199213, -0.926639764442595), (-0.7596206479056045, 0.048924374342336874), (-0.49581061588344877, -0.12784717276741908), (0.6437608077269181, 0.4495234224170379), (-0.35902711244632446, -0.27337416802046066), (0.46119571367001844, 0.6369625077084937), (-0.8337757474334115, 0.30803404049145233), (0.7567477274895906, 0.7425733436366662), (-0.05666196234341658, -0.7153313926145124), (-0.1712571411242514, -0.7293648513842237), (-0.18029887642403808, 0.9337582385391014), (0.15025609901930093, -0.2562979396981093), (-0.8740713363980595, -0.3026974138730323), (0.734282601227346, 0.44824566299076163), (-0.6450519022478793, 0.8973350411345271), (-0.12951887416193664, -0.9675733335426315), (-0.8496395992141339, -0.45856363991261984), (-0.05006755838234733, 0.08815703999052182), (-0.0819349209740825, 0.392640373018607), (0.38818643312858203, 0.017802320175963837), (-0.5804782726092039, -0.8114288891880093), (0.21174287430534067, -0.9779934844282805), (0.48485403501831414, -0.32072813452059323), (0.11816231997762872, 0.9901345882876014), (-0.32987818756568665, -0.790041653198585), (-0.751877131490938, 0.7158915912037904), (-0.27952689192889313, -0.990237696155138), (0.27114775350097875, -0.4153077703274628), (0.058473091300088376, -0.9049579783144228), (0.4753437616586973, 0.14657146392303133), (-0.1745580736849639, 0.4775850047960255), (-0.7249298752680955, -0.9641325799319125), (0.12624851616576604, -0.44348534069122003), (-0.973539445295293, 0.832103245702291), (-0.6743590940233655, 0.9387520845818487), (-0.16662572477960436, -0.900765313197615), (-0.5912590709584258, -0.5102679290062448), (0.8503235254831967, -0.33485304549647044), (0.08977059985414226, -0.5524679311716958), (0.9092630698742039, 0.651495043750099), (0.06998115038035468, -0.1554190026368456), (-0.7399797760579021, -0.006137159260573455), (-0.2757987315085746, -0.537347842353135), (-0.30389255510677926, 0.542418339351705), (0.05627319272720821, 0.6292518136210365), (-0.6203030673427403, 0.7900418292632594), (-0.7909573091413067, -0.396979443474341), (0.24824386339155158, 0.3388202675220937), (-0.0513868729451854, 0.17817438126698026), (0.9462416973938339, 0.8676690260189799), (-0.1139783605452167, 0.0038380988757436008), (-0.23121289597591965, -0.47531036160660034), (0.5828474701609332, -0.7839471493639378), (0.9770948454943438, -0.4683608606405316), (0.47740801560355584, -0.2883965096412553), (0.34009761202328015, 0.6546012541731507), (-0.4924202923168335, 0.7856942391002899), (0.6230983780135113, -0.19678697882493723), (-0.140144286728481, 0.9028641035510641), (0.02303038331068996, -0.3161713074917263), (-0.6435921111392493, 0.6404964303777942), (-0.7066891000784838, 0.45541585282911434), (-0.24137952675687369, -0.7447054863100193), (0.19991366412404066, -0.23703689667205108), (-0.9570794031819638, -0.9884389720144522), (-0.6797569127393104, 0.2082269559276082), (0.769667914938132, 0.3787028736840006), (0.5848065627210588, 0.21127658721982723), (0.4844247997855926, 0.7800864760973392), (0.4583131215187135, 0.8734790105541512), (-0.3952706913645596, 0.633963083692719), (-0.9486119273737694, 0.7587301881016015), (-0.46070283891613584, 0.5041607377115895), (0.995619007269551, 0.3803371649267848), (-0.2797833504752094, 0.9908037027712009), (-0.9167995403904445, -0.807580777212757), (-0.18027266265314745, -0.851839465543321), (0.6128426674227088, -0.09875532852024493), (0.011860446602936614, -0.8681653691688065), (-0.7107796408969822, 0.02980060781663707), (-0.06413027397564885, -0.13037851125697242), (0.11553944723254239, -0.4890274430377839), (0.3479886739255793, -0.8699677560350583), (0.38333167839236126, 0.4218707211211812), (0.638723023074957, -0.9683866946207558), (0.46354649116031754, 0.07563976922668769), (-0.06351773294699603, 0.8144404275239079), (0.5119372264249904, -0.9096216627089817), (-0.09883485075616494, 0.3297820865529715), (0.10358292605721098, -0.36692635243618077), (0.15047436546885562, -0.9806781334753645), (0.7617574165547447, 0.4763568144395216), (0.9160725920362325, 0.9823994209157814), (0.7548032671745575, 0.812465693071418), (0.3246690244662118, -0.3065870163715265), (-0.560268464119319, 0.9221103278210057), (0.4626072017685068, 0.15317773756585118), (0.6545368464007859, 0.9506033905866516), (0.5098893892275631, 0.7830254619622639), (-0.9966341637112501, 0.6861534690011151), (-0.385961778663223, 0.09296319784275209), (-0.6439053469798957, 0.8752161182375295), (0.5684180435556756, -0.7391524569031711), (-0.5741612839751691, -0.7694325902986678), (0.8870349468029142, -0.0626416154235423), (-0.7931632997873719, -0.1847117421834228), (-0.4170616485992771, -0.4058127574957917), (-0.012491529971446091, -0.7246396095112151), (-0.7011675969925535, -0.28220086603407024), (-0.1863646808060755, -0.9348786412235945), (0.7010535378320495, -0.8136524427155922), (-0.62997318656867, -0.5673706697790437), (0.20571346964486614, 0.8494676030862429), (-0.9582906806422553, 0.13304666328969184), (-0.9017636413609094, -0.7840101289980281), (-0.06844213395069287, 0.4449811706731899), (-0.6396613633959511, 0.7560570032865803), (0.17821628814178592, -0.6343776437447937), (0.644101079419162, -0.10231890186410197), (-0.9538502098940163, -0.4005239063329318), (-0.45575562738990283, 0.6260262571336148), (-0.022746028755935566, 0.7273548892777673), (-0.5843548196344637, -0.8970791745762465), (-0.134489546848358, -0.23508372044200576), (-0.4426578957700551, -0.4140837629408358), (-0.8960196125459656, 0.9648402032597405), (-0.5559247435005124, -0.9627298282745853), (-0.9816649175072354, 0.23566904569481584), (0.37830338720570555, 0.9659962089961276), (0.07032093120792404, -0.8448450554291562), (-0.043415679985484124, 0.2982047225662403), (-0.7426172167006169, -0.9450540467855513), (-0.7833001490152167, 0.8587880458484556), (0.5753231270739165, -0.3550369532737825), (0.9847762265520275, 0.5601827115776938), (0.7547992831124333, 0.28584939475098947), (-0.8448259708273849, -0.35476136665194846), (0.879827174886626, 0.88500567042747), (0.00876529646860802, -0.30836856894337306), (0.7747669072600987, 0.03129425244220663), (-0.9181318043271416, -0.1982212204694076), (-0.06470118131158964, 0.6372280272430135), (0.6586665563523673, -0.5780624300151294), (0.19158793464893065, -0.8718738170981424), (-0.6405141431119539, -0.5399480154588656), (-0.014859791537689349, 0.5613188554256261), (-0.5781514716971152, 0.2559171658208903), (-0.09436027264047842, -0.6637963328398158), (-0.6217035983366301, -0.4498016587418536), (-0.02539730686860331, -0.8160511451622021), (-0.1819750814557659, -0.48686881610529165), (-0.8311143734208255, 0.7345232437803397), (0.35720487022736136, 0.446537629591925), (0.040582999596036506, 0.27151188053807496), (-0.8582374284322447, -0.37397514967056456), (-0.2234253044314234, -0.5021431610668297), (0.061836579223409016, 0.2334741235096851), (-0.80899619273474, 0.061122116008547556), (0.09888582378400912, 0.13157005798444055), (0.4445239732731163, -0.00797318519075918), (-0.9232548892900476, 0.015610893892384903), (0.17258779115188272, -0.25500953936981885), (0.21398456462176796, 0.6500598840741447), (-0.3659869812873422, 0.29484229817933216), (-0.2749147404367873, -0.48538050541310906), (-0.5870409692233305, 0.282757960020392), (-0.009340854254250353, -0.5661727070520584), (0.715684675641828, 0.675955746635732), (-0.8905081791246903, 0.7234796547395257), (0.6429947842459898, 0.67193501065015), (0.42730640534452435, -0.925032998389832), (0.7829803203729666, 0.8067771780949617), (-0.9775718658097963, 0.5710827388382336), (0.0038854322067862768, 0.9691541535883614), (-0.7354493698452671, -0.7740512298723263), (0.6066203299748345, 0.7907036360462232), (-0.2224438094868928, -0.5466373525288557), (-0.6613459909460426, 0.17482481697589058), (-0.14091009854697045, -0.25741985123563094), (-0.6049343628632007, 0.7132474164128548), (0.6767791588126548, 0.46222825969908365), (-0.019881898612260862, 0.42921013485612036), (-0.024289173227493688, -0.6326047825520571), (0.9273609755463252, 0.9650020725623369), (0.43880148036486677, 0.9648468957926457), (0.7820211869090097, 0.7143859917652413), (0.10713771400332006, 0.87644702975162), (0.3603460364845872, 0.9734868379754906), (0.4646237172759058, -0.6227490350204024), (0.8685337181342596, -0.9486561516837511), (-0.4290526472599958, -0.07105881127723457), (-0.43449703896808, -0.43932628835061993), (0.8619314767156487, 0.959378844974651), (0.2524793886536818, -0.5086760933393686), (-0.968926808165882, -0.48180443592302513), (-0.9344049892159925, -0.9719354066164145), (0.8417012429814341, -0.846162566469103), (0.605011478012363, 0.6877711581719606), (0.29583324991339044, 0.3897339588911992), (0.3063194062122856, -0.4969091145392086), (-0.07403093613837086, -0.06210765636399951), (-0.8870943179841004, 0.13186549816749604), (-0.13905452148322728, -0.14200541685720025), (0.9059296265527592, -0.8674413586878444), (-0.8134297959876413, 0.8602852560727625), (0.5574039666928619, 0.5935991790218782), (-0.05988992995905518, 0.8331438388387209), (-0.15690479005758462, -0.09787464921091593), (-0.3499683268803646, -0.07036877228129979), (-0.7348170188722913, 0.6489097570964577), (0.08030663152612805, -0.4062556913602422), (-0.3948116107741906, -0.8419118545265418), (-0.7080619975080553, -0.9532463704924592), (0.9525099193607636, 0.7770440519594752), (0.8356696335520295, 0.11139106868318094), (0.37389056109299457, -0.10176149244871913), (-0.5179332268046919, 0.906755738195657), (-0.5101578900967876, -0.9795688509682434), (0.15559279752437916, -0.6175284856819807), (-0.3563748416756034, -0.7785942522281912), (-0.19025374281021157, 0.6459693559382012), (0.8948354100256368, -0.7186710154824598), (0.44374924585090136, 0.5837918431600173), (-0.4672648256848859, -0.3637024717244548), (-0.14026461181970284, 0.40814454352232743), (-0.8978006636593574, -0.7145974937988178), (0.11539804649202101, 0.14383105313034195), (-0.6062938753740512, 0.7502189488775537), (-0.4391572025223398, 0.9752356885866993), (-0.38715366747667934, -0.7985315162371476), (0.1255993226373906, 0.34908952025487716), (0.4225405126612505, 0.09137785183737068), (-0.13177079487908627, 0.22058823667211525), (-0.28381252837930226, 0.7711425341318037), (0.12869352408940005, -0.00020271374035063516), (-0.1448983949760465, -0.9880872282380235), (-0.0465272602088036, 0.3825699981736381), (-0.9077389357297825, 0.9009893307505754), (0.8110194938755062, -0.9800467040477308), (-0.01036226648551164, -0.20791141520235312), (-0.8491795910760915, -0.5161174572537066), (0.21510754077334227, 0.046435915914714965), (0.7015051124679705, -0.7139484315828417), (-0.8270226510406857, -0.037067448340805775), (0.3284454053998058, 0.7011277797513877), (0.8237931165115819, 0.7651378342874593), (0.3254212701250152, 0.9467579439573748), (0.6551253150336631, 0.09542055528534776), (0.059721730531613115, -0.2171872986083485), (0.4025247537451424, -0.23519298808428113), (-0.3656975388853696, 0.9488462674799432), (0.10234697465954268, -0.034042905991529704), (-0.021076834862308047, -0.30834904994034873), (-0.5425800701779875, -0.7901297323360688), (-0.5163096338241866, -0.0910731448043538), (0.775907526479902, -0.029927810692431223), (-0.5787010634781413, -0.17724734524515395), (-0.686139712926781, -0.6582853023191224), (-0.8201124220854916, 0.5324206558841762), (-0.5127613921846674, -0.46650367860717123), (0.4891824637526496, 0.061346961080255724), (-0.5571482675377373, 0.9411510539379417), (0.8707373419055771, 0.6207284256515975), (0.8219858954429966, -0.8779285711624081), (0.45726631268327833, 0.03270982726025018), (0.1853046782842407, -0.22408852515290034), (0.4255401852644969, -0.4449354754646797), (0.4317054766054196, -0.4691969281855446), (-0.5141023626569792, 0.7586320148155574), (0.7059121498036909, -0.2683408346536418), (0.09009019999661194, 0.7579800601346505), (0.747078544161313, -0.26381189782764225), (0.5335162579135664, -0.8201395593255578), (0.4399092773156905, 0.4001922617899951), (-0.2749366699470286, 0.22086561231547597), (0.09771281478875804, -0.8128675949424682), (0.8172959884714706, -0.22236236268899234), (-0.17248615077637086, 0.7974885426480312), (0.842252396378558, 0.7288571906820647), (0.7334957992325437, 0.07938913059765351), (0.39793866023322955, 0.8688245906412282), (0.7870291797356017, 0.06165030599614352), (-0.21473486896046423, 0.4392857648561843), (-0.5073085444740155, 0.4678281268067612), (-0.03340450051571797, 0.3363381514347139), (-0.30550362154772936, 0.7072959211525238), (-0.08235208900128632, 0.45302815156489107), (0.14964118143127214, -0.7335083814629508), (0.6494909995782758, -0.7605196537643031), (0.37417634836018854, -0.2745582477672208), (0.24402671028084932, -0.9659474167888), (0.6850340610798742, 0.3156075945350234), (-0.3353094019784082, 0.15936722855440655), (-0.35383706533582737, -0.4898161541205872), (0.08096129676569741, 0.984231777908692), (-0.09225205063861064, 0.11924130015978296), (-0.19117673582023098, -0.4570527844157015), (0.04484730422003991, -0.20431919075758143), (-0.214123335641055, -0.9923780601705212), (-0.5994170582611429, 0.6971508827916248), (0.8125161673575663, -0.5904108880645373), (-0.34462253292283207, 0.39677814793807964), (-0.4666775475043421, 0.7563498846928809), (-0.5353939698802537, 0.9372234589613415), (0.816330849123519, 0.7980310088153351), (-0.2442766721743026, 0.18660398148145663), (-0.795812123557933, -0.8439443207812483), (-0.7316221860345711, -0.00679059803926263), (0.029322252210048916, -0.039863229769652175), (0.8415655075406308, 0.2438708303677739), (-0.9738347839788026, 0.1820873912774983), (0.5812432844185678, -0.41807701493178806), (-0.11121009276117788, 0.09108995663689967), (0.4947780413182141, 0.731341348626044), (0.7549316153846815, 0.9940007248455749), (0.22703057664382742, -0.5973739903480808), (-0.5443322898224618, -0.9174453695604308), (0.32356085335906437, -0.4888945103497353), (0.359539113819318, -0.7408168135541069), (-0.22338680684947865, 0.28696110757103854), (-0.6034832533179653, -0.17871677245046125), (-0.1922451022511551, 0.6350068107028115), (-0.26205534723658164, -0.9428261933544171), (0.7931239883719934, 0.32715467498864514), (-0.37506617261760544, -0.1408871705323862), (0.060770083585807155, 0.8060976545229288), (-0.7736828407722232, -0.38511545538424485), (-0.4145664314854487, 0.5137234572891332), (0.05234994257136738, 0.832142969883906), (0.6007597711309947, 0.2405601651487772), (0.27450568752981797, -0.4408499884659134), (0.3389752585537307, 0.9808145570890736), (-0.5241924228584112, 0.2669518809982916), (-0.8763341953698638, -0.21111615960052754), (0.33981530567566987, -0.09232472231112476), (-0.8508778459163162, -0.2587109005842909), (0.8737211808432022, -0.5648439845189517), (0.8083427960826393, 0.11234062682554247), (-0.2712476783355593, -0.8774439655390605)] {
-            assert_eq!(mandel(x, y, limit), f(x, y, limit));
+    #[test]
+    fn calls() {
+        let reg_program = reg::verify(reg::FIB_RECURSIVE).unwrap();
+        let stack_program = stack::verify(stack::FIB_RECURSIVE, 1).unwrap();
+        let translated = translate::stack_to_reg(stack::FIB_RECURSIVE, 1).unwrap();
+        let translated = reg::verify(&translated).unwrap();
+        let (mut rvm, mut svm) = (reg::Vm::new(), stack::Vm::new());
+        for n in 0..15 {
+            let expected = fib_recursive(n as f64);
+            assert_eq!(rvm.run(&reg_program, &[n as f64]), expected);
+            assert_eq!(rvm.try_run(reg::FIB_RECURSIVE, &[n as f64]), Ok(expected));
+            assert_eq!(rvm.run(&translated, &[n as f64]), expected);
+            assert_eq!(svm.run(&stack_program, &[n as f64]), expected);
+            assert_eq!(svm.try_run(stack::FIB_RECURSIVE, &[n as f64]), Ok(expected));
+            assert_eq!(svm.stack(), []);
         }
+
+        // out of fuel in a callee, then the fast path again.
+        for fuel in [3, 10, 37] {
+            assert_eq!(rvm.run_with_fuel(&reg_program, &[10.0], fuel), rvm.try_run_with_fuel(reg::FIB_RECURSIVE, &[10.0], fuel));
+            assert_eq!(svm.run_with_fuel(&stack_program, &[10.0], fuel), svm.try_run_with_fuel(stack::FIB_RECURSIVE, &[10.0], fuel));
+        }
+        assert_eq!(rvm.run(&reg_program, &[10.0]), 55.0);
+        assert_eq!(svm.run(&stack_program, &[10.0]), 55.0);
+
+        // outside of calls, `Ret` is `Return`.
+        assert!(reg_program.calls() && !reg::verify(reg::FIB).unwrap().calls());
+        assert!(stack_program.calls() && !stack::verify(stack::FIB_SMART, 1).unwrap().calls());
+        let program = reg::verify(&[reg::Instruction::Ret { src: 0 }]).unwrap();
+        assert_eq!(rvm.run(&program, &[4.0]), 4.0);
+        let program = stack::verify(&[stack::Instruction::Ret], 1).unwrap();
+        assert_eq!(svm.run(&program, &[4.0]), 4.0);
+        assert_eq!(svm.try_run(&[stack::Instruction::Ret], &[4.0]), Ok(4.0));
+
+        // calls that never return.
+        use reg::Instruction as R;
+        use stack::Instruction as S;
+        for code in [&[R::Call { target: 0, base: 0 }, R::Return { src: 0 }], &[R::Call { target: 0, base: 7 }, R::Return { src: 0 }]] {
+            let program = reg::verify(code).unwrap();
+            assert_eq!(rvm.run_with_fuel(&program, &[], 1 << 20), Err(VmError::StackOverflow { pc: 0 }));
+            assert_eq!(rvm.try_run(code, &[]), Err(VmError::StackOverflow { pc: 0 }));
+        }
+        for code in [&[S::Enter { argc: 0 }, S::Call { target: 0 }, S::Return][..], &[S::LoadInt { value: 1 }, S::Enter { argc: 1 }, S::Dup, S::Call { target: 1 }, S::Return]] {
+            let program = stack::verify(code, 0).unwrap();
+            let pc = code.len() - 2;
+            assert_eq!(svm.run_with_fuel(&program, &[], 1 << 20), Err(VmError::StackOverflow { pc }));
+            assert_eq!(svm.try_run(code, &[]), Err(VmError::StackOverflow { pc }));
+        }
+
+        // a callee starts out with just its arguments.
+        assert_eq!(stack::verify(&[S::LoadInt { value: 1 }, S::LoadInt { value: 2 }, S::Enter { argc: 1 }, S::Call { target: 2 }, S::Return], 0).unwrap_err(),
+            VerifyError::DepthMismatch { pc: 2, expected: 2, found: 1 });
+        // and calls go to an `Enter`, which has their `argc`.
+        assert_eq!(stack::verify(&[S::LoadInt { value: 1 }, S::Call { target: 0 }, S::Return], 0).unwrap_err(),
+            VerifyError::BadCall { pc: 1 });
+        assert_eq!(svm.try_run(&[S::LoadInt { value: 1 }, S::Call { target: 0 }, S::Return], &[]), Err(VmError::BadCall { pc: 1 }));
+
+        assert_eq!(translate::reg_to_stack(reg::FIB_RECURSIVE, 1, translate::Strategy::Smart).unwrap_err(),
+            translate::TranslateError::Unsupported { pc: 0 });
+        let lifted: Vec<_> = reg::FIB_RECURSIVE.iter().map(|i| regalloc::Instruction::lift(*i)).collect();
+        assert_eq!(regalloc::allocate(&lifted, 1, None).unwrap_err(), regalloc::AllocError::Unsupported { pc: 0 });
+
+        // traces use absolute indices.
+        let trace = rvm.trace(reg::FIB_RECURSIVE, &[2.0], 1000);
+        assert_eq!(trace.result, Some(Ok(1.0)));
+        let rets: Vec<_> = trace.steps.iter().filter(|s| s.instruction.starts_with("ret")).map(|s| s.writes.clone()).collect();
+        assert_eq!(rets, [vec![(2, 1.0)], vec![(3, 0.0)], vec![(0, 1.0)], vec![]]);
+    }
+    #[test]
+    fn collatz_programs() {
+        const SOURCE: &str = "
+            fn collatz(n) {
+                let total = 0;
+                let i = 1;
+                while i <= n {
+                    let x = i;
+                    while x > 1 {
+                        if floor(x/2) == x/2 { x = x/2; }
+                        else { x = 3*x + 1; }
+                        total = total + 1;
+                    }
+                    i = i + 1;
+                }
+                return total;
+            }
+        ";
+
+        let reg_program = reg::verify(reg::COLLATZ).unwrap();
+        let stack_program = stack::verify(stack::COLLATZ, 1).unwrap();
+        let programs = [
+            reg::verify(&translate::stack_to_reg(stack::COLLATZ, 1).unwrap()).unwrap(),
+            reg::verify(&lang::compile_reg(SOURCE).unwrap()).unwrap(),
+        ];
+        let stack_programs = [
+            stack::verify(&translate::reg_to_stack(reg::COLLATZ, 1, translate::Strategy::Naive).unwrap(), 1).unwrap(),
+            stack::verify(&translate::reg_to_stack(reg::COLLATZ, 1, translate::Strategy::Smart).unwrap(), 1).unwrap(),
+            stack::verify(&lang::compile_stack(SOURCE).unwrap(), 1).unwrap(),
+        ];
+        let (mut rvm, mut svm) = (reg::Vm::new(), stack::Vm::new());
+        for n in [0.0, 1.0, 2.0, 7.0, 27.0, 100.0] {
+            let expected = collatz(n);
+            assert_eq!(rvm.run(&reg_program, &[n]), expected);
+            assert_eq!(rvm.try_run(reg::COLLATZ, &[n]), Ok(expected));
+            assert_eq!(svm.run(&stack_program, &[n]), expected);
+            assert_eq!(svm.try_run(stack::COLLATZ, &[n]), Ok(expected));
+            for program in &programs {
+                assert_eq!(rvm.run(program, &[n]), expected);
+            }
+            for program in &stack_programs {
+                assert_eq!(svm.run(program, &[n]), expected);
+            }
+        }
+        assert_eq!(collatz(27.0) - collatz(26.0), 111.0);
+
+        // division and friends leave the compact loop.
+        assert!(!reg_program.compact() && reg::verify(reg::MANDEL).unwrap().compact());
+        assert_eq!(rvm.run_with_fuel(&reg_program, &[27.0], 100), rvm.try_run_with_fuel(reg::COLLATZ, &[27.0], 100));
+        assert_eq!(svm.run_with_fuel(&stack_program, &[27.0], 100), svm.try_run_with_fuel(stack::COLLATZ, &[27.0], 100));
+
+        // 1, 2 and 3 take 0, 1 and 7 steps, 2 of them odd.
+        let mut profile = profile::Profile::new();
+        assert_eq!(rvm.run_profiled(&reg_program, &[3.0], &mut profile), collatz(3.0));
+        assert_eq!((profile.taken[11], profile.taken[18], profile.taken[21]), (2, 8, 3));
+        let report = profile::report_reg(reg::COLLATZ, &profile);
+        assert_eq!(report.per_pc[11].instruction, "jump_if_not L14, r8");
     }
 
+    #[test]
+    fn math_ops() {
+        let code = asm::assemble_reg("
+            sqrt  r2, r0
+            abs   r3, r1
+            floor r4, r1
+            min   r5, r0, r1
+            max   r6, r0, r1
+            neg   r7, r1
+            div   r8, r0, r1
+            lt    r9, r1, r0
+            le    r10, r0, r0
+            eq    r11, r0, r1
+            add   r2, r2, r3
+            add   r2, r2, r4
+            add   r2, r2, r5
+            add   r2, r2, r6
+            add   r2, r2, r7
+            add   r2, r2, r8
+            add   r2, r2, r9
+            add   r2, r2, r10
+            add   r2, r2, r11
+            return r2
+        ").unwrap();
+        let expected = |a: f64, b: f64| {
+            a.sqrt() + b.abs() + b.floor() + a.min(b) + a.max(b) - b + a/b
+                + f64::from(b < a) + 1.0 + f64::from(a == b)
+        };
+
+        let program = reg::verify(&code).unwrap();
+        let stack_code = translate::reg_to_stack(&code, 2, translate::Strategy::Smart).unwrap();
+        let stack_program = stack::verify(&stack_code, 2).unwrap();
+        let (mut rvm, mut svm) = (reg::Vm::new(), stack::Vm::new());
+        for (a, b) in [(4.0, -2.5), (9.0, 9.0), (0.25, 3.75)] {
+            assert_eq!(rvm.run(&program, &[a, b]), expected(a, b));
+            assert_eq!(rvm.try_run(&code, &[a, b]), Ok(expected(a, b)));
+            assert_eq!(svm.run(&stack_program, &[a, b]), expected(a, b));
+            assert_eq!(svm.try_run(&stack_code, &[a, b]), Ok(expected(a, b)));
+        }
+
+        // nan compares false, `jump_if` only skips on 0.
+        let code = asm::assemble_stack("
+                load 0
+                load 0
+                eq
+                jump_if done
+                load_int 7
+                return
+            done:
+                load_int 1
+                return
+        ").unwrap();
+        assert_eq!(stack::Vm::new().try_run(&code, &[f64::NAN]), Ok(7.0));
+        assert_eq!(stack::Vm::new().try_run(&code, &[1.5]), Ok(1.0));
+    }
 
     #[test]
-    fn reg_fib() {
+    fn values() {
+        use value::{Value, Tagged, Boxed};
+        use VmError::TypeError;
+
+        // the same results with every representation.
+        fn agree<V: Value>() {
+            let (mut rvm, mut svm) = (reg::Vm::<V>::default(), stack::Vm::<V>::default());
+            let cases = [
+                (reg::MANDEL,        stack::MANDEL_SMART,  &[0.239, -0.981, 100.0][..], mandel(0.239, -0.981, 100.0)),
+                (reg::COLLATZ,       stack::COLLATZ,       &[27.0],                     collatz(27.0)),
+                (reg::FIB_RECURSIVE, stack::FIB_RECURSIVE, &[12.0],                     fib_recursive(12.0)),
+            ];
+            for (reg_code, stack_code, args, expected) in cases {
+                let args: Vec<V> = args.iter().map(|x| V::from_f64(*x)).collect();
+                let program = reg::verify(reg_code).unwrap();
+                assert_eq!(rvm.run(&program, &args).to_f64(), expected);
+                assert_eq!(rvm.try_run(reg_code, &args).map(V::to_f64), Ok(expected));
+                let program = stack::verify(stack_code, args.len()).unwrap();
+                assert_eq!(svm.run(&program, &args).to_f64(), expected);
+                assert_eq!(svm.try_run(stack_code, &args).map(V::to_f64), Ok(expected));
+            }
+        }
+        agree::<f64>();
+        agree::<Tagged>();
+        agree::<Boxed>();
+
+        // ints stay ints until they don't fit.
+        let code = asm::assemble_reg("
+            load_int r1, 30000
+            mul      r2, r1, r1
+            mul      r3, r2, r1
+            add      r4, r0, r1
+            lt       r5, r0, r1
+            div      r6, r1, r1
+            neg      r7, r1
+            return   r0
+        ").unwrap();
+        use Tagged::*;
+        let expected = [Float(0.5), Int(30000), Int(900_000_000), Float(2.7e13), Float(30000.5), Bool(true), Float(1.0), Int(-30000)];
+        let mut vm = reg::Vm::<Tagged>::default();
+        vm.try_run(&code, &[Float(0.5)]).unwrap();
+        assert_eq!(vm.registers()[..8], expected);
+        let mut vm = reg::Vm::<Boxed>::default();
+        vm.run(&reg::verify(&code).unwrap(), &[Boxed::from_f64(0.5)]);
+        assert_eq!(vm.registers()[..8].iter().map(|v| v.get()).collect::<Vec<_>>(), expected);
         let mut vm = reg::Vm::new();
-        test_fib(|n| {
-            vm.run(reg::FIB, &[n])
-        });
+        vm.try_run(&code, &[0.5]).unwrap();
+        assert_eq!(vm.registers()[..8], [0.5, 30000.0, 9e8, 2.7e13, 30000.5, 1.0, 1.0, -30000.0]);
+
+        // bools only mix with bools, and only in `eq`.
+        for (source, pc) in [
+            ("lt r1, r0, r0 \n add r2, r1, r0 \n return r2", 1),
+            ("lt r1, r0, r0 \n eq r2, r1, r0 \n return r2", 1),
+            ("lt r1, r0, r0 \n set_counter r1 \n return r1", 1),
+            ("lt r1, r0, r0 \n loop_le 0, r1, r1 \n return r1", 1),
+        ] {
+            let code = asm::assemble_reg(source).unwrap();
+            let program = reg::verify(&code).unwrap();
+            assert_eq!(reg::Vm::<Tagged>::default().try_run(&code, &[Float(1.0)]), Err(TypeError { pc }));
+            assert_eq!(reg::Vm::<Tagged>::default().run_with_fuel(&program, &[Float(1.0)], 100), Err(TypeError { pc }));
+            assert_eq!(reg::Vm::<Boxed>::default().run_with_fuel(&program, &[Boxed::from_f64(1.0)], 100), Err(TypeError { pc }));
+            assert!(reg::Vm::new().try_run(&code, &[1.0]).is_ok());
+        }
+        let code = asm::assemble_reg("lt r1, r0, r0 \n eq r2, r1, r1 \n jump_if 4, r2 \n return r0 \n return r2").unwrap();
+        assert_eq!(reg::Vm::<Tagged>::default().try_run(&code, &[Float(1.0)]), Ok(Bool(true)));
+
+        let code = asm::assemble_stack("load 0 \n load 0 \n lt \n sqrt \n return").unwrap();
+        let program = stack::verify(&code, 1).unwrap();
+        assert_eq!(stack::Vm::<Tagged>::default().try_run(&code, &[Int(2)]), Err(TypeError { pc: 3 }));
+        assert_eq!(stack::Vm::<Boxed>::default().run_with_fuel(&program, &[Boxed::from_f64(2.0)], 100), Err(TypeError { pc: 3 }));
+        assert_eq!(stack::Vm::new().run(&program, &[2.0]), 0.0);
+
+        // the layout.
+        assert_eq!((core::mem::size_of::<Boxed>(), core::mem::size_of::<Tagged>()), (8, 16));
+        for value in [Int(-5), Int(i32::MIN), Bool(false), Bool(true), Float(-0.0), Float(f64::NEG_INFINITY), Float(1e300)] {
+            assert_eq!(Boxed::new(value).get(), value);
+        }
+        assert!(Boxed::from_f64(-f64::NAN).to_f64().is_nan());
+        assert_eq!(Boxed::from_f64(-f64::NAN), Boxed::from_f64(f64::NAN));
     }
 
     #[test]
-    fn reg_mandel() {
-        let mut vm = reg::Vm::new();
-        test_mandel(|x, y, n| {
-            vm.run(reg::MANDEL, &[x, y, n])
-        });
+    fn memory_programs() {
+        use value::Tagged;
+
+        let memory: Vec<f64> = (0..20).map(|i| (i * i % 11) as f64 - 4.5).collect();
+
+        let reg_programs = [
+            reg::verify(reg::DOT).unwrap(),
+            reg::verify(&translate::stack_to_reg(stack::DOT, 1).unwrap()).unwrap(),
+        ];
+        let stack_programs = [
+            stack::verify(stack::DOT, 1).unwrap(),
+            stack::verify(&translate::reg_to_stack(reg::DOT, 1, translate::Strategy::Naive).unwrap(), 1).unwrap(),
+            stack::verify(&translate::reg_to_stack(reg::DOT, 1, translate::Strategy::Smart).unwrap(), 1).unwrap(),
+        ];
+        let (mut rvm, mut svm) = (reg::Vm::new(), stack::Vm::new());
+        rvm.memory_mut().extend_from_slice(&memory);
+        svm.memory_mut().extend_from_slice(&memory);
+        for n in [0, 1, 5, 10] {
+            let sum: f64 = memory[..n].iter().sum();
+            let expected = dot(&memory[..n], &memory[n..2*n]);
+            let n = n as f64;
+            assert_eq!(rvm.try_run(reg::ARRAY_SUM, &[n]), Ok(sum));
+            assert_eq!(svm.try_run(stack::ARRAY_SUM, &[n]), Ok(sum));
+            assert_eq!(rvm.try_run(reg::DOT, &[n]), Ok(expected));
+            assert_eq!(svm.try_run(stack::DOT, &[n]), Ok(expected));
+            for program in &reg_programs {
+                assert_eq!(rvm.run(program, &[n]), expected);
+            }
+            for program in &stack_programs {
+                assert_eq!(svm.run(program, &[n]), expected);
+            }
+        }
+        assert!(!reg_programs[0].compact());
+
+        // addresses are checked in every mode.
+        let (reg_sum, stack_dot) = (reg::verify(reg::ARRAY_SUM).unwrap(), stack::verify(stack::DOT, 1).unwrap());
+        assert_eq!(rvm.try_run(reg::ARRAY_SUM, &[21.0]), Err(VmError::BadAddress { pc: 5 }));
+        assert_eq!(rvm.run_with_fuel(&reg_sum, &[21.0], 1000), Err(VmError::BadAddress { pc: 5 }));
+        assert_eq!(svm.try_run(stack::DOT, &[11.0]), Err(VmError::BadAddress { pc: 10 }));
+        assert_eq!(svm.run_with_fuel(&stack_dot, &[11.0], 1000), Err(VmError::BadAddress { pc: 10 }));
+
+        let code = asm::assemble_reg("
+            store_mem r0, r1
+            load_mem  r2, r1
+            return    r2
+        ").unwrap();
+        assert_eq!(rvm.try_run(&code, &[7.0, 19.0]), Ok(7.0));
+        assert_eq!(rvm.memory()[19], 7.0);
+        assert_eq!(rvm.memory().len(), 20);
+        for address in [20.0, -1.0, 0.5, f64::NAN] {
+            assert_eq!(rvm.try_run(&code, &[7.0, address]), Err(VmError::BadAddress { pc: 0 }));
+        }
+
+        let code = asm::assemble_stack("
+            load      s0
+            load      s1
+            store_mem
+            load      s0
+            load_mem
+            return
+        ").unwrap();
+        assert_eq!(svm.try_run(&code, &[3.0, -2.0]), Ok(-2.0));
+        assert_eq!(svm.memory()[3], -2.0);
+
+        // typed addresses are ints, or floats without a fraction.
+        let mut tvm = reg::Vm::<Tagged>::default();
+        tvm.memory_mut().extend((0..4).map(Tagged::Int));
+        assert_eq!(tvm.try_run(reg::ARRAY_SUM, &[Tagged::Int(4)]), Ok(Tagged::Int(6)));
+        assert_eq!(tvm.try_run(reg::ARRAY_SUM, &[Tagged::Float(3.0)]), Ok(Tagged::Int(3)));
+        let code = asm::assemble_reg("load_mem r1, r0\nreturn r1").unwrap();
+        assert_eq!(tvm.try_run(&code, &[Tagged::Float(2.0)]), Ok(Tagged::Int(2)));
+        assert_eq!(tvm.try_run(&code, &[Tagged::Int(-1)]), Err(VmError::BadAddress { pc: 0 }));
+        assert_eq!(tvm.try_run(&code, &[Tagged::Bool(true)]), Err(VmError::TypeError { pc: 0 }));
+
+        let config = bench::Config { warmup: 0, samples: 1 };
+        assert_eq!(bench::run_all(config, "dot/").len(), 6);
     }
 
     #[test]
-    fn stack_fib_smart() {
-        let mut vm = stack::Vm::new();
-        test_fib(|n| {
-            vm.run(stack::FIB_SMART, &[n])
-        });
+    fn bytecode_round_trip() {
+        use bytecode::Program;
+
+        let every_op = asm::assemble_reg("
+            load_int  r3, -300
+            copy      r4, r3
+            add       r4, r4, r0
+            sub       r4, r4, r0
+            mul       r4, r4, r0
+            div       r4, r4, r0
+            neg       r4, r4
+            lt        r5, r4, r0
+            le        r5, r4, r0
+            eq        r5, r4, r0
+            sqrt      r4, r0
+            abs       r4, r4
+            floor     r4, r4
+            min       r4, r4, r0
+            max       r4, r4, r0
+            jump_if   19, r5
+            jump_if_not 19, r5
+            set_counter r0
+            get_counter r4
+            loop      19
+            loop_le   20, r4, r0
+            store_mem r4, r1
+            load_mem  r4, r1
+            call      25, r6
+            return    r4
+            ret       r0
+        ").unwrap();
+
+        let mut programs = vec![Program::Reg { code: every_op, argc: 2, constants: vec![] }];
+        for code in [reg::FIB, reg::MANDEL, reg::ADD_CHAIN, reg::FIB_RECURSIVE, reg::COLLATZ, reg::DOT] {
+            programs.push(Program::Reg { code: code.into(), argc: 1, constants: vec![] });
+        }
+        for code in [stack::FIB_SMART, stack::FIB_RECURSIVE, stack::COLLATZ, stack::DOT] {
+            programs.push(Program::Stack { code: code.into(), argc: 1, constants: vec![] });
+        }
+        for code in [stack::MANDEL_SMART, stack::MANDEL_NAIVE, stack::MANDEL_SMART_NOPS_SAME, stack::MANDEL_SMART_NO_DUP] {
+            programs.push(Program::Stack { code: code.into(), argc: 3, constants: vec![] });
+        }
+
+        for program in &programs {
+            let bytes = bytecode::write(program).unwrap();
+            assert_eq!(bytecode::read(&bytes).as_ref(), Ok(program));
+        }
+
+        let bytes = bytecode::write(&Program::Reg { code: reg::FIB.into(), argc: 1, constants: vec![] }).unwrap();
+        assert_eq!(bytes[..16], [b'S', b'V', b'R', b'B', 3, 0, 0, 0, 1, 0, 4, 0, 9, 0, 0, 0]);
+        assert_eq!(bytes[16..18], [18, 0]);
+        let bytes = bytecode::write(&Program::Stack { code: stack::FIB_SMART.into(), argc: 1, constants: vec![] }).unwrap();
+        assert_eq!(bytes[6..12], [1, 0, 1, 0, 3, 0]);
     }
 
     #[test]
-    fn stack_fib_naive() {
-        let mut vm = stack::Vm::new();
-        test_fib(|n| {
-            vm.run(stack::FIB_NAIVE, &[n])
-        });
+    fn bytecode_errors() {
+        use bytecode::{Program, Error::*};
+
+        let fib = bytecode::write(&Program::Reg { code: reg::FIB.into(), argc: 1, constants: vec![] }).unwrap();
+        let with = |at: usize, byte: u8| {
+            let mut bytes = fib.clone();
+            bytes[at] = byte;
+            bytecode::read(&bytes)
+        };
+        assert_eq!(bytecode::read(&[]), Err(Truncated));
+        assert_eq!(bytecode::read(&fib[..fib.len() - 1]), Err(Truncated));
+        assert_eq!(bytecode::read(b"\x7fELF............"), Err(BadMagic));
+        assert_eq!(with(4, 4), Err(UnsupportedVersion { version: 4 }));
+        assert_eq!(with(4, 0), Err(UnsupportedVersion { version: 0 }));
+        assert_eq!(with(6, 2), Err(UnknownIsa { isa: 2 }));
+        assert_eq!(with(7, 1), Err(BadHeader));
+        assert_eq!(with(12, 200), Err(Truncated));
+        assert_eq!(with(16, 99), Err(UnknownOpcode { offset: 16, opcode: 99 }));
+        assert_eq!(with(10, 3), Err(FrameMismatch { header: 3, code: 4 }));
+        assert_eq!(with(9, 1), Err(TooManyArgs { count: 257 }));
+        // `loop 4` -> `loop 40`, before the empty constant pool.
+        assert_eq!(with(fib.len() - 7, 40), Err(Verify(VerifyError::TargetOutOfRange { pc: 7, target: 40 })));
+
+        let mut bytes = fib.clone();
+        bytes.push(0);
+        assert_eq!(bytecode::read(&bytes), Err(TrailingBytes { offset: fib.len() }));
+
+        let bad = Program::Stack { code: vec![stack::Instruction::Add], argc: 1, constants: vec![] };
+        assert_eq!(bytecode::write(&bad), Err(Verify(VerifyError::StackUnderflow { pc: 0, depth: 1 })));
+        assert_eq!(bytecode::write(&Program::Reg { code: reg::FIB.into(), argc: 300, constants: vec![] }), Err(TooManyArgs { count: 300 }));
+        assert_eq!(with(0, b'X').unwrap_err().to_string(), "not a bytecode file");
     }
 
     #[test]
-    fn stack_mandel_smart() {
-        let mut vm = stack::Vm::new();
-        test_mandel(|x, y, n| {
-            vm.run(stack::MANDEL_SMART, &[x, y, n])
-        });
+    fn bytecode_cli() {
+        let dir = std::env::temp_dir().join(format!("stack_vs_reg_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let cli = |args: &[&str]| bytecode::main(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+
+        std::fs::write(path("fib.asm"), disasm::disassemble_reg(reg::FIB)).unwrap();
+        std::fs::write(path("collatz.lang"), "fn collatz(n) { let x = n; while x > 1 { x = x - 1; } return x; }").unwrap();
+
+        assert_eq!(cli(&["build", "--argc", "1", "reg", &path("fib.asm"), &path("fib.svrb")]), Ok(()));
+        assert_eq!(cli(&["build", "stack", &path("collatz.lang"), &path("collatz.svrb")]), Ok(()));
+        let fib = bytecode::read(&std::fs::read(path("fib.svrb")).unwrap()).unwrap();
+        assert_eq!(fib, bytecode::Program::Reg { code: reg::FIB.into(), argc: 1, constants: vec![] });
+        assert_eq!(bytecode::read(&std::fs::read(path("collatz.svrb")).unwrap()).unwrap().argc(), 1);
+
+        assert_eq!(cli(&["run", &path("fib.svrb"), "10"]), Ok(()));
+        assert_eq!(cli(&["dis", &path("collatz.svrb")]), Ok(()));
+        assert!(cli(&["run", &path("fib.svrb")]).unwrap_err().ends_with("expects 1 argument(s), found 0"));
+        assert_eq!(cli(&["build", "reg", &path("fib.asm"), &path("x")]), Err(format!("{}: asm sources need `--argc`", path("fib.asm"))));
+        assert!(cli(&["build", "--argc", "1", "regs", &path("fib.asm"), &path("x")]).is_err());
+        assert!(cli(&["dis", &path("fib.asm")]).unwrap_err().ends_with("not a bytecode file"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn stack_mandel_naive() {
-        let mut vm = stack::Vm::new();
-        test_mandel(|x, y, n| {
-            vm.run(stack::MANDEL_NAIVE, &[x, y, n])
-        });
+    fn wide_programs() {
+        let source = long_source(|i| (i % 7).to_string());
+        let round: f64 = (0..200).map(|i| (i % 7) as f64).sum();
+
+        let reg_code = lang::compile_reg(&source).unwrap();
+        let stack_code = lang::compile_stack(&source).unwrap();
+        assert!(reg_code.len() > 256 && reg_code.contains(&reg::Instruction::Wide { high: [1, 0, 0] }));
+        assert!(stack_code.len() > 256 && stack_code.contains(&stack::Instruction::Wide { high: 3 }));
+
+        let reg_program = reg::verify(&reg_code).unwrap();
+        let stack_program = stack::verify(&stack_code, 1).unwrap();
+        assert!(!reg_program.compact());
+        for n in [0.0, 1.0, 3.0] {
+            assert_eq!(reg::Vm::new().run(&reg_program, &[n]), n * round);
+            assert_eq!(reg::Vm::new().try_run(&reg_code, &[n]), Ok(n * round));
+            assert_eq!(stack::Vm::new().run(&stack_program, &[n]), n * round);
+            assert_eq!(stack::Vm::new().try_run(&stack_code, &[n]), Ok(n * round));
+        }
+
+        assert_eq!(asm::assemble_reg(&disasm::disassemble_reg(&reg_code)).unwrap(), reg_code);
+        assert_eq!(asm::assemble_stack(&disasm::disassemble_stack(&stack_code, 1)).unwrap(), stack_code);
+
+        let code = asm::assemble_reg("
+            load_int  r300, 7
+            mul       r299, r0, r300
+            return    r299
+        ").unwrap();
+        assert_eq!(code, [
+            reg::Instruction::Wide { high: [1, 0, 0] },
+            reg::Instruction::LoadInt { dst: 44, value: 7 },
+            reg::Instruction::Wide { high: [1, 0, 1] },
+            reg::Instruction::Mul { dst: 43, src1: 0, src2: 44 },
+            reg::Instruction::Wide { high: [1, 0, 0] },
+            reg::Instruction::Return { src: 43 },
+        ]);
+        let program = reg::verify(&code).unwrap();
+        assert_eq!(program.registers(), 301);
+        assert_eq!(reg::Vm::new().run(&program, &[3.0]), 21.0);
+        assert_eq!(reg::Vm::new().try_run(&code, &[3.0]), Ok(21.0));
+        assert_eq!(disasm::disassemble_reg(&code).lines().next(), Some("        load_int    r300, 7             //   0"));
+
+        let code = asm::assemble_stack("load_int 1000\n return").unwrap();
+        assert_eq!(code, [stack::Instruction::Wide { high: 3 }, stack::Instruction::LoadInt { value: -24 }, stack::Instruction::Return]);
+        let program = stack::verify(&code, 0).unwrap();
+        assert_eq!(stack::Vm::new().run(&program, &[]), 1000.0);
+        assert_eq!(stack::Vm::new().try_run(&code, &[]), Ok(1000.0));
     }
 
     #[test]
-    fn stack_mandel_smart_nops_slow() {
-        let mut vm = stack::Vm::new();
-        test_mandel(|x, y, n| {
-            vm.run(stack::MANDEL_SMART_NOPS_SLOW, &[x, y, n])
-        });
+    fn wide_errors() {
+        use reg::Instruction::*;
+        use stack::Instruction as S;
+
+        let bad = |code: &[reg::Instruction]| (reg::verify(code).err(), reg::Vm::new().try_run(code, &[]).err());
+        assert_eq!(bad(&[Wide { high: [0; 3] }, Wide { high: [0; 3] }, Return { src: 0 }]), (Some(VerifyError::BadWide { pc: 0 }), Some(VmError::BadWide { pc: 0 })));
+        assert_eq!(bad(&[Wide { high: [0, 1, 0] }, Return { src: 0 }]), (Some(VerifyError::BadWide { pc: 0 }), Some(VmError::BadWide { pc: 0 })));
+        assert_eq!(bad(&[Wide { high: [0; 3] }]).0, Some(VerifyError::BadWide { pc: 0 }));
+        // jumps into its own prefix.
+        assert_eq!(reg::verify(&[Wide { high: [0; 3] }, Jump { target: 1 }]).unwrap_err(), VerifyError::BadWide { pc: 0 });
+
+        assert_eq!(stack::verify(&[S::Wide { high: 0 }, S::Load { src: 0 }, S::Return], 1).unwrap_err(), VerifyError::BadWide { pc: 0 });
+        assert_eq!(stack::verify(&[S::Wide { high: 0 }, S::Wide { high: 0 }, S::LoadInt { value: 0 }, S::Return], 0).unwrap_err(), VerifyError::BadWide { pc: 0 });
+        assert_eq!(stack::Vm::new().try_run(&[S::Wide { high: 0 }, S::Add], &[]), Err(VmError::BadWide { pc: 0 }));
+
+        let err = asm::assemble_reg("copy r0, r70000").unwrap_err();
+        assert_eq!(err.kind, asm::ErrorKind::OutOfRange("r70000".into()));
     }
 
     #[test]
-    fn stack_mandel_smart_nops_same() {
-        let mut vm = stack::Vm::new();
-        test_mandel(|x, y, n| {
-            vm.run(stack::MANDEL_SMART_NOPS_SAME, &[x, y, n])
-        });
+    fn constants() {
+        use bytecode::Program;
+
+        let source = "fn f(x) { return x * 0.5 + 100000 - 0.5; }";
+        let expected = |x: f64| x * 0.5 + 100000.0 - 0.5;
+        let (reg_code, reg_constants) = lang::compile_reg_with_constants(source).unwrap();
+        let (stack_code, stack_constants) = lang::compile_stack_with_constants(source).unwrap();
+        assert_eq!(reg_constants, [0.5, 100000.0]);
+        assert_eq!(stack_constants, [0.5, 100000.0]);
+        assert_eq!(lang::compile_reg_with_constants("fn f() { return 1; }").unwrap().1, []);
+
+        let reg_program = reg::verify(&reg_code).unwrap();
+        let stack_program = stack::verify(&stack_code, 1).unwrap();
+        assert!(!reg_program.compact());
+        let (mut rvm, mut svm) = (reg::Vm::new(), stack::Vm::new());
+        rvm.constants_mut().extend(&reg_constants);
+        svm.constants_mut().extend(&stack_constants);
+        for x in [0.0, 3.0, -7.25] {
+            assert_eq!(rvm.run(&reg_program, &[x]), expected(x));
+            assert_eq!(rvm.try_run(&reg_code, &[x]), Ok(expected(x)));
+            assert_eq!(svm.run(&stack_program, &[x]), expected(x));
+            assert_eq!(svm.try_run(&stack_code, &[x]), Ok(expected(x)));
+        }
+
+        let (mut rvm, mut svm) = (reg::Vm::new(), stack::Vm::new());
+        assert_eq!(rvm.try_run(&reg_code, &[1.0]), Err(VmError::BadConstant { pc: 0 }));
+        assert_eq!(rvm.run_with_fuel(&reg_program, &[1.0], 100), Err(VmError::BadConstant { pc: 0 }));
+        assert_eq!(svm.try_run(&stack_code, &[1.0]), Err(VmError::BadConstant { pc: 1 }));
+        assert_eq!(svm.run_with_fuel(&stack_program, &[1.0], 100), Err(VmError::BadConstant { pc: 1 }));
+
+        let program = Program::Stack { code: stack_code, argc: 1, constants: stack_constants };
+        let bytes = bytecode::write(&program).unwrap();
+        assert_eq!(bytes[bytes.len() - 20 ..], [2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xe0, 0x3f, 0, 0, 0, 0, 0, 0x6a, 0xf8, 0x40]);
+        assert_eq!(bytecode::read(&bytes), Ok(program));
+
+        // version 1 files end after the code.
+        let mut fib = bytecode::write(&Program::Reg { code: reg::FIB.into(), argc: 1, constants: vec![] }).unwrap();
+        fib.truncate(fib.len() - 4);
+        fib[4] = 1;
+        assert_eq!(bytecode::read(&fib), Ok(Program::Reg { code: reg::FIB.into(), argc: 1, constants: vec![] }));
+
+        // opcodes newer than the file's version.
+        let square = Program::Stack { code: vec![stack::Instruction::Load { src: 0 }, stack::Instruction::Square, stack::Instruction::Return], argc: 1, constants: vec![] };
+        let mut bytes = bytecode::write(&square).unwrap();
+        bytes[4] = 2;
+        assert_eq!(bytecode::read(&bytes), Ok(square));
+        bytes.truncate(bytes.len() - 4);
+        bytes[4] = 1;
+        assert_eq!(bytecode::read(&bytes), Err(bytecode::Error::UnsupportedVersion { version: 1 }));
+        let mut bytes = bytecode::write(&Program::Stack { code: stack::FIB_RECURSIVE.into(), argc: 1, constants: vec![] }).unwrap();
+        bytes[4] = 2;
+        assert_eq!(bytecode::read(&bytes), Err(bytecode::Error::UnsupportedVersion { version: 2 }));
+        let mut bytes = bytecode::write(&Program::Reg { code: reg_code, argc: 1, constants: reg_constants }).unwrap();
+        bytes[4] = 1;
+        assert_eq!(bytecode::read(&bytes), Err(bytecode::Error::UnsupportedVersion { version: 1 }));
     }
 
     #[test]
-    fn stack_mandel_smart_no_dup() {
+    fn packed_programs() {
+        let program = packed::encode_reg(&reg::verify(reg::FIB).unwrap()).unwrap();
+        // `Jump { target: 7 }` is 3 bytes, with the offset of `Loop`.
+        assert_eq!(program.code()[..13], [18, 0, 0, 1, 0, 0, 0, 2, 1, 0, 15, 23, 0]);
+        assert_eq!(program.code().len(), 28);
+        assert_eq!((program.pc(10), program.pc(23), program.pc(28)), (3, 7, 9));
+
+        let reg_run = |code: &[reg::Instruction], args: &[f64]| {
+            let program = reg::verify(code).unwrap();
+            let packed = packed::encode_reg(&program).unwrap();
+            assert_eq!(reg::Vm::new().run_packed(&packed, args), reg::Vm::new().run(&program, args));
+        };
+        let stack_run = |code: &[stack::Instruction], args: &[f64]| {
+            let program = stack::verify(code, args.len()).unwrap();
+            let packed = packed::encode_stack(&program).unwrap();
+            assert_eq!(stack::Vm::new().run_packed(&packed, args), stack::Vm::new().run(&program, args));
+        };
+        for n in [0.0, 1.0, 10.0] {
+            for code in [reg::FIB, reg::FIB_RECURSIVE, reg::COLLATZ] {
+                reg_run(code, &[n]);
+            }
+            for code in [stack::FIB_SMART, stack::FIB_NAIVE, stack::FIB_RECURSIVE, stack::COLLATZ] {
+                stack_run(code, &[n]);
+            }
+        }
+        for args in [[-0.5, 0.5, 50.0], [0.3, 0.6, 100.0]] {
+            reg_run(reg::MANDEL, &args);
+            for code in [stack::MANDEL_SMART, stack::MANDEL_NAIVE, stack::MANDEL_SMART_NOPS_SLOW] {
+                stack_run(code, &args);
+            }
+        }
+
+        let memory: Vec<f64> = (0..20).map(|i| i as f64 * 0.5).collect();
+        let (mut rvm, mut svm) = (reg::Vm::new(), stack::Vm::new());
+        rvm.memory_mut().extend_from_slice(&memory);
+        svm.memory_mut().extend_from_slice(&memory);
+        let reg_dot = packed::encode_reg(&reg::verify(reg::DOT).unwrap()).unwrap();
+        let stack_dot = packed::encode_stack(&stack::verify(stack::DOT, 1).unwrap()).unwrap();
+        assert_eq!(rvm.run_packed(&reg_dot, &[10.0]), dot(&memory[..10], &memory[10..]));
+        assert_eq!(svm.run_packed(&stack_dot, &[10.0]), dot(&memory[..10], &memory[10..]));
+
+        // targets past 255 don't need a prefix.
+        let source = long_source(|_| "0.5".into());
+        let (code, constants) = lang::compile_reg_with_constants(&source).unwrap();
+        let program = packed::encode_reg(&reg::verify(&code).unwrap()).unwrap();
+        rvm.constants_mut().extend(constants);
+        assert_eq!(rvm.run_packed(&program, &[3.0]), 300.0);
+        let (code, constants) = lang::compile_stack_with_constants(&source).unwrap();
+        let program = packed::encode_stack(&stack::verify(&code, 1).unwrap()).unwrap();
+        svm.constants_mut().extend(constants);
+        assert_eq!(svm.run_packed(&program, &[3.0]), 300.0);
+
+        let code = asm::assemble_reg("load_int r300, 7\n return r300").unwrap();
+        assert_eq!(packed::encode_reg(&reg::verify(&code).unwrap()).unwrap_err(), packed::Error::Unsupported { pc: 0 });
+    }
+
+    #[test]
+    fn dispatch_programs() {
+        use dispatch::{Dispatch, Match, Table, Closures, TailCalls};
+        use value::{Value, Tagged};
+
+        // every dispatch agrees with `run`.
+        fn agree<V: Value + 'static, D: Dispatch>() {
+            let (mut rvm, mut svm) = (reg::Vm::<V>::default(), stack::Vm::<V>::default());
+            let memory: Vec<V> = (0..20).map(|i| V::from_f64(i as f64 * 0.5)).collect();
+            rvm.memory_mut().extend_from_slice(&memory);
+            svm.memory_mut().extend_from_slice(&memory);
+
+            let source = long_source(|_| "0.5".into());
+            let (wide_reg, constants) = lang::compile_reg_with_constants(&source).unwrap();
+            rvm.constants_mut().extend(constants.iter().map(|x| V::from_f64(*x)));
+            let (wide_stack, constants) = lang::compile_stack_with_constants(&source).unwrap();
+            svm.constants_mut().extend(constants.iter().map(|x| V::from_f64(*x)));
+
+            let mut reg_run = |code: &[reg::Instruction], args: &[f64]| {
+                let args: Vec<V> = args.iter().map(|x| V::from_f64(*x)).collect();
+                let program = reg::verify(code).unwrap();
+                let expected = rvm.run(&program, &args);
+                assert_eq!(rvm.run_with(&reg::Dispatched::<V, D>::new(&program), &args), expected, "{}", D::NAME);
+            };
+            for n in [0.0, 1.0, 10.0] {
+                for code in [reg::FIB, reg::FIB_RECURSIVE, reg::COLLATZ, reg::DOT] {
+                    reg_run(code, &[n]);
+                }
+            }
+            reg_run(reg::MANDEL, &[0.239, -0.981, 100.0]);
+            reg_run(reg::ADD_CHAIN, &[1.0; 16]);
+            reg_run(&wide_reg, &[3.0]);
+
+            let mut stack_run = |code: &[stack::Instruction], args: &[f64]| {
+                let args: Vec<V> = args.iter().map(|x| V::from_f64(*x)).collect();
+                let program = stack::verify(code, args.len()).unwrap();
+                let expected = svm.run(&program, &args);
+                assert_eq!(svm.run_with(&stack::Dispatched::<V, D>::new(&program), &args), expected, "{}", D::NAME);
+            };
+            for n in [0.0, 1.0, 10.0] {
+                for code in [stack::FIB_SMART, stack::FIB_NAIVE, stack::FIB_RECURSIVE, stack::COLLATZ, stack::DOT] {
+                    stack_run(code, &[n]);
+                }
+            }
+            for code in [stack::MANDEL_SMART, stack::MANDEL_NAIVE, stack::MANDEL_SMART_NOPS_SLOW, stack::MANDEL_SMART_NO_DUP] {
+                stack_run(code, &[0.239, -0.981, 100.0]);
+            }
+            stack_run(&wide_stack, &[3.0]);
+        }
+        agree::<f64, Match>();
+        agree::<f64, Table>();
+        agree::<f64, Closures>();
+        agree::<f64, TailCalls>();
+        agree::<Tagged, Table>();
+        agree::<Tagged, TailCalls>();
+
+        // runs longer than a chain.
+        let program = reg::Dispatched::<f64, TailCalls>::new(&reg::verify(reg::FIB).unwrap());
+        let mut vm = reg::Vm::new();
+        test_fib(|n| vm.run_with(&program, &[n]));
+        let program = stack::Dispatched::<f64, Closures>::new(&stack::verify(stack::MANDEL_SMART, 3).unwrap());
         let mut vm = stack::Vm::new();
-        test_mandel(|x, y, n| {
-            vm.run(stack::MANDEL_SMART_NO_DUP, &[x, y, n])
-        });
+        test_mandel(|x, y, n| vm.run_with(&program, &[x, y, n]));
+
+        // and fails like it.
+        fn message(f: impl FnOnce()) -> String {
+            let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_err();
+            panic.downcast_ref::<String>().cloned().unwrap()
+        }
+        fn fail<D: Dispatch>() {
+            let code = asm::assemble_reg("lt r1, r0, r0 \n add r2, r1, r0 \n return r2").unwrap();
+            let program = reg::Dispatched::<Tagged, D>::new(&reg::verify(&code).unwrap());
+            assert_eq!(message(|| { reg::Vm::<Tagged>::default().run_with(&program, &[Tagged::Float(1.0)]); }), "TypeError { pc: 1 }");
+
+            let code = asm::assemble_stack("load 0 \n load 0 \n lt \n sqrt \n return").unwrap();
+            let program = stack::Dispatched::<Tagged, D>::new(&stack::verify(&code, 1).unwrap());
+            assert_eq!(message(|| { stack::Vm::<Tagged>::default().run_with(&program, &[Tagged::Int(2)]); }), "TypeError { pc: 3 }");
+        }
+        fail::<Match>();
+        fail::<Table>();
+        fail::<Closures>();
+        fail::<TailCalls>();
+
+        let config = bench::Config { warmup: 0, samples: 1 };
+        let measurements = bench::run_benchmarks(bench::dispatch_benchmarks(), config, "reg::FIB/");
+        let names: Vec<&str> = measurements.iter().map(|m| m.name).collect();
+        assert_eq!(names, ["match", "table", "closures", "tail calls"]);
+        let table = bench::dispatch_table(&measurements);
+        assert_eq!(table.lines().next().unwrap(), "program                              match             table          closures        tail calls");
+        assert!(table.lines().nth(1).unwrap().starts_with("reg::FIB  "));
     }
 
     #[test]
-    fn reg_add_chain() {
-        let add_regs: [f64; 16] = core::array::from_fn(|i| i as f64);
+    fn fused_programs() {
+        use value::{Value, Tagged};
+
+        // fused with the profile of `args`, same results.
+        fn reg_fused(code: &[reg::Instruction], args: &[f64], memory: &[f64]) -> fuse::Stats {
+            let mut vm = reg::Vm::new();
+            vm.memory_mut().extend_from_slice(memory);
+            let mut profile = profile::Profile::new();
+            let expected = vm.run_profiled(&reg::verify(code).unwrap(), args, &mut profile);
+            let (fused, stats) = fuse::reg(code, &profile);
+            assert_eq!(fused.len(), code.len() - stats.total());
+            assert_eq!(vm.run(&reg::verify(&fused).unwrap(), args), expected);
+
+            let mut tagged = reg::Vm::<Tagged>::default();
+            tagged.memory_mut().extend(memory.iter().map(|x| Tagged::from_f64(*x)));
+            let args: Vec<Tagged> = args.iter().map(|x| Tagged::from_f64(*x)).collect();
+            let expected = tagged.run(&reg::verify(code).unwrap(), &args);
+            assert_eq!(tagged.run(&reg::verify(&fused).unwrap(), &args), expected);
+            stats
+        }
+        fn stack_fused(code: &[stack::Instruction], args: &[f64], memory: &[f64]) -> fuse::Stats {
+            let mut vm = stack::Vm::new();
+            vm.memory_mut().extend_from_slice(memory);
+            let mut profile = profile::Profile::new();
+            let expected = vm.run_profiled(&stack::verify(code, args.len()).unwrap(), args, &mut profile);
+            let (fused, stats) = fuse::stack(code, &profile);
+            assert!(fused.len() < code.len() || stats.total() == 0);
+            assert_eq!(vm.run(&stack::verify(&fused, args.len()).unwrap(), args), expected);
+
+            let mut tagged = stack::Vm::<Tagged>::default();
+            tagged.memory_mut().extend(memory.iter().map(|x| Tagged::from_f64(*x)));
+            let args: Vec<Tagged> = args.iter().map(|x| Tagged::from_f64(*x)).collect();
+            let expected = tagged.run(&stack::verify(code, args.len()).unwrap(), &args);
+            assert_eq!(tagged.run(&stack::verify(&fused, args.len()).unwrap(), &args), expected);
+            stats
+        }
+
+        let memory: Vec<f64> = (0..20).map(|i| i as f64 * 0.5).collect();
+        let mandel_args = [0.239, -0.981, 100.0];
+
+        let stats = reg_fused(reg::MANDEL, &mandel_args, &[]);
+        assert_eq!((stats.get("mul_add"), stats.get("mul_sub")), (1, 1));
+        assert_eq!(reg_fused(reg::COLLATZ, &[27.0], &[]).get("jump_if_lt"), 1);
+        assert_eq!(reg_fused(reg::DOT, &[10.0], &memory).get("mul_add"), 1);
+        // calls.
+        assert_eq!(reg_fused(reg::FIB_RECURSIVE, &[10.0], &[]).total(), 0);
+        reg_fused(reg::FIB, &[10.0], &[]);
+
+        assert_eq!(stack_fused(stack::MANDEL_NAIVE, &mandel_args, &[]).get("mul_slots"), 5);
+        assert_eq!(stack_fused(stack::MANDEL_SMART, &mandel_args, &[]).get("square"), 4);
+        assert_eq!(stack_fused(stack::DOT, &[10.0], &memory).get("add_int"), 2);
+        stack_fused(stack::COLLATZ, &[27.0], &[]);
+        stack_fused(stack::FIB_RECURSIVE, &[10.0], &[]);
+        for code in [stack::FIB_SMART, stack::FIB_NAIVE] {
+            stack_fused(code, &[10.0], &[]);
+        }
+
         let mut vm = reg::Vm::new();
-        assert_eq!(vm.run(&reg::ADD_CHAIN, &add_regs), (16*15/2) as f64);
+        let mut profile = profile::Profile::new();
+        let program = reg::verify(reg::MANDEL).unwrap();
+        vm.run_profiled(&program, &mandel_args, &mut profile);
+        let fused = reg::verify(&fuse::reg(reg::MANDEL, &profile).0).unwrap();
+        test_mandel(|x, y, n| vm.run(&fused, &[x, y, n]));
+
+        // what the profile says to fuse.
+        let mut profile = profile::Profile::new();
+        stack::Vm::new().run_profiled(&stack::verify(stack::MANDEL_NAIVE, 3).unwrap(), &mandel_args, &mut profile);
+        let pairs = fuse::pairs_stack(stack::MANDEL_NAIVE, &profile);
+        assert_eq!((pairs[0].first, pairs[0].second), ("load", "load"));
+        assert!(pairs.windows(2).all(|w| w[0].count >= w[1].count));
+        assert!(pairs.iter().any(|pair| (pair.first, pair.second) == ("load", "mul")));
+
+        // cold code stays, and so do temporaries that are read later.
+        let code = asm::assemble_reg("
+            mul r3, r0, r1
+            add r2, r2, r3
+            lt r4, r0, r1
+            jump_if L6, r4
+            sub r2, r2, r3
+            return r2
+        L6: return r4").unwrap();
+        let everywhere = profile::Profile { runs: 1, executed: vec![1; code.len()], taken: vec![0; code.len()] };
+        assert_eq!(fuse::reg(&code, &profile::Profile::new()).1.total(), 0);
+        assert_eq!(fuse::reg(&code, &everywhere).1.total(), 0);
+        let code = asm::assemble_reg("
+            mul r3, r0, r1
+            add r2, r2, r3
+            lt r4, r0, r1
+            jump_if L6, r4
+            mul r3, r0, r0
+            sub r2, r2, r3
+        L6: return r2").unwrap();
+        let everywhere = profile::Profile { runs: 1, executed: vec![1; code.len()], taken: vec![0; code.len()] };
+        let (fused, stats) = fuse::reg(&code, &everywhere);
+        assert_eq!(stats.total(), 3);
+        assert_eq!(fused, asm::assemble_reg("
+            mul_add r2, r0, r1
+            jump_if_lt L3, r0, r1
+            mul_sub r2, r0, r0
+        L3: return r2").unwrap());
+        for args in [[1.0, 2.0, 3.0], [2.0, 1.0, 3.0]] {
+            assert_eq!(vm.run(&reg::verify(&fused).unwrap(), &args), vm.run(&reg::verify(&code).unwrap(), &args));
+        }
+
+        let code = asm::assemble_stack("
+            load 0
+            load_int 3
+            sub
+            load_int 0
+            sub
+            dup
+            mul
+            return").unwrap();
+        let everywhere = profile::Profile { runs: 1, executed: vec![1; code.len()], taken: vec![0; code.len()] };
+        let (fused, stats) = fuse::stack(&code, &everywhere);
+        assert_eq!((stats.get("add_int"), stats.get("square")), (1, 1));
+        assert_eq!(fused, asm::assemble_stack("load 0 \n add_int -3 \n load_int 0 \n sub \n square \n return").unwrap());
+        assert_eq!(stack::Vm::new().run(&stack::verify(&fused, 1).unwrap(), &[5.0]), 4.0);
+
+        // the superinstructions are instructions like any other,
+        let reg_code = asm::assemble_reg("
+        L0: mul_add r2, r0, r1
+            mul_sub r2, r0, r0
+            jump_if_lt L0, r2, r1
+            jump_if_le L0, r2, r0
+            return r2").unwrap();
+        let stack_code = asm::assemble_stack("
+            mul_slots s0, s0
+            add_int -1
+            square
+            return").unwrap();
+        assert_eq!(asm::assemble_reg(&disasm::disassemble_reg(&reg_code)).unwrap(), reg_code);
+        assert_eq!(asm::assemble_stack(&disasm::disassemble_stack(&stack_code, 1)).unwrap(), stack_code);
+        for program in [
+            bytecode::Program::Reg   { code: reg_code.clone(),   argc: 2, constants: vec![] },
+            bytecode::Program::Stack { code: stack_code.clone(), argc: 1, constants: vec![] },
+        ] {
+            assert_eq!(bytecode::read(&bytecode::write(&program).unwrap()), Ok(program));
+        }
+        assert_eq!(stack::Vm::new().run(&stack::verify(&stack_code, 1).unwrap(), &[3.0]), 64.0);
+        assert_eq!(stack::Vm::<Tagged>::default().run(&stack::verify(&stack_code, 1).unwrap(), &[Tagged::Int(3)]), Tagged::Int(64));
+
+        // `mul_slots` only has four bits per slot.
+        let err = asm::assemble_stack("mul_slots s3, s16").unwrap_err();
+        assert_eq!(err.kind, asm::ErrorKind::OutOfRange("s16".into()));
+        let code = asm::assemble_stack("load s16 \n load s0 \n mul \n return").unwrap();
+        let everywhere = profile::Profile { runs: 1, executed: vec![1; code.len()], taken: vec![0; code.len()] };
+        assert_eq!(fuse::stack(&code, &everywhere).0, code);
+
+        // except that the packed loops don't have them.
+        assert_eq!(packed::encode_reg(&reg::verify(&reg_code).unwrap()).unwrap_err(), packed::Error::Unsupported { pc: 0 });
+        assert_eq!(packed::encode_stack(&stack::verify(&stack_code, 1).unwrap()).unwrap_err(), packed::Error::Unsupported { pc: 0 });
     }
 
+
     #[test]
-    fn reg_add_fast() {
-        let add_regs: [f64; 16] = core::array::from_fn(|i| i as f64);
+    fn jit_programs() {
+        let native = cfg!(all(target_arch = "x86_64", target_os = "linux"));
+
+        let program = jit::Program::new(&reg::verify(reg::FIB).unwrap());
+        assert_eq!(program.is_native(), native);
+        assert_eq!(program.code_size() > 0, native);
         let mut vm = reg::Vm::new();
-        assert_eq!(vm.run(&reg::ADD_PAIRS, &add_regs), (16*15/2) as f64);
+        test_fib(|n| vm.run_jit(&program, &[n]));
+
+        let program = jit::Program::new(&reg::verify(reg::MANDEL).unwrap());
+        test_mandel(|x, y, n| vm.run_jit(&program, &[x, y, n]));
+
+        // same results as the interpreter.
+        let same = |code: &[reg::Instruction], args: &[f64], memory: &[f64], constants: &[f64]| {
+            let program = reg::verify(code).unwrap();
+            let jit = jit::Program::new(&program);
+            let mut vm = reg::Vm::new();
+            vm.memory_mut().extend_from_slice(memory);
+            vm.constants_mut().extend_from_slice(constants);
+            let expected = vm.run(&program, args);
+            let bits = |memory: &[f64]| memory.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+            let memory = bits(vm.memory_mut());
+            assert_eq!(vm.run_jit(&jit, args).to_bits(), expected.to_bits());
+            assert_eq!(bits(vm.memory_mut()), memory);
+        };
+        let memory: Vec<f64> = (0..20).map(|i| i as f64 * 0.5).collect();
+        same(reg::DOT, &[10.0], &memory, &[]);
+        same(reg::COLLATZ, &[27.0], &[], &[]);
+        for n in [0.0, 1.0, 2.0, 15.0] {
+            same(reg::FIB_RECURSIVE, &[n], &[], &[]);
+        }
+        let mut profile = profile::Profile::new();
+        reg::Vm::new().run_profiled(&reg::verify(reg::MANDEL).unwrap(), &[0.239, -0.981, 100.0], &mut profile);
+        let fused = fuse::reg(reg::MANDEL, &profile).0;
+        same(&fused, &[0.239, -0.981, 100.0], &[], &[]);
+        let (code, constants) = lang::compile_reg_with_constants("fn f(x) { return x * 0.5 + 100000 - 0.5; }").unwrap();
+        same(&code, &[3.0], &[], &constants);
+        let code = asm::assemble_reg("
+            load_int r300, 7
+            set_counter r300
+        L2: get_counter r1
+            min r2, r1, r300
+            max r3, r2, r0
+            floor r4, r0
+            sqrt r5, r3
+            neg r6, r5
+            abs r7, r6
+            eq r8, r0, r0
+            le r9, r1, r0
+            sub r10, r7, r4
+            div r10, r10, r9
+            store_mem r10, r1
+            loop L2
+            load_mem r0, r2
+            return r0").unwrap();
+        for x in [-1.5, 2.25, 3.0, f64::NAN] {
+            same(&code, &[x], &[0.0; 8], &[]);
+        }
+
+        // errors are the interpreter's.
+        let fails = |code: &[reg::Instruction], args: &[f64], memory: &[f64]| {
+            let mut vm = reg::Vm::new();
+            vm.memory_mut().extend_from_slice(memory);
+            let expected = vm.try_run(code, args).err();
+            let jit = jit::Program::new(&reg::verify(code).unwrap());
+            if jit.is_native() {
+                let mut registers = vec![0.0; reg::MAX_REGISTERS];
+                registers[..args.len()].copy_from_slice(args);
+                assert_eq!(jit.run(&mut registers, &mut memory.to_vec(), &[]).unwrap().err(), expected);
+            }
+            expected
+        };
+        let code = asm::assemble_reg("load_mem r1, r0 \n return r1").unwrap();
+        for x in [-1.0, 0.5, 4.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(fails(&code, &[x], &[1.0; 4]), Some(VmError::BadAddress { pc: 0 }));
+        }
+        assert_eq!(fails(&code, &[3.0], &[1.0; 4]), None);
+        let code = asm::assemble_reg("load_const r1, 0 \n return r1").unwrap();
+        assert_eq!(fails(&code, &[], &[]), Some(VmError::BadConstant { pc: 0 }));
+        use reg::Instruction::{Call, Return};
+        for code in [&[Call { target: 0, base: 0 }, Return { src: 0 }], &[Call { target: 0, base: 7 }, Return { src: 0 }]] {
+            assert_eq!(fails(code, &[], &[]), Some(VmError::StackOverflow { pc: 0 }));
+        }
+        // wide registers past the end, in a callee's window.
+        let code = asm::assemble_reg("
+            call L3, r16000
+            return r0
+        L3: copy r0, r400
+            ret r0").unwrap();
+        assert_eq!(fails(&code, &[], &[]), Some(VmError::BadRegister { pc: 3, index: 400 }));
     }
 }
-